@@ -0,0 +1,94 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Path, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use futures::{sink::SinkExt, stream::StreamExt};
+use proto::models::{DebugCommand, DebugEvent, DebugSessionResponse, RouteTestRequest};
+use serde_json::Value;
+use worpen_core::debugger::DebugClient;
+
+use crate::state::AppState;
+
+/// Start a debug session: the route runs with test data, paused before its first operation
+#[utoipa::path(
+    post,
+    path = "/api/v1/dynamic-routes/debug",
+    request_body = RouteTestRequest,
+    responses(
+        (status = 200, description = "Debug session started; attach to ws_path", body = DebugSessionResponse),
+        (status = 400, description = "Route cannot be debugged"),
+        (status = 404, description = "Route not found")
+    )
+)]
+pub async fn start_debug_session(
+    State(state): State<AppState>,
+    Json(request): Json<RouteTestRequest>,
+) -> Result<Json<DebugSessionResponse>, (StatusCode, Json<Value>)> {
+    match state.dynamic_route_service.start_debug_session(request).await {
+        Ok(session_id) => Ok(Json(DebugSessionResponse {
+            ws_path: format!("/api/v1/dynamic-routes/debug/{}/ws", session_id),
+            session_id,
+        })),
+        Err(e) => {
+            let status = if e.contains("not found") { StatusCode::NOT_FOUND } else { StatusCode::BAD_REQUEST };
+            Err((status, Json(serde_json::json!({ "error": e }))))
+        }
+    }
+}
+
+/// Attach to a debug session and drive it with `DebugCommand` messages
+pub async fn debug_session_ws(
+    ws: WebSocketUpgrade,
+    Path(session_id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.dynamic_route_service.attach_debugger(&session_id) {
+        Some(client) => ws.on_upgrade(move |socket| handle_debug_socket(socket, client)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Debug session not found or already attached").into_response(),
+    }
+}
+
+/// Relay commands to the paused execution and its events back, until it
+/// finishes. Closing the socket aborts the execution.
+async fn handle_debug_socket(socket: WebSocket, client: DebugClient) {
+    let (mut sender, mut receiver) = socket.split();
+    let DebugClient { commands, mut events } = client;
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some(event) = event else { break };
+                let finished = matches!(event, DebugEvent::Finished { .. });
+                let text = serde_json::to_string(&event).unwrap_or_default();
+                if sender.send(Message::Text(text)).await.is_err() || finished {
+                    break;
+                }
+            }
+            msg = receiver.next() => {
+                match msg {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str::<DebugCommand>(&text) {
+                        Ok(command) => {
+                            let _ = commands.send(command);
+                        }
+                        Err(e) => {
+                            let error = DebugEvent::Error { message: format!("Invalid command: {}", e) };
+                            let text = serde_json::to_string(&error).unwrap_or_default();
+                            if sender.send(Message::Text(text)).await.is_err() {
+                                break;
+                            }
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let _ = sender.close().await;
+}
@@ -1,23 +1,33 @@
 use axum::{
-    extract::{State, WebSocketUpgrade},
+    extract::{ConnectInfo, State, WebSocketUpgrade},
     response::{IntoResponse, Response},
-    http::{StatusCode, Method, Request},
+    http::{header, HeaderValue, StatusCode, Request},
     Json,
     body::Body,
 };
 use crate::state::AppState;
 use serde_json::Value;
-use proto::models::RouteType;
+use proto::models::{ResponseSchemaPolicy, RouteType};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use worpen_core::auth::{requires_auth, Identity};
+use worpen_core::budget::{self, LimitKind, Limits};
+use worpen_core::rate_limit::{ClientInfo, ClientKey, Decision};
+use worpen_core::services::dynamic_routes::{RouteLookup, RouteMatch};
 
 /// Temporary constant to control dynamic fallback logging
 /// Set to false to disable logging, true to enable
 const ENABLE_DYNAMIC_FALLBACK_LOGGING: bool = true;
 
+/// Set (to the number of mismatches) when a `warn` route's output breaks its response_schema
+const RESPONSE_SCHEMA_ERRORS_HEADER: &str = "x-response-schema-errors";
+
 /// Fallback handler برای dynamic routes
 /// این handler همه request های ثبت‌نشده رو میگیره و چک میکنه آیا dynamic route هست
 #[axum::debug_handler]
 pub async fn dynamic_route_fallback(
     State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     ws: Option<WebSocketUpgrade>,
     req: Request<Body>,
 ) -> Response {
@@ -29,83 +39,234 @@ pub async fn dynamic_route_fallback(
     }
     
     // پیدا کردن route با path و method
-    match find_dynamic_route(&state, &path, &method).await {
-        Some(route) => {
-            // ✅ CHECK ROUTE TYPE FIRST
-            match route.route_type {
-                RouteType::WebSocket => {
-                    // Dispatch to WebSocket handler
-                    if let Some(ws_upgrade) = ws {
-                        tracing::info!("Upgrading to WebSocket for route: {}", route.name);
-                        return handle_websocket_route(ws_upgrade, route, state).await;
-                    } else {
-                        tracing::error!("WebSocket route called without upgrade header");
-                        return (
-                            StatusCode::BAD_REQUEST,
-                            Json(serde_json::json!({
-                                "error": "WebSocket upgrade required",
-                                "message": "This route requires WebSocket protocol"
-                            }))
-                        ).into_response();
-                    }
-                }
-                RouteType::Http => {
-                    // Continue with HTTP logic
-                    match execute_dynamic_route(&state, route, req).await {
-                        Ok(response) => response,
-                        Err(e) => {
-                            tracing::error!("Error executing dynamic route: {}", e);
-                            (
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                                Json(serde_json::json!({
-                                    "error": "Route execution failed",
-                                    "message": e
-                                }))
-                            ).into_response()
-                        }
-                    }
-                }
+    let (route, path_params) = match state.dynamic_route_service.match_route(method.as_str(), &path).await {
+        RouteLookup::Matched(RouteMatch { route_id, params }) => {
+            match state.dynamic_route_service.get_route(&route_id).await {
+                Ok(Some(route)) => (route, params),
+                _ => return not_found_response(&method, &path),
             }
         }
-        None => {
-            // اگه route پیدا نشد، 404 برگردون
-            (
-                StatusCode::NOT_FOUND,
+        RouteLookup::MethodNotAllowed(allowed) => {
+            let allow = allowed.join(", ");
+            let mut response = (
+                StatusCode::METHOD_NOT_ALLOWED,
                 Json(serde_json::json!({
-                    "error": "Not Found",
-                    "message": format!("No route found for {} {}", method, path)
+                    "error": "Method Not Allowed",
+                    "message": format!("{} is not allowed for {}", method, path),
+                    "allowed": allowed
                 }))
-            ).into_response()
+            ).into_response();
+            if let Ok(value) = HeaderValue::from_str(&allow) {
+                response.headers_mut().insert(header::ALLOW, value);
+            }
+            return response;
         }
-    }
+        RouteLookup::NotFound => return not_found_response(&method, &path),
+    };
+
+    // Authenticate and rate-limit before any route logic runs, for HTTP and WebSocket routes alike
+    let headers = header_map(req.headers());
+    let query_params = query_params(req.uri());
+    let client = ClientInfo {
+        ip: connect_info.map(|ConnectInfo(addr)| addr.ip().to_string()),
+        identity: None,
+        headers: &headers,
+        path_params: &path_params,
+        query_params: &query_params,
+    };
+    let (auth, rate_limit) = match admit(&state, &route, client).await {
+        Ok(admitted) => admitted,
+        Err(response) => return response,
+    };
+
+    // ✅ CHECK ROUTE TYPE FIRST
+    let response = match route.route_type {
+        RouteType::WebSocket => {
+            // Dispatch to WebSocket handler
+            if let Some(ws_upgrade) = ws {
+                tracing::info!("Upgrading to WebSocket for route: {}", route.name);
+                handle_websocket_route(ws_upgrade, route, state, auth).await
+            } else {
+                tracing::error!("WebSocket route called without upgrade header");
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({
+                        "error": "WebSocket upgrade required",
+                        "message": "This route requires WebSocket protocol"
+                    }))
+                ).into_response()
+            }
+        }
+        RouteType::Http => {
+            // Continue with HTTP logic
+            match budget::watch(execute_dynamic_route(&state, route, path_params, auth, req)).await {
+                (Ok(response), _) => response,
+                (Err(e), exceeded) => {
+                    tracing::error!("Error executing dynamic route: {}", e);
+                    let (status, error) = match exceeded {
+                        Some(kind) => (limit_status(kind), "Execution limit exceeded"),
+                        None => (StatusCode::INTERNAL_SERVER_ERROR, "Route execution failed"),
+                    };
+                    (
+                        status,
+                        Json(serde_json::json!({
+                            "error": error,
+                            "message": e
+                        }))
+                    ).into_response()
+                }
+            }
+        }
+    };
+    with_decision(response, rate_limit.as_ref())
+}
+
+/// 508 for a route that ran out of fuel, 503 for the other limits
+pub(crate) fn limit_status(kind: LimitKind) -> StatusCode {
+    StatusCode::from_u16(kind.status_code()).unwrap_or(StatusCode::SERVICE_UNAVAILABLE)
 }
 
-async fn find_dynamic_route(
+/// Authenticate the caller and count the request against the route's rate limit.
+/// Limits that don't depend on who the caller is are charged first, so failed logins
+/// use up tokens too and are refused before their credentials are checked once the
+/// tokens run out. A rejected attempt on a limit keyed by the caller is charged to
+/// the key it resolves to without one, usually the IP.
+pub(crate) async fn admit(
     state: &AppState,
-    path: &str,
-    method: &Method,
-) -> Option<proto::models::RouteDefinition> {
-    // لیست گرفتن همه route ها
-    let routes = state.dynamic_route_service.list_routes().await.ok()?;
-    
-    // پیدا کردن route با path و method مطابق
-    routes.into_iter().find(|route| {
-        let route_method = match &route.method {
-            proto::models::HttpMethod::GET => "GET",
-            proto::models::HttpMethod::POST => "POST",
-            proto::models::HttpMethod::PUT => "PUT",
-            proto::models::HttpMethod::DELETE => "DELETE",
-            proto::models::HttpMethod::PATCH => "PATCH",
+    route: &proto::models::RouteDefinition,
+    client: ClientInfo<'_>,
+) -> Result<(Option<Identity>, Option<Decision>), Response> {
+    let by_caller = route.rate_limit.is_some()
+        && ClientKey::parse(route.rate_limit_key.as_deref()).is_ok_and(|key| key.depends_on_identity());
+    if !by_caller {
+        let decision = check_rate_limit(state, route, &client).await?;
+        return match authenticate(state, route, client.headers).await {
+            Ok(auth) => Ok((auth, decision)),
+            Err(response) => Err(with_decision(response, decision.as_ref())),
         };
-        route.path == path && 
-        route_method == method.as_str() &&
-        route.enabled
-    })
+    }
+
+    match authenticate(state, route, client.headers).await {
+        Ok(auth) => {
+            let decision = check_rate_limit(state, route, &ClientInfo { identity: auth.as_ref(), ..client }).await?;
+            Ok((auth, decision))
+        }
+        Err(response) => {
+            let decision = check_rate_limit(state, route, &client).await?;
+            Err(with_decision(response, decision.as_ref()))
+        }
+    }
+}
+
+/// Count a request against the route's per-minute limit for its client. A refused
+/// request gets its 429; an allowed one the decision to report in headers.
+async fn check_rate_limit(
+    state: &AppState,
+    route: &proto::models::RouteDefinition,
+    client: &ClientInfo<'_>,
+) -> Result<Option<Decision>, Response> {
+    let Some(limit) = route.rate_limit else {
+        return Ok(None);
+    };
+    let key = ClientKey::parse(route.rate_limit_key.as_deref())
+        .unwrap_or(ClientKey::Ip)
+        .resolve(client);
+    let decision = state.rate_limiter.check(&route.id, &key, limit).await;
+    if decision.allowed {
+        return Ok(Some(decision));
+    }
+    let response = (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(serde_json::json!({
+            "error": "Too Many Requests",
+            "message": format!("Rate limit of {} requests per minute exceeded", decision.limit),
+            "retry_after": decision.retry_after_secs
+        }))
+    ).into_response();
+    Err(with_rate_limit_headers(response, &decision))
+}
+
+fn with_decision(response: Response, decision: Option<&Decision>) -> Response {
+    match decision {
+        Some(decision) => with_rate_limit_headers(response, decision),
+        None => response,
+    }
+}
+
+fn with_rate_limit_headers(mut response: Response, decision: &Decision) -> Response {
+    for (name, value) in decision.headers() {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+}
+
+/// Request headers keyed by lowercase name
+pub(crate) fn header_map(headers: &axum::http::HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or("").to_string()))
+        .collect()
+}
+
+pub(crate) fn query_params(uri: &axum::http::Uri) -> HashMap<String, String> {
+    uri.query()
+        .map(|q| url::form_urlencoded::parse(q.as_bytes()).into_owned().collect())
+        .unwrap_or_default()
+}
+
+/// اگه route پیدا نشد، 404 برگردون
+fn not_found_response(method: &axum::http::Method, path: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({
+            "error": "Not Found",
+            "message": format!("No route found for {} {}", method, path)
+        }))
+    ).into_response()
+}
+
+/// Resolve the caller. Routes that require auth answer missing or invalid credentials
+/// with 401 and missing scopes/roles with 403; other routes still see a valid caller
+/// but ignore bad credentials.
+async fn authenticate(
+    state: &AppState,
+    route: &proto::models::RouteDefinition,
+    headers: &HashMap<String, String>,
+) -> Result<Option<Identity>, Response> {
+    let required = requires_auth(route);
+
+    match state.authenticator.authenticate(headers).await {
+        Ok(Some(identity)) => {
+            if required {
+                if let Err(e) = identity.authorize(&route.required_scopes, &route.required_roles) {
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        Json(serde_json::json!({ "error": "Forbidden", "message": e }))
+                    ).into_response());
+                }
+            }
+            Ok(Some(identity))
+        }
+        Ok(None) if required => Err(unauthorized_response("Authentication required")),
+        Err(e) if required => Err(unauthorized_response(&e)),
+        _ => Ok(None),
+    }
+}
+
+fn unauthorized_response(message: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(serde_json::json!({ "error": "Unauthorized", "message": message }))
+    ).into_response()
 }
 
 async fn execute_dynamic_route(
     state: &AppState,
     route: proto::models::RouteDefinition,
+    path_params: HashMap<String, String>,
+    auth: Option<Identity>,
     req: Request<Body>,
 ) -> Result<Response, String> {
     // Extract request data
@@ -123,41 +284,39 @@ async fn execute_dynamic_route(
             .unwrap_or_else(|_| serde_json::json!({}))
     };
     
-    // Extract query parameters
-    let query_params: std::collections::HashMap<String, String> = parts
-        .uri
-        .query()
-        .map(|q| {
-            url::form_urlencoded::parse(q.as_bytes())
-                .into_owned()
-                .collect()
-        })
-        .unwrap_or_default();
-    
-    // Extract headers
-    let headers: std::collections::HashMap<String, String> = parts
-        .headers
-        .iter()
-        .map(|(name, value)| {
-            (name.to_string(), value.to_str().unwrap_or("").to_string())
-        })
-        .collect();
+    // Extract query parameters and headers
+    let query_params = query_params(&parts.uri);
+    let headers = header_map(&parts.headers);
     
-    // Extract path parameters by parsing route path
-    let path_params = extract_path_params(&route.path, parts.uri.path());
+    // Enforce declared parameters and the request schema before any logic runs
+    let validated = match state.dynamic_route_service
+        .validate_request(&route, &path_params, &query_params, request_data)
+    {
+        Ok(validated) => validated,
+        Err(result) => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "Validation failed",
+                    "errors": result.errors
+                }))
+            ).into_response());
+        }
+    };
     
     // Create request object for variable resolution
     let request_object = serde_json::json!({
-        "payload": request_data,
+        "payload": validated.body,
         "headers": headers,
-        "params": path_params
+        "params": validated.path,
+        "query": validated.query
     });
     
     // ساخت execution context
     let mut context = proto::models::DynamicRouteExecutionContext {
         route_id: route.id.clone(),
         variables: std::collections::HashMap::new(),
-        request_payload: Some(request_data),
+        request_payload: Some(validated.body),
         path_params,
         query_params,
         functions: std::collections::HashMap::new(),
@@ -165,15 +324,51 @@ async fn execute_dynamic_route(
         error_context: None,
     };
     
+    // Declared parameters are readable by name with their coerced types
+    context.variables.extend(validated.params);
+    if let Some(identity) = &auth {
+        context.variables.insert("auth".to_string(), identity.to_value());
+    }
+    
     // Inject request object into variables for template resolution
     context.variables.insert("request".to_string(), request_object);
     
     // اجرای logic
     let result = state.dynamic_route_service
-        .execute_route_logic(&route.logic, &mut context)
+        .execute_route_logic(&route, &mut context)
         .await
         .map_err(|e| format!("Execution error: {}", e))?;
     
+    let schema_errors = match route.response_schema_policy {
+        ResponseSchemaPolicy::Off => vec![],
+        _ => state.dynamic_route_service.check_response(&route, &result),
+    };
+    if !schema_errors.is_empty() {
+        if route.response_schema_policy == ResponseSchemaPolicy::Enforce {
+            tracing::error!("Route {} broke its response_schema: {:?}", route.name, schema_errors);
+            return Ok((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": "Response does not match response_schema",
+                    "errors": schema_errors
+                }))
+            ).into_response());
+        }
+        tracing::warn!("Route {} broke its response_schema: {:?}", route.name, schema_errors);
+    }
+    
+    let mut response = build_response(result);
+    if !schema_errors.is_empty() {
+        response.headers_mut().insert(
+            RESPONSE_SCHEMA_ERRORS_HEADER,
+            HeaderValue::from(schema_errors.len()),
+        );
+    }
+    Ok(response)
+}
+
+/// Turn the value returned by route logic into an HTTP response
+fn build_response(result: Value) -> Response {
     // Check if result contains enhanced return metadata (value, status, headers, raw)
     if let Some(obj) = result.as_object() {
         if obj.contains_key("value") {
@@ -213,7 +408,7 @@ async fn execute_dynamic_route(
                         }
                     }
                 }
-                return Ok(response);
+                return response;
             } else {
                 // Normal JSON response with custom status and headers
                 let mut response = (status, Json(value.clone())).into_response();
@@ -228,7 +423,7 @@ async fn execute_dynamic_route(
                         }
                     }
                 }
-                return Ok(response);
+                return response;
             }
         }
     }
@@ -259,45 +454,18 @@ async fn execute_dynamic_route(
     // اگه html field داره، به صورت HTML برگردون
     if let Some(html) = response_json.get("html").and_then(|h| h.as_str()) {
         use axum::response::Html;
-        Ok((status, Html(html.to_string())).into_response())
+        (status, Html(html.to_string())).into_response()
     } else {
-        Ok((status, Json(response_json)).into_response())
-    }
-}
-
-/// Extract path parameters from route path and actual request path
-fn extract_path_params(route_path: &str, request_path: &str) -> std::collections::HashMap<String, String> {
-    let mut params = std::collections::HashMap::new();
-    
-    // Split paths into segments
-    let route_segments: Vec<&str> = route_path.trim_start_matches('/').split('/').collect();
-    let request_segments: Vec<&str> = request_path.trim_start_matches('/').split('/').collect();
-    
-    // If lengths don't match, return empty params
-    if route_segments.len() != request_segments.len() {
-        return params;
-    }
-    
-    // Compare segments
-    for (route_seg, req_seg) in route_segments.iter().zip(request_segments.iter()) {
-        if route_seg.starts_with('{') && route_seg.ends_with('}') {
-            // This is a parameter
-            let param_name = &route_seg[1..route_seg.len()-1];
-            params.insert(param_name.to_string(), req_seg.to_string());
-        } else if route_seg != req_seg {
-            // Static segments don't match, return empty params
-            return std::collections::HashMap::new();
-        }
+        (status, Json(response_json)).into_response()
     }
-    
-    params
 }
 
 /// Handle WebSocket route upgrade
-async fn handle_websocket_route(
+pub(crate) async fn handle_websocket_route(
     ws: WebSocketUpgrade,
     route: proto::models::RouteDefinition,
     state: AppState,
+    auth: Option<Identity>,
 ) -> Response {
     use axum::extract::ws::Message;
     use futures::{sink::SinkExt, stream::StreamExt};
@@ -307,6 +475,8 @@ async fn handle_websocket_route(
     ws.on_upgrade(move |socket| async move {
         let (mut sender, mut receiver) = socket.split();
         let connection_id = Uuid::new_v4().to_string();
+        let auth = auth.map(|identity| identity.to_value());
+        let limits = Limits::for_route(&route);
 
         // Get WebSocket manager
         let ws_manager = state
@@ -341,6 +511,8 @@ async fn handle_websocket_route(
                     &state,
                     &ws_manager,
                     &connection_id,
+                    limits,
+                    auth.as_ref(),
                     None,
                 )
                 .await;
@@ -359,6 +531,8 @@ async fn handle_websocket_route(
                                 &state,
                                 &ws_manager,
                                 &connection_id,
+                                limits,
+                                auth.as_ref(),
                                 Some(text),
                             )
                             .await;
@@ -383,6 +557,8 @@ async fn handle_websocket_route(
                     &state,
                     &ws_manager,
                     &connection_id,
+                    limits,
+                    auth.as_ref(),
                     None,
                 )
                 .await;
@@ -401,6 +577,8 @@ async fn execute_ws_logic(
     state: &AppState,
     ws_manager: &worpen_core::websocket::WebSocketManager,
     connection_id: &str,
+    limits: Limits,
+    auth: Option<&Value>,
     incoming_message: Option<String>,
 ) -> Result<Value, String> {
     use worpen_core::vm::memory::ExecutionMemory;
@@ -409,7 +587,7 @@ async fn execute_ws_logic(
     
     // Compile logic
     let mut compiler = LogicCompiler::new();
-    let program = compiler.compile(logic);
+    let program = compiler.compile(logic)?;
     let symbol_table = compiler.get_symbol_table().clone();
 
     // Create execution memory
@@ -427,6 +605,11 @@ async fn execute_ws_logic(
         memory.set(conn_index, Value::String(connection_id.to_string()));
     }
 
+    // Inject {{auth.*}} when the connection was authenticated
+    if let (Some(auth), Some(auth_index)) = (auth, symbol_table.get_index("auth")) {
+        memory.set(auth_index, auth.clone());
+    }
+
     // Create VM with WebSocket support
     let db_pool = state.dynamic_route_service.get_db_pool();
     let redis_pool = state.dynamic_route_service.get_redis_pool();
@@ -439,7 +622,37 @@ async fn execute_ws_logic(
         Some(ws_manager.clone()),
         Some(connection_id.to_string()),
     );
+    vm.set_datasources(state.dynamic_route_service.datasource_pools());
+
+    // Execute within the route's budget
+    budget::run(limits, vm.execute(&program)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
 
-    // Execute
-    vm.execute(&program).await
+    async fn status_of(state: &AppState, key: &str) -> StatusCode {
+        let request = Request::get("/api/guarded").header("x-api-key", key).body(Body::empty()).unwrap();
+        dynamic_route_fallback(State(state.clone()), None, None, request).await.status()
+    }
+
+    #[tokio::test]
+    async fn test_failed_logins_use_up_the_rate_limit() {
+        for key in [None, Some("api_key")] {
+            let (state, data_dir) = super::super::dynamic_ws::tests::app_state(json!({
+                "id": "guarded", "name": "guarded", "path": "/api/guarded",
+                "logic": [{"return": {"value": "ok"}}],
+                "auth_required": true, "rate_limit": 2, "rate_limit_key": key
+            })).await;
+
+            // Wrong keys are refused, then limited like any other request
+            assert_eq!(status_of(&state, "guess-1").await, StatusCode::UNAUTHORIZED);
+            assert_eq!(status_of(&state, "guess-2").await, StatusCode::UNAUTHORIZED);
+            assert_eq!(status_of(&state, "guess-3").await, StatusCode::TOO_MANY_REQUESTS, "keyed by {:?}", key);
+
+            std::fs::remove_dir_all(data_dir).ok();
+        }
+    }
 }
@@ -4,8 +4,10 @@ use axum::{
     http::StatusCode,
 };
 use crate::state::AppState;
-use proto::models::{RouteDefinition, RegisterRouteRequest, RouteTestRequest, RouteTestResponse, FunctionDef};
+use proto::models::{RouteDefinition, RegisterRouteRequest, RouteTestRequest, RouteTestResponse, RouteTrace, FunctionDef, QuarantinedRoute, DatasourceStatus};
 use serde_json::Value;
+use worpen_core::budget;
+use super::dynamic_fallback::limit_status;
 
 /// Register a new dynamic route
 #[utoipa::path(
@@ -32,21 +34,42 @@ pub async fn register_route(
         logic: req.logic,
         ws_hooks: req.ws_hooks,
         parameters: req.parameters,
+        request_schema: req.request_schema,
         response_schema: req.response_schema,
+        response_schema_policy: req.response_schema_policy,
         auth_required: req.auth_required,
+        required_scopes: req.required_scopes,
+        required_roles: req.required_roles,
         rate_limit: req.rate_limit,
+        rate_limit_key: req.rate_limit_key,
+        limits: req.limits,
+        optimize: req.optimize,
         enabled: req.enabled,
         version: req.version,
         created_at: String::new(), // Will be set by service
         updated_at: String::new(), // Will be set by service
         created_by: "system".to_string(), // Default creator
     };
+    // Compile errors reject the route with every diagnostic; warnings are reported back
+    let diagnostics = state.dynamic_route_service.lint_route(&route);
+    if diagnostics.iter().any(|d| d.is_error()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "Route has errors",
+                "diagnostics": diagnostics
+            }))
+        ));
+    }
+    let warnings: Vec<_> = diagnostics.into_iter().filter(|d| !d.is_error()).collect();
+
     match state.dynamic_route_service.register_route(route).await {
         Ok(route_id) => Ok((
             StatusCode::CREATED,
             Json(serde_json::json!({
                 "status": "REGISTERED",
                 "route_id": route_id,
+                "warnings": warnings,
                 "message": "Route registered successfully"
             }))
         )),
@@ -170,6 +193,8 @@ pub async fn test_route(
             error: Some(e),
             execution_time_ms: 0,
             steps_executed: vec![],
+            schema_errors: vec![],
+            trace: vec![],
         }),
     }
 }
@@ -182,7 +207,9 @@ pub async fn test_route(
     responses(
         (status = 200, description = "Execution result", body = Value),
         (status = 404, description = "Route not found"),
-        (status = 500, description = "Execution failed")
+        (status = 500, description = "Execution failed"),
+        (status = 503, description = "Execution deadline, memory or parallelism limit exceeded"),
+        (status = 508, description = "Execution ran out of fuel")
     )
 )]
 pub async fn execute_route(
@@ -190,22 +217,26 @@ pub async fn execute_route(
     Path(id): Path<String>,
     Json(payload): Json<Value>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
-    match state.dynamic_route_service.execute_route(
+    match budget::watch(state.dynamic_route_service.execute_route(
         &id,
         Some(payload),
         std::collections::HashMap::new(),
         std::collections::HashMap::new(),
-    ).await {
-        Ok(result) => Ok(Json(result)),
-        Err(e) if e.contains("not found") => Err((
+    )).await {
+        (Ok(result), _) => Ok(Json(result)),
+        (Err(e), Some(kind)) => Err((
+            limit_status(kind),
+            Json(serde_json::json!({"error": e}))
+        )),
+        (Err(e), None) if e.contains("not found") => Err((
             StatusCode::NOT_FOUND,
             Json(serde_json::json!({"error": e}))
         )),
-        Err(e) if e.contains("disabled") => Err((
+        (Err(e), None) if e.contains("disabled") => Err((
             StatusCode::FORBIDDEN,
             Json(serde_json::json!({"error": e}))
         )),
-        Err(e) => Err((
+        (Err(e), None) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({"error": e}))
         )),
@@ -231,6 +262,79 @@ pub async fn export_route(
     }
 }
 
+/// Sampled traces of the route's live executions, newest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/dynamic-routes/{id}/traces",
+    responses(
+        (status = 200, description = "Recent traces", body = Vec<RouteTrace>),
+        (status = 404, description = "Route not found")
+    )
+)]
+pub async fn get_route_traces(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<RouteTrace>>, StatusCode> {
+    match state.dynamic_route_service.get_route(&id).await {
+        Ok(Some(_)) => Ok(Json(state.dynamic_route_service.recent_traces(&id))),
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// The logic a route runs after the optimizer, and the program it compiles to
+#[utoipa::path(
+    get,
+    path = "/api/v1/dynamic-routes/{id}/optimized",
+    responses(
+        (status = 200, description = "Optimized logic and bytecode", body = Value),
+        (status = 404, description = "Route not found")
+    )
+)]
+pub async fn get_optimized_route(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    let route = match state.dynamic_route_service.get_route(&id).await {
+        Ok(Some(route)) => route,
+        _ => return Err(StatusCode::NOT_FOUND),
+    };
+    let plan = state.dynamic_route_service.cached_plan(&id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(serde_json::json!({
+        "route_id": id,
+        "optimize": route.optimize,
+        "logic": plan.logic.as_ref(),
+        "program": plan.bytecode.as_deref(),
+    })))
+}
+
+/// Persisted routes that failed to load on startup, and why
+#[utoipa::path(
+    get,
+    path = "/api/v1/dynamic-routes/quarantine",
+    responses(
+        (status = 200, description = "Quarantined route files", body = Vec<QuarantinedRoute>)
+    )
+)]
+pub async fn list_quarantined_routes(
+    State(state): State<AppState>,
+) -> Json<Vec<QuarantinedRoute>> {
+    Json(state.dynamic_route_service.quarantined_routes())
+}
+
+/// Configured datasources, with a health check of each
+#[utoipa::path(
+    get,
+    path = "/api/v1/datasources",
+    responses(
+        (status = 200, description = "Datasources and their health", body = Vec<DatasourceStatus>)
+    )
+)]
+pub async fn list_datasources(
+    State(state): State<AppState>,
+) -> Json<Vec<DatasourceStatus>> {
+    Json(state.dynamic_route_service.datasource_health().await)
+}
+
 /// Import route from JSON
 #[utoipa::path(
     post,
@@ -336,11 +440,32 @@ pub async fn get_route_stats(
                     acc
                 });
             
+            // Rate limit counts for every route that has a limit
+            let limiter_stats = state.rate_limiter.stats();
+            let rate_limits: Vec<Value> = routes.iter()
+                .filter_map(|r| r.rate_limit.map(|limit| (r, limit)))
+                .map(|(r, limit)| {
+                    let stats = limiter_stats.get(&r.id).cloned().unwrap_or_default();
+                    serde_json::json!({
+                        "route_id": r.id,
+                        "path": r.path,
+                        "limit_per_minute": limit,
+                        "key": r.rate_limit_key.as_deref().unwrap_or("ip"),
+                        "allowed": stats.allowed,
+                        "limited": stats.limited,
+                        "errors": stats.errors,
+                    })
+                })
+                .collect();
+            
             Json(serde_json::json!({
                 "total_routes": total,
                 "enabled_routes": enabled,
                 "disabled_routes": disabled,
                 "routes_by_method": by_method,
+                "rate_limit_backend": state.rate_limiter.backend_name(),
+                "rate_limits": rate_limits,
+                "execution_aborts": budget::abort_counts(),
             }))
         },
         Err(_) => Json(serde_json::json!({
@@ -348,6 +473,9 @@ pub async fn get_route_stats(
             "enabled_routes": 0,
             "disabled_routes": 0,
             "routes_by_method": {},
+            "rate_limit_backend": state.rate_limiter.backend_name(),
+            "rate_limits": [],
+            "execution_aborts": budget::abort_counts(),
         })),
     }
 }
@@ -78,9 +78,16 @@ pub async fn register_route_raw(
         logic: req.logic.clone(),
         ws_hooks: req.ws_hooks.clone(),
         parameters: req.parameters.clone(),
+        request_schema: req.request_schema.clone(),
         response_schema: req.response_schema.clone(),
+        response_schema_policy: req.response_schema_policy,
         auth_required: req.auth_required,
+        required_scopes: req.required_scopes.clone(),
+        required_roles: req.required_roles.clone(),
         rate_limit: req.rate_limit,
+        rate_limit_key: req.rate_limit_key.clone(),
+        limits: req.limits.clone(),
+        optimize: req.optimize,
         enabled: req.enabled,
         version: req.version.clone(),
         created_at: String::new(), // Will be set by service
@@ -88,12 +95,26 @@ pub async fn register_route_raw(
         created_by: "system".to_string(), // Default creator
     };
 
+    // Compile errors reject the route with every diagnostic; warnings are reported back
+    let diagnostics = state.dynamic_route_service.lint_route(&route);
+    if diagnostics.iter().any(|d| d.is_error()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "Route has errors",
+                "diagnostics": diagnostics
+            }))
+        ));
+    }
+    let warnings: Vec<_> = diagnostics.into_iter().filter(|d| !d.is_error()).collect();
+
     match state.dynamic_route_service.register_route(route).await {
         Ok(route_id) => Ok((
             StatusCode::CREATED,
             Json(serde_json::json!({
                 "status": "REGISTERED",
                 "route_id": route_id,
+                "warnings": warnings,
                 "route_name": req.name,
                 "format": format!("{:?}", format),
                 "message": "Route registered successfully"
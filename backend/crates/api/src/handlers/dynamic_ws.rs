@@ -1,19 +1,15 @@
 use axum::{
     extract::{
-        ws::{Message, WebSocket},
-        Path, State, WebSocketUpgrade,
+        ws::Message,
+        ConnectInfo, Path, State, WebSocketUpgrade,
     },
-    response::IntoResponse,
+    http::{HeaderMap, Uri},
+    response::Response,
 };
-use worpen_core::websocket::WebSocketManager;
-use worpen_core::vm::memory::ExecutionMemory;
-use worpen_core::vm::machine::VirtualMachine;
-use worpen_core::compiler::lowerer::LogicCompiler;
-use futures::{sink::SinkExt, stream::StreamExt};
 use proto::models::RouteType;
-use serde_json::Value;
-use tokio::sync::mpsc;
-use uuid::Uuid;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use worpen_core::rate_limit::ClientInfo;
 
 use crate::state::AppState;
 
@@ -22,7 +18,10 @@ pub async fn dynamic_ws_handler(
     ws: WebSocketUpgrade,
     Path(path): Path<String>,
     State(state): State<AppState>,
-) -> impl IntoResponse {
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
     // Find the WebSocket route
     let routes = match state.dynamic_route_service.list_routes().await {
         Ok(routes) => routes,
@@ -49,7 +48,22 @@ pub async fn dynamic_ws_handler(
     }
 
     if let Some(route) = found_route {
-        ws.on_upgrade(move |socket| handle_websocket(socket, route, state))
+        // Connections are authenticated and rate-limited like requests through the fallback
+        let headers = super::dynamic_fallback::header_map(&headers);
+        let query_params = super::dynamic_fallback::query_params(&uri);
+        let client = ClientInfo {
+            ip: connect_info.map(|ConnectInfo(addr)| addr.ip().to_string()),
+            identity: None,
+            headers: &headers,
+            path_params: &HashMap::new(),
+            query_params: &query_params,
+        };
+        let auth = match super::dynamic_fallback::admit(&state, &route, client).await {
+            Ok((auth, _)) => auth,
+            Err(response) => return response,
+        };
+        // Served like the fallback's WebSocket routes, with {{auth.*}} set for every hook
+        super::dynamic_fallback::handle_websocket_route(ws, route, state, auth).await
     } else {
         // Return 404-like response
         ws.on_upgrade(|mut socket| async move {
@@ -61,141 +75,109 @@ pub async fn dynamic_ws_handler(
     }
 }
 
-/// Handle individual WebSocket connection
-async fn handle_websocket(
-    socket: WebSocket,
-    route: proto::models::RouteDefinition,
-    state: AppState,
-) {
-    let (mut sender, mut receiver) = socket.split();
-    let connection_id = Uuid::new_v4().to_string();
-
-    // Create WebSocket manager if not exists
-    let ws_manager = state
-        .dynamic_route_service
-        .get_ws_manager()
-        .unwrap_or_default();
-
-    // Create channel for outgoing messages
-    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
-
-    // Register connection
-    ws_manager.register(connection_id.clone(), tx);
-
-    // Spawn task to forward messages from channel to WebSocket
-    let ws_manager_clone = ws_manager.clone();
-    let connection_id_clone = connection_id.clone();
-    tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if sender.send(Message::Text(msg)).await.is_err() {
-                break;
-            }
-        }
-        // Cleanup on disconnect
-        ws_manager_clone.unregister(&connection_id_clone);
-    });
-
-    // Execute on_connect hook if exists
-    if let Some(ref hooks) = route.ws_hooks {
-        if !hooks.on_connect.is_empty() {
-            let _ = execute_ws_logic(
-                &hooks.on_connect,
-                &state,
-                &ws_manager,
-                &connection_id,
-                None,
-            )
-            .await;
-        }
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use futures::StreamExt;
+    use infra::adapters::SqliteAgentRepository;
+    use serde_json::json;
+    use std::sync::Arc;
+    use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+    use worpen_core::auth::{AuthConfig, Authenticator};
+    use worpen_core::services::*;
+
+    /// App state with an API key provider (`k1` for `bot`) and this route registered
+    pub(crate) async fn app_state(route: serde_json::Value) -> (AppState, std::path::PathBuf) {
+        let pool = infra::initialize_db("sqlite::memory:").await.unwrap();
+        let agents = Arc::new(SqliteAgentRepository::new(pool.clone()));
+        let incidents = Arc::new(infra::repositories::SqliteIncidentRepository::new(pool.clone()));
+        let automations = Arc::new(infra::repositories::SqliteAutomationRepository::new(pool.clone()));
+
+        let data_dir = std::env::temp_dir().join(format!("worpen_ws_{}", uuid::Uuid::new_v4()));
+        let routes = Arc::new(DynamicRouteService::with_data_dir(data_dir.display().to_string()));
+        let mut definition = json!({
+            "description": "", "method": "GET", "parameters": [], "response_schema": null,
+            "enabled": true, "version": "1.0.0", "created_at": "", "updated_at": "", "created_by": "test"
+        });
+        definition.as_object_mut().unwrap().extend(route.as_object().unwrap().clone());
+        routes.register_route(serde_json::from_value(definition).unwrap()).await.unwrap();
+
+        let auth: AuthConfig = serde_json::from_value(json!({
+            "providers": [{"type": "api_key", "keys": [{"key": "k1", "sub": "bot"}]}]
+        })).unwrap();
+        let state = AppState {
+            agent_service: Arc::new(AgentService::new(agents.clone())),
+            dashboard_service: Arc::new(DashboardService::new(agents, incidents.clone())),
+            docker_service: Arc::new(DockerService::new()),
+            incident_service: Arc::new(IncidentService::new(incidents)),
+            automation_service: Arc::new(AutomationService::new(automations)),
+            pipeline_service: Arc::new(PipelineService::new()),
+            model_service: Arc::new(ModelService::new(routes.clone())),
+            dynamic_route_service: routes,
+            authenticator: Arc::new(Authenticator::from_config(&auth, None).unwrap()),
+            rate_limiter: Default::default(),
+            connected_agents: Default::default(),
+        };
+        (state, data_dir)
     }
 
-    // Main message loop
-    while let Some(msg_result) = receiver.next().await {
-        match msg_result {
-            Ok(Message::Text(text)) => {
-                // Execute on_message hook
-                if let Some(ref hooks) = route.ws_hooks {
-                    if !hooks.on_message.is_empty() {
-                        let _ = execute_ws_logic(
-                            &hooks.on_message,
-                            &state,
-                            &ws_manager,
-                            &connection_id,
-                            Some(text),
-                        )
-                        .await;
-                    }
-                }
-            }
-            Ok(Message::Close(_)) => {
-                break;
-            }
-            Err(_) => {
-                break;
-            }
-            _ => {}
-        }
+    /// A server with only `/api/ws` and one WebSocket route that greets the caller from `{{auth.sub}}`
+    async fn serve(rate_limit: Option<u32>) -> (std::net::SocketAddr, std::path::PathBuf) {
+        let (state, data_dir) = app_state(json!({
+            "id": "chat", "name": "chat", "path": "/api/chat",
+            "route_type": "web_socket", "logic": [{"comment": {"text": "hooks only"}}],
+            "ws_hooks": {
+                "on_connect": [{"ws_op": {"command": "send", "message": "hello {{auth.sub}}"}}],
+                "on_message": []
+            },
+            "auth_required": true, "rate_limit": rate_limit
+        })).await;
+
+        let app = Router::new().route("/api/ws/*path", get(dynamic_ws_handler)).with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (addr, data_dir)
     }
 
-    // Execute on_disconnect hook
-    if let Some(ref hooks) = route.ws_hooks {
-        if !hooks.on_disconnect.is_empty() {
-            let _ = execute_ws_logic(
-                &hooks.on_disconnect,
-                &state,
-                &ws_manager,
-                &connection_id,
-                None,
-            )
-            .await;
-        }
-    }
+    #[tokio::test]
+    async fn test_ws_hooks_see_the_caller() {
+        let (addr, data_dir) = serve(None).await;
 
-    // Unregister connection
-    ws_manager.unregister(&connection_id);
-}
+        let mut request = format!("ws://{}/api/ws/chat", addr).into_client_request().unwrap();
+        request.headers_mut().insert("x-api-key", "k1".parse().unwrap());
+        let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        let greeting = socket.next().await.unwrap().unwrap();
+        assert_eq!(greeting, tungstenite::Message::Text("hello bot".to_string()));
 
-/// Execute WebSocket logic operations
-async fn execute_ws_logic(
-    logic: &[proto::models::LogicOperation],
-    state: &AppState,
-    ws_manager: &WebSocketManager,
-    connection_id: &str,
-    incoming_message: Option<String>,
-) -> Result<Value, String> {
-    // Compile logic
-    let mut compiler = LogicCompiler::new();
-    let program = compiler.compile(logic);
-    let symbol_table = compiler.get_symbol_table().clone();
-
-    // Create execution memory
-    let mut memory = ExecutionMemory::new();
-
-    // Inject {{message}} variable if provided
-    if let Some(msg) = incoming_message {
-        if let Some(msg_index) = symbol_table.get_index("message") {
-            memory.set(msg_index, Value::String(msg));
+        // The route requires auth, so the upgrade is refused without a key
+        match tokio_tungstenite::connect_async(format!("ws://{}/api/ws/chat", addr)).await {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 401),
+            other => panic!("expected a 401, got {:?}", other.map(|(_, response)| response.status())),
         }
-    }
 
-    // Inject {{connection_id}} variable
-    if let Some(conn_index) = symbol_table.get_index("connection_id") {
-        memory.set(conn_index, Value::String(connection_id.to_string()));
+        std::fs::remove_dir_all(data_dir).ok();
     }
 
-    // Create VM with WebSocket support
-    let db_pool = state.dynamic_route_service.get_db_pool();
-    let redis_pool = state.dynamic_route_service.get_redis_pool();
-
-    let mut vm = VirtualMachine::with_all(
-        memory,
-        symbol_table,
-        db_pool,
-        redis_pool,
-        Some(ws_manager.clone()),
-        Some(connection_id.to_string()),
-    );
-
-    // Execute
-    vm.execute(&program).await
+    #[tokio::test]
+    async fn test_ws_route_rate_limit() {
+        let (addr, data_dir) = serve(Some(1)).await;
+        let connect = || {
+            let mut request = format!("ws://{}/api/ws/chat", addr).into_client_request().unwrap();
+            request.headers_mut().insert("x-api-key", "k1".parse().unwrap());
+            tokio_tungstenite::connect_async(request)
+        };
+
+        let (_socket, _) = connect().await.unwrap();
+        match connect().await {
+            Err(tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), 429);
+                assert!(response.headers().contains_key("retry-after"));
+            }
+            other => panic!("expected a 429, got {:?}", other.map(|(_, response)| response.status())),
+        }
+
+        std::fs::remove_dir_all(data_dir).ok();
+    }
 }
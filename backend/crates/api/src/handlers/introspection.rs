@@ -0,0 +1,113 @@
+//! Introspect an existing database and scaffold routes over its tables
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+    http::StatusCode,
+};
+use crate::state::AppState;
+use proto::models::TableScaffold;
+use serde::Deserialize;
+use serde_json::Value;
+use worpen_core::data_model::scaffold;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct IntrospectQuery {
+    /// Comma-separated tables to include; every table when absent
+    pub tables: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ScaffoldRequest {
+    /// Tables to register routes for; every table when absent
+    #[serde(default)]
+    pub tables: Option<Vec<String>>,
+}
+
+/// Read a datasource's tables and scaffold each one
+async fn scaffold_tables(state: &AppState, datasource: &str, only: Option<Vec<String>>) -> Result<Vec<TableScaffold>, (StatusCode, Json<Value>)> {
+    let pool = state.dynamic_route_service.datasource_pools().remove(datasource)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": format!("Unknown datasource '{}'", datasource) }))))?;
+    let tables = match only {
+        Some(names) => {
+            let mut tables = Vec::with_capacity(names.len());
+            for name in names {
+                tables.push(infra::introspect_table(&pool, &name).await
+                    .map_err(|e| (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e }))))?);
+            }
+            tables
+        }
+        None => infra::introspect(&pool).await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "error": e }))))?,
+    };
+    Ok(tables.into_iter().map(|table| scaffold::scaffold(table, datasource)).collect())
+}
+
+/// Tables of a datasource with their JSON Schema and a scaffold of routes, without registering anything
+#[utoipa::path(
+    get,
+    path = "/api/v1/datasources/{name}/introspect",
+    params(
+        ("tables" = Option<String>, Query, description = "Comma-separated tables to include")
+    ),
+    responses(
+        (status = 200, description = "Tables, schemas and scaffolded routes", body = Vec<TableScaffold>),
+        (status = 404, description = "Datasource not found")
+    )
+)]
+pub async fn introspect_datasource(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<IntrospectQuery>,
+) -> Result<Json<Vec<TableScaffold>>, (StatusCode, Json<Value>)> {
+    let only = query.tables.map(|tables| tables.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect());
+    scaffold_tables(&state, &name, only).await.map(Json)
+}
+
+/// Register the scaffolded routes of a datasource's tables
+///
+/// Routes that clash with registered ones are reported and left out; the rest are registered.
+#[utoipa::path(
+    post,
+    path = "/api/v1/datasources/{name}/scaffold",
+    request_body = Value,
+    responses(
+        (status = 200, description = "Registered and failed routes", body = Value),
+        (status = 404, description = "Datasource not found")
+    )
+)]
+pub async fn scaffold_datasource(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    body: String,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    // An empty body scaffolds every table
+    let request: ScaffoldRequest = match body.trim() {
+        "" => ScaffoldRequest::default(),
+        body => serde_json::from_str(body)
+            .map_err(|e| (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": format!("Invalid request: {}", e) }))))?,
+    };
+    let scaffolds = scaffold_tables(&state, &name, request.tables).await?;
+
+    let mut registered = Vec::new();
+    let mut failed = Vec::new();
+    let mut skipped_tables = Vec::new();
+    for scaffold in scaffolds {
+        if let Some(reason) = scaffold.skipped {
+            skipped_tables.push(serde_json::json!({ "table": scaffold.table.name, "reason": reason }));
+        }
+        for route in scaffold.routes {
+            let route_id = route.id.clone();
+            match state.dynamic_route_service.register_route(route).await {
+                Ok(id) => registered.push(id),
+                Err(e) => failed.push(serde_json::json!({ "route_id": route_id, "error": e })),
+            }
+        }
+    }
+    Ok(Json(serde_json::json!({
+        "datasource": name,
+        "registered": registered,
+        "failed": failed,
+        "skipped_tables": skipped_tables
+    })))
+}
@@ -15,6 +15,9 @@ pub mod dynamic_routes;
 pub mod dynamic_routes_yaml;
 pub mod dynamic_fallback;
 pub mod dynamic_ws;
+pub mod dynamic_debug;
+pub mod models;
+pub mod introspection;
 
 pub use ws::ws_handler;
 pub use dashboard::*;
@@ -29,6 +32,9 @@ pub use dynamic_routes::*;
 pub use dynamic_routes_yaml::*;
 pub use dynamic_fallback::*;
 pub use dynamic_ws::*;
+pub use dynamic_debug::*;
+pub use models::*;
+pub use introspection::*;
 
 /// Register a new agent in the Hive
 #[utoipa::path(
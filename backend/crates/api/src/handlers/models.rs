@@ -0,0 +1,117 @@
+//! Declarative data models: register a YAML/JSON model to migrate its table and generate its CRUD routes
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+    http::StatusCode,
+};
+use crate::state::AppState;
+use proto::models::{ModelApplyResult, ModelPlan, RegisteredModel};
+use serde_json::Value;
+use worpen_core::data_model;
+use worpen_core::services::models::ApplyOptions;
+
+fn bad_request(error: &str, details: String) -> (StatusCode, Json<Value>) {
+    (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": error, "details": details })))
+}
+
+/// List registered models with their migration history
+#[utoipa::path(
+    get,
+    path = "/api/v1/models",
+    responses(
+        (status = 200, description = "Registered models", body = Vec<RegisteredModel>)
+    )
+)]
+pub async fn list_models(
+    State(state): State<AppState>,
+) -> Json<Vec<RegisteredModel>> {
+    Json(state.model_service.list_models())
+}
+
+/// Get a registered model
+#[utoipa::path(
+    get,
+    path = "/api/v1/models/{name}",
+    responses(
+        (status = 200, description = "Model found", body = RegisteredModel),
+        (status = 404, description = "Model not found")
+    )
+)]
+pub async fn get_model(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<RegisteredModel>, StatusCode> {
+    state.model_service.get_model(&name).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Register a model (YAML or JSON), or a new version of one
+///
+/// Migrates the model's table and registers its list/get/create/update/delete routes.
+/// Migrations that drop or retype columns need `allow_data_loss=true`; generated routes
+/// edited since are kept unless `overwrite_edited=true`.
+#[utoipa::path(
+    post,
+    path = "/api/v1/models",
+    request_body = String,
+    params(
+        ("allow_data_loss" = Option<bool>, Query, description = "Run migrations that lose column data"),
+        ("overwrite_edited" = Option<bool>, Query, description = "Replace generated routes that were edited")
+    ),
+    responses(
+        (status = 200, description = "Model applied", body = ModelApplyResult),
+        (status = 400, description = "Invalid model or refused migration")
+    )
+)]
+pub async fn apply_model(
+    State(state): State<AppState>,
+    Query(options): Query<ApplyOptions>,
+    body: String,
+) -> Result<Json<ModelApplyResult>, (StatusCode, Json<Value>)> {
+    let model = data_model::parse(&body).map_err(|e| bad_request("Parse error", e))?;
+    state.model_service.apply(model, options).await
+        .map(Json)
+        .map_err(|e| bad_request("Model not applied", e))
+}
+
+/// Preview what registering a model would change: the migration and the state of each route
+#[utoipa::path(
+    post,
+    path = "/api/v1/models/plan",
+    request_body = String,
+    responses(
+        (status = 200, description = "Migration and route changes", body = ModelPlan),
+        (status = 400, description = "Invalid model")
+    )
+)]
+pub async fn plan_model(
+    State(state): State<AppState>,
+    body: String,
+) -> Result<Json<ModelPlan>, (StatusCode, Json<Value>)> {
+    let model = data_model::parse(&body).map_err(|e| bad_request("Parse error", e))?;
+    state.model_service.plan(&model).await
+        .map(Json)
+        .map_err(|e| bad_request("Invalid model", e))
+}
+
+/// Delete a model and its unedited generated routes; the table and its rows are kept
+#[utoipa::path(
+    delete,
+    path = "/api/v1/models/{name}",
+    responses(
+        (status = 200, description = "Model deleted", body = Value),
+        (status = 404, description = "Model not found")
+    )
+)]
+pub async fn delete_model(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if state.model_service.get_model(&name).is_none() {
+        return Err((StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": format!("Model '{}' not found", name) }))));
+    }
+    match state.model_service.delete_model(&name).await {
+        Ok(removed_routes) => Ok(Json(serde_json::json!({ "deleted": name, "removed_routes": removed_routes }))),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "error": e })))),
+    }
+}
@@ -39,7 +39,53 @@ async fn main() {
     let incident_service = std::sync::Arc::new(worpen_core::services::IncidentService::new(incident_repo));
     let automation_service = std::sync::Arc::new(worpen_core::services::AutomationService::new(automation_repo));
     let pipeline_service = std::sync::Arc::new(worpen_core::services::PipelineService::new());
-    let dynamic_route_service = std::sync::Arc::new(worpen_core::services::DynamicRouteService::new());
+    
+    // Databases dynamic routes run SQL on (DATASOURCES_CONFIG points at a JSON or YAML file);
+    // the server's own database is the default one unless the config names another
+    let mut datasources = match std::env::var("DATASOURCES_CONFIG") {
+        Ok(path) => {
+            let config = worpen_core::datasource::DatasourceConfig::load(&path).expect("Failed to load datasource config");
+            worpen_core::datasource::DatasourceRegistry::from_config(&config).expect("Failed to initialize datasources")
+        }
+        Err(_) => worpen_core::datasource::DatasourceRegistry::default(),
+    };
+    if !datasources.contains(worpen_core::datasource::DEFAULT_DATASOURCE) {
+        datasources.insert(worpen_core::datasource::DEFAULT_DATASOURCE, pool.clone(), false);
+    }
+    // One Redis pool (REDIS_URL) for the routes' redis_op steps and the rate limiter
+    let redis_pool = std::env::var("REDIS_URL").ok().map(|url| {
+        deadpool_redis::Config::from_url(url)
+            .create_pool(Some(deadpool_redis::Runtime::Tokio1))
+            .expect("Failed to create Redis pool")
+    });
+    let mut dynamic_route_service = worpen_core::services::DynamicRouteService::new();
+    dynamic_route_service.set_datasources(datasources);
+    if let Some(redis_pool) = &redis_pool {
+        dynamic_route_service.set_redis_pool(redis_pool.clone());
+    }
+    let dynamic_route_service = std::sync::Arc::new(dynamic_route_service);
+    let model_service = std::sync::Arc::new(worpen_core::services::ModelService::new(dynamic_route_service.clone()));
+    
+    // Authentication providers for dynamic routes (AUTH_CONFIG points at a JSON or YAML file)
+    let authenticator = match std::env::var("AUTH_CONFIG") {
+        Ok(path) => {
+            let config = worpen_core::auth::AuthConfig::load(&path).expect("Failed to load auth config");
+            worpen_core::auth::Authenticator::from_config(&config, Some(pool.clone()))
+                .expect("Failed to initialize auth providers")
+        }
+        Err(_) => {
+            tracing::warn!("AUTH_CONFIG not set: routes that require auth will reject every request");
+            worpen_core::auth::Authenticator::default()
+        }
+    };
+    let authenticator = std::sync::Arc::new(authenticator);
+    
+    // Rate limit buckets: shared through the app's Redis pool when there is one, in memory otherwise
+    let rate_limiter = match redis_pool {
+        Some(redis_pool) => worpen_core::rate_limit::RateLimiter::with_redis(redis_pool),
+        None => worpen_core::rate_limit::RateLimiter::default(),
+    };
+    let rate_limiter = std::sync::Arc::new(rate_limiter);
     
     let connected_agents = std::sync::Arc::new(dashmap::DashMap::new());
     
@@ -51,6 +97,9 @@ async fn main() {
         automation_service,
         pipeline_service,
         dynamic_route_service,
+        model_service,
+        authenticator,
+        rate_limiter,
         connected_agents,
     };
 
@@ -118,10 +167,21 @@ async fn main() {
         .route("/api/v1/dynamic-routes/formats", get(handlers::get_format_stats)) // Format info
         .route("/api/v1/dynamic-routes/stats", get(handlers::get_route_stats))
         .route("/api/v1/dynamic-routes/test", post(handlers::test_route))
+        .route("/api/v1/dynamic-routes/debug", post(handlers::start_debug_session))
+        .route("/api/v1/dynamic-routes/debug/:session_id/ws", get(handlers::debug_session_ws))
         .route("/api/v1/dynamic-routes/import", post(handlers::import_route))
+        .route("/api/v1/dynamic-routes/quarantine", get(handlers::list_quarantined_routes))
+        .route("/api/v1/datasources", get(handlers::list_datasources))
+        .route("/api/v1/datasources/:name/introspect", get(handlers::introspect_datasource))
+        .route("/api/v1/datasources/:name/scaffold", post(handlers::scaffold_datasource))
+        .route("/api/v1/models", get(handlers::list_models).post(handlers::apply_model))
+        .route("/api/v1/models/plan", post(handlers::plan_model))
+        .route("/api/v1/models/:name", get(handlers::get_model).delete(handlers::delete_model))
         .route("/api/v1/dynamic-routes/:id", get(handlers::get_route).put(handlers::update_route).delete(handlers::delete_route))
         .route("/api/v1/dynamic-routes/:id/execute", post(handlers::execute_route))
         .route("/api/v1/dynamic-routes/:id/export", get(handlers::export_route))
+        .route("/api/v1/dynamic-routes/:id/traces", get(handlers::get_route_traces))
+        .route("/api/v1/dynamic-routes/:id/optimized", get(handlers::get_optimized_route))
         // WebSocket Dynamic Routes
         .route("/api/ws/*path", get(handlers::dynamic_ws_handler))
         // Global Functions for zero-cost inlining
@@ -195,7 +255,7 @@ async fn main() {
 
     tracing::info!("listening on {}", addr);
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
 }
 
 
@@ -1,7 +1,9 @@
 use worpen_core::services::{
     AgentService, DashboardService, DockerService, IncidentService, 
-    AutomationService, PipelineService, DynamicRouteService
+    AutomationService, PipelineService, DynamicRouteService, ModelService
 };
+use worpen_core::auth::Authenticator;
+use worpen_core::rate_limit::RateLimiter;
 use std::sync::Arc;
 use dashmap::DashMap;
 use tokio::sync::mpsc::Sender;
@@ -15,5 +17,8 @@ pub struct AppState {
     pub automation_service: Arc<AutomationService>,
     pub pipeline_service: Arc<PipelineService>,
     pub dynamic_route_service: Arc<DynamicRouteService>,
+    pub model_service: Arc<ModelService>,
+    pub authenticator: Arc<Authenticator>,
+    pub rate_limiter: Arc<RateLimiter>,
     pub connected_agents: Arc<DashMap<uuid::Uuid, Sender<String>>>,
 }
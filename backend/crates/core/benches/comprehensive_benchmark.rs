@@ -6,10 +6,16 @@
 //! - Pipe operator overhead
 //! - Route execution time
 //! - Complex operation benchmarks
+//! - VM template resolution
+//! - Flat bytecode VM vs the tree walker on control-flow-heavy logic
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use worpen_core::parsers::route_parser::parse_route;
 use worpen_core::expression::{Tokenizer, Parser, Evaluator};
+use worpen_core::compiler::lowerer::LogicCompiler;
+use worpen_core::vm::machine::VirtualMachine;
+use worpen_core::vm::memory::ExecutionMemory;
+use proto::models::LogicOperation;
 use serde_json::json;
 use std::collections::HashMap;
 
@@ -308,6 +314,84 @@ fn bench_route_with_50_operations(c: &mut Criterion) {
     });
 }
 
+fn bench_vm_templates(c: &mut Criterion) {
+    // Template-heavy loop body: nested paths, mixed text, a lone {{var}} and ${expr}
+    let logic = vec![
+        LogicOperation::Set { var: "user".to_string(), value: json!({"id": 7, "name": "Ann", "tags": ["a", "b"]}) },
+        LogicOperation::Set { var: "items".to_string(), value: json!((0..50).collect::<Vec<_>>()) },
+        LogicOperation::Loop {
+            collection: "{{items}}".to_string(),
+            var: "item".to_string(),
+            body: vec![
+                LogicOperation::Set { var: "greeting".to_string(), value: json!("Hello {{user.name}}, item {{item}} of {{index}}") },
+                LogicOperation::Set { var: "total".to_string(), value: json!("${item * 10}") },
+                LogicOperation::Set {
+                    var: "row".to_string(),
+                    value: json!({"id": "{{user.id}}", "tags": "{{user.tags}}", "message": "{{greeting}}", "total": "{{total}}"}),
+                },
+            ],
+        },
+        LogicOperation::Return { value: json!("{{row}}"), status: None, headers: None, raw: None },
+    ];
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), compiler.get_symbol_table().clone());
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    c.bench_function("vm_template_resolution", |b| {
+        b.iter(|| {
+            rt.block_on(vm.execute(black_box(&program))).unwrap()
+        })
+    });
+}
+
+fn bench_vm_control_flow(c: &mut Criterion) {
+    // Mostly block entry and exit: nested loops and a try with a finally, and
+    // no conditions, whose evaluation would dominate the time
+    let logic = vec![
+        LogicOperation::Set { var: "items".to_string(), value: json!((0..200).collect::<Vec<_>>()) },
+        LogicOperation::Loop {
+            collection: "{{items}}".to_string(),
+            var: "i".to_string(),
+            body: vec![
+                LogicOperation::Try {
+                    body: vec![LogicOperation::Loop {
+                        collection: "[1, 2, 3]".to_string(),
+                        var: "j".to_string(),
+                        body: vec![
+                            LogicOperation::Set { var: "cell".to_string(), value: json!({"i": "{{i}}", "j": "{{j}}"}) },
+                            LogicOperation::Comment { text: "next cell".to_string() },
+                        ],
+                    }],
+                    catch: vec![],
+                    finally: Some(vec![LogicOperation::Set { var: "rows".to_string(), value: json!("{{i}}") }]),
+                },
+            ],
+        },
+        LogicOperation::Return { value: json!({"rows": "{{rows}}", "cell": "{{cell}}"}), status: None, headers: None, raw: None },
+    ];
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let tree = compiler.lower(&logic).unwrap();
+    let symbols = compiler.get_symbol_table().clone();
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("vm_control_flow");
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), symbols.clone());
+    group.bench_function("flat", |b| {
+        b.iter(|| {
+            rt.block_on(vm.execute(black_box(&program))).unwrap()
+        })
+    });
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), symbols);
+    group.bench_function("tree_walker", |b| {
+        b.iter(|| {
+            rt.block_on(vm.execute_tree(black_box(&tree))).unwrap()
+        })
+    });
+    group.finish();
+}
+
 criterion_group!(
     parsing_benches,
     bench_parse_simple_yaml,
@@ -347,10 +431,17 @@ criterion_group!(
     bench_nested_ternary
 );
 
+criterion_group!(
+    vm_benches,
+    bench_vm_templates,
+    bench_vm_control_flow
+);
+
 criterion_main!(
     parsing_benches,
     tokenizer_benches,
     evaluator_benches,
     pipe_benches,
-    control_flow_benches
+    control_flow_benches,
+    vm_benches
 );
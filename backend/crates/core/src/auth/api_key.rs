@@ -0,0 +1,66 @@
+use super::{constant_time_eq, AuthProvider, Headers, Identity};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyConfig {
+    /// Header carrying the key
+    #[serde(default = "default_header")]
+    pub header: String,
+    pub keys: Vec<ApiKey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub key: String,
+    /// Who the key belongs to, exposed as `{{auth.sub}}`
+    pub sub: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+fn default_header() -> String {
+    "x-api-key".to_string()
+}
+
+/// Static keys from the config file
+pub struct ApiKeyProvider {
+    config: ApiKeyConfig,
+}
+
+impl ApiKeyProvider {
+    pub fn new(mut config: ApiKeyConfig) -> Self {
+        config.header = config.header.to_ascii_lowercase();
+        Self { config }
+    }
+
+    fn identify(&self, headers: &Headers) -> Result<Option<Identity>, String> {
+        let Some(given) = headers.get(&self.config.header) else {
+            return Ok(None);
+        };
+        let key = self.config.keys.iter()
+            .find(|key| constant_time_eq(key.key.as_bytes(), given.as_bytes()))
+            .ok_or_else(|| "Invalid API key".to_string())?;
+        Ok(Some(Identity {
+            sub: key.sub.clone(),
+            provider: self.name().to_string(),
+            scopes: key.scopes.clone(),
+            roles: key.roles.clone(),
+            claims: Value::Object(Default::default()),
+        }))
+    }
+}
+
+impl AuthProvider for ApiKeyProvider {
+    fn name(&self) -> &str {
+        "api_key"
+    }
+
+    fn authenticate<'a>(&'a self, headers: &'a Headers) -> Pin<Box<dyn Future<Output = Result<Option<Identity>, String>> + Send + 'a>> {
+        Box::pin(async move { self.identify(headers) })
+    }
+}
@@ -0,0 +1,159 @@
+use super::{authorization, AuthProvider, Headers, Identity};
+use base64::Engine;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Row, SqlitePool};
+use std::future::Future;
+use std::pin::Pin;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasicConfig {
+    /// Table with `username`, `password_hash` (bcrypt), `scopes` and `roles` (space-separated)
+    #[serde(default = "default_table")]
+    pub table: String,
+}
+
+fn default_table() -> String {
+    "auth_credentials".to_string()
+}
+
+/// Hash the password of an unknown user is checked against
+static DUMMY_HASH: Lazy<String> = Lazy::new(|| {
+    bcrypt::hash("worpen-unknown-user", bcrypt::DEFAULT_COST).expect("Failed to hash dummy password")
+});
+
+/// HTTP Basic credentials checked against a SQLite table
+pub struct BasicProvider {
+    query: String,
+    pool: SqlitePool,
+}
+
+impl BasicProvider {
+    pub fn new(config: BasicConfig, pool: SqlitePool) -> Result<Self, String> {
+        let valid_name = config.table.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && config.table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(format!("Invalid credentials table name '{}'", config.table));
+        }
+        Ok(Self {
+            query: format!("SELECT password_hash, scopes, roles FROM {} WHERE username = ?", config.table),
+            pool,
+        })
+    }
+
+    async fn identify(&self, headers: &Headers) -> Result<Option<Identity>, String> {
+        let Some(encoded) = authorization(headers, "Basic") else {
+            return Ok(None);
+        };
+        let decoded = base64::engine::general_purpose::STANDARD.decode(encoded)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(|| "Malformed Basic credentials".to_string())?;
+        let (username, password) = decoded.split_once(':')
+            .ok_or_else(|| "Malformed Basic credentials".to_string())?;
+
+        let row = sqlx::query(&self.query)
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to look up credentials: {}", e))?;
+        let hash: Option<String> = match &row {
+            Some(row) => Some(row.try_get("password_hash").map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        // bcrypt is slow on purpose, so it runs off the async workers. Unknown users are
+        // checked against a dummy hash, so a miss takes as long as a wrong password.
+        let password = password.to_string();
+        let verified = tokio::task::spawn_blocking(move || {
+            bcrypt::verify(password, hash.as_deref().unwrap_or(&DUMMY_HASH)).unwrap_or(false)
+        })
+        .await
+        .map_err(|e| format!("Failed to check password: {}", e))?;
+        let (Some(row), true) = (row, verified) else {
+            return Err("Invalid username or password".to_string());
+        };
+        let list = |column: &str| -> Vec<String> {
+            row.try_get::<String, _>(column)
+                .map(|s| s.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default()
+        };
+
+        Ok(Some(Identity {
+            sub: username.to_string(),
+            provider: self.name().to_string(),
+            scopes: list("scopes"),
+            roles: list("roles"),
+            claims: Value::Object(Default::default()),
+        }))
+    }
+}
+
+impl AuthProvider for BasicProvider {
+    fn name(&self) -> &str {
+        "basic"
+    }
+
+    fn authenticate<'a>(&'a self, headers: &'a Headers) -> Pin<Box<dyn Future<Output = Result<Option<Identity>, String>> + Send + 'a>> {
+        Box::pin(self.identify(headers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(user: &str, password: &str) -> Headers {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+        [("authorization".to_string(), format!("Basic {}", encoded))].into()
+    }
+
+    #[tokio::test]
+    async fn test_checks_credentials_table() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE auth_credentials (username TEXT PRIMARY KEY, password_hash TEXT, scopes TEXT, roles TEXT)")
+            .execute(&pool).await.unwrap();
+        sqlx::query("INSERT INTO auth_credentials VALUES ('ana', ?, 'reports:read', 'viewer ops')")
+            .bind(bcrypt::hash("s3cret", 4).unwrap())
+            .execute(&pool).await.unwrap();
+
+        let provider = BasicProvider::new(BasicConfig { table: default_table() }, pool.clone()).unwrap();
+        let identity = provider.identify(&basic("ana", "s3cret")).await.unwrap().unwrap();
+        assert_eq!(identity.sub, "ana");
+        assert_eq!(identity.scopes, vec!["reports:read"]);
+        assert_eq!(identity.roles, vec!["viewer", "ops"]);
+
+        assert!(provider.identify(&basic("ana", "wrong")).await.is_err());
+        assert!(provider.identify(&basic("bob", "s3cret")).await.is_err());
+        assert!(provider.identify(&Headers::new()).await.unwrap().is_none());
+
+        assert!(BasicProvider::new(BasicConfig { table: "users; DROP TABLE x".to_string() }, pool).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_users_are_checked_off_the_runtime() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE auth_credentials (username TEXT PRIMARY KEY, password_hash TEXT, scopes TEXT, roles TEXT)")
+            .execute(&pool).await.unwrap();
+        let provider = BasicProvider::new(BasicConfig { table: default_table() }, pool).unwrap();
+
+        // The test runtime has one thread: the ticker only runs while bcrypt runs elsewhere
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+        assert!(provider.identify(&basic("nobody", "guess")).await.is_err());
+        ticker.abort();
+        assert!(ticks.load(Ordering::Relaxed) > 0);
+    }
+}
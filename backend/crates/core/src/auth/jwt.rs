@@ -0,0 +1,155 @@
+use super::{authorization, AuthProvider, Headers, Identity};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtConfig {
+    /// `HS256` or `RS256`
+    pub algorithm: String,
+    /// HS256: the shared secret; RS256: the PEM public key
+    pub key_file: String,
+    #[serde(default)]
+    pub issuer: Option<String>,
+    #[serde(default)]
+    pub audience: Option<String>,
+}
+
+/// Bearer tokens signed with a locally stored key
+pub struct JwtProvider {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl JwtProvider {
+    pub fn from_config(config: &JwtConfig) -> Result<Self, String> {
+        let key = std::fs::read(&config.key_file)
+            .map_err(|e| format!("Failed to read JWT key file {}: {}", config.key_file, e))?;
+        Self::new(config, &key)
+    }
+
+    pub fn new(config: &JwtConfig, key: &[u8]) -> Result<Self, String> {
+        let (algorithm, key) = match config.algorithm.to_ascii_uppercase().as_str() {
+            // Secret files usually end with a newline that isn't part of the secret
+            "HS256" => (Algorithm::HS256, DecodingKey::from_secret(key.trim_ascii_end())),
+            "RS256" => (
+                Algorithm::RS256,
+                DecodingKey::from_rsa_pem(key).map_err(|e| format!("Invalid RS256 public key: {}", e))?,
+            ),
+            other => return Err(format!("Unsupported JWT algorithm '{}'", other)),
+        };
+
+        let mut validation = Validation::new(algorithm);
+        match &config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        Ok(Self { key, validation })
+    }
+
+    fn identify(&self, headers: &Headers) -> Result<Option<Identity>, String> {
+        let Some(token) = authorization(headers, "Bearer") else {
+            return Ok(None);
+        };
+        let claims = decode::<Value>(token, &self.key, &self.validation)
+            .map_err(|e| format!("Invalid token: {}", e))?
+            .claims;
+        let sub = claims.get("sub")
+            .and_then(Value::as_str)
+            .ok_or_else(|| "Token has no 'sub' claim".to_string())?
+            .to_string();
+        Ok(Some(Identity {
+            sub,
+            provider: self.name().to_string(),
+            scopes: string_list(&claims, "scope", "scopes"),
+            roles: string_list(&claims, "role", "roles"),
+            claims,
+        }))
+    }
+}
+
+/// Read a space-separated string claim (`scope`) or an array claim (`scopes`)
+fn string_list(claims: &Value, single: &str, plural: &str) -> Vec<String> {
+    let mut list: Vec<String> = claims.get(single)
+        .and_then(Value::as_str)
+        .map(|s| s.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default();
+    if let Some(items) = claims.get(plural).and_then(Value::as_array) {
+        list.extend(items.iter().filter_map(Value::as_str).map(str::to_string));
+    }
+    list
+}
+
+impl AuthProvider for JwtProvider {
+    fn name(&self) -> &str {
+        "jwt"
+    }
+
+    fn authenticate<'a>(&'a self, headers: &'a Headers) -> Pin<Box<dyn Future<Output = Result<Option<Identity>, String>> + Send + 'a>> {
+        Box::pin(async move { self.identify(headers) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"test-secret\n";
+
+    fn config(audience: Option<&str>) -> JwtConfig {
+        JwtConfig {
+            algorithm: "HS256".to_string(),
+            key_file: String::new(),
+            issuer: Some("worpen".to_string()),
+            audience: audience.map(str::to_string),
+        }
+    }
+
+    fn bearer(claims: Value) -> Headers {
+        let token = encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(b"test-secret")).unwrap();
+        [("authorization".to_string(), format!("Bearer {}", token))].into()
+    }
+
+    fn exp() -> i64 {
+        chrono::Utc::now().timestamp() + 600
+    }
+
+    #[test]
+    fn test_hs256_claims() {
+        let provider = JwtProvider::new(&config(None), SECRET).unwrap();
+        let headers = bearer(json!({
+            "sub": "user-7", "iss": "worpen", "exp": exp(),
+            "scope": "orders:read orders:write", "roles": ["admin"], "tenant": "acme"
+        }));
+
+        let identity = provider.identify(&headers).unwrap().unwrap();
+        assert_eq!(identity.sub, "user-7");
+        assert_eq!(identity.scopes, vec!["orders:read", "orders:write"]);
+        assert_eq!(identity.roles, vec!["admin"]);
+        assert_eq!(identity.claims["tenant"], "acme");
+    }
+
+    #[test]
+    fn test_rejects_bad_tokens() {
+        let provider = JwtProvider::new(&config(Some("api")), SECRET).unwrap();
+        assert!(provider.identify(&Headers::new()).unwrap().is_none());
+
+        let wrong_audience = bearer(json!({"sub": "u", "iss": "worpen", "aud": "other", "exp": exp()}));
+        assert!(provider.identify(&wrong_audience).is_err());
+
+        let expired = bearer(json!({"sub": "u", "iss": "worpen", "aud": "api", "exp": 1}));
+        assert!(provider.identify(&expired).is_err());
+
+        let garbage: Headers = [("authorization".to_string(), "Bearer abc.def.ghi".to_string())].into();
+        assert!(provider.identify(&garbage).is_err());
+
+        assert!(JwtProvider::new(&JwtConfig { algorithm: "none".to_string(), ..config(None) }, SECRET).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! Authentication for dynamic routes
+//!
+//! Providers are configured in a JSON or YAML file and tried in order;
+//! the first one that recognises credentials in the request decides.
+
+pub mod api_key;
+pub mod basic;
+pub mod jwt;
+
+use proto::models::RouteDefinition;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+pub use api_key::ApiKeyProvider;
+pub use basic::BasicProvider;
+pub use jwt::JwtProvider;
+
+/// Request headers, names lowercased
+pub type Headers = HashMap<String, String>;
+
+/// Who made a request, as established by a provider.
+/// Exposed to route logic as `{{auth.sub}}`, `{{auth.claims.*}}`, `{{auth.scopes}}`, `{{auth.roles}}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Identity {
+    pub sub: String,
+    pub provider: String,
+    pub scopes: Vec<String>,
+    pub roles: Vec<String>,
+    pub claims: Value,
+}
+
+impl Identity {
+    pub fn to_value(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+
+    /// Every required scope must be granted; of the required roles, any one is enough
+    pub fn authorize(&self, required_scopes: &[String], required_roles: &[String]) -> Result<(), String> {
+        let missing: Vec<&str> = required_scopes.iter()
+            .filter(|scope| !self.scopes.contains(scope))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("Missing required scope(s): {}", missing.join(", ")));
+        }
+        if !required_roles.is_empty() && !required_roles.iter().any(|role| self.roles.contains(role)) {
+            return Err(format!("Requires one of the roles: {}", required_roles.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+/// A way of turning request credentials into an identity.
+/// Resolves to `Ok(None)` when the request carries no credentials this provider understands,
+/// and `Err` when it does but they are invalid.
+pub trait AuthProvider: Send + Sync {
+    fn name(&self) -> &str;
+    fn authenticate<'a>(&'a self, headers: &'a Headers) -> Pin<Box<dyn Future<Output = Result<Option<Identity>, String>> + Send + 'a>>;
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub providers: Vec<ProviderConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProviderConfig {
+    ApiKey(api_key::ApiKeyConfig),
+    Jwt(jwt::JwtConfig),
+    Basic(basic::BasicConfig),
+}
+
+impl AuthConfig {
+    /// Read a config file; `.yaml`/`.yml` files are parsed as YAML, anything else as JSON
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read auth config {}: {}", path, e))?;
+        if path.ends_with(".yaml") || path.ends_with(".yml") {
+            serde_yaml::from_str(&content).map_err(|e| format!("Failed to parse auth config {}: {}", path, e))
+        } else {
+            serde_json::from_str(&content).map_err(|e| format!("Failed to parse auth config {}: {}", path, e))
+        }
+    }
+}
+
+/// The configured providers, tried in order
+#[derive(Default)]
+pub struct Authenticator {
+    providers: Vec<Box<dyn AuthProvider>>,
+}
+
+impl Authenticator {
+    pub fn new(providers: Vec<Box<dyn AuthProvider>>) -> Self {
+        Self { providers }
+    }
+
+    /// Build the providers in `config`; Basic auth needs the database pool
+    pub fn from_config(config: &AuthConfig, db_pool: Option<sqlx::SqlitePool>) -> Result<Self, String> {
+        let mut providers: Vec<Box<dyn AuthProvider>> = Vec::new();
+        for provider in &config.providers {
+            providers.push(match provider {
+                ProviderConfig::ApiKey(config) => Box::new(ApiKeyProvider::new(config.clone())),
+                ProviderConfig::Jwt(config) => Box::new(JwtProvider::from_config(config)?),
+                ProviderConfig::Basic(config) => {
+                    let pool = db_pool.clone()
+                        .ok_or_else(|| "Basic auth provider requires a database".to_string())?;
+                    Box::new(BasicProvider::new(config.clone(), pool)?)
+                }
+            });
+        }
+        Ok(Self::new(providers))
+    }
+
+    pub fn provider_names(&self) -> Vec<&str> {
+        self.providers.iter().map(|p| p.name()).collect()
+    }
+
+    /// The identity from the first provider that recognises the request's credentials
+    pub async fn authenticate(&self, headers: &Headers) -> Result<Option<Identity>, String> {
+        for provider in &self.providers {
+            if let Some(identity) = provider.authenticate(headers).await? {
+                return Ok(Some(identity));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A route needs an authenticated caller when it says so or when it asks for scopes or roles
+pub fn requires_auth(route: &RouteDefinition) -> bool {
+    route.auth_required || !route.required_scopes.is_empty() || !route.required_roles.is_empty()
+}
+
+/// Compare secrets without stopping at the first differing byte
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// The credentials after `scheme ` in the Authorization header, if it uses that scheme
+pub(crate) fn authorization<'a>(headers: &'a Headers, scheme: &str) -> Option<&'a str> {
+    let value = headers.get("authorization")?;
+    let (given, credentials) = value.split_once(' ')?;
+    given.eq_ignore_ascii_case(scheme).then(|| credentials.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn identity() -> Identity {
+        Identity {
+            sub: "u1".to_string(),
+            provider: "test".to_string(),
+            scopes: vec!["reports:read".to_string(), "reports:write".to_string()],
+            roles: vec!["editor".to_string()],
+            claims: json!({}),
+        }
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_authorize_scopes_and_roles() {
+        let id = identity();
+        assert!(id.authorize(&strings(&["reports:read"]), &strings(&["admin", "editor"])).is_ok());
+        assert!(id.authorize(&strings(&["reports:read", "users:delete"]), &[]).unwrap_err().contains("users:delete"));
+        assert!(id.authorize(&[], &strings(&["admin"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_first_recognising_provider_wins() {
+        let config: AuthConfig = serde_json::from_value(json!({
+            "providers": [
+                {"type": "api_key", "keys": [{"key": "k1", "sub": "bot"}]},
+                {"type": "api_key", "header": "x-other-key", "keys": [{"key": "k2", "sub": "other"}]}
+            ]
+        })).unwrap();
+        let auth = Authenticator::from_config(&config, None).unwrap();
+        assert_eq!(auth.provider_names(), vec!["api_key", "api_key"]);
+
+        let headers: Headers = [("x-other-key".to_string(), "k2".to_string())].into();
+        assert_eq!(auth.authenticate(&headers).await.unwrap().unwrap().sub, "other");
+        assert_eq!(auth.authenticate(&Headers::new()).await.unwrap(), None);
+
+        let headers: Headers = [("x-api-key".to_string(), "wrong".to_string())].into();
+        assert!(auth.authenticate(&headers).await.is_err());
+    }
+
+    #[test]
+    fn test_basic_needs_database() {
+        let config: AuthConfig = serde_json::from_value(json!({"providers": [{"type": "basic"}]})).unwrap();
+        assert!(Authenticator::from_config(&config, None).is_err());
+    }
+}
@@ -0,0 +1,406 @@
+//! Execution budgets for route logic
+//!
+//! Every execution runs with fuel (operations and loop iterations it may use), a
+//! wall-clock deadline, a cap on the size of any stored value, a cap on
+//! `parallel` fan-out and a cap on how deeply function calls nest. Server-wide
+//! limits come from the environment; a route's `limits` can only tighten them.
+//! The budget lives in a task-local, so both the VM and the interpreter charge it
+//! without threading it through every call. Only the budget's own checks can
+//! mark an execution as aborted: an error that merely reads like a limit error,
+//! such as one the logic throws, is an ordinary error.
+
+use once_cell::sync::Lazy;
+use proto::models::{ExecutionLimits, RouteDefinition};
+use serde::Serialize;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+const LIMIT_ERROR_PREFIX: &str = "Execution limit exceeded (";
+
+/// Fuel units between deadline checks: tight loops never yield to the timeout
+const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_fuel: u64,
+    pub timeout: Duration,
+    pub max_value_bytes: usize,
+    pub max_parallel: usize,
+    pub max_call_depth: usize,
+}
+
+static GLOBAL_LIMITS: Lazy<Limits> = Lazy::new(|| {
+    fn env<T: std::str::FromStr>(name: &str, default: T) -> T {
+        std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+    }
+    Limits {
+        max_fuel: env("ROUTE_MAX_FUEL", 1_000_000),
+        timeout: Duration::from_millis(env("ROUTE_TIMEOUT_MS", 30_000)),
+        max_value_bytes: env("ROUTE_MAX_VALUE_BYTES", 16 * 1024 * 1024),
+        max_parallel: env("ROUTE_MAX_PARALLEL", 64),
+        max_call_depth: env("ROUTE_MAX_CALL_DEPTH", 256),
+    }
+});
+
+impl Limits {
+    /// Server-wide limits: `ROUTE_MAX_FUEL`, `ROUTE_TIMEOUT_MS`,
+    /// `ROUTE_MAX_VALUE_BYTES`, `ROUTE_MAX_PARALLEL` and `ROUTE_MAX_CALL_DEPTH`
+    pub fn global() -> Self {
+        *GLOBAL_LIMITS
+    }
+
+    /// The global limits, tightened by whatever the route sets
+    pub fn for_route(route: &RouteDefinition) -> Self {
+        Self::global().tightened(route.limits.as_ref())
+    }
+
+    pub fn tightened(self, route: Option<&ExecutionLimits>) -> Self {
+        let Some(route) = route else {
+            return self;
+        };
+        Self {
+            max_fuel: route.max_fuel.map_or(self.max_fuel, |v| v.min(self.max_fuel)),
+            timeout: route.timeout_ms.map_or(self.timeout, |v| Duration::from_millis(v).min(self.timeout)),
+            max_value_bytes: route.max_value_bytes.map_or(self.max_value_bytes, |v| v.min(self.max_value_bytes)),
+            max_parallel: route.max_parallel.map_or(self.max_parallel, |v| v.min(self.max_parallel)),
+            max_call_depth: route.max_call_depth.map_or(self.max_call_depth, |v| v.min(self.max_call_depth)),
+        }
+    }
+}
+
+/// Which limit an execution ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    Fuel,
+    Deadline,
+    Memory,
+    Parallelism,
+    CallDepth,
+}
+
+impl LimitKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitKind::Fuel => "fuel",
+            LimitKind::Deadline => "deadline",
+            LimitKind::Memory => "memory",
+            LimitKind::Parallelism => "parallelism",
+            LimitKind::CallDepth => "call_depth",
+        }
+    }
+
+    /// Running out of fuel or call depth almost always means a runaway loop or
+    /// recursion (508 Loop Detected); the other limits mean the server won't
+    /// spend more on this request (503)
+    pub fn status_code(&self) -> u16 {
+        match self {
+            LimitKind::Fuel | LimitKind::CallDepth => 508,
+            LimitKind::Deadline | LimitKind::Memory | LimitKind::Parallelism => 503,
+        }
+    }
+
+    fn error(&self, detail: impl std::fmt::Display) -> String {
+        format!("{}{}): {}", LIMIT_ERROR_PREFIX, self.as_str(), detail)
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+static ABORTS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+
+/// Executions aborted by each limit since startup
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AbortCounts {
+    pub fuel: u64,
+    pub deadline: u64,
+    pub memory: u64,
+    pub parallelism: u64,
+    pub call_depth: u64,
+}
+
+pub fn record_abort(kind: LimitKind) {
+    ABORTS[kind.index()].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn abort_counts() -> AbortCounts {
+    let count = |kind: LimitKind| ABORTS[kind.index()].load(Ordering::Relaxed);
+    AbortCounts {
+        fuel: count(LimitKind::Fuel),
+        deadline: count(LimitKind::Deadline),
+        memory: count(LimitKind::Memory),
+        parallelism: count(LimitKind::Parallelism),
+        call_depth: count(LimitKind::CallDepth),
+    }
+}
+
+struct Budget {
+    limits: Limits,
+    fuel_used: AtomicU64,
+    deadline: Instant,
+    /// The first limit the execution ran into, with the error it failed with
+    exceeded: OnceLock<(LimitKind, String)>,
+}
+
+impl Budget {
+    fn charge(&self, units: u64) -> Result<(), String> {
+        // An execution that ran into a limit is over, even if something swallowed the error
+        if let Some((_, error)) = self.exceeded.get() {
+            return Err(error.clone());
+        }
+        let before = self.fuel_used.fetch_add(units, Ordering::Relaxed);
+        let used = before + units;
+        if used > self.limits.max_fuel {
+            return Err(self.exceed(LimitKind::Fuel, format!("used more than {} operations", self.limits.max_fuel)));
+        }
+        if used / DEADLINE_CHECK_INTERVAL != before / DEADLINE_CHECK_INTERVAL && Instant::now() >= self.deadline {
+            return Err(self.exceed(LimitKind::Deadline, self.deadline_detail()));
+        }
+        Ok(())
+    }
+
+    /// Mark the execution as aborted by `kind`; returns the error to fail with
+    fn exceed(&self, kind: LimitKind, detail: String) -> String {
+        let error = kind.error(detail);
+        let _ = self.exceeded.set((kind, error.clone()));
+        error
+    }
+
+    fn deadline_detail(&self) -> String {
+        format!("took longer than {} ms", self.limits.timeout.as_millis())
+    }
+}
+
+tokio::task_local! {
+    static BUDGET: Budget;
+    static WATCH: Arc<Mutex<Option<LimitKind>>>;
+}
+
+/// Run `execution` within `limits`. Limit errors are counted once, at the
+/// outermost run; a nested run shares the budget it's already in.
+pub async fn run<T, F>(limits: Limits, execution: F) -> Result<T, String>
+where
+    F: Future<Output = Result<T, String>>,
+{
+    if BUDGET.try_with(|_| ()).is_ok() {
+        return execution.await;
+    }
+
+    let budget = Budget {
+        limits,
+        fuel_used: AtomicU64::new(0),
+        deadline: Instant::now() + limits.timeout,
+        exceeded: OnceLock::new(),
+    };
+    let deadline_error = LimitKind::Deadline.error(budget.deadline_detail());
+    let scoped = BUDGET.scope(budget, async {
+        let result = execution.await;
+        (result, exceeded())
+    });
+    let (result, exceeded) = tokio::time::timeout(limits.timeout, scoped).await
+        .unwrap_or((Err(deadline_error), Some(LimitKind::Deadline)));
+
+    if let (Err(e), Some(kind)) = (&result, exceeded) {
+        record_abort(kind);
+        let _ = WATCH.try_with(|watched| *watched.lock().unwrap() = Some(kind));
+        eprintln!("[WARN] Route execution aborted: {}", e);
+    }
+    result
+}
+
+/// Run `f` and return which limit aborted an execution [`run`] inside it, if any
+pub async fn watch<T, F>(f: F) -> (T, Option<LimitKind>)
+where
+    F: Future<Output = T>,
+{
+    let watched = Arc::new(Mutex::new(None));
+    let output = WATCH.scope(watched.clone(), f).await;
+    let kind = *watched.lock().unwrap();
+    (output, kind)
+}
+
+/// The limit the current execution ran into, if it ran into one
+pub fn exceeded() -> Option<LimitKind> {
+    BUDGET.try_with(|budget| budget.exceeded.get().map(|(kind, _)| *kind)).ok().flatten()
+}
+
+/// The error the current execution failed with when it ran into a limit
+pub fn limit_error() -> Option<String> {
+    BUDGET.try_with(|budget| budget.exceeded.get().map(|(_, error)| error.clone())).ok().flatten()
+}
+
+/// Use `units` of fuel; a no-op outside [`run`]
+pub fn charge(units: u64) -> Result<(), String> {
+    BUDGET.try_with(|budget| budget.charge(units)).unwrap_or(Ok(()))
+}
+
+/// Refuse a value bigger than the budget allows before it is stored
+pub fn check_value(value: &Value) -> Result<(), String> {
+    BUDGET.try_with(|budget| {
+        let max = budget.limits.max_value_bytes;
+        match approximate_size(value, max) {
+            size if size > max => Err(budget.exceed(LimitKind::Memory, format!("value of ~{} bytes is larger than {} bytes", size, max))),
+            _ => Ok(()),
+        }
+    }).unwrap_or(Ok(()))
+}
+
+/// Refuse a `parallel` operation with more tasks than the budget allows
+pub fn check_parallel(tasks: usize) -> Result<(), String> {
+    BUDGET.try_with(|budget| {
+        let max = budget.limits.max_parallel;
+        if tasks > max {
+            Err(budget.exceed(LimitKind::Parallelism, format!("{} parallel tasks, at most {} allowed", tasks, max)))
+        } else {
+            Ok(())
+        }
+    }).unwrap_or(Ok(()))
+}
+
+/// Refuse a function call that would nest `depth` calls deep
+pub fn check_call_depth(depth: usize) -> Result<(), String> {
+    BUDGET.try_with(|budget| {
+        let max = budget.limits.max_call_depth;
+        if depth > max {
+            Err(budget.exceed(LimitKind::CallDepth, format!("function calls nested more than {} deep", max)))
+        } else {
+            Ok(())
+        }
+    }).unwrap_or(Ok(()))
+}
+
+/// Roughly the value's size as JSON; stops counting once it passes `cap`
+fn approximate_size(value: &Value, cap: usize) -> usize {
+    match value {
+        Value::Null | Value::Bool(_) | Value::Number(_) => 8,
+        Value::String(s) => s.len() + 2,
+        Value::Array(items) => {
+            let mut size = 2;
+            for item in items {
+                size += approximate_size(item, cap) + 1;
+                if size > cap {
+                    break;
+                }
+            }
+            size
+        },
+        Value::Object(fields) => {
+            let mut size = 2;
+            for (key, item) in fields {
+                size += key.len() + 4 + approximate_size(item, cap);
+                if size > cap {
+                    break;
+                }
+            }
+            size
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn limits() -> Limits {
+        Limits {
+            max_fuel: 100,
+            timeout: Duration::from_secs(5),
+            max_value_bytes: 64,
+            max_parallel: 2,
+            max_call_depth: 3,
+        }
+    }
+
+    #[test]
+    fn test_route_limits_only_tighten() {
+        let route = ExecutionLimits {
+            max_fuel: Some(10),
+            timeout_ms: Some(60_000),
+            max_value_bytes: None,
+            max_parallel: Some(1),
+            max_call_depth: None,
+        };
+        let limits = limits().tightened(Some(&route));
+        assert_eq!(limits.max_fuel, 10);
+        assert_eq!(limits.timeout, Duration::from_secs(5));
+        assert_eq!(limits.max_value_bytes, 64);
+        assert_eq!(limits.max_parallel, 1);
+        assert_eq!(limits.max_call_depth, 3);
+    }
+
+    #[test]
+    fn test_limit_status_codes() {
+        assert_eq!(LimitKind::Fuel.status_code(), 508);
+        assert_eq!(LimitKind::Memory.status_code(), 503);
+        assert_eq!(LimitKind::Deadline.status_code(), 503);
+        assert_eq!(LimitKind::CallDepth.status_code(), 508);
+    }
+
+    #[tokio::test]
+    async fn test_budget_is_enforced_inside_run() {
+        assert!(charge(u64::MAX / 2).is_ok(), "no budget outside run");
+
+        let before = abort_counts().fuel;
+        let (result, exceeded) = watch(run(limits(), async {
+            charge(60)?;
+            check_value(&json!({"name": "short"}))?;
+            check_parallel(2)?;
+            check_call_depth(3)?;
+            assert_eq!(exceeded(), None);
+            charge(60)
+        })).await;
+        assert!(result.is_err());
+        assert_eq!(exceeded, Some(LimitKind::Fuel));
+        assert!(abort_counts().fuel > before);
+
+        let over = |check: fn() -> Result<(), String>| async move {
+            watch(run(limits(), async { check() })).await.1
+        };
+        assert_eq!(over(|| check_value(&json!("x".repeat(100)))).await, Some(LimitKind::Memory));
+        assert_eq!(over(|| check_parallel(3)).await, Some(LimitKind::Parallelism));
+        assert_eq!(over(|| check_call_depth(4)).await, Some(LimitKind::CallDepth));
+    }
+
+    #[tokio::test]
+    async fn test_only_the_budget_marks_an_execution_aborted() {
+        let (result, exceeded) = watch(run(limits(), async {
+            Err::<(), _>(LimitKind::Fuel.error("thrown by the logic"))
+        })).await;
+        assert!(result.is_err());
+        assert_eq!(exceeded, None);
+
+        // Once a limit is hit, the execution can't carry on by swallowing the error
+        let (result, exceeded) = watch(run(limits(), async {
+            let _ = check_value(&json!("x".repeat(100)));
+            charge(1)
+        })).await;
+        assert!(result.unwrap_err().contains("(memory)"));
+        assert_eq!(exceeded, Some(LimitKind::Memory));
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let quick = Limits { timeout: Duration::from_millis(20), ..limits() };
+        let (result, exceeded) = watch(run(quick, async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        })).await;
+        assert!(result.is_err());
+        assert_eq!(exceeded, Some(LimitKind::Deadline));
+
+        // A loop that never yields still notices the deadline through its fuel
+        let spinning = Limits { max_fuel: u64::MAX, timeout: Duration::from_millis(20), ..limits() };
+        let (result, exceeded) = watch(run::<(), _>(spinning, async {
+            loop {
+                charge(1)?;
+            }
+        })).await;
+        assert!(result.is_err());
+        assert_eq!(exceeded, Some(LimitKind::Deadline));
+    }
+}
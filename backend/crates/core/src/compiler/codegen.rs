@@ -0,0 +1,291 @@
+//! Lays the lowered operation tree out as a flat instruction stream
+//!
+//! Every nested block is emitted inline right after the instruction that
+//! enters it, and control flow between blocks becomes jumps: an `if` jumps to
+//! its otherwise block or past its `End`, a loop body ends in the `Next` that
+//! starts the following iteration, and a `try` records where its catch and
+//! finally blocks start in the program's handler table.
+//!
+//! Function bodies are laid out after the program's own `Halt`, each ending in
+//! a `Halt` of its own, and are entered through call frames.
+
+use crate::vm::instructions::{CompiledFunction, Handler, Instruction, Location, OptimizedOperation, Program, ProgramFunction};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Flatten `tree`, with operation paths starting at `root` (e.g. `logic`), and
+/// the bodies of the global functions it calls
+pub fn generate(root: &str, tree: &[OptimizedOperation], globals: &[Arc<CompiledFunction>]) -> Program {
+    let mut codegen = Codegen::default();
+    codegen.block(root, tree, 0);
+    codegen.emit(Instruction::Halt, None);
+    for function in globals {
+        codegen.declare(function, true);
+    }
+    // Bodies may define functions of their own, which are queued behind them
+    while let Some((index, function)) = codegen.pending.pop_front() {
+        codegen.functions[index].entry = codegen.code.len();
+        codegen.in_function = true;
+        // The body's paths are relative to the call running it
+        codegen.block("function", &function.body, 0);
+        codegen.emit(Instruction::Halt, None);
+    }
+    Program { code: codegen.code, locations: codegen.locations, handlers: codegen.handlers, functions: codegen.functions }
+}
+
+#[derive(Default)]
+struct Codegen {
+    code: Vec<Instruction>,
+    locations: Vec<Option<Location>>,
+    handlers: Vec<Handler>,
+    functions: Vec<ProgramFunction>,
+    /// Functions whose bodies are still to be laid out
+    pending: VecDeque<(usize, Arc<CompiledFunction>)>,
+    /// Emitting a function body, where a `return` leaves the whole body
+    in_function: bool,
+}
+
+impl Codegen {
+    /// Emit a block; a `return` ends the block it is in, so it jumps to the block's
+    /// end, or in a function body leaves the body
+    fn block(&mut self, path: &str, ops: &[OptimizedOperation], depth: usize) {
+        let mut returns = vec![];
+        for (i, op) in ops.iter().enumerate() {
+            self.operation(format!("{}[{}]", path, i), op, depth);
+            let last = i + 1 == ops.len();
+            if matches!(op, OptimizedOperation::Return { .. }) {
+                if self.in_function && !(last && depth == 0) {
+                    self.emit(Instruction::Exit, None);
+                } else if !last {
+                    returns.push(self.emit(Instruction::Jump { target: 0 }, None));
+                }
+            }
+        }
+        let end = self.code.len();
+        for jump in returns {
+            self.code[jump] = Instruction::Jump { target: end };
+        }
+    }
+
+    fn operation(&mut self, path: String, op: &OptimizedOperation, depth: usize) {
+        let location = Some(Location { path: path.clone(), depth });
+        match op {
+            OptimizedOperation::If { condition, then, otherwise } => {
+                let at = self.emit(Instruction::Halt, location);
+                self.block(&format!("{}.then", path), then, depth + 1);
+                let otherwise = otherwise.as_ref().map(|ops| {
+                    let skip = self.emit(Instruction::Jump { target: 0 }, None);
+                    let start = self.code.len();
+                    self.block(&format!("{}.otherwise", path), ops, depth + 1);
+                    self.code[skip] = Instruction::Jump { target: self.code.len() };
+                    start
+                });
+                let end = self.emit(Instruction::End, None);
+                self.code[at] = Instruction::If { condition: condition.clone(), otherwise, end };
+            },
+            OptimizedOperation::Switch { value, cases, default } => {
+                let at = self.emit(Instruction::Halt, location);
+                let mut starts = vec![];
+                let mut exits = vec![];
+                for (i, case) in cases.iter().enumerate() {
+                    starts.push((case.value.clone(), self.code.len()));
+                    self.block(&format!("{}.cases[{}].operations", path, i), &case.operations, depth + 1);
+                    exits.push(self.emit(Instruction::Jump { target: 0 }, None));
+                }
+                let default = default.as_ref().map(|ops| {
+                    let start = self.code.len();
+                    self.block(&format!("{}.default", path), ops, depth + 1);
+                    start
+                });
+                let end = self.emit(Instruction::End, None);
+                for jump in exits {
+                    self.code[jump] = Instruction::Jump { target: end };
+                }
+                self.code[at] = Instruction::Switch { value: value.clone(), cases: starts, default, end };
+            },
+            OptimizedOperation::Loop { collection, var_index, body } => {
+                let at = self.emit(Instruction::Halt, location);
+                self.block(&format!("{}.body", path), body, depth + 1);
+                let next = self.emit(Instruction::Next { body: at + 1 }, None);
+                self.code[at] = Instruction::Loop { collection: collection.clone(), var_index: *var_index, next };
+            },
+            OptimizedOperation::While { condition, body, max_iterations } => {
+                let at = self.emit(Instruction::Halt, location);
+                self.block(&format!("{}.body", path), body, depth + 1);
+                let next = self.emit(Instruction::Next { body: at + 1 }, None);
+                self.code[at] = Instruction::While { condition: condition.clone(), max_iterations: *max_iterations, next };
+            },
+            OptimizedOperation::Break => {
+                self.emit(Instruction::Break, location);
+            },
+            OptimizedOperation::Continue => {
+                self.emit(Instruction::Continue, location);
+            },
+            OptimizedOperation::Try { body, catch, finally } => {
+                let handler = self.handlers.len();
+                self.handlers.push(Handler { catch: 0, finally: None });
+                self.emit(Instruction::Try { handler }, location);
+                self.block(&format!("{}.body", path), body, depth + 1);
+                let skip = self.emit(Instruction::Jump { target: 0 }, None);
+                let catch_start = self.code.len();
+                self.block(&format!("{}.catch", path), catch, depth + 1);
+                let finally = finally.as_ref().map(|ops| {
+                    let start = self.emit(Instruction::Finally, None);
+                    self.block(&format!("{}.finally", path), ops, depth + 1);
+                    start
+                });
+                let end = self.emit(Instruction::End, None);
+                self.code[skip] = Instruction::Jump { target: finally.unwrap_or(end) };
+                self.handlers[handler] = Handler { catch: catch_start, finally };
+            },
+            OptimizedOperation::Transaction { body, savepoint, datasource } => {
+                self.emit(Instruction::Transaction { savepoint: *savepoint, datasource: datasource.clone() }, location);
+                self.block(&format!("{}.body", path), body, depth + 1);
+                self.emit(Instruction::End, None);
+            },
+            OptimizedOperation::Parallel { tasks, max_concurrent } => {
+                let at = self.emit(Instruction::Halt, location);
+                let starts = tasks.iter().enumerate().map(|(i, task)| {
+                    let start = self.code.len();
+                    self.block(&format!("{}.tasks[{}]", path, i), task, depth + 1);
+                    self.emit(Instruction::Halt, None);
+                    start
+                }).collect();
+                let end = self.code.len();
+                self.code[at] = Instruction::Parallel { tasks: starts, max_concurrent: *max_concurrent, end };
+            },
+            OptimizedOperation::DefineFunction { function } => {
+                let function = self.declare(function, false);
+                self.emit(Instruction::DefineFunction { function }, location);
+            },
+            OptimizedOperation::CallFunction { name, args, output_var_index } => {
+                self.emit(Instruction::CallFunction { name: name.clone(), args: args.clone(), output_var_index: *output_var_index }, location);
+            },
+            other => {
+                self.emit(Instruction::Op(other.clone()), location);
+            },
+        }
+    }
+
+    /// Add a function to the program and queue its body; returns its index
+    fn declare(&mut self, function: &Arc<CompiledFunction>, global: bool) -> usize {
+        self.functions.push(ProgramFunction {
+            name: function.name.clone(),
+            param_indices: function.param_indices.clone(),
+            symbols: function.symbols.clone(),
+            entry: 0,
+            global,
+        });
+        self.pending.push_back((self.functions.len() - 1, function.clone()));
+        self.functions.len() - 1
+    }
+
+    /// Append an instruction and return its index; control flow is emitted as a
+    /// placeholder first and filled in once its blocks' positions are known
+    fn emit(&mut self, instruction: Instruction, location: Option<Location>) -> usize {
+        self.code.push(instruction);
+        self.locations.push(location);
+        self.code.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::lowerer::LogicCompiler;
+    use proto::models::LogicOperation;
+    use serde_json::{json, Value};
+
+    fn set(var: &str, value: Value) -> LogicOperation {
+        LogicOperation::Set { var: var.to_string(), value }
+    }
+
+    fn kinds(program: &Program) -> Vec<&'static str> {
+        program.code.iter().map(Instruction::kind).collect()
+    }
+
+    #[test]
+    fn test_blocks_are_laid_out_inline_with_jumps() {
+        let logic = vec![
+            LogicOperation::If {
+                condition: "{{x}} > 0".to_string(),
+                then: vec![set("y", json!(1))],
+                otherwise: Some(vec![set("y", json!(2))]),
+            },
+            LogicOperation::Loop {
+                collection: "[1, 2]".to_string(),
+                var: "item".to_string(),
+                body: vec![LogicOperation::Continue],
+            },
+        ];
+        let program = LogicCompiler::new().compile(&logic).unwrap();
+
+        assert_eq!(kinds(&program), vec!["if", "set", "jump", "set", "end", "loop", "continue", "next", "halt"]);
+        assert!(matches!(program.code[0], Instruction::If { otherwise: Some(3), end: 4, .. }));
+        assert!(matches!(program.code[2], Instruction::Jump { target: 4 }));
+        assert!(matches!(program.code[5], Instruction::Loop { next: 7, .. }));
+        assert!(matches!(program.code[7], Instruction::Next { body: 6 }));
+
+        let paths: Vec<Option<&str>> = program.locations.iter().map(|l| l.as_ref().map(|l| l.path.as_str())).collect();
+        assert_eq!(paths, vec![
+            Some("logic[0]"), Some("logic[0].then[0]"), None, Some("logic[0].otherwise[0]"), None,
+            Some("logic[1]"), Some("logic[1].body[0]"), None, None,
+        ]);
+        assert_eq!(program.locations[1].as_ref().unwrap().depth, 1);
+    }
+
+    #[test]
+    fn test_try_handlers_and_function_bodies() {
+        let logic = vec![
+            LogicOperation::Try {
+                body: vec![LogicOperation::Throw { message: "boom".to_string(), code: None }],
+                catch: vec![set("caught", json!(true))],
+                finally: Some(vec![set("done", json!(true))]),
+            },
+            LogicOperation::DefineFunction {
+                name: "f".to_string(),
+                params: vec!["n".to_string()],
+                body: vec![
+                    LogicOperation::If {
+                        condition: "{{n}} > 0".to_string(),
+                        then: vec![LogicOperation::Return { value: json!(1), status: None, headers: None, raw: None }],
+                        otherwise: None,
+                    },
+                    LogicOperation::Return { value: json!(0), status: None, headers: None, raw: None },
+                ],
+            },
+        ];
+        let program = LogicCompiler::new().compile(&logic).unwrap();
+
+        assert_eq!(kinds(&program), vec![
+            "try", "throw", "jump", "set", "finally", "set", "end", "define_function", "halt",
+            "if", "return", "exit", "end", "return", "halt",
+        ]);
+        assert_eq!(program.handlers.len(), 1);
+        assert_eq!((program.handlers[0].catch, program.handlers[0].finally), (3, Some(4)));
+        assert!(matches!(program.code[2], Instruction::Jump { target: 4 }));
+
+        // The body follows the program's halt, with slots of its own
+        assert!(matches!(program.code[7], Instruction::DefineFunction { function: 0 }));
+        let function = &program.functions[0];
+        assert_eq!((function.name.as_str(), function.entry, function.global), ("f", 9, false));
+        assert_eq!(function.symbols.get_index("n"), Some(0));
+        // A nested return leaves the whole body, a final one just runs into its halt
+        assert!(matches!(program.code[11], Instruction::Exit));
+        assert_eq!(program.locations[10].as_ref().unwrap().path, "function[0].then[0]");
+    }
+
+    #[test]
+    fn test_transactions_close_with_an_end() {
+        let logic = vec![LogicOperation::Transaction {
+            body: vec![set("a", json!(1)), LogicOperation::Transaction { body: vec![set("b", json!(2))], savepoint: true, datasource: None }],
+            savepoint: false,
+            datasource: None,
+        }];
+        let program = LogicCompiler::new().compile(&logic).unwrap();
+
+        assert_eq!(kinds(&program), vec!["transaction", "set", "transaction", "set", "end", "end", "halt"]);
+        assert!(matches!(program.code[2], Instruction::Transaction { savepoint: true, .. }));
+        assert_eq!(program.locations[3].as_ref().unwrap().path, "logic[0].body[1].body[0]");
+    }
+}
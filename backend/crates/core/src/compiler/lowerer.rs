@@ -1,30 +1,180 @@
 use crate::compiler::symbol_table::SymbolTable;
-use crate::vm::instructions::{OptimizedOperation, OptimizedSwitchCase};
-use proto::models::LogicOperation;
+use crate::compiler::codegen;
+use crate::compiler::diagnostics::{error_summary, Diagnostic};
+use crate::vm::instructions::{CompiledFunction, OptimizedOperation, OptimizedSwitchCase, Program, RedisCommand};
+use crate::vm::template::{CompiledValue, Template};
+use crate::expression::CompiledExpression;
+use crate::services::dynamic_routes::{redis, sql};
+use crate::sql_params::NamedQuery;
+use proto::models::{FunctionDef, LogicOperation};
 use serde_json::Value;
 use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Variables the engines provide without the logic assigning them
+pub(crate) const IMPLICIT_VARIABLES: &[&str] = &[
+    "request", "auth", "error", "index", "loop", "message", "connection_id",
+    "db_result", "http_response", "math_result", "string_result", "date_result",
+];
 
 pub struct LogicCompiler {
     symbol_table: SymbolTable,
+    diagnostics: Vec<Diagnostic>,
+    /// Path of the operation being compiled, e.g. ["logic[3]", "then[0]"]
+    path: Vec<String>,
+    loop_depth: usize,
+    /// Functions callable without a `define_function` in the logic itself
+    known_functions: HashSet<String>,
+    /// Variables supplied by the request (route parameters, payload fields)
+    inputs: HashSet<String>,
+    /// Every variable the logic assigns somewhere, and every function it defines
+    assigned: HashSet<String>,
+    defined_functions: HashSet<String>,
+    /// Global functions whose bodies are compiled in once the logic calls them
+    global_definitions: HashMap<String, FunctionDef>,
+    /// The global functions compiled so far, in the order first called
+    globals: Vec<Arc<CompiledFunction>>,
 }
 
 impl LogicCompiler {
     pub fn new() -> Self {
         Self {
             symbol_table: SymbolTable::new(),
+            diagnostics: Vec::new(),
+            path: Vec::new(),
+            loop_depth: 0,
+            known_functions: HashSet::new(),
+            inputs: HashSet::new(),
+            assigned: HashSet::new(),
+            defined_functions: HashSet::new(),
+            global_definitions: HashMap::new(),
+            globals: Vec::new(),
+        }
+    }
+
+    /// Treat these names as callable through `call_function` (global functions)
+    pub fn with_functions(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.known_functions.extend(names);
+        self
+    }
+
+    /// Compile calls to these global functions into the program, with their bodies
+    pub fn with_global_functions(mut self, functions: impl IntoIterator<Item = FunctionDef>) -> Self {
+        for function in functions {
+            self.known_functions.insert(function.name.clone());
+            self.global_definitions.insert(function.name.clone(), function);
         }
+        self
     }
 
-    pub fn compile(&mut self, logic: &[LogicOperation]) -> Vec<OptimizedOperation> {
-        logic.iter().map(|op| self.compile_operation(op)).collect()
+    /// Treat these variables as supplied at run time
+    pub fn with_inputs(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.inputs.extend(names);
+        self
+    }
+
+    /// Compile logic operations to a VM program.
+    /// Fails when any error diagnostic is reported; warnings are kept in `diagnostics()`.
+    pub fn compile(&mut self, logic: &[LogicOperation]) -> Result<Program, String> {
+        self.compile_root("logic", logic)
+    }
+
+    /// Like `compile`, with paths starting at `root` (e.g. `ws_hooks.on_message`)
+    pub fn compile_root(&mut self, root: &str, logic: &[LogicOperation]) -> Result<Program, String> {
+        let tree = self.lower_root(root, logic)?;
+        Ok(codegen::generate(root, &tree, &self.globals))
+    }
+
+    /// Lower logic operations to the operation tree the program is generated from
+    pub fn lower(&mut self, logic: &[LogicOperation]) -> Result<Vec<OptimizedOperation>, String> {
+        self.lower_root("logic", logic)
+    }
+
+    /// Like `lower`, with diagnostic paths starting at `root`
+    pub fn lower_root(&mut self, root: &str, logic: &[LogicOperation]) -> Result<Vec<OptimizedOperation>, String> {
+        let first_diagnostic = self.diagnostics.len();
+        self.collect_definitions(logic);
+        // The top level is the outermost scope itself
+        let program = self.compile_operations(root, logic);
+        match error_summary(&self.diagnostics[first_diagnostic..]) {
+            Some(errors) => Err(errors),
+            None => Ok(program),
+        }
+    }
+
+    /// Everything reported so far, in the order found
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Compile a nested block in a scope of its own
+    fn compile_block(&mut self, field: &str, logic: &[LogicOperation]) -> Vec<OptimizedOperation> {
+        self.symbol_table.enter_scope();
+        let program = self.compile_operations(field, logic);
+        self.symbol_table.exit_scope();
+        program
+    }
+
+    fn compile_operations(&mut self, field: &str, logic: &[LogicOperation]) -> Vec<OptimizedOperation> {
+        let mut terminated_by: Option<&str> = None;
+        let mut program = Vec::with_capacity(logic.len());
+
+        for (i, op) in logic.iter().enumerate() {
+            self.path.push(format!("{}[{}]", field, i));
+            if let Some(terminator) = terminated_by.take() {
+                self.warning(format!("Unreachable operation after '{}'", terminator));
+            }
+            terminated_by = match op {
+                LogicOperation::Return { .. } => Some("return"),
+                LogicOperation::Throw { .. } => Some("throw"),
+                LogicOperation::Break => Some("break"),
+                LogicOperation::Continue => Some("continue"),
+                _ => None,
+            };
+            program.push(self.compile_operation(op));
+            self.path.pop();
+        }
+        program
+    }
+
+    /// Compile a loop or function body, where break/continue bind to `loop_depth`
+    fn compile_body(&mut self, field: &str, logic: &[LogicOperation], loop_depth: usize) -> Vec<OptimizedOperation> {
+        let outer = std::mem::replace(&mut self.loop_depth, loop_depth);
+        let program = self.compile_block(field, logic);
+        self.loop_depth = outer;
+        program
+    }
+
+    /// Compile a function body against a symbol table of its own, parameters first
+    fn compile_function(&mut self, name: &str, params: &[String], body: &[LogicOperation], field: &str) -> Arc<CompiledFunction> {
+        let outer = std::mem::take(&mut self.symbol_table);
+        let param_indices = params.iter().map(|p| self.symbol_table.shadow(p.clone())).collect();
+        let body = self.compile_body(field, body, 0);
+        let symbols = std::mem::replace(&mut self.symbol_table, outer);
+        Arc::new(CompiledFunction { name: name.to_string(), param_indices, symbols: Arc::new(symbols), body })
+    }
+
+    /// Compile the body of a global function the first time it is called. The
+    /// definition is taken out first, so calls from within its own body find it
+    /// already being compiled.
+    fn compile_global(&mut self, name: &str) {
+        let Some(definition) = self.global_definitions.remove(name) else {
+            return;
+        };
+        self.assigned.extend(definition.params.iter().cloned());
+        self.collect_definitions(&definition.logic);
+        let path = std::mem::replace(&mut self.path, vec![format!("functions.{}", name)]);
+        let function = self.compile_function(name, &definition.params, &definition.logic, "logic");
+        self.path = path;
+        self.globals.push(function);
     }
 
     fn compile_operation(&mut self, op: &LogicOperation) -> OptimizedOperation {
         match op {
             LogicOperation::Return { value, status, headers, raw } => {
-                self.register_variables_in_value(value);
                 OptimizedOperation::Return { 
-                    value: value.clone(),
+                    value: self.compile_value(value),
                     status: *status,
                     headers: headers.clone(),
                     raw: *raw,
@@ -35,223 +185,430 @@ impl LogicCompiler {
                 OptimizedOperation::Comment { text: text.clone() }
             },
             LogicOperation::Set { var, value } => {
-                self.register_variables_in_value(value);
-                let var_index = self.symbol_table.register(var.clone());
-                OptimizedOperation::Set { var_index, value: value.clone() }
+                let value = self.compile_value(value);
+                let var_index = self.assign(var);
+                OptimizedOperation::Set { var_index, value }
             },
             LogicOperation::Get { var } => {
                 let var_index = self.symbol_table.register(var.clone());
                 OptimizedOperation::Get { var_index }
             },
-            LogicOperation::QueryDb { query, params } => {
-                for param in params {
-                    self.register_variables_in_value(param);
-                }
-                OptimizedOperation::QueryDb { query: query.clone(), params: params.clone() }
+            LogicOperation::QueryDb { query, params, datasource } => {
+                let (args, params) = sql::split_params(params);
+                let (query, args) = self.compile_sql(query, args, params);
+                self.symbol_table.register("db_result".to_string());
+                OptimizedOperation::QueryDb { query, args, datasource: datasource.clone() }
             },
-            LogicOperation::SqlOp { query, args, output_var } => {
-                // Register variables in args
-                for arg in args {
-                    self.register_variables_in_value(arg);
-                }
-                // Register output variable
-                let output_var_index = self.symbol_table.register(output_var.clone());
-                
-                // For args, we need to convert them to indices
-                // We'll resolve the args at runtime from variable references
-                let arg_indices: Vec<usize> = args.iter()
-                    .filter_map(|arg| {
-                        if let serde_json::Value::String(s) = arg {
-                            // Extract variable name from {{var}}
-                            let re = regex::Regex::new(r"\{\{([^}]+)\}\}").unwrap();
-                            if let Some(cap) = re.captures(s) {
-                                if let Some(var_match) = cap.get(1) {
-                                    let var_name = var_match.as_str();
-                                    return Some(self.symbol_table.register(var_name.to_string()));
-                                }
-                            }
-                        }
-                        None
-                    })
-                    .collect();
+            LogicOperation::SqlOp { query, args, params, datasource, output_var } => {
+                let (query, args) = self.compile_sql(query, args, params.as_ref());
+                let output_var_index = self.assign(output_var);
                 
                 OptimizedOperation::SqlOp { 
-                    query: query.clone(), 
-                    arg_indices,
+                    query, 
+                    args,
+                    datasource: datasource.clone(),
                     output_var_index 
                 }
             },
-            LogicOperation::RedisOp { command, key, value, ttl_seconds, output_var } => {
-                // Register variables in key and value strings
-                self.register_variables_in_string(key);
-                if let Some(v) = value {
-                    self.register_variables_in_string(v);
-                }
+            LogicOperation::RedisOp { command, key, args, ttl_seconds, commands, output_var } => {
+                let pipeline = command.eq_ignore_ascii_case(redis::PIPELINE);
+                let commands = if pipeline {
+                    if !key.is_empty() || !args.is_empty() || ttl_seconds.is_some() {
+                        self.error("PIPELINE takes only `commands`");
+                    }
+                    if commands.is_empty() {
+                        self.error("PIPELINE needs at least one command");
+                    }
+                    commands.iter()
+                        .map(|c| self.compile_redis(&c.command, &c.key, &c.args, c.ttl_seconds))
+                        .collect()
+                } else {
+                    if !commands.is_empty() {
+                        self.warning(format!("`commands` is only sent by PIPELINE, not {}", command));
+                    }
+                    vec![self.compile_redis(command, key, args, *ttl_seconds)]
+                };
                 
                 // Register output variable if provided
-                let output_var_index = output_var.as_ref()
-                    .map(|var| self.symbol_table.register(var.clone()));
+                let output_var_index = output_var.as_ref().map(|var| self.assign(var));
                 
-                OptimizedOperation::RedisOp {
-                    command: command.clone(),
-                    key: key.clone(),
-                    value: value.clone(),
-                    ttl_seconds: *ttl_seconds,
-                    output_var_index,
-                }
+                OptimizedOperation::RedisOp { commands, pipeline, output_var_index }
             },
             LogicOperation::WsOp { command, message, channel } => {
-                // Register variables in message string
-                self.register_variables_in_string(message);
-                if let Some(ch) = channel {
-                    self.register_variables_in_string(ch);
-                }
-                
                 OptimizedOperation::WsOp {
                     command: command.clone(),
-                    message: message.clone(),
-                    channel: channel.clone(),
+                    message: self.compile_template(message),
+                    channel: channel.as_ref().map(|ch| self.compile_template(ch)),
                 }
             },
             LogicOperation::HttpRequest { url, method, body, headers, timeout_ms } => {
-                if let Some(b) = body {
-                    self.register_variables_in_value(b);
-                }
-                OptimizedOperation::HttpRequest { url: url.clone(), method: method.clone(), body: body.clone(), headers: headers.clone(), timeout_ms: *timeout_ms }
+                let url = self.compile_template(url);
+                let body = body.as_ref().map(|b| self.compile_value(b));
+                let headers = headers.as_ref().map(|h| {
+                    h.iter().map(|(key, value)| (key.clone(), self.compile_template(value))).collect()
+                });
+                self.symbol_table.register("http_response".to_string());
+                OptimizedOperation::HttpRequest { url, method: method.clone(), body, headers, timeout_ms: *timeout_ms }
             },
             LogicOperation::If { condition, then, otherwise } => {
-                self.register_variables_in_string(condition);
-                let then_ops = self.compile(then);
-                let otherwise_ops = otherwise.as_ref().map(|ops| self.compile(ops));
-                OptimizedOperation::If { condition: condition.clone(), then: then_ops, otherwise: otherwise_ops }
+                let condition = self.compile_condition(condition);
+                let then_ops = self.compile_block("then", then);
+                let otherwise_ops = otherwise.as_ref().map(|ops| self.compile_block("otherwise", ops));
+                OptimizedOperation::If { condition, then: then_ops, otherwise: otherwise_ops }
             },
             LogicOperation::Loop { collection, var, body } => {
-                self.register_variables_in_string(collection);
-                let var_index = self.symbol_table.register(var.clone());
-                let body_ops = self.compile(body);
-                OptimizedOperation::Loop { collection: collection.clone(), var_index, body: body_ops }
+                // The collection is either {{var}}, a bare variable name or a JSON array literal
+                let collection = self.compile_template(collection);
+                self.register_variable_name(collection.source());
+                // The loop also exposes the current position as {{index}}
+                self.symbol_table.register("index".to_string());
+                // The loop variable belongs to the body, shadowing any variable of the same name
+                self.symbol_table.enter_scope();
+                let var_index = self.symbol_table.shadow(var.clone());
+                let body_ops = self.compile_body("body", body, self.loop_depth + 1);
+                self.symbol_table.exit_scope();
+                OptimizedOperation::Loop { collection, var_index, body: body_ops }
             },
             LogicOperation::Switch { value, cases, default } => {
-                self.register_variables_in_string(value);
-                let cases_ops = cases.iter().map(|case| {
+                let value = self.compile_condition(value);
+                let cases_ops = cases.iter().enumerate().map(|(i, case)| {
                     self.register_variables_in_value(&case.value);
                     OptimizedSwitchCase {
                         value: case.value.clone(),
-                        operations: self.compile(&case.operations),
+                        operations: self.compile_block(&format!("cases[{}].operations", i), &case.operations),
                     }
                 }).collect();
-                let default_ops = default.as_ref().map(|ops| self.compile(ops));
-                OptimizedOperation::Switch { value: value.clone(), cases: cases_ops, default: default_ops }
+                let default_ops = default.as_ref().map(|ops| self.compile_block("default", ops));
+                OptimizedOperation::Switch { value, cases: cases_ops, default: default_ops }
             },
             LogicOperation::While { condition, body, max_iterations } => {
-                self.register_variables_in_string(condition);
-                let body_ops = self.compile(body);
-                OptimizedOperation::While { condition: condition.clone(), body: body_ops, max_iterations: *max_iterations }
+                let condition = self.compile_condition(condition);
+                let body_ops = self.compile_body("body", body, self.loop_depth + 1);
+                OptimizedOperation::While { condition, body: body_ops, max_iterations: *max_iterations }
+            },
+            LogicOperation::Break => {
+                if self.loop_depth == 0 {
+                    self.error("'break' outside of a loop");
+                }
+                OptimizedOperation::Break
+            },
+            LogicOperation::Continue => {
+                if self.loop_depth == 0 {
+                    self.error("'continue' outside of a loop");
+                }
+                OptimizedOperation::Continue
             },
-            LogicOperation::Break => OptimizedOperation::Break,
-            LogicOperation::Continue => OptimizedOperation::Continue,
             LogicOperation::Try { body, catch, finally } => {
-                let body_ops = self.compile(body);
-                let catch_ops = self.compile(catch);
-                let finally_ops = finally.as_ref().map(|ops| self.compile(ops));
+                // The catch block sees the failure as {{error}}
+                self.symbol_table.register("error".to_string());
+                let body_ops = self.compile_block("body", body);
+                let catch_ops = self.compile_block("catch", catch);
+                let finally_ops = finally.as_ref().map(|ops| self.compile_block("finally", ops));
                 OptimizedOperation::Try { body: body_ops, catch: catch_ops, finally: finally_ops }
             },
+            LogicOperation::Transaction { body, savepoint, datasource } => {
+                OptimizedOperation::Transaction { body: self.compile_block("body", body), savepoint: *savepoint, datasource: datasource.clone() }
+            },
             LogicOperation::Throw { message, code } => {
-                OptimizedOperation::Throw { message: message.clone(), code: code.clone() }
+                OptimizedOperation::Throw { message: self.compile_template(message), code: code.clone() }
             },
             LogicOperation::Parallel { tasks, max_concurrent } => {
-                let tasks_ops = tasks.iter().map(|task| self.compile(task)).collect();
+                // Each task runs on its own, so break/continue can't reach an enclosing loop
+                let tasks_ops = tasks.iter().enumerate()
+                    .map(|(i, task)| self.compile_body(&format!("tasks[{}]", i), task, 0))
+                    .collect();
                 OptimizedOperation::Parallel { tasks: tasks_ops, max_concurrent: *max_concurrent }
             },
-            LogicOperation::AwaitAll { task_ids } => {
-                OptimizedOperation::AwaitAll { task_ids: task_ids.clone() }
-            },
             LogicOperation::DefineFunction { name, params, body } => {
-                let param_indices = params.iter().map(|p| self.symbol_table.register(p.clone())).collect();
-                let body_ops = self.compile(body);
-                OptimizedOperation::DefineFunction { name: name.clone(), param_indices, body: body_ops }
+                OptimizedOperation::DefineFunction { function: self.compile_function(name, params, body, "body") }
             },
             LogicOperation::CallFunction { name, args, output_var } => {
-                for arg in args {
-                    self.register_variables_in_value(arg);
+                if !self.defined_functions.contains(name) && !self.known_functions.contains(name) {
+                    self.error(format!("Call to unknown function '{}'", name));
+                } else if !self.defined_functions.contains(name) {
+                    self.compile_global(name);
                 }
-                let output_var_index = self.symbol_table.register(output_var.clone());
-                OptimizedOperation::CallFunction { name: name.clone(), args: args.clone(), output_var_index }
-            },
-            LogicOperation::Map { input, transform } => {
-                self.register_variables_in_string(input);
-                self.register_variables_in_string(transform);
-                OptimizedOperation::Map { input: input.clone(), transform: transform.clone() }
-            },
-            LogicOperation::Filter { input, condition } => {
-                self.register_variables_in_string(input);
-                self.register_variables_in_string(condition);
-                OptimizedOperation::Filter { input: input.clone(), condition: condition.clone() }
-            },
-            LogicOperation::Aggregate { input, operation } => {
-                self.register_variables_in_string(input);
-                OptimizedOperation::Aggregate { input: input.clone(), operation: operation.clone() }
+                let args = args.iter().map(|arg| self.compile_value(arg)).collect();
+                let output_var_index = self.assign(output_var);
+                OptimizedOperation::CallFunction { name: name.clone(), args, output_var_index }
             },
             LogicOperation::StringOp { operation, input, args } => {
-                self.register_variables_in_string(input);
+                if operation == "regex_match" {
+                    self.check_regex(args.first());
+                }
+                let input_template = self.compile_template(input);
+                // `join` reads the variable named by `input` directly
+                self.register_variable_name(input);
                 for arg in args {
                     self.register_variables_in_value(arg);
                 }
-                OptimizedOperation::StringOp { operation: operation.clone(), input: input.clone(), args: args.clone() }
+                self.symbol_table.register("string_result".to_string());
+                OptimizedOperation::StringOp { operation: operation.clone(), input: input_template, args: args.clone() }
             },
             LogicOperation::MathOp { operation, args } => {
-                for arg in args {
-                    self.register_variables_in_value(arg);
-                }
-                OptimizedOperation::MathOp { operation: operation.clone(), args: args.clone() }
+                let args = args.iter().map(|arg| self.compile_value(arg)).collect();
+                self.symbol_table.register("math_result".to_string());
+                OptimizedOperation::MathOp { operation: operation.clone(), args }
             },
             LogicOperation::DateOp { operation, args } => {
                 for arg in args {
                     self.register_variables_in_value(arg);
                 }
+                self.symbol_table.register("date_result".to_string());
                 OptimizedOperation::DateOp { operation: operation.clone(), args: args.clone() }
             },
             LogicOperation::JsonOp { operation, input, args } => {
-                self.register_variables_in_string(input);
+                let input_template = self.compile_template(input);
+                // The input names a variable rather than a template
+                self.register_variable_name(input);
                 for arg in args {
                     self.register_variables_in_value(arg);
                 }
-                OptimizedOperation::JsonOp { operation: operation.clone(), input: input.clone(), args: args.clone() }
+                OptimizedOperation::JsonOp { operation: operation.clone(), input: input_template, args: args.clone() }
             },
             LogicOperation::Log { level, message } => {
-                self.register_variables_in_string(message);
-                OptimizedOperation::Log { level: level.clone(), message: message.clone() }
+                OptimizedOperation::Log { level: level.clone(), message: self.compile_template(message) }
             },
             LogicOperation::Sleep { duration_ms } => {
                 OptimizedOperation::Sleep { duration_ms: *duration_ms }
             },
-            LogicOperation::ExecuteScript { language, code } => {
-                OptimizedOperation::ExecuteScript { language: language.clone(), code: code.clone() }
+            // Compilation fails on these, so the placeholders never run
+            LogicOperation::ExecuteScript { language, .. } => {
+                self.error(format!("Unsupported operation 'execute_script': no '{}' script runtime is available", language));
+                OptimizedOperation::Comment { text: "execute_script".to_string() }
+            },
+            LogicOperation::Map { .. } | LogicOperation::Filter { .. } | LogicOperation::Aggregate { .. } => {
+                let name = op.kind();
+                self.error(format!("Unsupported operation '{}': build the result in a loop over the collection instead", name));
+                OptimizedOperation::Comment { text: name.to_string() }
+            },
+            LogicOperation::AwaitAll { .. } => {
+                self.error("Unsupported operation 'await_all': a parallel step already waits for all of its tasks".to_string());
+                OptimizedOperation::Comment { text: "await_all".to_string() }
             },
             LogicOperation::CustomOp(operation_map) => {
-                // Custom operations: scan all values for variables
-                for value in operation_map.values() {
-                    self.register_variables_in_value(value);
+                let name = operation_map.keys().next().map(|k| k.as_str()).unwrap_or("unknown");
+                self.error(format!("Unsupported operation '{}'", name));
+                OptimizedOperation::Comment { text: name.to_string() }
+            },
+        }
+    }
+
+    /// Parse a condition once and register the variables it reads
+    fn compile_condition(&mut self, source: &str) -> CompiledExpression {
+        let condition = match CompiledExpression::compile(source) {
+            Ok(condition) => condition,
+            Err(e) => {
+                self.error(e);
+                return CompiledExpression::compile("false").expect("literal condition");
+            }
+        };
+        let roots: Vec<String> = condition.variable_paths()
+            .map(|path| path.split('.').next().unwrap_or(path).to_string())
+            .collect();
+        for root in &roots {
+            self.symbol_table.register(root.clone());
+        }
+        self.check_reads(roots.iter().map(String::as_str));
+        condition.in_scope(self.symbol_table.scope())
+    }
+
+    /// Lower a templated string into segments bound to symbol slots
+    fn compile_template(&mut self, source: &str) -> Template {
+        let template = Template::compile(source, &mut self.symbol_table);
+        self.check_reads(template.variable_roots());
+        template
+    }
+
+    fn compile_value(&mut self, value: &Value) -> CompiledValue {
+        let value = CompiledValue::compile(value, &mut self.symbol_table);
+        let roots: Vec<&str> = value.templates().into_iter().flat_map(Template::variable_roots).collect();
+        self.check_reads(roots);
+        value
+    }
+
+    /// Warn about variables nothing assigns; they can still come from the request payload.
+    /// Reading a variable after the block that set it has ended is an error.
+    fn check_reads<'a>(&mut self, roots: impl IntoIterator<Item = &'a str>) {
+        let mut reported = HashSet::new();
+        for root in roots {
+            if self.check_scope(root) {
+                continue;
+            }
+            let known = self.assigned.contains(root)
+                || self.inputs.contains(root)
+                || IMPLICIT_VARIABLES.contains(&root);
+            if !known && reported.insert(root.to_string()) {
+                self.warning(format!("Variable '{}' is never assigned and is not a declared input", root));
+            }
+        }
+    }
+
+    /// Report a read of a variable whose scope has ended; true if it was one
+    fn check_scope(&mut self, name: &str) -> bool {
+        let out_of_scope = self.symbol_table.is_out_of_scope(name);
+        if out_of_scope {
+            self.error(format!("Variable '{}' is read outside of the block that sets it", name));
+        }
+        out_of_scope
+    }
+
+    /// Slot a write goes to. Request inputs and the engines' implicit variables
+    /// always belong to the outermost scope; anything else is declared in the
+    /// current block unless an enclosing one already has it.
+    fn assign(&mut self, var: &str) -> usize {
+        if self.inputs.contains(var) || IMPLICIT_VARIABLES.contains(&var) {
+            self.symbol_table.register(var.to_string())
+        } else {
+            self.symbol_table.declare(var.to_string())
+        }
+    }
+
+    /// A literal pattern must be a valid regex; templated patterns are checked at run time
+    fn check_regex(&mut self, pattern: Option<&Value>) {
+        if let Some(Value::String(pattern)) = pattern {
+            if !pattern.contains("{{") && !pattern.contains("${") {
+                if let Err(e) = Regex::new(pattern) {
+                    self.error(format!("Invalid regex '{}': {}", pattern, e));
                 }
-                OptimizedOperation::CustomOp(operation_map.clone())
+            }
+        }
+    }
+
+    /// Record the variables and functions the logic defines, wherever they appear
+    fn collect_definitions(&mut self, logic: &[LogicOperation]) {
+        for op in logic {
+            match op {
+                LogicOperation::Set { var, .. } => { self.assigned.insert(var.clone()); },
+                LogicOperation::SqlOp { output_var, .. } => { self.assigned.insert(output_var.clone()); },
+                LogicOperation::RedisOp { output_var: Some(var), .. } => { self.assigned.insert(var.clone()); },
+                LogicOperation::CallFunction { output_var, .. } => { self.assigned.insert(output_var.clone()); },
+                LogicOperation::If { then, otherwise, .. } => {
+                    self.collect_definitions(then);
+                    if let Some(ops) = otherwise {
+                        self.collect_definitions(ops);
+                    }
+                },
+                LogicOperation::Loop { var, body, .. } => {
+                    self.assigned.insert(var.clone());
+                    self.collect_definitions(body);
+                },
+                LogicOperation::Switch { cases, default, .. } => {
+                    for case in cases {
+                        self.collect_definitions(&case.operations);
+                    }
+                    if let Some(ops) = default {
+                        self.collect_definitions(ops);
+                    }
+                },
+                LogicOperation::While { body, .. } | LogicOperation::Transaction { body, .. } => self.collect_definitions(body),
+                LogicOperation::Try { body, catch, finally } => {
+                    self.collect_definitions(body);
+                    self.collect_definitions(catch);
+                    if let Some(ops) = finally {
+                        self.collect_definitions(ops);
+                    }
+                },
+                LogicOperation::Parallel { tasks, .. } => {
+                    for task in tasks {
+                        self.collect_definitions(task);
+                    }
+                },
+                LogicOperation::DefineFunction { name, params, body } => {
+                    self.defined_functions.insert(name.clone());
+                    self.assigned.extend(params.iter().cloned());
+                    self.collect_definitions(body);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn current_path(&self) -> String {
+        self.path.join(".")
+    }
+
+    fn error(&mut self, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic::error(self.current_path(), message));
+    }
+
+    fn warning(&mut self, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic::warning(self.current_path(), message));
+    }
+
+    /// A statement in positional form, with its arguments compiled. Args are
+    /// bound in order and keep their value's type.
+    fn compile_sql(&mut self, query: &str, args: &[Value], params: Option<&HashMap<String, Value>>) -> (String, Vec<CompiledValue>) {
+        let (statement, args) = match sql::positional_args(query, args, params) {
+            Ok((statement, args)) => {
+                let expected = sql::parameter_count(&statement);
+                if expected != args.len() {
+                    self.error(format!("Query takes {} argument(s) but {} given", expected, args.len()));
+                }
+                (statement, args)
+            },
+            Err(e) => {
+                self.error(e);
+                (query.to_string(), args.to_vec())
+            },
+        };
+        if let Some(params) = params {
+            let used = NamedQuery::parse(query).param_names;
+            let mut unused: Vec<&String> = params.keys().filter(|name| !used.contains(name)).collect();
+            unused.sort();
+            for name in unused {
+                self.warning(format!("Parameter '{}' is not used by the query", name));
+            }
+        }
+        let args = args.iter().map(|arg| self.compile_value(arg)).collect();
+        (statement, args)
+    }
+
+    /// A Redis command with its arguments checked against what the command takes
+    fn compile_redis(&mut self, command: &str, key: &str, args: &[Value], ttl_seconds: Option<u64>) -> RedisCommand {
+        let command = command.to_ascii_uppercase();
+        match redis::check(&command, key, args, ttl_seconds) {
+            Err(e) => self.error(e),
+            Ok(()) if ttl_seconds.is_some() && !redis::takes_ttl(&command) => {
+                self.warning(format!("ttl_seconds has no effect on {}", command));
             },
+            Ok(()) => {},
+        }
+        RedisCommand {
+            key: self.compile_template(key),
+            args: args.iter().map(|arg| self.compile_value(arg)).collect(),
+            command,
+            ttl_seconds,
         }
     }
 
     fn register_variables_in_value(&mut self, value: &Value) {
-        if let Value::String(s) = value {
-            self.register_variables_in_string(s);
+        match value {
+            Value::String(s) => self.register_variables_in_string(s),
+            Value::Array(items) => {
+                for item in items {
+                    self.register_variables_in_value(item);
+                }
+            },
+            Value::Object(obj) => {
+                for item in obj.values() {
+                    self.register_variables_in_value(item);
+                }
+            },
+            _ => {}
         }
-        // For nested objects/arrays, we could recurse, but for now, just strings
     }
 
+    /// Register the variables a string reads for operations that keep the raw string
     fn register_variables_in_string(&mut self, s: &str) {
-        // Use regex to find {{variable}} patterns
-        let re = Regex::new(r"\{\{([^}]+)\}\}").unwrap();
-        for cap in re.captures_iter(s) {
-            if let Some(var) = cap.get(1) {
-                self.symbol_table.register(var.as_str().to_string());
-            }
+        self.compile_template(s);
+    }
+
+    fn register_variable_name(&mut self, name: &str) {
+        let trimmed = name.trim();
+        if !trimmed.is_empty() && !trimmed.starts_with('[') && !trimmed.starts_with('{') {
+            self.symbol_table.register(trimmed.to_string());
+            self.check_scope(trimmed);
         }
     }
 
@@ -264,4 +621,221 @@ impl Default for LogicCompiler {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::diagnostics::Severity;
+    use serde_json::json;
+
+    fn set(var: &str, value: Value) -> LogicOperation {
+        LogicOperation::Set { var: var.to_string(), value }
+    }
+
+    fn ret(value: Value) -> LogicOperation {
+        LogicOperation::Return { value, status: None, headers: None, raw: None }
+    }
+
+    fn diagnostics(logic: &[LogicOperation]) -> Vec<(String, Severity, String)> {
+        let mut compiler = LogicCompiler::new().with_inputs(["id".to_string()]);
+        let _ = compiler.compile(logic);
+        compiler.diagnostics().iter()
+            .map(|d| (d.path.clone(), d.severity, d.message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_logic_has_no_diagnostics() {
+        let logic = vec![
+            set("total", json!(0)),
+            LogicOperation::Loop {
+                collection: "[1, 2]".to_string(),
+                var: "n".to_string(),
+                body: vec![set("total", json!("${total + n}")), LogicOperation::Continue],
+            },
+            ret(json!({"id": "{{id}}", "total": "{{total}}", "who": "{{request.user}}"})),
+        ];
+        assert!(diagnostics(&logic).is_empty());
+    }
+
+    #[test]
+    fn test_break_outside_loop_is_error_with_path() {
+        let logic = vec![
+            set("x", json!(1)),
+            LogicOperation::If { condition: "{{x}} > 0".to_string(), then: vec![LogicOperation::Break], otherwise: None },
+        ];
+        let found = diagnostics(&logic);
+        assert_eq!(found, vec![("logic[1].then[0]".to_string(), Severity::Error, "'break' outside of a loop".to_string())]);
+        assert!(LogicCompiler::new().compile(&logic).unwrap_err().contains("logic[1].then[0]"));
+    }
+
+    #[test]
+    fn test_function_body_does_not_see_enclosing_loop() {
+        let logic = vec![LogicOperation::While {
+            condition: "true".to_string(),
+            body: vec![LogicOperation::DefineFunction { name: "f".to_string(), params: vec![], body: vec![LogicOperation::Continue] }],
+            max_iterations: Some(1),
+        }];
+        let found = diagnostics(&logic);
+        assert_eq!(found[0].0, "logic[0].body[0].body[0]");
+        assert_eq!(found[0].1, Severity::Error);
+    }
+
+    #[test]
+    fn test_unreachable_and_undefined_are_warnings() {
+        let logic = vec![
+            ret(json!("{{totl}}")),
+            set("total", json!(1)),
+        ];
+        let found = diagnostics(&logic);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0], ("logic[0]".to_string(), Severity::Warning, "Variable 'totl' is never assigned and is not a declared input".to_string()));
+        assert_eq!(found[1], ("logic[1]".to_string(), Severity::Warning, "Unreachable operation after 'return'".to_string()));
+        assert!(LogicCompiler::new().compile(&logic).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_function_and_invalid_regex_are_errors() {
+        let logic = vec![
+            LogicOperation::CallFunction { name: "missing".to_string(), args: vec![], output_var: "out".to_string() },
+            LogicOperation::StringOp { operation: "regex_match".to_string(), input: "abc".to_string(), args: vec![json!("([a-z")] },
+        ];
+        let found = diagnostics(&logic);
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|d| d.1 == Severity::Error));
+        assert_eq!(found[0].2, "Call to unknown function 'missing'");
+        assert!(found[1].2.starts_with("Invalid regex '([a-z'"));
+
+        // Global functions can be declared to the compiler
+        let mut compiler = LogicCompiler::new().with_functions(["missing".to_string()]);
+        assert!(compiler.compile(&logic[..1]).is_ok());
+    }
+
+    #[test]
+    fn test_sql_argument_count_must_match_placeholders() {
+        let sql = |query: &str, args: Vec<Value>| LogicOperation::SqlOp { query: query.to_string(), args, params: None, datasource: None, output_var: "rows".to_string() };
+        let logic = vec![
+            sql("SELECT * FROM users WHERE id = ? AND active = ?", vec![json!("{{id}}")]),
+            sql("SELECT * FROM users WHERE id = ? AND name LIKE '%?%'", vec![json!("{{id}}")]),
+        ];
+        let found = diagnostics(&logic);
+        assert_eq!(found, vec![("logic[0]".to_string(), Severity::Error, "Query takes 2 argument(s) but 1 given".to_string())]);
+    }
+
+    #[test]
+    fn test_redis_commands_are_checked() {
+        let redis = |command: &str, key: &str, args: Vec<Value>, ttl_seconds: Option<u64>, commands: Vec<proto::models::RedisCommand>| LogicOperation::RedisOp {
+            command: command.to_string(), key: key.to_string(), args, ttl_seconds, commands, output_var: None,
+        };
+        let zadd = |args| proto::models::RedisCommand { command: "zadd".to_string(), key: "board".to_string(), args, ttl_seconds: None };
+        let logic = vec![
+            redis("hset", "user:{{id}}", vec![json!("name"), json!("Ann"), json!("age")], None, vec![]),
+            redis("GET", "user:{{id}}", vec![], Some(60), vec![]),
+            redis("PIPELINE", "", vec![], None, vec![zadd(vec![json!(10), json!("ann")]), zadd(vec![json!(10)])]),
+            redis("HSET", "user:{{id}}", vec![json!("{{fields}}")], Some(60), vec![]),
+        ];
+        let found = diagnostics(&logic);
+        assert_eq!(found, vec![
+            ("logic[0]".to_string(), Severity::Error, "HSET takes field/value pairs after the key, got 3".to_string()),
+            ("logic[1]".to_string(), Severity::Warning, "ttl_seconds has no effect on GET".to_string()),
+            ("logic[2]".to_string(), Severity::Error, "ZADD takes score/member pairs after the key, got 1".to_string()),
+            ("logic[3]".to_string(), Severity::Warning, "Variable 'fields' is never assigned and is not a declared input".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_map_is_unsupported() {
+        let logic = vec![set("numbers", json!([1, 2])), LogicOperation::Map { input: "numbers".to_string(), transform: "x * 2".to_string() }];
+        let found = diagnostics(&logic);
+        assert_eq!(found, vec![("logic[1]".to_string(), Severity::Error, "Unsupported operation 'map': build the result in a loop over the collection instead".to_string())]);
+        assert!(LogicCompiler::new().compile(&logic).is_err());
+    }
+
+    #[test]
+    fn test_filter_is_unsupported() {
+        let logic = vec![set("users", json!([])), LogicOperation::Filter { input: "users".to_string(), condition: "age > 18".to_string() }];
+        let found = diagnostics(&logic);
+        assert_eq!(found, vec![("logic[1]".to_string(), Severity::Error, "Unsupported operation 'filter': build the result in a loop over the collection instead".to_string())]);
+        assert!(LogicCompiler::new().compile(&logic).is_err());
+    }
+
+    #[test]
+    fn test_aggregate_is_unsupported() {
+        let logic = vec![set("prices", json!([3, 4])), LogicOperation::Aggregate { input: "prices".to_string(), operation: "sum".to_string() }];
+        let found = diagnostics(&logic);
+        assert_eq!(found, vec![("logic[1]".to_string(), Severity::Error, "Unsupported operation 'aggregate': build the result in a loop over the collection instead".to_string())]);
+        assert!(LogicCompiler::new().compile(&logic).is_err());
+    }
+
+    #[test]
+    fn test_await_all_is_unsupported() {
+        let logic = vec![LogicOperation::If {
+            condition: "true".to_string(),
+            then: vec![LogicOperation::AwaitAll { task_ids: vec!["a".to_string()] }],
+            otherwise: None,
+        }];
+        let found = diagnostics(&logic);
+        assert_eq!(found, vec![("logic[0].then[0]".to_string(), Severity::Error, "Unsupported operation 'await_all': a parallel step already waits for all of its tasks".to_string())]);
+        assert!(LogicCompiler::new().compile(&logic).is_err());
+    }
+
+    #[test]
+    fn test_reads_outside_the_setting_block_are_errors() {
+        let logic = vec![
+            LogicOperation::If {
+                condition: "{{id}} > 0".to_string(),
+                then: vec![set("note", json!("positive")), set("id", json!(0))],
+                otherwise: None,
+            },
+            LogicOperation::Loop {
+                collection: "[1, 2]".to_string(),
+                var: "n".to_string(),
+                body: vec![set("seen", json!("{{n}}"))],
+            },
+            LogicOperation::Parallel { tasks: vec![vec![set("task", json!(1))]], max_concurrent: None },
+            // Inputs stay route variables wherever they are set
+            ret(json!({"note": "{{note}}", "n": "{{n}}", "task": "{{task}}", "id": "{{id}}"})),
+        ];
+        let found = diagnostics(&logic);
+        let errors: Vec<&str> = found.iter().filter(|d| d.1 == Severity::Error).map(|d| d.2.as_str()).collect();
+        assert_eq!(errors, vec![
+            "Variable 'n' is read outside of the block that sets it",
+            "Variable 'note' is read outside of the block that sets it",
+            "Variable 'task' is read outside of the block that sets it",
+        ]);
+        assert!(found.iter().all(|d| d.0 == "logic[3]"));
+    }
+
+    #[test]
+    fn test_blocks_assign_outer_variables_and_shadow_loop_variables() {
+        let logic = vec![
+            set("item", json!("outer")),
+            set("total", json!(0)),
+            LogicOperation::Loop {
+                collection: "[1, 2]".to_string(),
+                var: "item".to_string(),
+                body: vec![set("double", json!("${item * 2}")), set("total", json!("${total + double}"))],
+            },
+            LogicOperation::If {
+                condition: "true".to_string(),
+                then: vec![set("label", json!("{{total}}"))],
+                otherwise: None,
+            },
+            ret(json!({"item": "{{item}}", "total": "{{total}}"})),
+        ];
+        let mut compiler = LogicCompiler::new();
+        let tree = compiler.lower(&logic).unwrap();
+        let symbols = compiler.get_symbol_table();
+        let (OptimizedOperation::Loop { var_index, body, .. }, OptimizedOperation::If { then, .. }) = (&tree[2], &tree[3]) else {
+            panic!("unexpected tree: {:?}", tree);
+        };
+        // The loop variable is a variable of its own; `total` is the route's
+        assert_ne!(Some(*var_index), symbols.get_index("item"));
+        assert!(matches!(body[1], OptimizedOperation::Set { var_index, .. } if Some(var_index) == symbols.get_index("total")));
+        // Once the loop ends, its slots are reused
+        let OptimizedOperation::Set { var_index: double, .. } = body[0] else { panic!() };
+        let loop_slots = [*var_index, double];
+        assert!(matches!(then[0], OptimizedOperation::Set { var_index, .. } if loop_slots.contains(&var_index)));
+        assert_eq!(symbols.get_index("double"), None);
+    }
+}
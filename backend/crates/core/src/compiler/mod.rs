@@ -1,2 +1,5 @@
 pub mod symbol_table;
-pub mod lowerer;
\ No newline at end of file
+pub mod lowerer;
+pub mod codegen;
+pub mod diagnostics;
+pub mod optimizer;
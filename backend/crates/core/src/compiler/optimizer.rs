@@ -0,0 +1,631 @@
+//! Rewrites route logic before it is compiled, keeping what the route does
+//!
+//! Runs on the logic as registered, after small global functions are inlined:
+//!
+//! - `${...}` and `{{expr}}` placeholders of `set` and `return` values that read
+//!   no variables and call only deterministic functions are evaluated here, once
+//! - an `if` whose condition is such a constant is replaced by the branch it takes
+//! - `comment`s are dropped, and so are `set`s of variables nothing reads
+//! - expressions in `set` values of a loop body that read nothing the loop
+//!   changes are computed once before the loop, into a `__loop_N` variable
+//!
+//! Blocks keep the result they end with: a block's last value can be what a
+//! route, function or parallel task returns, so an operation whose value might
+//! be that result is only removed when a later `set` or `return` replaces it.
+
+use crate::compiler::lowerer::IMPLICIT_VARIABLES;
+use crate::expression::condition::is_path;
+use crate::expression::CompiledExpression;
+use crate::services::dynamic_routes::sql;
+use crate::vm::template::{Segment, Template};
+use proto::models::{FunctionDef, LogicOperation};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+/// Prefix of the variables hoisted loop-invariant expressions are kept in
+const HOISTED_PREFIX: &str = "__loop_";
+
+#[derive(Default)]
+pub struct Optimizer {
+    /// Global functions the logic may call; their bodies read the caller's variables by name
+    global_functions: Vec<FunctionDef>,
+    /// Number of variables hoisted expressions were given so far
+    hoisted: usize,
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_global_functions(mut self, functions: Vec<FunctionDef>) -> Self {
+        self.global_functions = functions;
+        self
+    }
+
+    /// The optimized version of `logic`
+    pub fn optimize(&mut self, logic: &[LogicOperation]) -> Vec<LogicOperation> {
+        let mut logic = self.block(logic);
+        // Scripts and custom operations may read anything
+        if !logic.iter().any(is_opaque) {
+            loop {
+                let mut reads = HashSet::new();
+                collect_reads(&logic, &mut reads);
+                for function in &self.global_functions {
+                    collect_reads(&function.logic, &mut reads);
+                }
+                if !remove_unread_sets(&mut logic, &reads) {
+                    break;
+                }
+            }
+        }
+        logic
+    }
+
+    fn block(&mut self, ops: &[LogicOperation]) -> Vec<LogicOperation> {
+        let mut out = Vec::with_capacity(ops.len());
+        for (i, op) in ops.iter().enumerate() {
+            let mut op = op.clone();
+            for block in blocks_mut(&mut op) {
+                *block = self.block(block);
+            }
+            match op {
+                LogicOperation::Comment { .. } => {},
+                LogicOperation::Set { var, value } => {
+                    out.push(LogicOperation::Set { var, value: fold_value(&value) });
+                },
+                LogicOperation::Return { value, status, headers, raw } => {
+                    out.push(LogicOperation::Return { value: fold_value(&value), status, headers, raw });
+                },
+                LogicOperation::If { condition, then, otherwise } => match constant_condition(&condition) {
+                    // A branch that never runs leaves the result as it was
+                    Some(taken) => match if taken { Some(then) } else { otherwise } {
+                        Some(branch) if can_splice(&branch, &ops[i + 1..]) => out.extend(branch),
+                        Some(branch) => out.push(LogicOperation::If { condition: "true".to_string(), then: branch, otherwise: None }),
+                        None => {},
+                    },
+                    None => out.push(LogicOperation::If { condition, then, otherwise }),
+                },
+                LogicOperation::Loop { collection, var, mut body } => {
+                    out.extend(self.hoist(&mut body, Some(&var)));
+                    out.push(LogicOperation::Loop { collection, var, body });
+                },
+                LogicOperation::While { condition, mut body, max_iterations } => {
+                    out.extend(self.hoist(&mut body, None));
+                    out.push(LogicOperation::While { condition, body, max_iterations });
+                },
+                other => out.push(other),
+            }
+        }
+        out
+    }
+
+    /// Take the loop-invariant expressions out of `body`, returning the `set`s
+    /// that compute them, to run before the loop
+    fn hoist(&mut self, body: &mut Vec<LogicOperation>, loop_var: Option<&str>) -> Vec<LogicOperation> {
+        if body.iter().any(is_opaque) {
+            return vec![];
+        }
+        let mut written: HashSet<String> = IMPLICIT_VARIABLES.iter().map(|name| name.to_string()).collect();
+        written.extend(loop_var.map(str::to_string));
+        collect_writes(body, &mut written);
+
+        // What nested loops hoisted can move further out, when it's invariant here too
+        let mut sets = vec![];
+        let mut hoisted = HashMap::new();
+        let mut i = 0;
+        while i < body.len() {
+            match &body[i] {
+                LogicOperation::Set { var, value: Value::String(source) }
+                    if var.starts_with(HOISTED_PREFIX) && is_invariant(source, &written) =>
+                {
+                    hoisted.insert(source.clone(), var.clone());
+                    sets.push(body.remove(i));
+                },
+                _ => i += 1,
+            }
+        }
+        if !sets.is_empty() {
+            written = IMPLICIT_VARIABLES.iter().map(|name| name.to_string()).collect();
+            written.extend(loop_var.map(str::to_string));
+            collect_writes(body, &mut written);
+        }
+
+        self.hoist_block(body, &written, &mut hoisted, &mut sets);
+        sets
+    }
+
+    /// Replace invariant placeholders of `set` values in `ops` and the branches
+    /// below them; nested loops did their own bodies already
+    fn hoist_block(
+        &mut self,
+        ops: &mut [LogicOperation],
+        written: &HashSet<String>,
+        hoisted: &mut HashMap<String, String>,
+        sets: &mut Vec<LogicOperation>,
+    ) {
+        for op in ops {
+            match op {
+                LogicOperation::Set { value, .. } => {
+                    *value = self.hoist_value(value, written, hoisted, sets);
+                },
+                LogicOperation::If { .. }
+                | LogicOperation::Switch { .. }
+                | LogicOperation::Try { .. }
+                | LogicOperation::Transaction { .. } => {
+                    for block in blocks_mut(op) {
+                        self.hoist_block(block, written, hoisted, sets);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn hoist_value(
+        &mut self,
+        value: &Value,
+        written: &HashSet<String>,
+        hoisted: &mut HashMap<String, String>,
+        sets: &mut Vec<LogicOperation>,
+    ) -> Value {
+        match value {
+            Value::String(source) => {
+                let template = Template::parse(source);
+                let mut out = String::new();
+                for segment in template.segments() {
+                    match segment {
+                        Segment::Text(text) => out.push_str(text),
+                        Segment::Variable(var) => out.push_str(&var.raw),
+                        Segment::Expression { expr, raw } if reads_only(expr, written) => {
+                            let name = hoisted.entry(raw.clone()).or_insert_with(|| {
+                                let name = format!("{}{}", HOISTED_PREFIX, self.hoisted);
+                                self.hoisted += 1;
+                                sets.push(LogicOperation::Set { var: name.clone(), value: Value::String(raw.clone()) });
+                                name
+                            });
+                            out.push_str(&format!("{{{{{}}}}}", name));
+                        },
+                        Segment::Expression { raw, .. } => out.push_str(raw),
+                    }
+                }
+                Value::String(out)
+            },
+            Value::Array(items) => {
+                Value::Array(items.iter().map(|item| self.hoist_value(item, written, hoisted, sets)).collect())
+            },
+            Value::Object(fields) => Value::Object(
+                fields.iter().map(|(key, item)| (key.clone(), self.hoist_value(item, written, hoisted, sets))).collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+/// True for an expression that reads variables, none of which are in `written`
+fn reads_only(expr: &CompiledExpression, written: &HashSet<String>) -> bool {
+    let mut roots = expr.variable_paths().map(root).peekable();
+    roots.peek().is_some() && expr.is_deterministic() && roots.all(|root| !written.contains(root))
+}
+
+/// True when every placeholder of `source` reads only variables outside `written`
+fn is_invariant(source: &str, written: &HashSet<String>) -> bool {
+    Template::parse(source).segments().iter().all(|segment| match segment {
+        Segment::Text(_) => true,
+        Segment::Variable(var) => !written.contains(&var.root),
+        Segment::Expression { expr, .. } => expr.is_deterministic() && expr.variable_paths().all(|path| !written.contains(root(path))),
+    })
+}
+
+fn root(path: &str) -> &str {
+    path.split(['.', '[']).next().unwrap_or(path)
+}
+
+/// The value of a condition that is the same on every run, if it is
+fn constant_condition(condition: &str) -> Option<bool> {
+    let expr = CompiledExpression::compile(condition).ok().filter(CompiledExpression::is_constant)?;
+    expr.is_true(|_| None).ok()
+}
+
+/// Evaluate the constant placeholders of a value, where the result compiles back to the same value
+fn fold_value(value: &Value) -> Value {
+    match value {
+        Value::String(source) => fold_string(source).unwrap_or_else(|| value.clone()),
+        Value::Array(items) => Value::Array(items.iter().map(fold_value).collect()),
+        Value::Object(fields) => Value::Object(fields.iter().map(|(key, item)| (key.clone(), fold_value(item))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// A template whose placeholders are all constant, as the value the VM would resolve it to
+fn fold_string(source: &str) -> Option<Value> {
+    let template = Template::parse(source);
+    if template.is_literal() {
+        return None;
+    }
+    let constant = |expr: &CompiledExpression| expr.is_constant().then(|| expr.evaluate_strict(|_| None).ok()).flatten();
+
+    // A single placeholder is its typed value
+    if let Some(Segment::Expression { expr, .. }) = template.single_placeholder() {
+        return constant(expr).filter(compiles_to_itself);
+    }
+    let mut text = String::new();
+    for segment in template.segments() {
+        match segment {
+            Segment::Text(literal) => text.push_str(literal),
+            Segment::Expression { expr, .. } => match constant(expr)? {
+                Value::String(s) => text.push_str(&s),
+                other => text.push_str(&other.to_string()),
+            },
+            Segment::Variable(_) => return None,
+        }
+    }
+    // Rendered text is read back as JSON at run time, and so is a literal
+    Template::parse(&text).is_literal().then_some(Value::String(text))
+}
+
+/// True when the compiler turns `value` back into itself: strings in it are
+/// neither templates nor JSON text
+fn compiles_to_itself(value: &Value) -> bool {
+    match value {
+        Value::String(s) => Template::parse(s).is_literal() && serde_json::from_str::<Value>(s).is_err(),
+        Value::Array(items) => items.iter().all(compiles_to_itself),
+        Value::Object(fields) => fields.values().all(compiles_to_itself),
+        _ => true,
+    }
+}
+
+/// Whether the ops of a taken branch can replace the `if`: the branch doesn't
+/// end the block it is in early, and the block's result comes out the same
+fn can_splice(branch: &[LogicOperation], rest: &[LogicOperation]) -> bool {
+    let leaves_block = branch.iter().any(|op| {
+        matches!(op, LogicOperation::Return { .. } | LogicOperation::Break | LogicOperation::Continue)
+    });
+    !leaves_block && (matches!(branch.last(), Some(LogicOperation::Set { .. })) || result_replaced(rest))
+}
+
+/// True when a `set` or `return` in `rest` replaces the block's result before
+/// anything could end the block with it
+fn result_replaced(rest: &[LogicOperation]) -> bool {
+    for op in rest {
+        match op {
+            LogicOperation::Set { .. } | LogicOperation::Return { .. } => return true,
+            _ if ends_loop(op) => return false,
+            _ => {},
+        }
+    }
+    false
+}
+
+fn ends_loop(op: &LogicOperation) -> bool {
+    matches!(op, LogicOperation::Break | LogicOperation::Continue)
+        || blocks(op).iter().any(|block| block.iter().any(ends_loop))
+}
+
+/// Remove `set`s of variables that are never read, recursively; true if any were
+fn remove_unread_sets(ops: &mut Vec<LogicOperation>, reads: &HashSet<String>) -> bool {
+    let mut removed = false;
+    for op in ops.iter_mut() {
+        for block in blocks_mut(op) {
+            removed |= remove_unread_sets(block, reads);
+        }
+    }
+    // Walk backwards, knowing whether a later op replaces the block's result
+    let mut replaced = false;
+    for i in (0..ops.len()).rev() {
+        match &ops[i] {
+            LogicOperation::Set { var, .. } if replaced && !reads.contains(var) => {
+                ops.remove(i);
+                removed = true;
+            },
+            LogicOperation::Set { .. } | LogicOperation::Return { .. } => replaced = true,
+            op if ends_loop(op) => replaced = false,
+            _ => {},
+        }
+    }
+    removed
+}
+
+/// True for operations whose reads and writes can't be known ahead of time
+fn is_opaque(op: &LogicOperation) -> bool {
+    matches!(op, LogicOperation::ExecuteScript { .. } | LogicOperation::CustomOp(_))
+        || blocks(op).iter().any(|block| block.iter().any(is_opaque))
+}
+
+/// Root names of the variables `ops` may read. Errs on the side of reading too
+/// much: every string an operation takes counts, as a template, as a condition
+/// and as a bare variable name.
+fn collect_reads(ops: &[LogicOperation], reads: &mut HashSet<String>) {
+    for op in ops {
+        for_each_input(op, &mut |s| {
+            let template = Template::parse(s);
+            reads.extend(template.variable_roots().into_iter().map(str::to_string));
+            if is_path(s.trim()) {
+                reads.insert(root(s.trim()).to_string());
+            }
+            if let Ok(expr) = CompiledExpression::compile(s) {
+                reads.extend(expr.variable_paths().map(|path| root(path).to_string()));
+            }
+        });
+        for block in blocks(op) {
+            collect_reads(block, reads);
+        }
+    }
+}
+
+/// Names of the variables `ops` assign
+fn collect_writes(ops: &[LogicOperation], written: &mut HashSet<String>) {
+    for op in ops {
+        match op {
+            LogicOperation::Set { var, .. } | LogicOperation::Loop { var, .. } => {
+                written.insert(var.clone());
+            },
+            LogicOperation::SqlOp { output_var, .. } | LogicOperation::CallFunction { output_var, .. } => {
+                written.insert(output_var.clone());
+            },
+            LogicOperation::RedisOp { output_var: Some(output_var), .. } => {
+                written.insert(output_var.clone());
+            },
+            _ => {},
+        }
+        for block in blocks(op) {
+            collect_writes(block, written);
+        }
+    }
+}
+
+/// Call `f` with every string an operation reads, not counting nested blocks
+fn for_each_input(op: &LogicOperation, f: &mut dyn FnMut(&str)) {
+    fn values(values: &[Value], f: &mut dyn FnMut(&str)) {
+        for value in values {
+            strings(value, f);
+        }
+    }
+    fn strings(value: &Value, f: &mut dyn FnMut(&str)) {
+        match value {
+            Value::String(s) => f(s),
+            Value::Array(items) => values(items, f),
+            Value::Object(fields) => fields.values().for_each(|item| strings(item, f)),
+            _ => {},
+        }
+    }
+    // `:name` parameters without a value in `params` read the variable of that name
+    fn statement(query: &str, args: &[Value], params: Option<&HashMap<String, Value>>, f: &mut dyn FnMut(&str)) {
+        f(query);
+        match sql::positional_args(query, args, params) {
+            Ok((_, args)) => values(&args, f),
+            Err(_) => {
+                values(args, f);
+                params.iter().flat_map(|params| params.values()).for_each(|value| strings(value, f));
+            },
+        }
+    }
+    match op {
+        LogicOperation::Return { value, headers, .. } => {
+            strings(value, f);
+            headers.iter().flat_map(|headers| headers.values()).for_each(|header| f(header));
+        },
+        LogicOperation::QueryDb { query, params, .. } => {
+            let (args, params) = sql::split_params(params);
+            statement(query, args, params, f);
+        },
+        LogicOperation::SqlOp { query, args, params, .. } => statement(query, args, params.as_ref(), f),
+        LogicOperation::RedisOp { key, args, commands, .. } => {
+            f(key);
+            values(args, f);
+            for command in commands {
+                f(&command.key);
+                values(&command.args, f);
+            }
+        },
+        LogicOperation::WsOp { message, channel, .. } => {
+            f(message);
+            channel.iter().for_each(|channel| f(channel));
+        },
+        LogicOperation::HttpRequest { url, body, headers, .. } => {
+            f(url);
+            body.iter().for_each(|body| strings(body, f));
+            headers.iter().flat_map(|headers| headers.values()).for_each(|header| f(header));
+        },
+        LogicOperation::If { condition, .. } | LogicOperation::While { condition, .. } => f(condition),
+        LogicOperation::Loop { collection, .. } => f(collection),
+        LogicOperation::Switch { value, cases, .. } => {
+            f(value);
+            cases.iter().for_each(|case| strings(&case.value, f));
+        },
+        LogicOperation::Throw { message, code } => {
+            f(message);
+            code.iter().for_each(|code| f(code));
+        },
+        LogicOperation::AwaitAll { task_ids } => task_ids.iter().for_each(|id| f(id)),
+        LogicOperation::CallFunction { args, .. }
+        | LogicOperation::MathOp { args, .. }
+        | LogicOperation::DateOp { args, .. } => values(args, f),
+        LogicOperation::Map { input, transform } => {
+            f(input);
+            f(transform);
+        },
+        LogicOperation::Filter { input, condition } => {
+            f(input);
+            f(condition);
+        },
+        LogicOperation::Aggregate { input, .. } => f(input),
+        LogicOperation::Set { value, .. } => strings(value, f),
+        LogicOperation::Get { var } => f(var),
+        LogicOperation::StringOp { input, args, .. } | LogicOperation::JsonOp { input, args, .. } => {
+            f(input);
+            values(args, f);
+        },
+        LogicOperation::Log { message, .. } => f(message),
+        LogicOperation::Comment { .. }
+        | LogicOperation::Break
+        | LogicOperation::Continue
+        | LogicOperation::Try { .. }
+        | LogicOperation::Transaction { .. }
+        | LogicOperation::Parallel { .. }
+        | LogicOperation::DefineFunction { .. }
+        | LogicOperation::Sleep { .. }
+        | LogicOperation::ExecuteScript { .. }
+        | LogicOperation::CustomOp(_) => {},
+    }
+}
+
+/// The blocks nested directly in an operation
+fn blocks(op: &LogicOperation) -> Vec<&Vec<LogicOperation>> {
+    match op {
+        LogicOperation::If { then, otherwise, .. } => std::iter::once(then).chain(otherwise).collect(),
+        LogicOperation::Loop { body, .. }
+        | LogicOperation::While { body, .. }
+        | LogicOperation::Transaction { body, .. }
+        | LogicOperation::DefineFunction { body, .. } => vec![body],
+        LogicOperation::Switch { cases, default, .. } => {
+            cases.iter().map(|case| &case.operations).chain(default).collect()
+        },
+        LogicOperation::Try { body, catch, finally } => [body, catch].into_iter().chain(finally).collect(),
+        LogicOperation::Parallel { tasks, .. } => tasks.iter().collect(),
+        _ => vec![],
+    }
+}
+
+fn blocks_mut(op: &mut LogicOperation) -> Vec<&mut Vec<LogicOperation>> {
+    match op {
+        LogicOperation::If { then, otherwise, .. } => std::iter::once(then).chain(otherwise).collect(),
+        LogicOperation::Loop { body, .. }
+        | LogicOperation::While { body, .. }
+        | LogicOperation::Transaction { body, .. }
+        | LogicOperation::DefineFunction { body, .. } => vec![body],
+        LogicOperation::Switch { cases, default, .. } => {
+            cases.iter_mut().map(|case| &mut case.operations).chain(default).collect()
+        },
+        LogicOperation::Try { body, catch, finally } => [body, catch].into_iter().chain(finally).collect(),
+        LogicOperation::Parallel { tasks, .. } => tasks.iter_mut().collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn set(var: &str, value: Value) -> LogicOperation {
+        LogicOperation::Set { var: var.to_string(), value }
+    }
+
+    fn ret(value: Value) -> LogicOperation {
+        LogicOperation::Return { value, status: None, headers: None, raw: None }
+    }
+
+    fn optimize(logic: Vec<LogicOperation>) -> Value {
+        serde_json::to_value(Optimizer::new().optimize(&logic)).unwrap()
+    }
+
+    #[test]
+    fn test_constant_placeholders_are_folded() {
+        let logic = vec![
+            set("total", json!("${2 * 21}")),
+            set("label", json!("{{ 'a' | upper }}-${1 + 1}")),
+            set("text", json!("${'12'}")),
+            set("stamp", json!("${now()}")),
+            ret(json!({"total": "{{total}}", "label": "{{label}}", "text": "{{text}}", "stamp": "{{stamp}}", "n": "${10 / 4}"})),
+        ];
+        assert_eq!(optimize(logic), json!([
+            {"set": {"var": "total", "value": 42.0}},
+            {"set": {"var": "label", "value": "A-2.0"}},
+            // "12" would compile to a number, and now() changes
+            {"set": {"var": "text", "value": "${'12'}"}},
+            {"set": {"var": "stamp", "value": "${now()}"}},
+            {"return": {"value": {"total": "{{total}}", "label": "{{label}}", "text": "{{text}}", "stamp": "{{stamp}}", "n": 2.5}}},
+        ]));
+    }
+
+    #[test]
+    fn test_constant_conditions_keep_only_the_branch_taken() {
+        let logic = vec![
+            LogicOperation::Comment { text: "setup".to_string() },
+            LogicOperation::If {
+                condition: "1 > 2".to_string(),
+                then: vec![set("mode", json!("debug"))],
+                otherwise: Some(vec![set("mode", json!("live"))]),
+            },
+            LogicOperation::If { condition: "false".to_string(), then: vec![set("mode", json!("off"))], otherwise: None },
+            LogicOperation::If { condition: "{{flag}}".to_string(), then: vec![set("mode", json!("flag"))], otherwise: None },
+            // Its `return` ends only the branch, so the branch stays a block
+            LogicOperation::If { condition: "true".to_string(), then: vec![ret(json!("early"))], otherwise: None },
+            ret(json!("{{mode}}")),
+        ];
+        assert_eq!(optimize(logic), json!([
+            {"set": {"var": "mode", "value": "live"}},
+            {"if": {"condition": "{{flag}}", "then": [{"set": {"var": "mode", "value": "flag"}}], "otherwise": null}},
+            {"if": {"condition": "true", "then": [{"return": {"value": "early"}}], "otherwise": null}},
+            {"return": {"value": "{{mode}}"}},
+        ]));
+    }
+
+    #[test]
+    fn test_unread_sets_are_dropped_unless_they_are_the_result() {
+        let logic = vec![
+            set("unused", json!(1)),
+            set("only_feeds_unused", json!(2)),
+            set("also_unused", json!("{{only_feeds_unused}}")),
+            set("used", json!(3)),
+            LogicOperation::If {
+                condition: "{{used}} > 0".to_string(),
+                then: vec![set("last_in_block", json!(4))],
+                otherwise: None,
+            },
+            set("tail", json!("{{used}}")),
+        ];
+        assert_eq!(optimize(logic), json!([
+            {"set": {"var": "used", "value": 3}},
+            {"if": {"condition": "{{used}} > 0", "then": [{"set": {"var": "last_in_block", "value": 4}}], "otherwise": null}},
+            {"set": {"var": "tail", "value": "{{used}}"}},
+        ]));
+
+        // Global functions read the caller's variables by name
+        let function = FunctionDef {
+            name: "f".to_string(),
+            params: vec![],
+            logic: vec![ret(json!("{{shared}}"))],
+        };
+        let logic = vec![set("shared", json!(1)), ret(json!(null))];
+        let optimized = Optimizer::new().with_global_functions(vec![function]).optimize(&logic);
+        assert_eq!(optimized.len(), 2);
+    }
+
+    #[test]
+    fn test_loop_invariant_expressions_are_hoisted() {
+        let logic = vec![
+            set("rate", json!(0.2)),
+            set("totals", json!([])),
+            LogicOperation::Loop {
+                collection: "{{items}}".to_string(),
+                var: "item".to_string(),
+                body: vec![
+                    LogicOperation::Loop {
+                        collection: "{{item.parts}}".to_string(),
+                        var: "part".to_string(),
+                        body: vec![set("tax", json!("${rate * 100} / ${part.price * rate}"))],
+                    },
+                    set("totals", json!("${totals + [item.price * (1 + rate)]}")),
+                    set("label", json!("Rate: ${rate * 100}%")),
+                ],
+            },
+            ret(json!({"totals": "{{totals}}", "tax": "{{tax}}", "label": "{{label}}"})),
+        ];
+        assert_eq!(optimize(logic), json!([
+            {"set": {"var": "rate", "value": 0.2}},
+            {"set": {"var": "totals", "value": []}},
+            // Hoisted out of the inner loop first, then moved on out of the outer one
+            {"set": {"var": "__loop_0", "value": "${rate * 100}"}},
+            {"loop": {"collection": "{{items}}", "var": "item", "body": [
+                {"loop": {"collection": "{{item.parts}}", "var": "part", "body": [
+                    {"set": {"var": "tax", "value": "{{__loop_0}} / ${part.price * rate}"}},
+                ]}},
+                {"set": {"var": "totals", "value": "${totals + [item.price * (1 + rate)]}"}},
+                {"set": {"var": "label", "value": "Rate: {{__loop_0}}%"}},
+            ]}},
+            {"return": {"value": {"totals": "{{totals}}", "tax": "{{tax}}", "label": "{{label}}"}}},
+        ]));
+    }
+}
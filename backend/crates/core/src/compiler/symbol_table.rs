@@ -1,35 +1,129 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug, Clone)]
+/// Variable slots of a route or function body, in nested lexical scopes.
+///
+/// The outermost scope holds the route's own variables (inputs, implicit
+/// variables and anything set at the top level). Every nested block opens a
+/// scope of its own: a `set` assigns a variable an enclosing scope already has,
+/// and otherwise declares it in the innermost scope, while loop variables and
+/// function parameters always declare a new variable, shadowing any outer one.
+/// When a scope ends its slots are handed out again to later declarations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SymbolTable {
-    name_to_index: HashMap<String, usize>,
+    scopes: Vec<Scope>,
+    /// Name of each slot; a reused slot carries the name last declared in it
     index_to_name: Vec<String>,
+    /// Slots of ended scopes, reused before new ones are added
+    free: Vec<usize>,
+    /// Scope declarations go to while compiling
+    current: usize,
+    /// Names declared in scopes that have ended, and not visible since
+    ended: HashSet<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Scope {
+    parent: Option<usize>,
+    names: HashMap<String, usize>,
 }
 
 impl SymbolTable {
+    /// The outermost scope, e.g. the route level
+    pub const OUTERMOST: usize = 0;
+
     pub fn new() -> Self {
         Self {
-            name_to_index: HashMap::new(),
+            scopes: vec![Scope { parent: None, names: HashMap::new() }],
             index_to_name: Vec::new(),
+            free: Vec::new(),
+            current: Self::OUTERMOST,
+            ended: HashSet::new(),
         }
     }
 
-    /// Register a new variable and return its unique index.
-    /// If the variable is already registered, return the existing index.
+    /// Slot of a variable that is read: the visible one, or a new variable of
+    /// the outermost scope (something the request or the engine provides).
+    /// A name whose scope has ended gets a slot of its own that nothing writes;
+    /// check `is_out_of_scope` to report it.
     pub fn register(&mut self, name: String) -> usize {
-        if let Some(&index) = self.name_to_index.get(&name) {
-            index
-        } else {
-            let index = self.index_to_name.len();
-            self.name_to_index.insert(name.clone(), index);
-            self.index_to_name.push(name);
-            index
+        if let Some(index) = self.visible(&name) {
+            return index;
+        }
+        if self.ended.contains(&name) {
+            return self.allocate(name);
+        }
+        self.declare_in(Self::OUTERMOST, name)
+    }
+
+    /// Slot of a variable that is assigned: the visible one, or a new variable
+    /// of the current scope
+    pub fn declare(&mut self, name: String) -> usize {
+        match self.visible(&name) {
+            Some(index) => index,
+            None => self.declare_in(self.current, name),
+        }
+    }
+
+    /// Declare a new variable in the current scope, shadowing any visible one
+    pub fn shadow(&mut self, name: String) -> usize {
+        self.declare_in(self.current, name)
+    }
+
+    /// Open a scope nested in the current one
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(Scope { parent: Some(self.current), names: HashMap::new() });
+        self.current = self.scopes.len() - 1;
+    }
+
+    /// Close the current scope; its slots can be reused from here on
+    pub fn exit_scope(&mut self) {
+        let scope = &self.scopes[self.current];
+        let Some(parent) = scope.parent else {
+            return;
+        };
+        self.free.extend(scope.names.values().copied());
+        let names: Vec<String> = scope.names.keys().cloned().collect();
+        self.current = parent;
+        for name in names {
+            if self.visible(&name).is_none() {
+                self.ended.insert(name);
+            }
+        }
+    }
+
+    /// The scope being compiled
+    pub fn scope(&self) -> usize {
+        self.current
+    }
+
+    /// True when `name` is read where it isn't visible, after the scope that declared it ended
+    pub fn is_out_of_scope(&self, name: &str) -> bool {
+        self.visible(name).is_none() && self.ended.contains(name)
+    }
+
+    /// Index of a variable as seen from `scope`, looking outwards
+    pub fn resolve(&self, scope: usize, name: &str) -> Option<usize> {
+        let mut next = self.scopes.get(scope).map(|_| scope);
+        while let Some(id) = next {
+            if let Some(&index) = self.scopes[id].names.get(name) {
+                return Some(index);
+            }
+            next = self.scopes[id].parent;
         }
+        None
     }
 
-    /// Get the index for a variable name.
+    /// Get the index of a variable of the outermost scope.
     pub fn get_index(&self, name: &str) -> Option<usize> {
-        self.name_to_index.get(name).copied()
+        self.resolve(Self::OUTERMOST, name)
+    }
+
+    /// Index of the variable the name refers to in the outermost scope, or
+    /// else in the innermost scope that declared it (e.g. for a debugger)
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.get_index(name)
+            .or_else(|| self.scopes.iter().rev().find_map(|scope| scope.names.get(name).copied()))
     }
 
     /// Get the name for a variable index.
@@ -37,7 +131,7 @@ impl SymbolTable {
         self.index_to_name.get(index).map(|s| s.as_str())
     }
 
-    /// Get the number of registered variables.
+    /// Get the number of slots.
     pub fn len(&self) -> usize {
         self.index_to_name.len()
     }
@@ -46,10 +140,89 @@ impl SymbolTable {
     pub fn is_empty(&self) -> bool {
         self.index_to_name.is_empty()
     }
+
+    fn visible(&self, name: &str) -> Option<usize> {
+        self.resolve(self.current, name)
+    }
+
+    fn declare_in(&mut self, scope: usize, name: String) -> usize {
+        // Variables of the outermost scope can be read before they are set, so
+        // they never take over a slot an ended scope may have left a value in
+        let index = if scope == Self::OUTERMOST {
+            self.allocate(name.clone())
+        } else {
+            self.reuse(name.clone())
+        };
+        self.ended.remove(&name);
+        self.scopes[scope].names.insert(name, index);
+        index
+    }
+
+    fn reuse(&mut self, name: String) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.index_to_name[index] = name;
+                index
+            },
+            None => self.allocate(name),
+        }
+    }
+
+    fn allocate(&mut self, name: String) -> usize {
+        self.index_to_name.push(name);
+        self.index_to_name.len() - 1
+    }
 }
 
 impl Default for SymbolTable {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_assigns_visible_variables_and_declares_new_ones_locally() {
+        let mut symbols = SymbolTable::new();
+        let total = symbols.declare("total".to_string());
+        symbols.enter_scope();
+        assert_eq!(symbols.declare("total".to_string()), total);
+        let tmp = symbols.declare("tmp".to_string());
+        assert_eq!(symbols.resolve(symbols.scope(), "tmp"), Some(tmp));
+        symbols.exit_scope();
+
+        assert_eq!(symbols.get_index("tmp"), None);
+        assert!(symbols.is_out_of_scope("tmp"));
+        assert!(!symbols.is_out_of_scope("total"));
+        // Declaring it where it's visible again brings it back in scope
+        symbols.declare("tmp".to_string());
+        assert!(!symbols.is_out_of_scope("tmp"));
+    }
+
+    #[test]
+    fn test_shadowing_and_slot_reuse() {
+        let mut symbols = SymbolTable::new();
+        let outer = symbols.declare("item".to_string());
+        symbols.enter_scope();
+        let inner = symbols.shadow("item".to_string());
+        let loop_scope = symbols.scope();
+        assert_ne!(inner, outer);
+        assert_eq!(symbols.resolve(loop_scope, "item"), Some(inner));
+        symbols.exit_scope();
+        assert_eq!(symbols.get_index("item"), Some(outer));
+        assert!(!symbols.is_out_of_scope("item"));
+        // Resolving from the ended scope still finds its own variable
+        assert_eq!(symbols.resolve(loop_scope, "item"), Some(inner));
+
+        // A later block reuses the slot; the outermost scope never does
+        symbols.enter_scope();
+        assert_eq!(symbols.declare("other".to_string()), inner);
+        assert_eq!(symbols.get_name(inner), Some("other"));
+        assert_eq!(symbols.register("fresh".to_string()), 2);
+        symbols.exit_scope();
+        assert_eq!(symbols.len(), 3);
+    }
+}
@@ -0,0 +1,337 @@
+//! The list/get/create/update/delete routes of a model
+//!
+//! Routes are ordinary route definitions in the logic DSL, so they can be
+//! edited after they are generated. Their ids are derived from the model
+//! name, which is how a later version of the model finds them again.
+
+use super::migration::{quote, NOW};
+use super::{base_path, has_range_filter, table_name};
+use proto::models::{DataModel, FieldType, HttpMethod, LogicOperation, ModelField, RouteDefinition, RouteParameter};
+use serde_json::{json, Map, Value};
+
+/// The actions a model generates a route for, in order
+pub const ACTIONS: [&str; 5] = ["list", "get", "create", "update", "delete"];
+
+const DEFAULT_PAGE_SIZE: u32 = 20;
+
+pub fn route_id(model_name: &str, action: &str) -> String {
+    format!("model-{}-{}", model_name, action)
+}
+
+/// Every route of a model, in the order of [`ACTIONS`]
+pub fn generate_routes(model: &DataModel) -> Vec<RouteDefinition> {
+    vec![list_route(model), get_route(model), create_route(model), update_route(model), delete_route(model)]
+}
+
+/// `GET <base>`: a page of rows, filtered by field, sorted by `sort` (`-field` for descending)
+fn list_route(model: &DataModel) -> RouteDefinition {
+    let table = quote(&table_name(model));
+    let mut conditions = Vec::new();
+    let mut parameters = Vec::new();
+    for field in &model.fields {
+        let column = quote(&field.name);
+        conditions.push(format!("(:{0} IS NULL OR {1} = :{0})", field.name, column));
+        parameters.push(optional("query", &field.name, field.field_type));
+        if has_range_filter(field) {
+            for (suffix, operator) in [("min", ">="), ("max", "<=")] {
+                let name = format!("{}_{}", field.name, suffix);
+                conditions.push(format!("(:{0} IS NULL OR {1} {2} :{0})", name, column, operator));
+                parameters.push(optional("query", &name, field.field_type));
+            }
+        }
+    }
+    let filter = conditions.join(" AND ");
+
+    let sortable: Vec<&str> = ["id", "created_at", "updated_at"].into_iter()
+        .chain(model.fields.iter().map(|f| f.name.as_str()))
+        .collect();
+    let order: Vec<String> = sortable.iter()
+        .flat_map(|name| [
+            format!("CASE WHEN :sort = '{}' THEN {} END ASC", name, quote(name)),
+            format!("CASE WHEN :sort = '-{}' THEN {} END DESC", name, quote(name)),
+        ])
+        .chain([format!("{} ASC", quote("id"))])
+        .collect();
+    parameters.push(RouteParameter {
+        validation: Some(format!("^-?({})$", sortable.join("|"))),
+        ..parameter("query", "sort", "string", Some(json!("id")))
+    });
+    for (name, default) in [("limit", DEFAULT_PAGE_SIZE), ("offset", 0)] {
+        parameters.push(RouteParameter { validation: Some("^[0-9]+$".to_string()), ..parameter("query", name, "number", Some(json!(default))) });
+    }
+
+    let logic = vec![
+        sql_op(model, format!(
+            "SELECT * FROM {} WHERE {} ORDER BY {} LIMIT min(:limit, {}) OFFSET :offset",
+            table, filter, order.join(", "), model.max_page_size,
+        )),
+        LogicOperation::SqlOp {
+            query: format!(
+                "SELECT COUNT(*) AS total, min(:limit, {}) AS \"limit\", :offset AS \"offset\" FROM {} WHERE {}",
+                model.max_page_size, table, filter,
+            ),
+            args: vec![],
+            params: None,
+            datasource: model.datasource.clone(),
+            output_var: "page".to_string(),
+        },
+        respond(json!({
+            "items": "{{records}}",
+            "total": "{{page[0].total}}",
+            "limit": "{{page[0].limit}}",
+            "offset": "{{page[0].offset}}",
+        }), None),
+    ];
+    route(model, "list", format!("List {}", table_name(model)), base_path(model), HttpMethod::GET, parameters, None, logic)
+}
+
+/// `GET <base>/{id}`
+fn get_route(model: &DataModel) -> RouteDefinition {
+    let logic = vec![
+        sql_op(model, format!("SELECT * FROM {} WHERE {} = :id", quote(&table_name(model)), quote("id"))),
+        found_or_404(not_found(model), respond(json!("{{records[0]}}"), None)),
+    ];
+    route(model, "get", format!("Get a {}", model.name), item_path(model), HttpMethod::GET, vec![id_parameter()], None, logic)
+}
+
+/// `POST <base>`: insert a row from the body, answering 201 with it
+fn create_route(model: &DataModel) -> RouteDefinition {
+    let columns: Vec<String> = model.fields.iter().map(|f| quote(&f.name)).collect();
+    let values: Vec<String> = model.fields.iter().map(|f| format!(":{}", f.name)).collect();
+    let logic = vec![
+        sql_op(model, format!(
+            "INSERT INTO {} ({}) VALUES ({}) RETURNING *",
+            quote(&table_name(model)), columns.join(", "), values.join(", "),
+        )),
+        respond(json!("{{records[0]}}"), Some(201)),
+    ];
+    let parameters = model.fields.iter().map(body_parameter).collect();
+    route(model, "create", format!("Create a {}", model.name), base_path(model), HttpMethod::POST, parameters, Some(body_schema(model)), logic)
+}
+
+/// `PUT <base>/{id}`: replace a row's fields with the body
+fn update_route(model: &DataModel) -> RouteDefinition {
+    let assignments: Vec<String> = model.fields.iter()
+        .map(|f| format!("{} = :{}", quote(&f.name), f.name))
+        .chain([format!("{} = {}", quote("updated_at"), NOW)])
+        .collect();
+    let logic = vec![
+        sql_op(model, format!(
+            "UPDATE {} SET {} WHERE {} = :id RETURNING *",
+            quote(&table_name(model)), assignments.join(", "), quote("id"),
+        )),
+        found_or_404(not_found(model), respond(json!("{{records[0]}}"), None)),
+    ];
+    let parameters = std::iter::once(id_parameter()).chain(model.fields.iter().map(body_parameter)).collect();
+    route(model, "update", format!("Update a {}", model.name), item_path(model), HttpMethod::PUT, parameters, Some(body_schema(model)), logic)
+}
+
+/// `DELETE <base>/{id}`
+fn delete_route(model: &DataModel) -> RouteDefinition {
+    let logic = vec![
+        sql_op(model, format!("DELETE FROM {} WHERE {} = :id RETURNING {}", quote(&table_name(model)), quote("id"), quote("id"))),
+        found_or_404(not_found(model), respond(json!({"id": "{{id}}", "deleted": true}), None)),
+    ];
+    route(model, "delete", format!("Delete a {}", model.name), item_path(model), HttpMethod::DELETE, vec![id_parameter()], None, logic)
+}
+
+fn item_path(model: &DataModel) -> String {
+    format!("{}/{{id:int}}", base_path(model))
+}
+
+fn sql_op(model: &DataModel, query: String) -> LogicOperation {
+    LogicOperation::SqlOp { query, args: vec![], params: None, datasource: model.datasource.clone(), output_var: "records".to_string() }
+}
+
+pub(super) fn respond(value: Value, status: Option<u16>) -> LogicOperation {
+    LogicOperation::Return { value, status, headers: None, raw: None }
+}
+
+/// Answer 404 with `error` when the statement matched no row, and with `found` otherwise.
+/// A return only ends its own block, so `found` can't simply follow the check.
+pub(super) fn found_or_404(error: String, found: LogicOperation) -> LogicOperation {
+    LogicOperation::If {
+        condition: "{{records | length}} == 0".to_string(),
+        then: vec![respond(json!({"error": error}), Some(404))],
+        otherwise: Some(vec![found]),
+    }
+}
+
+fn not_found(model: &DataModel) -> String {
+    format!("{} {{{{id}}}} not found", model.name)
+}
+
+fn id_parameter() -> RouteParameter {
+    parameter("path", "id", "number", None)
+}
+
+/// Body fields the request leaves out are bound as their default, or NULL
+fn body_parameter(field: &ModelField) -> RouteParameter {
+    let default = match (&field.default, field.required) {
+        (None, true) => None,
+        (default, _) => Some(default.clone().unwrap_or(Value::Null)),
+    };
+    parameter("body", &field.name, data_type(field.field_type), default)
+}
+
+/// An optional parameter that reads as null when absent, so `:name IS NULL` holds
+fn optional(source: &str, name: &str, field_type: FieldType) -> RouteParameter {
+    parameter(source, name, data_type(field_type), Some(Value::Null))
+}
+
+/// A parameter is required unless it has a default
+pub(super) fn parameter(source: &str, name: &str, data_type: &str, default: Option<Value>) -> RouteParameter {
+    RouteParameter {
+        name: name.to_string(),
+        param_type: source.to_string(),
+        data_type: data_type.to_string(),
+        required: default.is_none(),
+        default_value: default,
+        validation: None,
+    }
+}
+
+fn data_type(field_type: FieldType) -> &'static str {
+    match field_type {
+        FieldType::Integer | FieldType::Number => "number",
+        FieldType::Boolean => "boolean",
+        FieldType::String | FieldType::Datetime => "string",
+    }
+}
+
+/// JSON Schema of create and update bodies, with the fields' constraints
+fn body_schema(model: &DataModel) -> Value {
+    let mut properties = Map::new();
+    for field in &model.fields {
+        let mut schema = Map::new();
+        let json_type = match field.field_type {
+            FieldType::String | FieldType::Datetime => "string",
+            FieldType::Integer => "integer",
+            FieldType::Number => "number",
+            FieldType::Boolean => "boolean",
+        };
+        schema.insert("type".to_string(), if field.required { json!(json_type) } else { json!([json_type, "null"]) });
+        if field.field_type == FieldType::Datetime {
+            schema.insert("format".to_string(), json!("date-time"));
+        }
+        if let Some(min) = field.min {
+            schema.insert("minimum".to_string(), json!(min));
+        }
+        if let Some(max) = field.max {
+            schema.insert("maximum".to_string(), json!(max));
+        }
+        if let Some(max_length) = field.max_length {
+            schema.insert("maxLength".to_string(), json!(max_length));
+        }
+        if let Some(values) = &field.values {
+            let mut allowed = values.clone();
+            if !field.required {
+                allowed.push(Value::Null);
+            }
+            schema.insert("enum".to_string(), json!(allowed));
+        }
+        properties.insert(field.name.clone(), Value::Object(schema));
+    }
+    let required: Vec<&str> = model.fields.iter()
+        .filter(|f| f.required && f.default.is_none())
+        .map(|f| f.name.as_str())
+        .collect();
+    json!({"type": "object", "properties": properties, "required": required})
+}
+
+#[allow(clippy::too_many_arguments)]
+fn route(
+    model: &DataModel,
+    action: &str,
+    name: String,
+    path: String,
+    method: HttpMethod,
+    parameters: Vec<RouteParameter>,
+    request_schema: Option<Value>,
+    logic: Vec<LogicOperation>,
+) -> RouteDefinition {
+    RouteDefinition {
+        id: route_id(&model.name, action),
+        name,
+        description: format!("Generated from model '{}'", model.name),
+        path,
+        method,
+        route_type: Default::default(),
+        logic,
+        ws_hooks: None,
+        parameters,
+        request_schema,
+        response_schema: None,
+        response_schema_policy: Default::default(),
+        auth_required: model.auth_required,
+        required_scopes: vec![],
+        required_roles: vec![],
+        rate_limit: None,
+        rate_limit_key: None,
+        limits: None,
+        optimize: true,
+        enabled: true,
+        version: "1.0.0".to_string(),
+        created_at: String::new(),
+        updated_at: String::new(),
+        created_by: format!("model:{}", model.name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_model::parse;
+    use crate::services::dynamic_routes::params::RequestValidator;
+    use crate::compiler::lowerer::LogicCompiler;
+    use std::collections::HashMap;
+
+    fn product() -> DataModel {
+        parse("name: product\ntable: products\nmax_page_size: 50\nfields:\n  - {name: title, type: string, required: true}\n  - {name: price, type: number}\n").unwrap()
+    }
+
+    #[test]
+    fn test_generates_a_route_per_action() {
+        let routes = generate_routes(&product());
+        let summary: Vec<(String, &str, &str)> = routes.iter().map(|r| (r.id.clone(), r.method.as_str(), r.path.as_str())).collect();
+        assert_eq!(summary, vec![
+            ("model-product-list".to_string(), "GET", "/api/products"),
+            ("model-product-get".to_string(), "GET", "/api/products/{id:int}"),
+            ("model-product-create".to_string(), "POST", "/api/products"),
+            ("model-product-update".to_string(), "PUT", "/api/products/{id:int}"),
+            ("model-product-delete".to_string(), "DELETE", "/api/products/{id:int}"),
+        ]);
+        for route in &routes {
+            LogicCompiler::new().compile(&route.logic).unwrap_or_else(|e| panic!("{}: {}", route.id, e));
+            RequestValidator::compile(route).unwrap_or_else(|_| panic!("{}: invalid parameters", route.id));
+        }
+    }
+
+    #[test]
+    fn test_list_parameters_are_checked() {
+        let list = list_route(&product());
+        let names: Vec<&str> = list.parameters.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["title", "price", "price_min", "price_max", "sort", "limit", "offset"]);
+        let LogicOperation::SqlOp { query, .. } = &list.logic[0] else { panic!("expected a query") };
+        assert!(query.ends_with("LIMIT min(:limit, 50) OFFSET :offset"), "{}", query);
+
+        let validator = RequestValidator::compile(&list).unwrap();
+        let query = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>();
+        let request = validator.validate(&HashMap::new(), &query(&[("sort", "-price")]), Value::Null).unwrap();
+        assert_eq!((&request.params["sort"], &request.params["limit"], &request.params["title"]), (&json!("-price"), &json!(20), &Value::Null));
+        assert!(validator.validate(&HashMap::new(), &query(&[("sort", "price; DROP TABLE products")]), Value::Null).is_err());
+        assert!(validator.validate(&HashMap::new(), &query(&[("limit", "-1")]), Value::Null).is_err());
+    }
+
+    #[test]
+    fn test_bodies_follow_the_fields() {
+        let create = create_route(&product());
+        let validator = RequestValidator::compile(&create).unwrap();
+        let request = validator.validate(&HashMap::new(), &HashMap::new(), json!({"title": "pen"})).unwrap();
+        assert_eq!(request.params["price"], Value::Null);
+        let errors = validator.validate(&HashMap::new(), &HashMap::new(), json!({"price": "cheap"})).unwrap_err().errors;
+        let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+        // The parameter and the schema each report the mistyped price
+        assert_eq!(paths, vec!["body.title", "body.price", "body.price", "body"]);
+    }
+}
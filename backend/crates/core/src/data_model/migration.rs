@@ -0,0 +1,281 @@
+//! The statements that bring a model's table from one version of the model to the next
+//!
+//! New fields SQLite can add in place become `ALTER TABLE ... ADD COLUMN`.
+//! Anything else (a removed, changed or unique field) rebuilds the table:
+//! a copy with the new columns is filled from the old one, which is then
+//! dropped. Indexes are dropped and created to match.
+
+use super::{table_name, IMPLICIT_COLUMNS};
+use proto::models::{DataModel, FieldType, ModelField, ModelIndex};
+use serde_json::Value;
+
+/// Table, in each datasource, recording the migrations applied to its models
+pub const HISTORY_TABLE: &str = "_worpen_model_migrations";
+
+pub const HISTORY_DDL: &str = "CREATE TABLE IF NOT EXISTS _worpen_model_migrations (\
+    model TEXT NOT NULL, \
+    version INTEGER NOT NULL, \
+    statements TEXT NOT NULL, \
+    applied_at TEXT NOT NULL, \
+    PRIMARY KEY (model, version))";
+
+/// SQL for the current time, as stored in `created_at` and `updated_at`
+pub const NOW: &str = "strftime('%Y-%m-%dT%H:%M:%fZ', 'now')";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Migration {
+    pub statements: Vec<String>,
+    /// Drops or retypes columns, losing their data
+    pub destructive: bool,
+}
+
+/// The migration from `previous`, the model as last applied, to `next`
+pub fn plan(previous: Option<&DataModel>, next: &DataModel) -> Result<Migration, String> {
+    let table = table_name(next);
+    let Some(previous) = previous else {
+        let mut statements = vec![create_table(&table, next)];
+        statements.extend(next.indexes.iter().map(|index| create_index(&table, index)));
+        return Ok(Migration { statements, destructive: false });
+    };
+    if previous.datasource != next.datasource {
+        return Err(format!(
+            "Model '{}' can't move from datasource '{}' to '{}'",
+            next.name,
+            previous.datasource.as_deref().unwrap_or("default"),
+            next.datasource.as_deref().unwrap_or("default"),
+        ));
+    }
+
+    let previous_table = table_name(previous);
+    let field = |model: &DataModel, name: &str| model.fields.iter().find(|f| f.name == name).cloned();
+    let removed: Vec<&ModelField> = previous.fields.iter().filter(|f| field(next, &f.name).is_none()).collect();
+    let retyped = previous.fields.iter()
+        .any(|old| field(next, &old.name).is_some_and(|new| new.field_type != old.field_type));
+    let changed = previous.fields.iter()
+        .any(|old| field(next, &old.name).is_some_and(|new| column_definition(&new) != column_definition(old)));
+    let added: Vec<&ModelField> = next.fields.iter().filter(|f| field(previous, &f.name).is_none()).collect();
+    let rebuild = !removed.is_empty() || changed || added.iter().any(|f| !can_add_column(f));
+
+    let mut statements = Vec::new();
+    if rebuild {
+        let kept: Vec<String> = IMPLICIT_COLUMNS.iter().map(|c| c.to_string())
+            .chain(next.fields.iter().filter(|f| field(previous, &f.name).is_some()).map(|f| f.name.clone()))
+            .map(|c| quote(&c))
+            .collect();
+        let copy = format!("{}__rebuild", table);
+        statements.push(create_table(&copy, next));
+        statements.push(format!(
+            "INSERT INTO {} ({}) SELECT {} FROM {}",
+            quote(&copy), kept.join(", "), kept.join(", "), quote(&previous_table),
+        ));
+        statements.push(format!("DROP TABLE {}", quote(&previous_table)));
+        statements.push(format!("ALTER TABLE {} RENAME TO {}", quote(&copy), quote(&table)));
+        statements.extend(next.indexes.iter().map(|index| create_index(&table, index)));
+        return Ok(Migration { statements, destructive: !removed.is_empty() || retyped });
+    }
+
+    // Indexes keep the name they were created with, so a renamed table gets new ones
+    let renamed = previous_table != table;
+    if renamed {
+        statements.push(format!("ALTER TABLE {} RENAME TO {}", quote(&previous_table), quote(&table)));
+    }
+    for index in &previous.indexes {
+        if renamed || !next.indexes.contains(index) {
+            statements.push(format!("DROP INDEX IF EXISTS {}", quote(&index_name(&previous_table, index))));
+        }
+    }
+    for field in added {
+        statements.push(format!("ALTER TABLE {} ADD COLUMN {}", quote(&table), column_definition(field)));
+    }
+    for index in &next.indexes {
+        if renamed || !previous.indexes.contains(index) {
+            statements.push(create_index(&table, index));
+        }
+    }
+    Ok(Migration { statements, destructive: false })
+}
+
+/// `ADD COLUMN` can't add a unique column, nor a NOT NULL one without a default
+fn can_add_column(field: &ModelField) -> bool {
+    !field.unique && (!field.required || field.default.is_some())
+}
+
+fn create_table(table: &str, model: &DataModel) -> String {
+    let mut columns = vec![format!("{} INTEGER PRIMARY KEY AUTOINCREMENT", quote("id"))];
+    columns.extend(model.fields.iter().map(column_definition));
+    for timestamp in ["created_at", "updated_at"] {
+        columns.push(format!("{} TEXT NOT NULL DEFAULT ({})", quote(timestamp), NOW));
+    }
+    format!("CREATE TABLE {} ({})", quote(table), columns.join(", "))
+}
+
+fn create_index(table: &str, index: &ModelIndex) -> String {
+    let columns: Vec<String> = index.fields.iter().map(|f| quote(f)).collect();
+    format!(
+        "CREATE {}INDEX {} ON {} ({})",
+        if index.unique { "UNIQUE " } else { "" },
+        quote(&index_name(table, index)),
+        quote(table),
+        columns.join(", "),
+    )
+}
+
+/// Indexes are named after their table, columns and uniqueness, so that a changed index is a new one
+pub fn index_name(table: &str, index: &ModelIndex) -> String {
+    format!("{}_{}_{}", if index.unique { "uidx" } else { "idx" }, table, index.fields.join("_"))
+}
+
+/// A field's column, with its constraints
+pub fn column_definition(field: &ModelField) -> String {
+    let column = quote(&field.name);
+    let mut definition = format!("{} {}", column, column_type(field.field_type));
+    if field.required {
+        definition.push_str(" NOT NULL");
+    }
+    if field.unique {
+        definition.push_str(" UNIQUE");
+    }
+    if let Some(default) = &field.default {
+        definition.push_str(&format!(" DEFAULT {}", literal(default)));
+    }
+    let mut checks = Vec::new();
+    if let Some(min) = field.min {
+        checks.push(format!("{} >= {}", column, min));
+    }
+    if let Some(max) = field.max {
+        checks.push(format!("{} <= {}", column, max));
+    }
+    if let Some(max_length) = field.max_length {
+        checks.push(format!("length({}) <= {}", column, max_length));
+    }
+    if let Some(values) = &field.values {
+        let values: Vec<String> = values.iter().map(literal).collect();
+        checks.push(format!("{} IN ({})", column, values.join(", ")));
+    }
+    if !checks.is_empty() {
+        definition.push_str(&format!(" CHECK ({})", checks.join(" AND ")));
+    }
+    definition
+}
+
+/// BOOLEAN columns are read back as `true`/`false`; datetimes are RFC 3339 text
+fn column_type(field_type: FieldType) -> &'static str {
+    match field_type {
+        FieldType::String | FieldType::Datetime => "TEXT",
+        FieldType::Integer => "INTEGER",
+        FieldType::Number => "REAL",
+        FieldType::Boolean => "BOOLEAN",
+    }
+}
+
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Bool(b) => (*b as i64).to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => format!("'{}'", s.replace('\'', "''")),
+        other => format!("'{}'", other.to_string().replace('\'', "''")),
+    }
+}
+
+/// Model identifiers are checked to be plain lowercase names, but quote them anyway
+pub fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_model::parse;
+    use sqlx::SqlitePool;
+
+    fn model(fields: &str, indexes: &str) -> DataModel {
+        parse(&format!("name: product\nfields: {}\nindexes: {}\n", fields, indexes)).unwrap()
+    }
+
+    #[test]
+    fn test_new_model_creates_its_table() {
+        let next = model(r#"[{name: title, type: string, required: true, max_length: 20}, {name: state, type: string, enum: [a, "b'c"], default: a}]"#, "[{fields: [state]}]");
+        let migration = plan(None, &next).unwrap();
+        assert_eq!(migration.statements, vec![
+            "CREATE TABLE \"product\" (\"id\" INTEGER PRIMARY KEY AUTOINCREMENT, \
+             \"title\" TEXT NOT NULL CHECK (length(\"title\") <= 20), \
+             \"state\" TEXT DEFAULT 'a' CHECK (\"state\" IN ('a', 'b''c')), \
+             \"created_at\" TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')), \
+             \"updated_at\" TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')))".to_string(),
+            "CREATE INDEX \"idx_product_state\" ON \"product\" (\"state\")".to_string(),
+        ]);
+        assert!(!migration.destructive);
+    }
+
+    #[test]
+    fn test_added_fields_and_indexes_alter_the_table() {
+        let previous = model("[{name: title, type: string}]", "[{fields: [title]}]");
+        let next = model(
+            "[{name: title, type: string}, {name: stock, type: integer, required: true, default: 0, min: 0}]",
+            "[{fields: [title], unique: true}]",
+        );
+        assert_eq!(plan(Some(&previous), &next).unwrap().statements, vec![
+            "DROP INDEX IF EXISTS \"idx_product_title\"",
+            "ALTER TABLE \"product\" ADD COLUMN \"stock\" INTEGER NOT NULL DEFAULT 0 CHECK (\"stock\" >= 0)",
+            "CREATE UNIQUE INDEX \"uidx_product_title\" ON \"product\" (\"title\")",
+        ]);
+        assert_eq!(plan(Some(&next), &next).unwrap(), Migration::default());
+    }
+
+    #[test]
+    fn test_other_changes_rebuild_the_table() {
+        let previous = model("[{name: title, type: string}, {name: price, type: number}]", "[{fields: [price]}]");
+
+        // A new field ADD COLUMN can't add
+        let next = model("[{name: title, type: string}, {name: price, type: number}, {name: sku, type: string, unique: true}]", "[{fields: [price]}]");
+        let migration = plan(Some(&previous), &next).unwrap();
+        assert!(!migration.destructive);
+        assert_eq!(&migration.statements[1..], &[
+            "INSERT INTO \"product__rebuild\" (\"id\", \"created_at\", \"updated_at\", \"title\", \"price\") \
+             SELECT \"id\", \"created_at\", \"updated_at\", \"title\", \"price\" FROM \"product\"".to_string(),
+            "DROP TABLE \"product\"".to_string(),
+            "ALTER TABLE \"product__rebuild\" RENAME TO \"product\"".to_string(),
+            "CREATE INDEX \"idx_product_price\" ON \"product\" (\"price\")".to_string(),
+        ]);
+
+        // Dropping or retyping a field loses data
+        let next = model("[{name: title, type: string}]", "[]");
+        assert!(plan(Some(&previous), &next).unwrap().destructive);
+        let next = model("[{name: title, type: string}, {name: price, type: integer}]", "[{fields: [price]}]");
+        assert!(plan(Some(&previous), &next).unwrap().destructive);
+        let next = model("[{name: title, type: string, required: true}, {name: price, type: number}]", "[{fields: [price]}]");
+        assert!(!plan(Some(&previous), &next).unwrap().destructive);
+
+        let mut moved = previous.clone();
+        moved.datasource = Some("shop".to_string());
+        assert_eq!(plan(Some(&previous), &moved).unwrap_err(), "Model 'product' can't move from datasource 'default' to 'shop'");
+    }
+
+    #[tokio::test]
+    async fn test_migrations_keep_existing_rows() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let run = |migration: Migration| {
+            let pool = pool.clone();
+            async move {
+                for statement in migration.statements {
+                    sqlx::query(&statement).execute(&pool).await.unwrap();
+                }
+            }
+        };
+        let first = model("[{name: title, type: string}, {name: price, type: number}]", "[{fields: [price]}]");
+        run(plan(None, &first).unwrap()).await;
+        sqlx::query("INSERT INTO product (title, price) VALUES ('pen', 1.5)").execute(&pool).await.unwrap();
+
+        let mut second = model("[{name: title, type: string, required: true}, {name: stock, type: integer, default: 3}]", "[{fields: [title]}]");
+        second.table = Some("products".to_string());
+        run(plan(Some(&first), &second).unwrap()).await;
+
+        let row: (i64, String, i64, String) = sqlx::query_as("SELECT id, title, stock, created_at FROM products").fetch_one(&pool).await.unwrap();
+        assert_eq!((row.0, row.1.as_str(), row.2), (1, "pen", 3));
+        assert!(row.3.ends_with('Z'));
+        let indexes: Vec<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'products'")
+            .fetch_all(&pool).await.unwrap();
+        assert_eq!(indexes, vec!["idx_products_title"]);
+    }
+}
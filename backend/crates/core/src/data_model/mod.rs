@@ -0,0 +1,256 @@
+//! Declarative data models
+//!
+//! A model names a table and its fields; from it come the migration that
+//! brings the table up to date and a set of CRUD routes over it:
+//!
+//! ```yaml
+//! name: product
+//! table: products
+//! fields:
+//!   - name: title
+//!     type: string
+//!     required: true
+//!     max_length: 200
+//!   - name: price
+//!     type: number
+//!     min: 0
+//!   - name: status
+//!     type: string
+//!     enum: [draft, published]
+//!     default: draft
+//! indexes:
+//!   - fields: [status, price]
+//! ```
+//!
+//! Every table also gets an `id` primary key and `created_at`/`updated_at`
+//! timestamps, which fields can't redefine.
+//!
+//! Tables that already exist can't become models, but [`scaffold`] turns an
+//! introspected table into a schema and routes to start from.
+
+pub mod crud;
+pub mod migration;
+pub mod scaffold;
+
+use proto::models::{DataModel, FieldType, ModelField};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Columns every model table has
+pub const IMPLICIT_COLUMNS: [&str; 3] = ["id", "created_at", "updated_at"];
+
+/// Names the generated routes use for their own parameters and variables
+const RESERVED_NAMES: [&str; 5] = ["limit", "offset", "sort", "records", "page"];
+
+/// Parse a model from YAML or JSON text
+pub fn parse(content: &str) -> Result<DataModel, String> {
+    serde_yaml::from_str(content).map_err(|e| format!("Failed to parse model: {}", e))
+}
+
+/// Check a model, reporting every problem found
+pub fn validate(model: &DataModel) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for (what, name) in [("Model name", Some(&model.name)), ("Table name", model.table.as_ref())] {
+        if let Some(name) = name {
+            if !is_identifier(name) {
+                errors.push(format!("{} '{}' must be lowercase letters, digits and underscores", what, name));
+            }
+        }
+    }
+    if table_name(model).starts_with("sqlite_") || table_name(model).starts_with("_worpen") {
+        errors.push(format!("Table name '{}' is reserved", table_name(model)));
+    }
+    if let Some(path) = &model.path {
+        if !path.starts_with('/') || path.len() < 2 || path.ends_with('/') {
+            errors.push(format!("Path '{}' must start with '/' and not end with one", path));
+        }
+    }
+    if model.fields.is_empty() {
+        errors.push("A model needs at least one field".to_string());
+    }
+    if model.max_page_size == 0 {
+        errors.push("max_page_size must be at least 1".to_string());
+    }
+
+    let mut names = HashSet::new();
+    for field in &model.fields {
+        errors.extend(field_errors(field));
+        if !names.insert(field.name.as_str()) {
+            errors.push(format!("Field '{}' is defined more than once", field.name));
+        }
+    }
+    // Range filters are named after their field, so they must not clash with another one
+    for field in model.fields.iter().filter(|f| has_range_filter(f)) {
+        for filter in [format!("{}_min", field.name), format!("{}_max", field.name)] {
+            if names.contains(filter.as_str()) {
+                errors.push(format!("Field '{}' clashes with a range filter of field '{}'", filter, field.name));
+            }
+        }
+    }
+
+    for (i, index) in model.indexes.iter().enumerate() {
+        if index.fields.is_empty() {
+            errors.push(format!("indexes[{}] has no fields", i));
+        }
+        for name in &index.fields {
+            if !names.contains(name.as_str()) && !IMPLICIT_COLUMNS.contains(&name.as_str()) {
+                errors.push(format!("indexes[{}] refers to unknown field '{}'", i, name));
+            }
+        }
+    }
+    let mut index_names = HashSet::new();
+    for index in &model.indexes {
+        let name = migration::index_name(&table_name(model), index);
+        if !index_names.insert(name.clone()) {
+            errors.push(format!("Index on ({}) is defined more than once", index.fields.join(", ")));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+fn field_errors(field: &ModelField) -> Vec<String> {
+    let mut errors = Vec::new();
+    let name = &field.name;
+    if !is_identifier(name) {
+        errors.push(format!("Field name '{}' must be lowercase letters, digits and underscores", name));
+    }
+    if IMPLICIT_COLUMNS.contains(&name.as_str()) || RESERVED_NAMES.contains(&name.as_str()) {
+        errors.push(format!("Field name '{}' is reserved", name));
+    }
+    let numeric = matches!(field.field_type, FieldType::Integer | FieldType::Number);
+    if (field.min.is_some() || field.max.is_some()) && !numeric {
+        errors.push(format!("Field '{}': min and max only apply to integer and number fields", name));
+    }
+    if let (Some(min), Some(max)) = (field.min, field.max) {
+        if min > max {
+            errors.push(format!("Field '{}': min is greater than max", name));
+        }
+    }
+    if field.max_length.is_some() && field.field_type != FieldType::String {
+        errors.push(format!("Field '{}': max_length only applies to string fields", name));
+    }
+    if let Some(values) = &field.values {
+        if values.is_empty() {
+            errors.push(format!("Field '{}': enum needs at least one value", name));
+        }
+        for value in values {
+            if !fits_type(value, field.field_type) {
+                errors.push(format!("Field '{}': enum value {} is not a {}", name, value, type_name(field.field_type)));
+            }
+        }
+    }
+    if let Some(default) = &field.default {
+        if !fits_type(default, field.field_type) {
+            errors.push(format!("Field '{}': default {} is not a {}", name, default, type_name(field.field_type)));
+        } else if field.values.as_ref().is_some_and(|values| !values.contains(default)) {
+            errors.push(format!("Field '{}': default {} is not one of its enum values", name, default));
+        }
+    }
+    errors
+}
+
+/// The table a model is stored in
+pub fn table_name(model: &DataModel) -> String {
+    model.table.clone().unwrap_or_else(|| model.name.clone())
+}
+
+/// Base path of a model's routes
+pub fn base_path(model: &DataModel) -> String {
+    model.path.clone().unwrap_or_else(|| format!("/api/{}", table_name(model)))
+}
+
+/// Integer, number and datetime fields can also be filtered by range
+pub fn has_range_filter(field: &ModelField) -> bool {
+    matches!(field.field_type, FieldType::Integer | FieldType::Number | FieldType::Datetime)
+}
+
+pub fn type_name(field_type: FieldType) -> &'static str {
+    match field_type {
+        FieldType::String => "string",
+        FieldType::Integer => "integer",
+        FieldType::Number => "number",
+        FieldType::Boolean => "boolean",
+        FieldType::Datetime => "datetime",
+    }
+}
+
+fn fits_type(value: &Value, field_type: FieldType) -> bool {
+    match field_type {
+        FieldType::String | FieldType::Datetime => value.is_string(),
+        FieldType::Integer => value.is_i64() || value.is_u64(),
+        FieldType::Number => value.is_number(),
+        FieldType::Boolean => value.is_boolean(),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRODUCT: &str = r#"
+name: product
+table: products
+fields:
+  - name: title
+    type: string
+    required: true
+    max_length: 200
+  - name: price
+    type: number
+    min: 0
+  - name: status
+    type: string
+    enum: [draft, published]
+    default: draft
+indexes:
+  - fields: [status, price]
+"#;
+
+    #[test]
+    fn test_parses_and_accepts_a_model() {
+        let model = parse(PRODUCT).unwrap();
+        assert_eq!(model.max_page_size, 100);
+        assert_eq!(model.fields[2].values, Some(vec![Value::from("draft"), Value::from("published")]));
+        validate(&model).unwrap();
+        assert_eq!((table_name(&model), base_path(&model)), ("products".to_string(), "/api/products".to_string()));
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        let model = parse(r#"
+name: Product
+fields:
+  - { name: id, type: integer }
+  - { name: price, type: number, min: 5, max: 1 }
+  - { name: price_min, type: number }
+  - { name: title, type: string, min: 1, default: 3 }
+  - { name: title, type: boolean }
+  - { name: state, type: string, enum: [a, b], default: c }
+indexes:
+  - fields: [missing]
+"#).unwrap();
+        let errors: Vec<String> = validate(&model).unwrap_err().split("; ").map(str::to_string).collect();
+        assert_eq!(errors, vec![
+            "Model name 'Product' must be lowercase letters, digits and underscores",
+            "Field name 'id' is reserved",
+            "Field 'price': min is greater than max",
+            "Field 'title': min and max only apply to integer and number fields",
+            "Field 'title': default 3 is not a string",
+            "Field 'title' is defined more than once",
+            "Field 'state': default \"c\" is not one of its enum values",
+            "Field 'price_min' clashes with a range filter of field 'price'",
+            "indexes[0] refers to unknown field 'missing'",
+        ]);
+    }
+}
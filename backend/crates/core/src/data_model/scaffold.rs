@@ -0,0 +1,410 @@
+//! Routes and a JSON Schema over a table that already exists
+//!
+//! Where a model's table is made to match the model, a scaffold starts from a
+//! table as introspection found it. Its routes are a starting point to review
+//! and edit, so nothing here keeps track of them once they're registered.
+
+use super::crud::{found_or_404, parameter, respond};
+use super::migration::quote;
+use super::RESERVED_NAMES;
+use proto::models::{ColumnInfo, HttpMethod, LogicOperation, RouteDefinition, RouteParameter, TableInfo, TableScaffold};
+use crate::datasource::DEFAULT_DATASOURCE;
+use serde_json::{json, Map, Value};
+
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+
+/// The schema and routes of a table in a datasource. A table whose columns
+/// can't all be bound as parameters gets a schema but no routes.
+pub fn scaffold(table: TableInfo, datasource: &str) -> TableScaffold {
+    let schema = table_schema(&table);
+    let (routes, skipped) = match scaffold_routes(&table, datasource) {
+        Ok(routes) => (routes, None),
+        Err(e) => (vec![], Some(e)),
+    };
+    TableScaffold { table, schema, routes, skipped }
+}
+
+/// JSON type of a column, following SQLite's rules for the affinity of a declared type
+pub fn json_type(declared_type: &str) -> &'static str {
+    let declared = declared_type.to_ascii_uppercase();
+    let has = |part: &str| declared.contains(part);
+    if has("INT") {
+        "integer"
+    } else if has("CHAR") || has("CLOB") || has("TEXT") {
+        "string"
+    } else if has("BLOB") || has("JSON") {
+        "object"
+    } else if has("REAL") || has("FLOA") || has("DOUB") {
+        "number"
+    } else if has("BOOL") {
+        "boolean"
+    } else if declared.is_empty() || has("DATE") || has("TIME") {
+        "string"
+    } else {
+        "number"
+    }
+}
+
+/// JSON Schema of a row of the table
+pub fn table_schema(table: &TableInfo) -> Value {
+    let required: Vec<&str> = table.columns.iter().filter(|c| c.not_null || c.primary_key > 0).map(|c| c.name.as_str()).collect();
+    json!({
+        "title": table.name,
+        "type": "object",
+        "properties": properties(table, |c| c.not_null || c.primary_key > 0),
+        "required": required,
+    })
+}
+
+/// Every route over the table: list and create, and get, update and delete
+/// by primary key when it has one
+pub fn scaffold_routes(table: &TableInfo, datasource: &str) -> Result<Vec<RouteDefinition>, String> {
+    for column in &table.columns {
+        if !is_parameter_name(&column.name) || RESERVED_NAMES.contains(&column.name.as_str()) {
+            return Err(format!("Column '{}' of table '{}' can't be bound as a route parameter", column.name, table.name));
+        }
+    }
+    let scaffold = Scaffold { table, datasource };
+    let mut routes = vec![scaffold.list_route(), scaffold.create_route()];
+    if !scaffold.keys().is_empty() {
+        routes.insert(1, scaffold.get_route());
+        if table.columns.iter().any(|c| c.primary_key == 0) {
+            routes.push(scaffold.update_route());
+        }
+        routes.push(scaffold.delete_route());
+    }
+    Ok(routes)
+}
+
+struct Scaffold<'a> {
+    table: &'a TableInfo,
+    datasource: &'a str,
+}
+
+impl Scaffold<'_> {
+    /// `GET <base>`: a page of rows in key order, filtered by column
+    fn list_route(&self) -> RouteDefinition {
+        let mut conditions = Vec::new();
+        let mut parameters = Vec::new();
+        for column in self.table.columns.iter().filter(|c| json_type(&c.declared_type) != "object") {
+            conditions.push(format!("(:{0} IS NULL OR {1} = :{0})", column.name, quote(&column.name)));
+            parameters.push(parameter("query", &column.name, data_type(column), Some(Value::Null)));
+        }
+        for (name, default) in [("limit", DEFAULT_PAGE_SIZE), ("offset", 0)] {
+            parameters.push(RouteParameter { validation: Some("^[0-9]+$".to_string()), ..parameter("query", name, "number", Some(json!(default))) });
+        }
+        let filter = if conditions.is_empty() { "1".to_string() } else { conditions.join(" AND ") };
+        let order = match self.keys().as_slice() {
+            [] => "rowid".to_string(),
+            keys => keys.iter().map(|c| quote(&c.name)).collect::<Vec<_>>().join(", "),
+        };
+        let table = quote(&self.table.name);
+        let logic = vec![
+            self.sql_op("records", format!(
+                "SELECT * FROM {} WHERE {} ORDER BY {} LIMIT min(:limit, {}) OFFSET :offset",
+                table, filter, order, MAX_PAGE_SIZE,
+            )),
+            self.sql_op("page", format!(
+                "SELECT COUNT(*) AS total, min(:limit, {}) AS \"limit\", :offset AS \"offset\" FROM {} WHERE {}",
+                MAX_PAGE_SIZE, table, filter,
+            )),
+            respond(json!({
+                "items": "{{records}}",
+                "total": "{{page[0].total}}",
+                "limit": "{{page[0].limit}}",
+                "offset": "{{page[0].offset}}",
+            }), None),
+        ];
+        self.route("list", format!("List {}", self.table.name), self.base_path(), HttpMethod::GET, parameters, None, logic)
+    }
+
+    /// `GET <base>/<key>`
+    fn get_route(&self) -> RouteDefinition {
+        let logic = vec![
+            self.sql_op("records", format!("SELECT * FROM {} WHERE {}", quote(&self.table.name), self.key_filter())),
+            found_or_404(self.not_found(), respond(json!("{{records[0]}}"), None)),
+        ];
+        self.route("get", format!("Get a row of {}", self.table.name), self.item_path(), HttpMethod::GET, self.key_parameters(), None, logic)
+    }
+
+    /// `POST <base>`: insert a row from the body; columns it leaves out take their default
+    fn create_route(&self) -> RouteDefinition {
+        let columns: Vec<String> = self.table.columns.iter().map(|c| quote(&c.name)).collect();
+        let values: Vec<String> = self.table.columns.iter()
+            .map(|c| match &c.default {
+                Some(default) => format!("COALESCE(:{}, {})", c.name, default),
+                None => format!(":{}", c.name),
+            })
+            .collect();
+        let logic = vec![
+            self.sql_op("records", format!(
+                "INSERT INTO {} ({}) VALUES ({}) RETURNING *",
+                quote(&self.table.name), columns.join(", "), values.join(", "),
+            )),
+            respond(json!("{{records[0]}}"), Some(201)),
+        ];
+        let parameters = self.table.columns.iter()
+            .map(|c| {
+                let default = if must_insert(c, self.table) { None } else { Some(Value::Null) };
+                parameter("body", &c.name, data_type(c), default)
+            })
+            .collect();
+        let schema = self.body_schema(|c| must_insert(c, self.table));
+        self.route("create", format!("Create a row of {}", self.table.name), self.base_path(), HttpMethod::POST, parameters, Some(schema), logic)
+    }
+
+    /// `PATCH <base>/<key>`: change the columns the body gives, keeping the others
+    fn update_route(&self) -> RouteDefinition {
+        let values: Vec<&ColumnInfo> = self.table.columns.iter().filter(|c| c.primary_key == 0).collect();
+        let assignments: Vec<String> = values.iter()
+            .map(|c| format!("{0} = COALESCE(:{1}, {0})", quote(&c.name), c.name))
+            .collect();
+        let logic = vec![
+            self.sql_op("records", format!(
+                "UPDATE {} SET {} WHERE {} RETURNING *",
+                quote(&self.table.name), assignments.join(", "), self.key_filter(),
+            )),
+            found_or_404(self.not_found(), respond(json!("{{records[0]}}"), None)),
+        ];
+        let parameters = self.key_parameters().into_iter()
+            .chain(values.iter().map(|c| parameter("body", &c.name, data_type(c), Some(Value::Null))))
+            .collect();
+        let schema = self.body_schema(|_| false);
+        self.route("update", format!("Update a row of {}", self.table.name), self.item_path(), HttpMethod::PATCH, parameters, Some(schema), logic)
+    }
+
+    /// `DELETE <base>/<key>`
+    fn delete_route(&self) -> RouteDefinition {
+        let keys: Vec<String> = self.keys().iter().map(|c| quote(&c.name)).collect();
+        let mut deleted: Map<String, Value> = self.keys().iter()
+            .map(|c| (c.name.clone(), json!(format!("{{{{{}}}}}", c.name))))
+            .collect();
+        deleted.insert("deleted".to_string(), json!(true));
+        let logic = vec![
+            self.sql_op("records", format!(
+                "DELETE FROM {} WHERE {} RETURNING {}",
+                quote(&self.table.name), self.key_filter(), keys.join(", "),
+            )),
+            found_or_404(self.not_found(), respond(Value::Object(deleted), None)),
+        ];
+        self.route("delete", format!("Delete a row of {}", self.table.name), self.item_path(), HttpMethod::DELETE, self.key_parameters(), None, logic)
+    }
+
+    /// Primary key columns, in key order
+    fn keys(&self) -> Vec<&ColumnInfo> {
+        let mut keys: Vec<&ColumnInfo> = self.table.columns.iter().filter(|c| c.primary_key > 0).collect();
+        keys.sort_by_key(|c| c.primary_key);
+        keys
+    }
+
+    fn key_filter(&self) -> String {
+        self.keys().iter().map(|c| format!("{} = :{}", quote(&c.name), c.name)).collect::<Vec<_>>().join(" AND ")
+    }
+
+    fn key_parameters(&self) -> Vec<RouteParameter> {
+        self.keys().iter().map(|c| parameter("path", &c.name, data_type(c), None)).collect()
+    }
+
+    fn not_found(&self) -> String {
+        let key: Vec<String> = self.keys().iter().map(|c| format!("{{{{{}}}}}", c.name)).collect();
+        format!("{} {} not found", self.table.name, key.join("/"))
+    }
+
+    /// Tables outside the default datasource are served under its name, so equal table names don't clash
+    fn base_path(&self) -> String {
+        match self.datasource {
+            DEFAULT_DATASOURCE => format!("/api/{}", self.table.name),
+            datasource => format!("/api/{}/{}", datasource, self.table.name),
+        }
+    }
+
+    fn item_path(&self) -> String {
+        let segments: Vec<String> = self.keys().iter()
+            .map(|c| match json_type(&c.declared_type) {
+                "integer" => format!("{{{}:int}}", c.name),
+                _ => format!("{{{}}}", c.name),
+            })
+            .collect();
+        format!("{}/{}", self.base_path(), segments.join("/"))
+    }
+
+    /// Schema of a create or update body, where only the columns `required` picks must be given
+    fn body_schema(&self, required: impl Fn(&ColumnInfo) -> bool) -> Value {
+        let names: Vec<&str> = self.table.columns.iter().filter(|c| required(c)).map(|c| c.name.as_str()).collect();
+        json!({"type": "object", "properties": properties(self.table, &required), "required": names})
+    }
+
+    fn sql_op(&self, output_var: &str, query: String) -> LogicOperation {
+        let datasource = (self.datasource != DEFAULT_DATASOURCE).then(|| self.datasource.to_string());
+        LogicOperation::SqlOp { query, args: vec![], params: None, datasource, output_var: output_var.to_string() }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn route(
+        &self,
+        action: &str,
+        name: String,
+        path: String,
+        method: HttpMethod,
+        parameters: Vec<RouteParameter>,
+        request_schema: Option<Value>,
+        logic: Vec<LogicOperation>,
+    ) -> RouteDefinition {
+        let id = match self.datasource {
+            DEFAULT_DATASOURCE => format!("scaffold-{}-{}", self.table.name, action),
+            datasource => format!("scaffold-{}-{}-{}", datasource, self.table.name, action),
+        };
+        RouteDefinition {
+            id,
+            name,
+            description: format!("Scaffolded from table '{}' of datasource '{}'", self.table.name, self.datasource),
+            path,
+            method,
+            route_type: Default::default(),
+            logic,
+            ws_hooks: None,
+            parameters,
+            request_schema,
+            response_schema: None,
+            response_schema_policy: Default::default(),
+            auth_required: false,
+            required_scopes: vec![],
+            required_roles: vec![],
+            rate_limit: None,
+            rate_limit_key: None,
+            limits: None,
+            optimize: true,
+            enabled: true,
+            version: "1.0.0".to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            created_by: format!("scaffold:{}", self.datasource),
+        }
+    }
+}
+
+/// Column schemas; a column `non_null` doesn't pick may also be null
+fn properties(table: &TableInfo, non_null: impl Fn(&ColumnInfo) -> bool) -> Map<String, Value> {
+    table.columns.iter()
+        .map(|column| {
+            let json_type = json_type(&column.declared_type);
+            let mut schema = Map::new();
+            schema.insert("type".to_string(), if non_null(column) { json!(json_type) } else { json!([json_type, "null"]) });
+            if let Some(key) = table.foreign_keys.iter().find(|key| key.column == column.name) {
+                let target = key.references_column.as_deref().map(|c| format!("({})", c)).unwrap_or_default();
+                schema.insert("description".to_string(), json!(format!("References {}{}", key.references_table, target)));
+            }
+            (column.name.clone(), Value::Object(schema))
+        })
+        .collect()
+}
+
+/// Inserts must give a column that can't be null and has nothing to fall back on
+fn must_insert(column: &ColumnInfo, table: &TableInfo) -> bool {
+    (column.not_null || column.primary_key > 0) && column.default.is_none() && !is_rowid_alias(column, table)
+}
+
+/// An `INTEGER PRIMARY KEY` column is assigned by SQLite when left out
+fn is_rowid_alias(column: &ColumnInfo, table: &TableInfo) -> bool {
+    column.primary_key > 0
+        && column.declared_type.eq_ignore_ascii_case("INTEGER")
+        && table.columns.iter().filter(|c| c.primary_key > 0).count() == 1
+}
+
+/// Route parameters have no integer type; they're checked as numbers
+fn data_type(column: &ColumnInfo) -> &'static str {
+    match json_type(&column.declared_type) {
+        "integer" => "number",
+        other => other,
+    }
+}
+
+fn is_parameter_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::lowerer::LogicCompiler;
+    use crate::services::dynamic_routes::params::RequestValidator;
+    use proto::models::ForeignKeyInfo;
+    use std::collections::HashMap;
+
+    fn column(name: &str, declared_type: &str, not_null: bool, default: Option<&str>, primary_key: u32) -> ColumnInfo {
+        ColumnInfo { name: name.to_string(), declared_type: declared_type.to_string(), not_null, default: default.map(str::to_string), primary_key }
+    }
+
+    fn order_items() -> TableInfo {
+        TableInfo {
+            name: "order_items".to_string(),
+            columns: vec![
+                column("id", "INTEGER", false, None, 1),
+                column("order_id", "TEXT", true, None, 0),
+                column("quantity", "INTEGER", true, Some("1"), 0),
+                column("unit_price", "REAL", true, None, 0),
+                column("note", "", false, None, 0),
+            ],
+            foreign_keys: vec![ForeignKeyInfo { column: "order_id".to_string(), references_table: "orders".to_string(), references_column: Some("id".to_string()) }],
+        }
+    }
+
+    #[test]
+    fn test_declared_types_map_like_sqlite_affinity() {
+        let types: Vec<&str> = ["BIGINT", "VARCHAR(20)", "BLOB", "DOUBLE PRECISION", "BOOLEAN", "DATETIME", "DECIMAL(10,2)", "", "JSON"]
+            .into_iter().map(json_type).collect();
+        assert_eq!(types, vec!["integer", "string", "object", "number", "boolean", "string", "number", "string", "object"]);
+    }
+
+    #[test]
+    fn test_schema_describes_a_row() {
+        let schema = table_schema(&order_items());
+        assert_eq!(schema["required"], json!(["id", "order_id", "quantity", "unit_price"]));
+        assert_eq!(schema["properties"]["note"], json!({"type": ["string", "null"]}));
+        assert_eq!(schema["properties"]["order_id"], json!({"type": "string", "description": "References orders(id)"}));
+    }
+
+    #[test]
+    fn test_routes_cover_the_table() {
+        let routes = scaffold_routes(&order_items(), "shop").unwrap();
+        let summary: Vec<(&str, &str, &str)> = routes.iter().map(|r| (r.id.as_str(), r.method.as_str(), r.path.as_str())).collect();
+        assert_eq!(summary, vec![
+            ("scaffold-shop-order_items-list", "GET", "/api/shop/order_items"),
+            ("scaffold-shop-order_items-get", "GET", "/api/shop/order_items/{id:int}"),
+            ("scaffold-shop-order_items-create", "POST", "/api/shop/order_items"),
+            ("scaffold-shop-order_items-update", "PATCH", "/api/shop/order_items/{id:int}"),
+            ("scaffold-shop-order_items-delete", "DELETE", "/api/shop/order_items/{id:int}"),
+        ]);
+        for route in &routes {
+            LogicCompiler::new().compile(&route.logic).unwrap_or_else(|e| panic!("{}: {}", route.id, e));
+            RequestValidator::compile(route).unwrap_or_else(|_| panic!("{}: invalid parameters", route.id));
+        }
+
+        // The id is assigned and quantity has a default, so neither has to be given
+        let create = &routes[2];
+        let LogicOperation::SqlOp { query, datasource, .. } = &create.logic[0] else { panic!("expected a query") };
+        assert!(query.contains("VALUES (:id, :order_id, COALESCE(:quantity, 1), :unit_price, :note)"), "{}", query);
+        assert_eq!(datasource.as_deref(), Some("shop"));
+        let validator = RequestValidator::compile(create).unwrap();
+        let errors = validator.validate(&HashMap::new(), &HashMap::new(), json!({"quantity": 2})).unwrap_err().errors;
+        let mut paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+        paths.dedup();
+        assert_eq!(paths, vec!["body.order_id", "body.unit_price", "body"]);
+    }
+
+    #[test]
+    fn test_tables_without_keys_or_usable_columns() {
+        let mut log = order_items();
+        log.columns[0].primary_key = 0;
+        let ids: Vec<String> = scaffold_routes(&log, DEFAULT_DATASOURCE).unwrap().into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["scaffold-order_items-list", "scaffold-order_items-create"]);
+
+        log.columns.push(column("unit price", "REAL", false, None, 0));
+        let scaffolded = scaffold(log, DEFAULT_DATASOURCE);
+        assert!(scaffolded.routes.is_empty());
+        assert_eq!(scaffolded.skipped.as_deref(), Some("Column 'unit price' of table 'order_items' can't be bound as a route parameter"));
+    }
+}
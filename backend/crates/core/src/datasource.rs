@@ -0,0 +1,193 @@
+//! Named SQLite databases dynamic routes run their SQL on
+//!
+//! Operations pick one with `datasource:`; those without use the one named
+//! `default`. Datasources come from a JSON or YAML file:
+//!
+//! ```yaml
+//! datasources:
+//!   - name: shop
+//!     url: "sqlite:data/shop.db?mode=rwc"
+//!     max_connections: 5
+//!   - name: reports
+//!     url: "sqlite:data/reports.db"
+//!     read_only: true
+//! ```
+
+use futures::future::join_all;
+use proto::models::DatasourceStatus;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The datasource of operations that don't name one
+pub const DEFAULT_DATASOURCE: &str = "default";
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DatasourceConfig {
+    #[serde(default)]
+    pub datasources: Vec<DatasourceDefinition>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatasourceDefinition {
+    pub name: String,
+    /// SQLite connection URL, such as `sqlite:data/shop.db?mode=rwc`
+    pub url: String,
+    /// Open the database read-only, so that writes fail
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+}
+
+fn default_max_connections() -> u32 {
+    10
+}
+
+impl DatasourceConfig {
+    /// Read a config file; `.yaml`/`.yml` files are parsed as YAML, anything else as JSON
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read datasource config {}: {}", path, e))?;
+        if path.ends_with(".yaml") || path.ends_with(".yml") {
+            serde_yaml::from_str(&content).map_err(|e| format!("Failed to parse datasource config {}: {}", path, e))
+        } else {
+            serde_json::from_str(&content).map_err(|e| format!("Failed to parse datasource config {}: {}", path, e))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Datasource {
+    pool: SqlitePool,
+    read_only: bool,
+}
+
+/// The datasources routes can use, by name
+#[derive(Debug, Clone, Default)]
+pub struct DatasourceRegistry {
+    sources: HashMap<String, Datasource>,
+}
+
+impl DatasourceRegistry {
+    /// A pool for each configured datasource. Pools connect on first use, so a
+    /// database that can't be opened doesn't keep the server from starting;
+    /// its health check reports it.
+    pub fn from_config(config: &DatasourceConfig) -> Result<Self, String> {
+        let mut registry = Self::default();
+        for definition in &config.datasources {
+            if registry.sources.contains_key(&definition.name) {
+                return Err(format!("Datasource '{}' is defined more than once", definition.name));
+            }
+            if definition.max_connections == 0 {
+                return Err(format!("Datasource '{}' needs at least one connection", definition.name));
+            }
+            let options = SqliteConnectOptions::from_str(&definition.url)
+                .map_err(|e| format!("Invalid URL for datasource '{}': {}", definition.name, e))?
+                .read_only(definition.read_only);
+            let pool = SqlitePoolOptions::new()
+                .max_connections(definition.max_connections)
+                .connect_lazy_with(options);
+            registry.insert(&definition.name, pool, definition.read_only);
+        }
+        Ok(registry)
+    }
+
+    /// Add an open pool, replacing the datasource of the same name
+    pub fn insert(&mut self, name: &str, pool: SqlitePool, read_only: bool) {
+        self.sources.insert(name.to_string(), Datasource { pool, read_only });
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sources.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SqlitePool> {
+        self.sources.get(name).map(|source| &source.pool)
+    }
+
+    /// Every datasource's pool, by name
+    pub fn pools(&self) -> HashMap<String, SqlitePool> {
+        self.sources.iter().map(|(name, source)| (name.clone(), source.pool.clone())).collect()
+    }
+
+    /// Settings and pool state of each datasource, by name, checked with a `SELECT 1`
+    pub async fn health(&self) -> Vec<DatasourceStatus> {
+        let mut names: Vec<&String> = self.sources.keys().collect();
+        names.sort();
+        join_all(names.into_iter().map(|name| async move {
+            let source = &self.sources[name];
+            let check = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, sqlx::query("SELECT 1").execute(&source.pool)).await;
+            let error = match check {
+                Ok(Ok(_)) => None,
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(_) => Some(format!("No answer within {}s", HEALTH_CHECK_TIMEOUT.as_secs())),
+            };
+            DatasourceStatus {
+                name: name.clone(),
+                read_only: source.read_only,
+                max_connections: source.pool.options().get_max_connections(),
+                connections: source.pool.size(),
+                idle_connections: source.pool.num_idle(),
+                healthy: error.is_none(),
+                error,
+            }
+        })).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(name: &str, url: &str, read_only: bool) -> DatasourceDefinition {
+        DatasourceDefinition { name: name.to_string(), url: url.to_string(), read_only, max_connections: 2 }
+    }
+
+    #[tokio::test]
+    async fn test_registry_opens_configured_datasources() {
+        let dir = std::env::temp_dir().join(format!("worpen_datasources_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shop.db");
+        let shop = format!("sqlite:{}?mode=rwc", path.display());
+        let reports = format!("sqlite:{}", path.display());
+        let missing = format!("sqlite:{}", dir.join("missing.db").display());
+
+        let config = DatasourceConfig { datasources: vec![
+            definition("shop", &shop, false),
+            definition("reports", &reports, true),
+            definition("missing", &missing, false),
+        ] };
+        let registry = DatasourceRegistry::from_config(&config).unwrap();
+        sqlx::query("CREATE TABLE t (a INTEGER)").execute(registry.get("shop").unwrap()).await.unwrap();
+
+        let err = sqlx::query("INSERT INTO t VALUES (1)").execute(registry.get("reports").unwrap()).await.unwrap_err();
+        assert!(err.to_string().contains("readonly"), "unexpected error: {}", err);
+
+        let health = registry.health().await;
+        let names: Vec<&str> = health.iter().map(|status| status.name.as_str()).collect();
+        assert_eq!(names, vec!["missing", "reports", "shop"]);
+        assert!(!health[0].healthy && health[0].error.is_some());
+        assert!(health[1].healthy && health[1].read_only);
+        assert_eq!((health[2].healthy, health[2].max_connections), (true, 2));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_registry_rejects_invalid_config() {
+        let twice = DatasourceConfig { datasources: vec![
+            definition("a", "sqlite::memory:", false),
+            definition("a", "sqlite::memory:", false),
+        ] };
+        assert!(DatasourceRegistry::from_config(&twice).unwrap_err().contains("more than once"));
+
+        let config: DatasourceConfig = serde_yaml::from_str("datasources:\n  - name: a\n    url: \"sqlite::memory:\"\n").unwrap();
+        assert_eq!((config.datasources[0].read_only, config.datasources[0].max_connections), (false, 10));
+    }
+}
@@ -0,0 +1,232 @@
+//! Step debugging of route logic
+//!
+//! A debug session runs a route on the VM with a [`Debugger`] attached. Before
+//! every operation the VM asks the debugger whether to pause there. While paused,
+//! the debugger answers the client's commands, reading and writing the VM's memory
+//! by variable name, until it is told to go on.
+
+pub use proto::models::{DebugCommand, DebugEvent};
+use crate::compiler::symbol_table::SymbolTable;
+use crate::vm::memory::ExecutionMemory;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TryRecvError};
+
+/// How long a debug session may live, paused or not, instead of the route's timeout
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+const SESSION_CLOSED: &str = "Debug session closed";
+const SESSION_STOPPED: &str = "Debug session stopped";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepMode {
+    /// Only breakpoints pause
+    Run,
+    /// Pause before the next operation
+    Into,
+    /// Pause before the next operation at this depth or above
+    Over(usize),
+    /// Pause before the next operation above this depth
+    Out(usize),
+}
+
+/// The VM's end of a debug session
+pub struct Debugger {
+    commands: mpsc::UnboundedReceiver<DebugCommand>,
+    events: mpsc::UnboundedSender<DebugEvent>,
+    breakpoints: BTreeSet<String>,
+    mode: StepMode,
+}
+
+/// The client's end of a debug session
+pub struct DebugClient {
+    pub commands: mpsc::UnboundedSender<DebugCommand>,
+    pub events: mpsc::UnboundedReceiver<DebugEvent>,
+}
+
+/// A debugger that pauses before the first operation, so the client can set
+/// breakpoints first, and the client that drives it
+pub fn session() -> (Debugger, DebugClient) {
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, event_rx) = mpsc::unbounded_channel();
+    let debugger = Debugger {
+        commands: command_rx,
+        events: event_tx,
+        breakpoints: BTreeSet::new(),
+        mode: StepMode::Into,
+    };
+    (debugger, DebugClient { commands: command_tx, events: event_rx })
+}
+
+impl Debugger {
+    /// Another sender for the client's events, e.g. to report how the execution ended
+    pub fn events(&self) -> mpsc::UnboundedSender<DebugEvent> {
+        self.events.clone()
+    }
+
+    /// Called before the operation at `path` runs; returns once the execution may go
+    /// on, or with an error when the client stopped the session or went away
+    pub async fn before(
+        &mut self,
+        path: &str,
+        op: &str,
+        depth: usize,
+        memory: &mut ExecutionMemory,
+        symbols: &SymbolTable,
+    ) -> Result<(), String> {
+        // Commands sent while running, e.g. new breakpoints or a pause
+        loop {
+            match self.commands.try_recv() {
+                Ok(command) => {
+                    self.handle(command, depth, memory, symbols)?;
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Err(SESSION_CLOSED.to_string()),
+            }
+        }
+
+        let breakpoint = self.breakpoints.contains(path);
+        let pause = breakpoint || match self.mode {
+            StepMode::Run => false,
+            StepMode::Into => true,
+            StepMode::Over(at) => depth <= at,
+            StepMode::Out(at) => depth < at,
+        };
+        if !pause {
+            return Ok(());
+        }
+
+        self.send(DebugEvent::Paused { path: path.to_string(), op: op.to_string(), depth, breakpoint });
+        loop {
+            let command = self.commands.recv().await.ok_or_else(|| SESSION_CLOSED.to_string())?;
+            if self.handle(command, depth, memory, symbols)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Apply a command; `Ok(true)` when it resumes the execution
+    fn handle(
+        &mut self,
+        command: DebugCommand,
+        depth: usize,
+        memory: &mut ExecutionMemory,
+        symbols: &SymbolTable,
+    ) -> Result<bool, String> {
+        match command {
+            DebugCommand::Continue => self.mode = StepMode::Run,
+            DebugCommand::StepInto | DebugCommand::Pause => self.mode = StepMode::Into,
+            DebugCommand::StepOver => self.mode = StepMode::Over(depth),
+            DebugCommand::StepOut => self.mode = StepMode::Out(depth),
+            DebugCommand::Stop => return Err(SESSION_STOPPED.to_string()),
+            DebugCommand::SetBreakpoints { paths } => {
+                self.breakpoints = paths.into_iter().collect();
+                self.send(DebugEvent::Breakpoints { paths: self.breakpoints.iter().cloned().collect() });
+                return Ok(false);
+            },
+            DebugCommand::Inspect { var: None } => {
+                self.send(DebugEvent::Variables { values: variables(memory, symbols) });
+                return Ok(false);
+            },
+            DebugCommand::Inspect { var: Some(var) } => {
+                match symbols.find(&var) {
+                    Some(index) => {
+                        let value = memory.get(index).cloned().unwrap_or(Value::Null);
+                        self.send(DebugEvent::Variables { values: HashMap::from([(var, value)]) });
+                    },
+                    None => self.send(unknown_variable(&var)),
+                }
+                return Ok(false);
+            },
+            DebugCommand::SetVariable { var, value } => {
+                match symbols.find(&var) {
+                    Some(index) => {
+                        memory.set(index, value.clone());
+                        self.send(DebugEvent::Variables { values: HashMap::from([(var, value)]) });
+                    },
+                    None => self.send(unknown_variable(&var)),
+                }
+                return Ok(false);
+            },
+        }
+        Ok(true)
+    }
+
+    fn send(&self, event: DebugEvent) {
+        // A client that went away is noticed when its commands channel closes
+        let _ = self.events.send(event);
+    }
+}
+
+/// Every variable the program knows about, by name
+fn variables(memory: &ExecutionMemory, symbols: &SymbolTable) -> HashMap<String, Value> {
+    (0..symbols.len())
+        .filter_map(|index| {
+            let name = symbols.get_name(index)?;
+            Some((name.to_string(), memory.get(index).cloned().unwrap_or(Value::Null)))
+        })
+        .collect()
+}
+
+fn unknown_variable(var: &str) -> DebugEvent {
+    DebugEvent::Error { message: format!("Unknown variable '{}'", var) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        symbols.register("x".to_string());
+        symbols
+    }
+
+    #[tokio::test]
+    async fn test_applies_commands_sent_while_running() {
+        let (mut debugger, mut client) = session();
+        let symbols = symbols();
+        let mut memory = ExecutionMemory::new();
+        memory.set(0, json!(1));
+
+        client.commands.send(DebugCommand::Inspect { var: None }).unwrap();
+        client.commands.send(DebugCommand::SetVariable { var: "x".to_string(), value: json!(5) }).unwrap();
+        client.commands.send(DebugCommand::Inspect { var: Some("y".to_string()) }).unwrap();
+        client.commands.send(DebugCommand::Continue).unwrap();
+        debugger.before("logic[0]", "set", 0, &mut memory, &symbols).await.unwrap();
+
+        // While running, the commands above were already applied; only the pause follows them
+        assert_eq!(memory.get(0), Some(&json!(5)));
+        assert_eq!(client.events.recv().await, Some(DebugEvent::Variables { values: HashMap::from([("x".to_string(), json!(1))]) }));
+        assert_eq!(client.events.recv().await, Some(DebugEvent::Variables { values: HashMap::from([("x".to_string(), json!(5))]) }));
+        assert!(matches!(client.events.recv().await, Some(DebugEvent::Error { .. })));
+        assert!(client.events.try_recv().is_err(), "continued before reaching the pause");
+    }
+
+    #[tokio::test]
+    async fn test_step_modes_and_breakpoints() {
+        let (mut debugger, client) = session();
+        let symbols = symbols();
+        let mut memory = ExecutionMemory::new();
+
+        debugger.mode = StepMode::Over(1);
+        assert!(debugger.handle(DebugCommand::Continue, 1, &mut memory, &symbols).unwrap());
+        assert_eq!(debugger.mode, StepMode::Run);
+        debugger.before("logic[0].then[0]", "set", 1, &mut memory, &symbols).await.unwrap();
+
+        client.commands.send(DebugCommand::SetBreakpoints { paths: vec!["logic[1]".to_string()] }).unwrap();
+        client.commands.send(DebugCommand::StepOut).unwrap();
+        debugger.before("logic[0].then[1]", "set", 1, &mut memory, &symbols).await.unwrap();
+        assert_eq!(debugger.mode, StepMode::Out(1));
+
+        client.commands.send(DebugCommand::Stop).unwrap();
+        let error = debugger.before("logic[1]", "return", 0, &mut memory, &symbols).await.unwrap_err();
+        assert_eq!(error, SESSION_STOPPED);
+
+        drop(client);
+        let error = debugger.before("logic[2]", "return", 0, &mut memory, &symbols).await.unwrap_err();
+        assert_eq!(error, SESSION_CLOSED);
+    }
+}
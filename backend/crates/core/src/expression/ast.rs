@@ -1,6 +1,7 @@
 // Abstract Syntax Tree for expressions
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use crate::expression::functions::is_deterministic;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Expr<'a> {
@@ -95,14 +96,15 @@ pub struct PipeFilter<'a> {
 }
 
 impl<'a> Expr<'a> {
-    /// Check if expression is a constant (no variables)
+    /// Check if expression is a constant (no variables, and no calls to functions
+    /// like `now()` or `uuid()` whose result changes between calls)
     pub fn is_constant(&self) -> bool {
         match self {
             Expr::Number(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Null => true,
             Expr::Variable(_) => false,
             Expr::Binary { left, right, .. } => left.is_constant() && right.is_constant(),
             Expr::Unary { expr, .. } => expr.is_constant(),
-            Expr::Call { args, .. } => args.iter().all(|a| a.is_constant()),
+            Expr::Call { name, args } => is_deterministic(name) && args.iter().all(|a| a.is_constant()),
             Expr::Pipe { expr, filters } => {
                 expr.is_constant() && filters.iter().all(|f| f.args.iter().all(|a| a.is_constant()))
             }
@@ -114,6 +116,24 @@ impl<'a> Expr<'a> {
         }
     }
     
+    /// Check if the expression gives the same value whenever its variables are the same
+    pub fn is_deterministic(&self) -> bool {
+        match self {
+            Expr::Number(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Null | Expr::Variable(_) => true,
+            Expr::Binary { left, right, .. } => left.is_deterministic() && right.is_deterministic(),
+            Expr::Unary { expr, .. } => expr.is_deterministic(),
+            Expr::Call { name, args } => is_deterministic(name) && args.iter().all(|a| a.is_deterministic()),
+            Expr::Pipe { expr, filters } => {
+                expr.is_deterministic() && filters.iter().all(|f| f.args.iter().all(|a| a.is_deterministic()))
+            }
+            Expr::Ternary { condition, true_expr, false_expr } => {
+                condition.is_deterministic() && true_expr.is_deterministic() && false_expr.is_deterministic()
+            }
+            Expr::Array(items) => items.iter().all(|i| i.is_deterministic()),
+            Expr::Object(pairs) => pairs.iter().all(|(_, v)| v.is_deterministic()),
+        }
+    }
+
     /// Get all variable names used in this expression
     pub fn variables(&self) -> Vec<Cow<'a, str>> {
         let mut vars = Vec::new();
@@ -235,6 +255,10 @@ mod tests {
             right: Box::new(Expr::Number(2.0)),
         };
         assert!(!binary_with_var.is_constant());
+
+        let call = |name: &'static str| Expr::Call { name: Cow::Borrowed(name), args: vec![Expr::Number(3.0)] };
+        assert!(call("abs").is_constant());
+        assert!(!call("random_string").is_constant());
     }
     
     #[test]
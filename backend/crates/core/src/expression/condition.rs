@@ -0,0 +1,388 @@
+// Compiled conditions for if / while / switch
+// Parsed once into an Expr and evaluated against whatever variable store the engine uses
+
+use crate::expression::{Evaluator, Expr, Parser, Tokenizer};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// Interpreter-side cache, keyed by condition source
+static CONDITION_CACHE: Lazy<RwLock<HashMap<String, Arc<CompiledExpression>>>> = Lazy::new(|| {
+    RwLock::new(HashMap::new())
+});
+
+/// An expression parsed at compile time.
+///
+/// Accepts every condition style routes use today:
+///   "{{age}} > 18"              template placeholders compared with operators
+///   "{{order.status}} == paid"  bare words next to placeholders are string literals
+///   "{{ count | length }} == 0" full expressions inside placeholders
+///   "${total > 100}"            expression syntax
+///   "retries < 3"               plain expressions, identifiers are variables
+///
+/// Serialized as its source text, with the scope it was compiled in unless
+/// that is the outermost one.
+#[derive(Debug, Clone)]
+pub struct CompiledExpression {
+    source: String,
+    expr: Expr<'static>,
+    /// (identifier used in `expr`, variable path it reads)
+    variables: Vec<(String, String)>,
+    /// Symbol table scope the VM looks its variables up in
+    scope: usize,
+}
+
+impl CompiledExpression {
+    /// Parse a condition or switch subject
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let mut translator = Translator::default();
+        let template_style = source.contains("{{");
+        let translated = translator.translate(source, template_style)?;
+
+        let tokens = Tokenizer::new(&translated).tokenize()
+            .map_err(|e| format!("Invalid condition '{}': {}", source, e))?;
+        let expr = Parser::new(tokens).parse_complete()
+            .map_err(|e| format!("Invalid condition '{}': {}", source, e))?
+            .into_owned();
+
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+            variables: translator.variables,
+            scope: 0,
+        })
+    }
+
+    /// Resolve variables in this scope of the program's symbol table rather than the outermost one
+    pub fn in_scope(mut self, scope: usize) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    pub fn scope(&self) -> usize {
+        self.scope
+    }
+
+    /// Compile once and share across calls (used by the interpreter, which has no compile step)
+    pub fn cached(source: &str) -> Result<Arc<Self>, String> {
+        if let Some(compiled) = CONDITION_CACHE.read().unwrap().get(source) {
+            return Ok(compiled.clone());
+        }
+        let compiled = Arc::new(Self::compile(source)?);
+        CONDITION_CACHE.write().unwrap().insert(source.to_string(), compiled.clone());
+        Ok(compiled)
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// True when the expression reads no variables and calls only deterministic
+    /// functions, so it evaluates to the same value every time
+    pub fn is_constant(&self) -> bool {
+        self.variables.is_empty() && self.expr.is_constant()
+    }
+
+    /// True unless the expression calls a function like `now()` or `uuid()`
+    pub fn is_deterministic(&self) -> bool {
+        self.expr.is_deterministic()
+    }
+
+    /// Variable paths read by the expression, e.g. `user.age`
+    pub fn variable_paths(&self) -> impl Iterator<Item = &str> {
+        self.variables.iter().map(|(_, path)| path.as_str())
+    }
+
+    /// Evaluate with `lookup` resolving variable paths; missing variables are null.
+    /// Numeric text (query params, interpolated results) is read as a number,
+    /// matching how string conditions compared before.
+    pub fn evaluate<F>(&self, lookup: F) -> Result<Value, String>
+    where
+        F: Fn(&str) -> Option<Value>,
+    {
+        let variables: HashMap<String, Value> = self.variables.iter()
+            .map(|(name, path)| (name.clone(), numeric_text_as_number(lookup(path).unwrap_or(Value::Null))))
+            .collect();
+        self.evaluate_with(&variables)
+    }
+
+    /// Evaluate with values exactly as stored; a missing variable is an error.
+    /// Used for template placeholders, which keep their text when they can't be resolved.
+    pub fn evaluate_strict<F>(&self, lookup: F) -> Result<Value, String>
+    where
+        F: Fn(&str) -> Option<Value>,
+    {
+        let variables = self.variables.iter()
+            .map(|(name, path)| {
+                lookup(path)
+                    .map(|value| (name.clone(), value))
+                    .ok_or_else(|| format!("Undefined variable: {}", path))
+            })
+            .collect::<Result<HashMap<String, Value>, String>>()?;
+        self.evaluate_with(&variables)
+    }
+
+    fn evaluate_with(&self, variables: &HashMap<String, Value>) -> Result<Value, String> {
+        Evaluator::with_borrowed_variables(variables)
+            .evaluate(&self.expr)
+            .map_err(|e| format!("Expression '{}' failed: {}", self.source, e))
+    }
+
+    /// Evaluate and apply the expression engine's truthiness rules
+    pub fn is_true<F>(&self, lookup: F) -> Result<bool, String>
+    where
+        F: Fn(&str) -> Option<Value>,
+    {
+        let value = self.evaluate(lookup)?;
+        Ok(Evaluator::new().is_truthy(&value))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredExpression {
+    Source(String),
+    Scoped { source: String, scope: usize },
+}
+
+impl Serialize for CompiledExpression {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.scope == 0 {
+            return serializer.serialize_str(&self.source);
+        }
+        StoredExpression::Scoped { source: self.source.clone(), scope: self.scope }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CompiledExpression {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (source, scope) = match StoredExpression::deserialize(deserializer)? {
+            StoredExpression::Source(source) => (source, 0),
+            StoredExpression::Scoped { source, scope } => (source, scope),
+        };
+        Self::compile(&source).map(|expr| expr.in_scope(scope)).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Rewrites condition syntax into plain expression syntax.
+/// Variable paths become identifiers (`user.age` → `__path_0`) because the
+/// expression parser has no member access.
+#[derive(Default)]
+struct Translator {
+    variables: Vec<(String, String)>,
+}
+
+impl Translator {
+    fn translate(&mut self, source: &str, template_style: bool) -> Result<String, String> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut out = String::with_capacity(source.len());
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+
+            if c == '{' && chars.get(i + 1) == Some(&'{') {
+                let end = find(&chars, i + 2, "}}")
+                    .ok_or_else(|| format!("Unterminated '{{{{' in condition '{}'", source))?;
+                let inner: String = chars[i + 2..end].iter().collect();
+                let inner = inner.trim();
+                if is_path(inner) {
+                    out.push_str(&self.bind(inner));
+                } else {
+                    out.push('(');
+                    out.push_str(&self.translate(inner, false)?);
+                    out.push(')');
+                }
+                i = end + 2;
+            } else if c == '$' && chars.get(i + 1) == Some(&'{') {
+                let end = find(&chars, i + 2, "}")
+                    .ok_or_else(|| format!("Unterminated '${{' in condition '{}'", source))?;
+                let inner: String = chars[i + 2..end].iter().collect();
+                out.push('(');
+                out.push_str(&self.translate(inner.trim(), false)?);
+                out.push(')');
+                i = end + 1;
+            } else if c == '\'' || c == '"' {
+                // Copy string literals untouched
+                let start = i;
+                i += 1;
+                while i < chars.len() && chars[i] != c {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i = (i + 1).min(chars.len());
+                out.extend(&chars[start..i]);
+            } else if c.is_ascii_digit() {
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+            } else if c.is_alphabetic() || c == '_' {
+                let start = i;
+                while i < chars.len() && is_ident_char(chars[i]) {
+                    i += 1;
+                }
+                let mut nested = false;
+                loop {
+                    if chars.get(i) == Some(&'.') && chars.get(i + 1).is_some_and(|c| is_ident_char(*c)) {
+                        i += 1;
+                        while i < chars.len() && is_ident_char(chars[i]) {
+                            i += 1;
+                        }
+                        nested = true;
+                    } else if chars.get(i) == Some(&'[') {
+                        match find(&chars, i + 1, "]") {
+                            Some(close) if close > i + 1 && chars[i + 1..close].iter().all(|c| c.is_ascii_digit()) => {
+                                i = close + 1;
+                                nested = true;
+                            }
+                            _ => break,
+                        }
+                    } else {
+                        break;
+                    }
+                }
+
+                let word: String = chars[start..i].iter().collect();
+                if nested {
+                    out.push_str(&self.bind(&word));
+                } else if matches!(word.as_str(), "true" | "false" | "null")
+                    || next_non_space(&chars, i) == Some('(')
+                    || out.trim_end().ends_with('|')
+                {
+                    // Literals, function calls and filter names stay as written
+                    out.push_str(&word);
+                } else if template_style {
+                    // "{{status}} == active": bare words are text, as in the string templates
+                    out.push_str(&format!("'{}'", word));
+                } else {
+                    out.push_str(&self.bind(&word));
+                }
+            } else {
+                out.push(c);
+                i += 1;
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Identifier under which `path` is visible to the evaluator
+    fn bind(&mut self, path: &str) -> String {
+        // a[0].b reads the same as a.0.b
+        let path = path.replace('[', ".").replace(']', "");
+        if let Some((name, _)) = self.variables.iter().find(|(_, p)| *p == path) {
+            return name.clone();
+        }
+        let name = if path.contains('.') {
+            format!("__path_{}", self.variables.len())
+        } else {
+            path.clone()
+        };
+        self.variables.push((name.clone(), path));
+        name
+    }
+}
+
+fn numeric_text_as_number(value: Value) -> Value {
+    if let Value::String(text) = &value {
+        if let Some(number) = text.trim().parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+            return Value::Number(number);
+        }
+    }
+    value
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// A plain variable reference such as `user.name` or `items[0].id`
+pub(crate) fn is_path(s: &str) -> bool {
+    let starts_ok = s.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_');
+    starts_ok
+        && !matches!(s, "true" | "false" | "null")
+        && s.chars().all(|c| is_ident_char(c) || matches!(c, '.' | '[' | ']'))
+}
+
+fn find(chars: &[char], from: usize, pattern: &str) -> Option<usize> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    (from..chars.len()).find(|&i| chars[i..].starts_with(&pattern))
+}
+
+fn next_non_space(chars: &[char], from: usize) -> Option<char> {
+    chars[from..].iter().copied().find(|c| !c.is_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn eval(source: &str, vars: Value) -> Value {
+        let compiled = CompiledExpression::compile(source).unwrap();
+        compiled.evaluate(|path| {
+            let mut current = &vars;
+            for part in path.split('.') {
+                current = match current {
+                    Value::Array(items) => items.get(part.parse::<usize>().ok()?)?,
+                    other => other.get(part)?,
+                };
+            }
+            Some(current.clone())
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_template_style_comparison() {
+        assert_eq!(eval("{{age}} > 18", json!({"age": 21})), json!(true));
+        assert_eq!(eval("{{a}} < {{b}}", json!({"a": 5, "b": 3})), json!(false));
+        assert_eq!(eval("{{is_even}} == 0", json!({"is_even": 0})), json!(true));
+    }
+
+    #[test]
+    fn test_bare_words_are_text_next_to_placeholders() {
+        assert_eq!(eval("{{order.status}} == pending", json!({"order": {"status": "pending"}})), json!(true));
+        assert_eq!(eval("{{item}} == stop", json!({"item": "go"})), json!(false));
+    }
+
+    #[test]
+    fn test_expression_styles() {
+        assert_eq!(eval("{{ counter < 5 }}", json!({"counter": 2})), json!(true));
+        assert_eq!(eval("${total > 100}", json!({"total": 50})), json!(false));
+        assert_eq!(eval("retries < 3 && name != ''", json!({"retries": 1, "name": "x"})), json!(true));
+        assert_eq!(eval("{{ tags | length }} == 2", json!({"tags": ["a", "b"]})), json!(true));
+        assert_eq!(eval("rows[0].count > 0", json!({"rows": [{"count": 4}]})), json!(true));
+    }
+
+    #[test]
+    fn test_numeric_text_compares_as_number() {
+        assert_eq!(eval("{{n}} < 3", json!({"n": "1.0"})), json!(true));
+        assert_eq!(eval("{{limit}} == 10", json!({"limit": "10"})), json!(true));
+    }
+
+    #[test]
+    fn test_missing_variables_are_null() {
+        assert_eq!(eval("{{ user == null }}", json!({})), json!(true));
+    }
+
+    #[test]
+    fn test_invalid_condition_is_compile_error() {
+        assert!(CompiledExpression::compile("{{age}} >").is_err());
+        assert!(CompiledExpression::compile("{{age > 3").is_err());
+        assert!(CompiledExpression::compile("a b").is_err());
+    }
+
+    #[test]
+    fn test_serializes_as_source() {
+        let compiled = CompiledExpression::compile("{{n}} <= 1").unwrap();
+        let text = serde_json::to_string(&compiled).unwrap();
+        assert_eq!(text, "\"{{n}} <= 1\"");
+        let back: CompiledExpression = serde_json::from_str(&text).unwrap();
+        assert_eq!(back.source(), "{{n}} <= 1");
+    }
+}
@@ -130,8 +130,8 @@ impl<'a> Evaluator<'a> {
             BinaryOp::Mod => self.numeric_op(left, right, |a, b| a % b),
             BinaryOp::Pow => self.numeric_op(left, right, |a, b| a.powf(b)),
             
-            BinaryOp::Eq => Ok(Value::Bool(left == right)),
-            BinaryOp::Ne => Ok(Value::Bool(left != right)),
+            BinaryOp::Eq => Ok(Value::Bool(self.values_equal(left, right))),
+            BinaryOp::Ne => Ok(Value::Bool(!self.values_equal(left, right))),
             
             BinaryOp::Lt => self.comparison_op(left, right, |a, b| a < b),
             BinaryOp::Le => self.comparison_op(left, right, |a, b| a <= b),
@@ -208,7 +208,15 @@ impl<'a> Evaluator<'a> {
         Ok(Value::Bool(op(a, b)))
     }
     
-    fn is_truthy(&self, val: &Value) -> bool {
+    /// Equality where numbers compare by value, so `3 == 3.0`
+    fn values_equal(&self, left: &Value, right: &Value) -> bool {
+        match (left, right) {
+            (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+            _ => left == right,
+        }
+    }
+    
+    pub fn is_truthy(&self, val: &Value) -> bool {
         match val {
             Value::Null => false,
             Value::Bool(b) => *b,
@@ -364,6 +364,15 @@ static BUILTIN_FUNCTIONS: Lazy<HashMap<String, EvaluatorFunction>> = Lazy::new(|
     functions
 });
 
+/// Built-ins that can answer differently each time they are called
+const NONDETERMINISTIC: &[&str] = &["uuid", "now", "now_unix", "today", "now_time", "random_int", "random_float", "random_string"];
+
+/// True unless the function reads the clock or a random source, so calling it
+/// with the same arguments always gives the same result
+pub fn is_deterministic(name: &str) -> bool {
+    !NONDETERMINISTIC.contains(&name)
+}
+
 pub fn get_builtin_functions() -> HashMap<String, EvaluatorFunction> {
     // Zero-cost clone of the map containing Arcs
     BUILTIN_FUNCTIONS.clone()
@@ -8,9 +8,11 @@ pub mod evaluator;
 pub mod filters;
 pub mod template;
 pub mod functions;
+pub mod condition;
 
 pub use tokenizer::{Tokenizer, Token, TokenType};
 pub use parser::Parser;
 pub use ast::{Expr, BinaryOp, UnaryOp, PipeFilter};
 pub use evaluator::Evaluator;
 pub use template::resolve_templates;
+pub use condition::CompiledExpression;
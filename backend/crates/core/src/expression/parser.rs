@@ -22,6 +22,15 @@ impl<'a> Parser<'a> {
         self.expression()
     }
     
+    /// Parse and require that every token was consumed
+    pub fn parse_complete(&mut self) -> Result<Expr<'a>, String> {
+        let expr = self.expression()?;
+        if !self.is_at_end() {
+            return Err(format!("Unexpected token: {:?}", self.peek().token_type));
+        }
+        Ok(expr)
+    }
+    
     // expression → ternary
     fn expression(&mut self) -> Result<Expr<'a>, String> {
         self.ternary()
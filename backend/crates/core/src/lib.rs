@@ -10,6 +10,13 @@ pub mod loops;
 pub mod compiler;
 pub mod vm;
 pub mod websocket;
+pub mod auth;
+pub mod rate_limit;
+pub mod budget;
+pub mod trace;
+pub mod debugger;
+pub mod datasource;
+pub mod data_model;
 
 pub use domain::*;
 pub use ports::*;
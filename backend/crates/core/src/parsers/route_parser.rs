@@ -59,7 +59,7 @@ pub fn parse_route_with_format(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use proto::models::HttpMethod;
+    use proto::models::{HttpMethod, LogicOperation, SqlParams};
 
     #[test]
     fn test_parse_simple_json() {
@@ -191,4 +191,36 @@ version: "1.0.0"
         let route = parse_route(yaml).unwrap();
         assert_eq!(route.name, "test_with_comment");
     }
+
+    #[test]
+    fn test_parse_yaml_named_sql_params() {
+        let yaml = r#"
+name: named_params
+path: /api/users
+method: GET
+logic:
+  - query_db:
+      query: "SELECT * FROM users WHERE id = :id"
+      params: {id: "{{user_id}}"}
+  - sql_op:
+      query: "SELECT * FROM users WHERE age > :min_age"
+      params:
+        min_age: "${age - 1}"
+      output_var: users
+parameters: []
+enabled: true
+version: "1.0.0"
+"#;
+
+        let route = parse_route(yaml).unwrap();
+        let LogicOperation::QueryDb { params: SqlParams::Named(named), .. } = &route.logic[0] else {
+            panic!("expected query_db with named params, got {:?}", route.logic[0]);
+        };
+        assert_eq!(named["id"], "{{user_id}}");
+        let LogicOperation::SqlOp { params: Some(params), args, .. } = &route.logic[1] else {
+            panic!("expected sql_op with params, got {:?}", route.logic[1]);
+        };
+        assert!(args.is_empty());
+        assert_eq!(params["min_age"], "${age - 1}");
+    }
 }
@@ -0,0 +1,419 @@
+//! Token-bucket rate limiting for dynamic routes
+//!
+//! A route's `rate_limit` is requests per minute per client: each client's bucket
+//! holds that many tokens and refills continuously. Buckets live in memory, or in
+//! Redis when limits must hold across instances.
+
+use crate::auth::Identity;
+use crate::services::dynamic_routes::utils::resolve_string;
+use crate::vm::template::Template;
+use dashmap::DashMap;
+use proto::models::{DynamicRouteExecutionContext, LoopControl};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Policy {
+    pub capacity: u32,
+    /// Tokens added per second
+    pub refill_per_sec: f64,
+}
+
+impl Policy {
+    pub fn per_minute(limit: u32) -> Self {
+        Self { capacity: limit, refill_per_sec: limit as f64 / 60.0 }
+    }
+
+    /// The decision for a bucket left with `tokens` after the request was or wasn't let through
+    fn decide(&self, allowed: bool, tokens: f64) -> Decision {
+        let seconds = |missing: f64| (missing.max(0.0) / self.refill_per_sec).ceil() as u64;
+        Decision {
+            allowed,
+            limit: self.capacity,
+            remaining: tokens.max(0.0).floor() as u32,
+            reset_secs: seconds(self.capacity as f64 - tokens),
+            retry_after_secs: (!allowed).then(|| seconds(1.0 - tokens).max(1)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again
+    pub reset_secs: u64,
+    /// Seconds until the next request can succeed, when this one was refused
+    pub retry_after_secs: Option<u64>,
+}
+
+impl Decision {
+    /// `RateLimit-*` headers, plus `Retry-After` when the request was refused
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![
+            ("RateLimit-Limit", self.limit.to_string()),
+            ("RateLimit-Remaining", self.remaining.to_string()),
+            ("RateLimit-Reset", self.reset_secs.to_string()),
+        ];
+        if let Some(retry_after) = self.retry_after_secs {
+            headers.push(("Retry-After", retry_after.to_string()));
+        }
+        headers
+    }
+}
+
+/// Where buckets are stored
+pub trait RateLimitBackend: Send + Sync {
+    fn name(&self) -> &str;
+    /// Take one token from `bucket`
+    fn acquire<'a>(&'a self, bucket: &'a str, policy: Policy) -> Pin<Box<dyn Future<Output = Result<Decision, String>> + Send + 'a>>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    policy: Policy,
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * self.policy.refill_per_sec).min(self.policy.capacity as f64)
+    }
+}
+
+/// Buckets in this process only
+#[derive(Default)]
+pub struct MemoryBackend {
+    buckets: DashMap<String, Bucket>,
+    acquisitions: AtomicU64,
+}
+
+/// Drop full buckets every this many acquisitions so idle clients don't pile up
+const PRUNE_INTERVAL: u64 = 10_000;
+
+impl MemoryBackend {
+    fn take(&self, bucket: &str, policy: Policy) -> Decision {
+        if self.acquisitions.fetch_add(1, Ordering::Relaxed) % PRUNE_INTERVAL == PRUNE_INTERVAL - 1 {
+            self.prune();
+        }
+
+        let now = Instant::now();
+        let mut entry = self.buckets.entry(bucket.to_string())
+            .or_insert_with(|| Bucket { tokens: policy.capacity as f64, updated: now, policy });
+        // The route's limit may have changed since the bucket was created
+        entry.policy = policy;
+        entry.tokens = entry.refilled(now);
+        entry.updated = now;
+
+        let allowed = entry.tokens >= 1.0;
+        if allowed {
+            entry.tokens -= 1.0;
+        }
+        policy.decide(allowed, entry.tokens)
+    }
+
+    fn prune(&self) {
+        let now = Instant::now();
+        self.buckets.retain(|_, bucket| bucket.refilled(now) < bucket.policy.capacity as f64);
+    }
+}
+
+impl RateLimitBackend for MemoryBackend {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn acquire<'a>(&'a self, bucket: &'a str, policy: Policy) -> Pin<Box<dyn Future<Output = Result<Decision, String>> + Send + 'a>> {
+        Box::pin(async move { Ok(self.take(bucket, policy)) })
+    }
+}
+
+/// Buckets shared by every instance through Redis; the refill and take run
+/// atomically in a script using the Redis clock.
+pub struct RedisBackend {
+    pool: deadpool_redis::Pool,
+    script: redis::Script,
+}
+
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local refill = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) + tonumber(time[2]) / 1000000
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(state[1]) or capacity
+local ts = tonumber(state[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * refill)
+local allowed = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+  allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', tostring(now))
+redis.call('EXPIRE', KEYS[1], math.ceil(capacity / refill) + 1)
+return {allowed, tostring(tokens)}
+"#;
+
+impl RedisBackend {
+    pub fn new(pool: deadpool_redis::Pool) -> Self {
+        Self { pool, script: redis::Script::new(TOKEN_BUCKET_SCRIPT) }
+    }
+
+    async fn take(&self, bucket: &str, policy: Policy) -> Result<Decision, String> {
+        let mut conn = self.pool.get().await
+            .map_err(|e| format!("Failed to get Redis connection: {}", e))?;
+        let (allowed, tokens): (i64, String) = self.script
+            .key(format!("worpen:ratelimit:{}", bucket))
+            .arg(policy.capacity)
+            .arg(policy.refill_per_sec)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| format!("Redis rate limit error: {}", e))?;
+        let tokens: f64 = tokens.parse().map_err(|_| format!("Bad token count from Redis: {}", tokens))?;
+        Ok(policy.decide(allowed == 1, tokens))
+    }
+}
+
+impl RateLimitBackend for RedisBackend {
+    fn name(&self) -> &str {
+        "redis"
+    }
+
+    fn acquire<'a>(&'a self, bucket: &'a str, policy: Policy) -> Pin<Box<dyn Future<Output = Result<Decision, String>> + Send + 'a>> {
+        Box::pin(self.take(bucket, policy))
+    }
+}
+
+/// Who a route's limit is counted against
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientKey {
+    /// The caller's IP address (the default)
+    Ip,
+    /// The authenticated caller (`auth.sub`), e.g. the owner of an API key; falls back to the IP
+    ApiKey,
+    /// A template over `request.headers`, `request.params`, `request.query` and `auth`,
+    /// e.g. `{{request.headers.x-tenant-id}}`; falls back to the IP when it renders empty
+    Template(String),
+}
+
+/// What a client key can be derived from
+pub struct ClientInfo<'a> {
+    pub ip: Option<String>,
+    pub identity: Option<&'a Identity>,
+    pub headers: &'a HashMap<String, String>,
+    pub path_params: &'a HashMap<String, String>,
+    pub query_params: &'a HashMap<String, String>,
+}
+
+impl ClientKey {
+    /// Parse a route's `rate_limit_key`: `ip`, `api_key` or a template
+    pub fn parse(spec: Option<&str>) -> Result<Self, String> {
+        match spec.map(str::trim) {
+            None | Some("") | Some("ip") => Ok(Self::Ip),
+            Some("api_key") => Ok(Self::ApiKey),
+            Some(template) if template.contains("{{") || template.contains("${") => Ok(Self::Template(template.to_string())),
+            Some(other) => Err(format!("Expected 'ip', 'api_key' or a template, got '{}'", other)),
+        }
+    }
+
+    /// Whether the key names the authenticated caller, so it is only known after authentication
+    pub fn depends_on_identity(&self) -> bool {
+        match self {
+            Self::Ip => false,
+            Self::ApiKey => true,
+            Self::Template(template) => Template::parse(template).variable_roots().contains(&"auth"),
+        }
+    }
+
+    pub fn resolve(&self, client: &ClientInfo) -> String {
+        let ip = || format!("ip:{}", client.ip.as_deref().unwrap_or("unknown"));
+        match self {
+            Self::Ip => ip(),
+            Self::ApiKey => client.identity.map(|id| format!("sub:{}", id.sub)).unwrap_or_else(ip),
+            Self::Template(template) => {
+                let key = resolve_string(template, &Self::template_context(client));
+                if key.is_empty() || key.contains("{{") || key.contains("${") {
+                    ip()
+                } else {
+                    format!("key:{}", key)
+                }
+            }
+        }
+    }
+
+    fn template_context(client: &ClientInfo) -> DynamicRouteExecutionContext {
+        let mut variables = HashMap::new();
+        variables.insert("request".to_string(), serde_json::json!({
+            "headers": client.headers,
+            "params": client.path_params,
+            "query": client.query_params,
+            "ip": client.ip,
+        }));
+        if let Some(identity) = client.identity {
+            variables.insert("auth".to_string(), identity.to_value());
+        }
+        DynamicRouteExecutionContext {
+            route_id: String::new(),
+            variables,
+            request_payload: None,
+            path_params: HashMap::new(),
+            query_params: HashMap::new(),
+            functions: HashMap::new(),
+            loop_control: LoopControl::default(),
+            error_context: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RateLimitStats {
+    pub allowed: u64,
+    pub limited: u64,
+    /// Backend failures; those requests were let through
+    pub errors: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    allowed: AtomicU64,
+    limited: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// Applies route limits against a backend and keeps per-route counts
+pub struct RateLimiter {
+    backend: Box<dyn RateLimitBackend>,
+    stats: DashMap<String, Counters>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(Box::new(MemoryBackend::default()))
+    }
+}
+
+impl RateLimiter {
+    pub fn new(backend: Box<dyn RateLimitBackend>) -> Self {
+        Self { backend, stats: DashMap::new() }
+    }
+
+    pub fn with_redis(pool: deadpool_redis::Pool) -> Self {
+        Self::new(Box::new(RedisBackend::new(pool)))
+    }
+
+    pub fn backend_name(&self) -> &str {
+        self.backend.name()
+    }
+
+    /// Count a request from `client_key` against the route's per-minute limit.
+    /// If the backend fails the request is let through, so an outage doesn't take routes down.
+    pub async fn check(&self, route_id: &str, client_key: &str, per_minute: u32) -> Decision {
+        let policy = Policy::per_minute(per_minute);
+        let bucket = format!("{}:{}", route_id, client_key);
+        let result = self.backend.acquire(&bucket, policy).await;
+
+        // The entry is only taken after the await so no map shard stays locked across it
+        let counters = self.stats.entry(route_id.to_string()).or_default();
+        match result {
+            Ok(decision) => {
+                let counter = if decision.allowed { &counters.allowed } else { &counters.limited };
+                counter.fetch_add(1, Ordering::Relaxed);
+                decision
+            }
+            Err(e) => {
+                eprintln!("[WARN] Rate limiter ({}) failed for route {}: {}", self.backend.name(), route_id, e);
+                counters.errors.fetch_add(1, Ordering::Relaxed);
+                policy.decide(true, policy.capacity as f64)
+            }
+        }
+    }
+
+    /// Counts per route id
+    pub fn stats(&self) -> HashMap<String, RateLimitStats> {
+        self.stats.iter()
+            .map(|entry| {
+                let c = entry.value();
+                (entry.key().clone(), RateLimitStats {
+                    allowed: c.allowed.load(Ordering::Relaxed),
+                    limited: c.limited.load(Ordering::Relaxed),
+                    errors: c.errors.load(Ordering::Relaxed),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[tokio::test]
+    async fn test_bucket_drains_then_refuses() {
+        let limiter = RateLimiter::default();
+        for remaining in (0..3).rev() {
+            let decision = limiter.check("r1", "ip:1.2.3.4", 3).await;
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+
+        let refused = limiter.check("r1", "ip:1.2.3.4", 3).await;
+        assert!(!refused.allowed);
+        // one token every 20s at 3/min
+        assert_eq!(refused.retry_after_secs, Some(20));
+        assert!(refused.headers().iter().any(|(name, value)| *name == "Retry-After" && value == "20"));
+
+        // other clients and routes have their own buckets
+        assert!(limiter.check("r1", "ip:5.6.7.8", 3).await.allowed);
+        assert!(limiter.check("r2", "ip:1.2.3.4", 3).await.allowed);
+
+        let stats = limiter.stats();
+        assert_eq!(stats["r1"], RateLimitStats { allowed: 4, limited: 1, errors: 0 });
+    }
+
+    #[test]
+    fn test_refill() {
+        let backend = MemoryBackend::default();
+        let policy = Policy::per_minute(60);
+        backend.buckets.insert("b".to_string(), Bucket {
+            tokens: 0.0,
+            updated: Instant::now() - std::time::Duration::from_secs(5),
+            policy,
+        });
+        let decision = backend.take("b", policy);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 4);
+    }
+
+    #[test]
+    fn test_client_keys() {
+        let headers: HashMap<String, String> = [("x-tenant".to_string(), "acme".to_string())].into();
+        let empty = HashMap::new();
+        let identity = Identity {
+            sub: "bot".to_string(),
+            provider: "api_key".to_string(),
+            scopes: vec![],
+            roles: vec![],
+            claims: Value::Null,
+        };
+        let client = ClientInfo {
+            ip: Some("10.0.0.1".to_string()),
+            identity: Some(&identity),
+            headers: &headers,
+            path_params: &empty,
+            query_params: &empty,
+        };
+
+        assert_eq!(ClientKey::parse(None).unwrap().resolve(&client), "ip:10.0.0.1");
+        assert_eq!(ClientKey::parse(Some("api_key")).unwrap().resolve(&client), "sub:bot");
+        assert_eq!(ClientKey::parse(Some("{{request.headers.x-tenant}}")).unwrap().resolve(&client), "key:acme");
+        assert_eq!(ClientKey::parse(Some("{{request.headers.missing}}")).unwrap().resolve(&client), "ip:10.0.0.1");
+        assert!(ClientKey::parse(Some("tenant")).is_err());
+    }
+}
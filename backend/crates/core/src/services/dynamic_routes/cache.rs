@@ -1,7 +1,12 @@
+use std::collections::HashMap;
 use std::sync::Arc;
-use proto::models::LogicOperation;
-use crate::vm::instructions::OptimizedOperation;
+use proto::models::{FunctionDef, LogicOperation, RouteDefinition};
+use serde::{Deserialize, Serialize};
+use crate::vm::instructions::Program;
 use crate::compiler::symbol_table::SymbolTable;
+use crate::budget::Limits;
+use super::params::RequestValidator;
+use super::response::ResponseValidator;
 
 /// A cached execution plan for a dynamic route.
 /// This structure holds a pre-processed, cheap-to-clone version of the route's logic.
@@ -16,9 +21,15 @@ pub struct ExecutionPlan {
     /// Route version for invalidation checks (optional, good practice).
     pub version: String,
     /// Compiled bytecode for VM execution.
-    pub bytecode: Option<Arc<Vec<OptimizedOperation>>>,
+    pub bytecode: Option<Arc<Program>>,
     /// Symbol table for variable resolution.
     pub symbol_table: Option<Arc<SymbolTable>>,
+    /// Parameter declarations and request schema checked before execution.
+    pub validator: Arc<RequestValidator>,
+    /// Response schema checked against the route's output.
+    pub response_validator: Arc<ResponseValidator>,
+    /// Execution budget: global limits tightened by the route's own.
+    pub limits: Limits,
 }
 
 impl ExecutionPlan {
@@ -30,6 +41,67 @@ impl ExecutionPlan {
             version,
             bytecode: None,
             symbol_table: None,
+            validator: Arc::new(RequestValidator::default()),
+            response_validator: Arc::new(ResponseValidator::default()),
+            limits: Limits::global(),
         }
     }
 }
+
+/// Bumped whenever the layout of stored plans or the bytecode changes, and whenever
+/// lowering or compile-time validation does: a plan stored by an older build may hold
+/// code, or accept a route, that this one would compile differently or reject.
+pub const PLAN_FORMAT: u32 = 5;
+
+/// A compiled execution plan as stored next to its route (`routes/<id>.plan`).
+/// It is used on startup instead of recompiling the route, as long as it was
+/// written by the same compiler from the same route and global functions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistedPlan {
+    pub compiler_version: String,
+    pub source_hash: String,
+    /// The route logic after optimization, as the bytecode was compiled from it
+    pub logic: Arc<Vec<LogicOperation>>,
+    pub bytecode: Option<Arc<Program>>,
+    pub symbol_table: Option<Arc<SymbolTable>>,
+}
+
+impl PersistedPlan {
+    pub fn new(plan: &ExecutionPlan, source_hash: String) -> Self {
+        Self {
+            compiler_version: compiler_version(),
+            source_hash,
+            logic: plan.logic.clone(),
+            bytecode: plan.bytecode.clone(),
+            symbol_table: plan.symbol_table.clone(),
+        }
+    }
+
+    /// True when the plan was compiled by this build from the given sources
+    pub fn is_current(&self, source_hash: &str) -> bool {
+        self.compiler_version == compiler_version() && self.source_hash == source_hash
+    }
+}
+
+/// The compiler a plan was written by: the crate version and the plan format
+pub fn compiler_version() -> String {
+    format!("{}+plan{}", env!("CARGO_PKG_VERSION"), PLAN_FORMAT)
+}
+
+/// Hash of everything a route's plan is compiled from: the route itself and
+/// the global functions it may call or inline
+pub fn source_hash(route: &RouteDefinition, functions: &HashMap<String, FunctionDef>) -> String {
+    let mut names: Vec<&String> = functions.keys().collect();
+    names.sort();
+    let functions: Vec<&FunctionDef> = names.into_iter().map(|name| &functions[name]).collect();
+    let source = serde_json::json!({ "route": route, "functions": functions });
+    fingerprint(&source.to_string())
+}
+
+/// FNV-1a hash of some text, the same across builds and platforms
+pub fn fingerprint(source: &str) -> String {
+    let hash = source.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
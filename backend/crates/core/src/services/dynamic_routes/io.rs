@@ -14,45 +14,67 @@ pub async fn handle_http_request(
 ) -> Result<Value, String> {
     steps.push(format!("HTTP {} request to: {}", method, url));
     
-    // Resolve URL with variables
+    // Resolve URL, headers and body with variables
     let resolved_url = resolve_string(url, context);
+    let resolved_headers: Vec<(String, String)> = headers.iter()
+        .flatten()
+        .map(|(key, value)| (key.clone(), resolve_string(value, context)))
+        .collect();
+    let body_string = match body {
+        Some(b) => Some(http_body_string(resolve_variables(b, context))?),
+        None => None,
+    };
+    
+    let result = send_http_request(&resolved_url, method, &resolved_headers, body_string, *timeout_ms).await?;
     
+    context.variables.insert("http_response".to_string(), result.clone());
+    Ok(result)
+}
+
+/// Serialize a resolved request body; strings are sent as-is
+pub fn http_body_string(resolved_body: Value) -> Result<String, String> {
+    match resolved_body {
+        Value::String(s) => Ok(s),
+        other => serde_json::to_string(&other).map_err(|e| format!("Failed to serialize body: {}", e)),
+    }
+}
+
+/// Send an HTTP request with already-resolved inputs (shared by interpreter and VM)
+pub async fn send_http_request(
+    resolved_url: &str,
+    method: &str,
+    headers: &[(String, String)],
+    body: Option<String>,
+    timeout_ms: Option<u64>,
+) -> Result<Value, String> {
     // Create HTTP client
     let mut client_builder = reqwest::Client::builder();
     
     // Set timeout if specified
     if let Some(timeout) = timeout_ms {
-        client_builder = client_builder.timeout(std::time::Duration::from_millis(*timeout));
+        client_builder = client_builder.timeout(std::time::Duration::from_millis(timeout));
     }
     
     let client = client_builder.build().map_err(|e| format!("Failed to create HTTP client: {}", e))?;
     
     // Build request
     let mut request_builder = match method {
-        "GET" => client.get(&resolved_url),
-        "POST" => client.post(&resolved_url),
-        "PUT" => client.put(&resolved_url),
-        "DELETE" => client.delete(&resolved_url),
-        "PATCH" => client.patch(&resolved_url),
-        "HEAD" => client.head(&resolved_url),
+        "GET" => client.get(resolved_url),
+        "POST" => client.post(resolved_url),
+        "PUT" => client.put(resolved_url),
+        "DELETE" => client.delete(resolved_url),
+        "PATCH" => client.patch(resolved_url),
+        "HEAD" => client.head(resolved_url),
         _ => return Err(format!("Unsupported HTTP method: {}", method)),
     };
     
     // Add headers if present
-    if let Some(hdrs) = headers {
-        for (key, value) in hdrs {
-            let resolved_value = resolve_string(value, context);
-            request_builder = request_builder.header(key, resolved_value);
-        }
+    for (key, value) in headers {
+        request_builder = request_builder.header(key, value);
     }
     
     // Add body if present
-    if let Some(b) = body {
-        let resolved_body = resolve_variables(b, context);
-        let body_string = match resolved_body {
-            Value::String(s) => s,
-            _ => serde_json::to_string(&resolved_body).map_err(|e| format!("Failed to serialize body: {}", e))?,
-        };
+    if let Some(body_string) = body {
         request_builder = request_builder.body(body_string);
     }
     
@@ -67,32 +89,38 @@ pub async fn handle_http_request(
         Err(_) => Value::String(response_text),
     };
     
-    let result = serde_json::json!({
+    Ok(serde_json::json!({
         "status": status_code,
         "body": response_body,
         "url": resolved_url,
         "method": method,
-    });
-    
-    context.variables.insert("http_response".to_string(), result.clone());
-    Ok(result)
+    }))
 }
 
-pub fn handle_query_db(query: &str, _params: &[Value], context: &mut DynamicRouteExecutionContext, steps: &mut Vec<String>) -> Value {
+/// The interpreter has no database connection; `query_db` only runs on the VM
+pub fn handle_query_db(query: &str, steps: &mut Vec<String>) -> Result<Value, String> {
     steps.push(format!("Execute DB query: {}", query));
-    // TODO: Implement real database connection
-    let result = serde_json::json!({
-        "rows": [],
-        "count": 0,
+    Err("Database pool not available for query_db".to_string())
+}
+
+/// Result shape of `query_db`: the rows a statement yielded and how many
+pub fn query_db_result(query: &str, rows: Value) -> Value {
+    let count = rows.as_array().map_or(0, Vec::len);
+    serde_json::json!({
+        "rows": rows,
+        "count": count,
         "query": query,
-    });
-    context.variables.insert("db_result".to_string(), result.clone());
-    result
+    })
 }
 
 pub fn handle_log(level: &str, message: &str, context: &DynamicRouteExecutionContext, steps: &mut Vec<String>) {
     let resolved_msg = resolve_string(message, context);
     steps.push(format!("[{}] {}", level.to_uppercase(), resolved_msg));
+    emit_log(level, &resolved_msg);
+}
+
+/// Print a resolved log line (shared by interpreter and VM)
+pub fn emit_log(level: &str, resolved_msg: &str) {
     println!("[{}] {}", level.to_uppercase(), resolved_msg);
 }
 
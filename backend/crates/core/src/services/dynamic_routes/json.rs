@@ -8,7 +8,11 @@ pub fn handle_json_op(operation: &str, input: &str, args: &[Value], context: &Dy
     } else {
         serde_json::from_str(input).unwrap_or(Value::Null)
     };
-    
+    compute_json_op(operation, input_val, args)
+}
+
+/// Apply a JSON operation to a resolved input value (shared by interpreter and VM)
+pub fn compute_json_op(operation: &str, input_val: Value, args: &[Value]) -> Value {
     match operation {
         "stringify" => Value::String(serde_json::to_string(&input_val).unwrap_or_default()),
         "parse" => {
@@ -8,6 +8,11 @@ pub fn handle_math_op(operation: &str, args: &[Value], context: &DynamicRouteExe
         .map(|v| resolve_variables(v, context))
         .collect();
     
+    compute_math_op(operation, &resolved_args)
+}
+
+/// Apply a math operation to already-resolved arguments (shared by interpreter and VM)
+pub fn compute_math_op(operation: &str, resolved_args: &[Value]) -> Value {
     let numbers: Vec<f64> = resolved_args.iter()
         .filter_map(|v| match v {
             Value::Number(n) => n.as_f64(),
@@ -17,7 +22,7 @@ pub fn handle_math_op(operation: &str, args: &[Value], context: &DynamicRouteExe
         .collect();
     
     match operation {
-        "sum" => Value::Number(serde_json::Number::from_f64(numbers.iter().sum()).unwrap()),
+        "sum" | "add" => Value::Number(serde_json::Number::from_f64(numbers.iter().sum()).unwrap()),
         "avg" => {
             let avg = numbers.iter().sum::<f64>() / numbers.len() as f64;
             Value::Number(serde_json::Number::from_f64(avg).unwrap())
@@ -5,8 +5,14 @@ pub mod string;
 pub mod date;
 pub mod json;
 pub mod io;
+pub mod sql;
+pub mod redis;
+pub mod router;
+pub mod params;
+pub mod response;
 pub mod execution;
 pub mod service;
 
 pub use execution::execute_logic_extended;
 pub use service::DynamicRouteService;
+pub use router::{DynamicRouter, RouteLookup, RouteMatch};
@@ -0,0 +1,370 @@
+use crate::compiler::diagnostics::Diagnostic;
+use crate::validation::{compile_schema, schema_errors, ValidationError, ValidationResult};
+use jsonschema::JSONSchema;
+use proto::models::{RouteDefinition, RouteParameter};
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A route's parameter declarations and request schema, compiled once and
+/// checked against every incoming request before any logic runs.
+#[derive(Default)]
+pub struct RequestValidator {
+    params: Vec<CompiledParam>,
+    schema: Option<JSONSchema>,
+}
+
+struct CompiledParam {
+    param: RouteParameter,
+    source: ParamSource,
+    pattern: Option<Regex>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ParamSource {
+    Path,
+    Query,
+    Body,
+}
+
+/// Request inputs after coercion and defaults
+#[derive(Debug, Clone, Default)]
+pub struct ValidatedRequest {
+    /// All path parameters; declared ones coerced to their `data_type`
+    pub path: Map<String, Value>,
+    /// All query parameters; declared ones coerced to their `data_type`
+    pub query: Map<String, Value>,
+    /// The body with defaults of declared body fields filled in
+    pub body: Value,
+    /// Every declared parameter that has a value, by name
+    pub params: Map<String, Value>,
+}
+
+impl RequestValidator {
+    /// Compile a route's declarations; each problem is reported against its field
+    pub fn compile(route: &RouteDefinition) -> Result<Self, Vec<Diagnostic>> {
+        let mut diagnostics = Vec::new();
+        let mut params = Vec::new();
+
+        for (i, param) in route.parameters.iter().enumerate() {
+            let source = match param.param_type.as_str() {
+                "path" => ParamSource::Path,
+                "query" => ParamSource::Query,
+                "body" => ParamSource::Body,
+                other => {
+                    diagnostics.push(Diagnostic::error(
+                        format!("parameters[{}].param_type", i),
+                        format!("Unknown parameter type '{}'", other),
+                    ));
+                    continue;
+                }
+            };
+            if !matches!(param.data_type.as_str(), "string" | "number" | "boolean" | "object" | "array") {
+                diagnostics.push(Diagnostic::error(
+                    format!("parameters[{}].data_type", i),
+                    format!("Unknown data type '{}'", param.data_type),
+                ));
+            }
+            let pattern = match &param.validation {
+                Some(pattern) => match Regex::new(pattern) {
+                    Ok(regex) => Some(regex),
+                    Err(e) => {
+                        diagnostics.push(Diagnostic::error(
+                            format!("parameters[{}].validation", i),
+                            format!("Invalid regex '{}': {}", pattern, e),
+                        ));
+                        None
+                    }
+                },
+                None => None,
+            };
+            params.push(CompiledParam { param: param.clone(), source, pattern });
+        }
+
+        let schema = match &route.request_schema {
+            Some(schema) => match compile_schema(schema) {
+                Ok(compiled) => Some(compiled),
+                Err(e) => {
+                    diagnostics.push(Diagnostic::error("request_schema", e));
+                    None
+                }
+            },
+            None => None,
+        };
+
+        if diagnostics.is_empty() {
+            Ok(Self { params, schema })
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    /// Coerce, default and check the request, collecting every field error
+    pub fn validate(
+        &self,
+        path_params: &HashMap<String, String>,
+        query_params: &HashMap<String, String>,
+        body: Value,
+    ) -> Result<ValidatedRequest, ValidationResult> {
+        let mut request = ValidatedRequest {
+            path: strings_to_map(path_params),
+            query: strings_to_map(query_params),
+            body,
+            params: Map::new(),
+        };
+        let mut errors = Vec::new();
+
+        for compiled in &self.params {
+            let param = &compiled.param;
+            let field = format!("{}.{}", param.param_type, param.name);
+            let error = |message: String| ValidationError {
+                path: field.clone(),
+                message,
+                schema_path: String::new(),
+            };
+
+            let provided = match compiled.source {
+                ParamSource::Path => path_params.get(&param.name).map(|raw| coerce_text(raw, &param.data_type)),
+                ParamSource::Query => query_params.get(&param.name).map(|raw| coerce_text(raw, &param.data_type)),
+                ParamSource::Body => request.body.get(&param.name).filter(|v| !v.is_null()).map(|value| {
+                    check_type(value, &param.data_type).map(|_| value.clone())
+                }),
+            };
+
+            let value = match provided {
+                Some(Ok(value)) => {
+                    if let Some(pattern) = &compiled.pattern {
+                        let text = match &value {
+                            Value::String(s) => s.clone(),
+                            other => other.to_string(),
+                        };
+                        if !pattern.is_match(&text) {
+                            errors.push(error(format!("'{}' does not match pattern '{}'", text, pattern.as_str())));
+                            continue;
+                        }
+                    }
+                    value
+                }
+                Some(Err(message)) => {
+                    errors.push(error(message));
+                    continue;
+                }
+                None => match &param.default_value {
+                    Some(default) => default.clone(),
+                    None if param.required => {
+                        errors.push(error(format!("Missing required {} parameter '{}'", param.param_type, param.name)));
+                        continue;
+                    }
+                    None => continue,
+                },
+            };
+
+            match compiled.source {
+                ParamSource::Path => {
+                    request.path.insert(param.name.clone(), value.clone());
+                }
+                ParamSource::Query => {
+                    request.query.insert(param.name.clone(), value.clone());
+                }
+                ParamSource::Body => {
+                    if request.body.is_null() {
+                        request.body = Value::Object(Map::new());
+                    }
+                    if let Some(body) = request.body.as_object_mut() {
+                        body.insert(param.name.clone(), value.clone());
+                    }
+                }
+            }
+            request.params.insert(param.name.clone(), value);
+        }
+
+        if let Some(schema) = &self.schema {
+            errors.extend(schema_errors(schema, &request.body).into_iter().map(|mut e| {
+                // JSON pointer "/user/email" → "body.user.email"
+                e.path = format!("body{}", e.path.replace('/', "."));
+                e
+            }));
+        }
+
+        if errors.is_empty() {
+            Ok(request)
+        } else {
+            Err(ValidationResult::from_errors(errors))
+        }
+    }
+}
+
+impl fmt::Debug for RequestValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestValidator")
+            .field("params", &self.params.iter().map(|p| &p.param.name).collect::<Vec<_>>())
+            .field("has_schema", &self.schema.is_some())
+            .finish()
+    }
+}
+
+fn strings_to_map(params: &HashMap<String, String>) -> Map<String, Value> {
+    params.iter().map(|(k, v)| (k.clone(), Value::String(v.clone()))).collect()
+}
+
+/// Turn a path or query string into the declared type
+fn coerce_text(raw: &str, data_type: &str) -> Result<Value, String> {
+    match data_type {
+        "number" => {
+            if let Ok(n) = raw.parse::<i64>() {
+                return Ok(Value::from(n));
+            }
+            raw.parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number)
+                .ok_or_else(|| format!("Expected a number, got '{}'", raw))
+        }
+        "boolean" => match raw {
+            "true" | "1" => Ok(Value::Bool(true)),
+            "false" | "0" => Ok(Value::Bool(false)),
+            _ => Err(format!("Expected a boolean, got '{}'", raw)),
+        },
+        "object" | "array" => {
+            let value: Value = serde_json::from_str(raw)
+                .map_err(|_| format!("Expected JSON {}, got '{}'", data_type, raw))?;
+            check_type(&value, data_type)?;
+            Ok(value)
+        }
+        _ => Ok(Value::String(raw.to_string())),
+    }
+}
+
+/// Check that a JSON body value already has the declared type
+fn check_type(value: &Value, data_type: &str) -> Result<(), String> {
+    let matches = match data_type {
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        _ => true,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(format!("Expected {}, got {}", data_type, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::models::HttpMethod;
+    use serde_json::json;
+
+    fn param(name: &str, param_type: &str, data_type: &str, required: bool) -> RouteParameter {
+        RouteParameter {
+            name: name.to_string(),
+            param_type: param_type.to_string(),
+            data_type: data_type.to_string(),
+            required,
+            default_value: None,
+            validation: None,
+        }
+    }
+
+    fn route(parameters: Vec<RouteParameter>, request_schema: Option<Value>) -> RouteDefinition {
+        RouteDefinition {
+            id: "r".to_string(),
+            name: "r".to_string(),
+            description: String::new(),
+            path: "/items/:id".to_string(),
+            method: HttpMethod::POST,
+            route_type: Default::default(),
+            logic: vec![],
+            ws_hooks: None,
+            parameters,
+            request_schema,
+            response_schema: None,
+            response_schema_policy: Default::default(),
+            auth_required: false,
+            required_scopes: vec![],
+            required_roles: vec![],
+            rate_limit: None,
+            rate_limit_key: None,
+            limits: None,
+            optimize: true,
+            enabled: true,
+            version: "1.0.0".to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            created_by: String::new(),
+        }
+    }
+
+    fn strings(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_coerces_and_defaults() {
+        let mut limit = param("limit", "query", "number", false);
+        limit.default_value = Some(json!(20));
+        let mut id = param("id", "path", "number", true);
+        id.validation = Some("^[0-9]+$".to_string());
+        let validator = RequestValidator::compile(&route(
+            vec![id, limit, param("active", "query", "boolean", false)],
+            None,
+        )).unwrap();
+
+        let request = validator
+            .validate(&strings(&[("id", "42")]), &strings(&[("active", "true"), ("q", "x")]), json!({}))
+            .unwrap();
+        assert_eq!(request.path["id"], json!(42));
+        assert_eq!(request.query["active"], json!(true));
+        assert_eq!(request.query["limit"], json!(20));
+        assert_eq!(request.query["q"], json!("x"));
+        assert_eq!(request.params.len(), 3);
+    }
+
+    #[test]
+    fn test_reports_every_error() {
+        let mut id = param("id", "path", "string", true);
+        id.validation = Some("^[0-9]+$".to_string());
+        let schema = json!({
+            "type": "object",
+            "properties": {"user": {"type": "object", "properties": {"age": {"type": "number"}}}},
+            "required": ["user"]
+        });
+        let validator = RequestValidator::compile(&route(
+            vec![
+                id,
+                param("limit", "query", "number", true),
+                param("page", "query", "number", false),
+                param("tags", "body", "array", true),
+            ],
+            Some(schema),
+        )).unwrap();
+
+        let result = validator
+            .validate(&strings(&[("id", "abc")]), &strings(&[("page", "two")]), json!({"user": {"age": "old"}}))
+            .unwrap_err();
+        assert!(!result.valid);
+        let paths: Vec<&str> = result.errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["path.id", "query.limit", "query.page", "body.tags", "body.user.age"]);
+    }
+
+    #[test]
+    fn test_compile_reports_bad_declarations() {
+        let mut bad_regex = param("id", "path", "string", true);
+        bad_regex.validation = Some("[a-z".to_string());
+        let diagnostics = RequestValidator::compile(&route(
+            vec![bad_regex, param("x", "header", "string", false), param("y", "query", "int", false)],
+            Some(json!({"type": 12})),
+        )).unwrap_err();
+        let paths: Vec<&str> = diagnostics.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, vec![
+            "parameters[0].validation",
+            "parameters[1].param_type",
+            "parameters[2].data_type",
+            "request_schema",
+        ]);
+    }
+}
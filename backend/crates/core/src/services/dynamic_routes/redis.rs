@@ -0,0 +1,365 @@
+//! The commands `redis_op` sends: how many arguments each takes, how arguments
+//! are written, and how replies are read back as typed JSON
+
+use serde_json::{json, Map, Value};
+
+/// Sends its `commands` in one round trip instead of running a command itself
+pub const PIPELINE: &str = "PIPELINE";
+
+/// How many arguments a command takes; after its key, or in all for commands
+/// whose keys may come from `args`
+#[derive(Debug, Clone, Copy)]
+enum Arity {
+    Exactly(usize),
+    Range(usize, usize),
+    AtLeast(usize),
+    /// An even number, at least two; named by what each pair is
+    Pairs(&'static str),
+}
+
+impl Arity {
+    fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exactly(n) => count == n,
+            Arity::Range(min, max) => (min..=max).contains(&count),
+            Arity::AtLeast(min) => count >= min,
+            Arity::Pairs(_) => count >= 2 && count.is_multiple_of(2),
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            Arity::Exactly(0) => "no arguments".to_string(),
+            Arity::Exactly(n) => format!("{} argument(s)", n),
+            Arity::Range(min, max) => format!("{} to {} arguments", min, max),
+            Arity::AtLeast(min) => format!("at least {} argument(s)", min),
+            Arity::Pairs(pair) => format!("{} pairs", pair),
+        }
+    }
+}
+
+/// How a command's reply becomes JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyKind {
+    /// Integers as numbers, strings as the JSON they hold or as text, arrays as arrays
+    Plain,
+    /// A 0/1 integer as a boolean
+    Flag,
+    /// Field/value pairs as an object
+    Hash,
+    /// Member/score pairs as `[{"member": ..., "score": ...}]`
+    Scored,
+}
+
+/// Whether the command needs `key`, its arity, and how its reply is read.
+/// Commands that don't need a key count it among their arguments when given.
+fn spec(command: &str) -> Option<(bool, Arity, ReplyKind)> {
+    use Arity::*;
+    use ReplyKind::*;
+    Some(match command {
+        "GET" | "TTL" | "INCR" | "DECR" | "SMEMBERS" => (true, Exactly(0), Plain),
+        "SET" | "HGET" => (true, Exactly(1), Plain),
+        // Further keys to delete or count
+        "DEL" | "EXISTS" => (true, AtLeast(0), Plain),
+        "EXPIRE" => (true, Range(0, 1), Flag),
+        "MGET" => (false, AtLeast(1), Plain),
+        "MSET" => (false, Pairs("key/value"), Plain),
+        "HSET" => (true, Pairs("field/value"), Plain),
+        "HGETALL" => (true, Exactly(0), Hash),
+        "HDEL" | "LPUSH" | "RPUSH" | "SADD" => (true, AtLeast(1), Plain),
+        "LPOP" => (true, Range(0, 1), Plain),
+        "LRANGE" => (true, Exactly(2), Plain),
+        "SISMEMBER" => (true, Exactly(1), Flag),
+        "ZADD" => (true, Pairs("score/member"), Plain),
+        // start, stop and an optional WITHSCORES
+        "ZRANGE" | "ZREVRANGE" => (true, Range(2, 3), Plain),
+        _ => return None,
+    })
+}
+
+/// Commands whose `ttl_seconds` expires the key once they have written it;
+/// SET and EXPIRE take it as part of the command itself
+pub fn takes_ttl(command: &str) -> bool {
+    matches!(command, "SET" | "EXPIRE" | "INCR" | "DECR" | "HSET" | "LPUSH" | "RPUSH" | "SADD" | "ZADD")
+}
+
+/// Whether a single argument may be an object standing for the command's pairs:
+/// HSET's fields, or MSET's keys when none is given as `key`
+fn takes_object(command: &str, key: &str) -> bool {
+    command == "HSET" || (command == "MSET" && key.is_empty())
+}
+
+/// A string whose value is only known when the route runs
+fn is_placeholder(value: &Value) -> bool {
+    matches!(value, Value::String(s) if s.contains("{{") || s.contains("${"))
+}
+
+/// Check a command's key and arguments; placeholders pass for any value.
+/// `command` is upper case.
+pub fn check(command: &str, key: &str, args: &[Value], ttl_seconds: Option<u64>) -> Result<(), String> {
+    if command == PIPELINE {
+        return Err("PIPELINE can't be nested".to_string());
+    }
+    let (needs_key, arity, _) = spec(command).ok_or_else(|| format!("Unsupported Redis command: {}", command))?;
+    if needs_key && key.is_empty() {
+        return Err(format!("{} needs a key", command));
+    }
+    let count = if needs_key { args.len() } else { args.len() + usize::from(!key.is_empty()) };
+    let single_object = takes_object(command, key) && args.len() == 1 && (args[0].is_object() || is_placeholder(&args[0]));
+    if !arity.accepts(count) && !single_object {
+        let after_key = if needs_key { " after the key" } else { "" };
+        return Err(format!("{} takes {}{}, got {}", command, arity.describe(), after_key, count));
+    }
+    match command {
+        "EXPIRE" if ttl_seconds.is_some() == (count == 1) => {
+            Err("EXPIRE takes its seconds from either ttl_seconds or one argument".to_string())
+        },
+        "ZRANGE" | "ZREVRANGE" if count == 3 && !is_placeholder(&args[2]) && !is_withscores(&args[2]) => {
+            Err(format!("{}'s third argument can only be WITHSCORES", command))
+        },
+        _ => Ok(()),
+    }
+}
+
+fn is_withscores(value: &Value) -> bool {
+    matches!(value, Value::String(s) if s.eq_ignore_ascii_case("WITHSCORES"))
+}
+
+/// Write an argument the way Redis stores it: strings as they are, anything
+/// else as its JSON text, so it reads back with its type
+pub fn encode(value: Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s),
+        Value::Null => Err("Redis arguments can't be null".to_string()),
+        other => Ok(other.to_string()),
+    }
+}
+
+/// A command ready to send, with its arguments resolved and written out
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedisRequest {
+    pub command: String,
+    pub args: Vec<String>,
+    /// Seconds to expire the key in after the command
+    pub expire: Option<u64>,
+    pub reply: ReplyKind,
+}
+
+impl RedisRequest {
+    /// Build a request from resolved arguments. `command` is upper case.
+    pub fn new(command: &str, key: &str, args: Vec<Value>, ttl_seconds: Option<u64>) -> Result<Self, String> {
+        let args = match args.as_slice() {
+            [Value::Object(object)] if takes_object(command, key) => object.iter()
+                .flat_map(|(field, value)| [Value::String(field.clone()), value.clone()])
+                .collect(),
+            [other] if takes_object(command, key) => {
+                return Err(format!("{} takes pairs or an object, got {}", command, other));
+            },
+            _ => args,
+        };
+        check(command, key, &args, ttl_seconds)?;
+
+        let reply = match (command, args.get(2)) {
+            ("ZRANGE" | "ZREVRANGE", Some(arg)) if is_withscores(arg) => ReplyKind::Scored,
+            _ => spec(command).map(|(_, _, reply)| reply).unwrap_or(ReplyKind::Plain),
+        };
+        let mut written = Vec::with_capacity(args.len() + 2);
+        if !key.is_empty() {
+            written.push(key.to_string());
+        }
+        for arg in args {
+            written.push(encode(arg)?);
+        }
+        let mut expire = None;
+        match (command, ttl_seconds) {
+            ("SET", Some(ttl)) => written.extend(["EX".to_string(), ttl.to_string()]),
+            ("EXPIRE", Some(ttl)) => written.push(ttl.to_string()),
+            (command, Some(ttl)) if takes_ttl(command) => expire = Some(ttl),
+            _ => {},
+        }
+        Ok(Self { command: command.to_string(), args: written, expire, reply })
+    }
+
+    /// Send requests in one round trip and decode each reply
+    pub async fn send_all<C: redis::aio::ConnectionLike>(conn: &mut C, requests: &[RedisRequest]) -> Result<Vec<Value>, String> {
+        let mut pipe = redis::pipe();
+        for request in requests {
+            pipe.cmd(&request.command).arg(request.args.as_slice());
+            if let (Some(ttl), Some(key)) = (request.expire, request.args.first()) {
+                pipe.cmd("EXPIRE").arg(key).arg(ttl).ignore();
+            }
+        }
+        let label = match requests {
+            [request] => request.command.as_str(),
+            _ => PIPELINE,
+        };
+        let replies: Vec<redis::Value> = pipe.query_async(conn).await
+            .map_err(|e| format!("Redis {} error: {}", label, e))?;
+        Ok(requests.iter().zip(replies).map(|(request, reply)| request.decode(reply)).collect())
+    }
+
+    /// Turn the command's reply into JSON
+    pub fn decode(&self, reply: redis::Value) -> Value {
+        match self.reply {
+            ReplyKind::Plain => plain(reply),
+            ReplyKind::Flag => match reply {
+                redis::Value::Int(n) => Value::Bool(n != 0),
+                other => plain(other),
+            },
+            ReplyKind::Hash => Value::Object(pairs(reply).into_iter()
+                .map(|(field, value)| (text(&field), plain(value)))
+                .collect::<Map<String, Value>>()),
+            ReplyKind::Scored => Value::Array(pairs(reply).into_iter()
+                .map(|(member, score)| json!({"member": plain(member), "score": score_value(score)}))
+                .collect()),
+        }
+    }
+}
+
+/// Stored text reads back as the JSON it holds, or as the text itself
+fn stored(bytes: &[u8]) -> Value {
+    let text = String::from_utf8_lossy(bytes);
+    serde_json::from_str(&text).unwrap_or_else(|_| Value::String(text.into_owned()))
+}
+
+fn plain(reply: redis::Value) -> Value {
+    match reply {
+        redis::Value::Nil => Value::Null,
+        redis::Value::Int(n) => Value::from(n),
+        redis::Value::BulkString(bytes) => stored(&bytes),
+        redis::Value::Array(items) | redis::Value::Set(items) => Value::Array(items.into_iter().map(plain).collect()),
+        redis::Value::Map(entries) => Value::Object(entries.into_iter().map(|(k, v)| (text(&k), plain(v))).collect()),
+        redis::Value::Okay => Value::String("OK".to_string()),
+        redis::Value::SimpleString(s) => Value::String(s),
+        redis::Value::Double(f) => Value::from(f),
+        redis::Value::Boolean(b) => Value::Bool(b),
+        redis::Value::VerbatimString { text, .. } => Value::String(text),
+        redis::Value::Attribute { data, .. } => plain(*data),
+        redis::Value::Push { data, .. } => Value::Array(data.into_iter().map(plain).collect()),
+        other => Value::String(format!("{:?}", other)),
+    }
+}
+
+/// A reply used as an object key or member name
+fn text(reply: &redis::Value) -> String {
+    match reply {
+        redis::Value::BulkString(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        redis::Value::SimpleString(s) => s.clone(),
+        other => match plain(other.clone()) {
+            Value::String(s) => s,
+            value => value.to_string(),
+        },
+    }
+}
+
+/// Scores are sent as text; infinite ones stay text since JSON has no infinity
+fn score_value(reply: redis::Value) -> Value {
+    match reply {
+        redis::Value::BulkString(bytes) => {
+            let text = String::from_utf8_lossy(&bytes);
+            serde_json::from_str::<Value>(&text).ok().filter(Value::is_number)
+                .unwrap_or_else(|| Value::String(text.into_owned()))
+        },
+        other => plain(other),
+    }
+}
+
+/// Pairs from a flat array (RESP2), an array of two-item arrays, or a map (RESP3)
+fn pairs(reply: redis::Value) -> Vec<(redis::Value, redis::Value)> {
+    match reply {
+        redis::Value::Map(entries) => entries,
+        redis::Value::Array(items) if items.iter().all(|item| matches!(item, redis::Value::Array(pair) if pair.len() == 2)) && !items.is_empty() => {
+            items.into_iter().filter_map(|item| match item {
+                redis::Value::Array(pair) => {
+                    let mut pair = pair.into_iter();
+                    Some((pair.next()?, pair.next()?))
+                },
+                _ => None,
+            }).collect()
+        },
+        redis::Value::Array(items) => {
+            let mut items = items.into_iter();
+            let mut pairs = Vec::new();
+            while let (Some(first), Some(second)) = (items.next(), items.next()) {
+                pairs.push((first, second));
+            }
+            pairs
+        },
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bulk(text: &str) -> redis::Value {
+        redis::Value::BulkString(text.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_check_arity() {
+        assert!(check("GET", "k", &[], None).is_ok());
+        assert_eq!(check("GET", "", &[], None).unwrap_err(), "GET needs a key");
+        assert_eq!(check("SET", "k", &[], None).unwrap_err(), "SET takes 1 argument(s) after the key, got 0");
+        assert_eq!(check("FLUSHALL", "", &[], None).unwrap_err(), "Unsupported Redis command: FLUSHALL");
+        assert!(check("HSET", "k", &[json!("f"), json!(1)], None).is_ok());
+        assert!(check("HSET", "k", &[json!("{{fields}}")], None).is_ok());
+        assert!(check("HSET", "k", &[json!("f")], None).is_err());
+        assert!(check("ZADD", "k", &[json!(1), json!("a"), json!(2)], None).is_err());
+        assert!(check("MGET", "", &[json!("a"), json!("b")], None).is_ok());
+        assert_eq!(check("MGET", "", &[], None).unwrap_err(), "MGET takes at least 1 argument(s), got 0");
+        assert!(check("MSET", "a", &[json!(1)], None).is_ok());
+        assert!(check("MSET", "", &[json!({"a": 1})], None).is_ok());
+        assert!(check("EXPIRE", "k", &[], Some(10)).is_ok());
+        assert!(check("EXPIRE", "k", &[json!(10)], None).is_ok());
+        assert!(check("EXPIRE", "k", &[], None).is_err());
+        assert!(check("ZRANGE", "k", &[json!(0), json!(-1), json!("withscores")], None).is_ok());
+        assert!(check("ZRANGE", "k", &[json!(0), json!(-1), json!("LIMIT")], None).is_err());
+        assert!(check(PIPELINE, "", &[], None).is_err());
+    }
+
+    #[test]
+    fn test_requests_write_args_and_ttl() {
+        let set = RedisRequest::new("SET", "user:1", vec![json!({"name": "Ann"})], Some(60)).unwrap();
+        assert_eq!(set.args, vec!["user:1", "{\"name\":\"Ann\"}", "EX", "60"]);
+        assert_eq!(set.expire, None);
+
+        let hset = RedisRequest::new("HSET", "h", vec![json!({"a": 1, "b": "x"})], Some(30)).unwrap();
+        assert_eq!((hset.args, hset.expire), (vec!["h".to_string(), "a".into(), "1".into(), "b".into(), "x".into()], Some(30)));
+        assert!(RedisRequest::new("HSET", "h", vec![json!("not an object")], None).is_err());
+        assert!(RedisRequest::new("SET", "k", vec![Value::Null], None).is_err());
+
+        let expire = RedisRequest::new("EXPIRE", "k", vec![], Some(5)).unwrap();
+        assert_eq!((expire.args, expire.expire), (vec!["k".to_string(), "5".into()], None));
+
+        let mset = RedisRequest::new("MSET", "", vec![json!({"a": true})], None).unwrap();
+        assert_eq!(mset.args, vec!["a", "true"]);
+        assert_eq!(RedisRequest::new("ZREVRANGE", "z", vec![json!(0), json!(9), json!("WITHSCORES")], None).unwrap().reply, ReplyKind::Scored);
+    }
+
+    #[test]
+    fn test_replies_are_typed() {
+        let get = RedisRequest::new("GET", "k", vec![], None).unwrap();
+        assert_eq!(get.decode(bulk("{\"a\":[1,2]}")), json!({"a": [1, 2]}));
+        assert_eq!(get.decode(bulk("10")), json!(10));
+        assert_eq!(get.decode(bulk("plain text")), json!("plain text"));
+        assert_eq!(get.decode(redis::Value::Nil), Value::Null);
+
+        let member = RedisRequest::new("SISMEMBER", "s", vec![json!("a")], None).unwrap();
+        assert_eq!(member.decode(redis::Value::Int(1)), json!(true));
+
+        let hash = RedisRequest::new("HGETALL", "h", vec![], None).unwrap();
+        assert_eq!(hash.decode(redis::Value::Array(vec![bulk("name"), bulk("Ann"), bulk("age"), bulk("30")])), json!({"name": "Ann", "age": 30}));
+        assert_eq!(hash.decode(redis::Value::Map(vec![(bulk("n"), bulk("1"))])), json!({"n": 1}));
+
+        let scored = RedisRequest::new("ZRANGE", "z", vec![json!(0), json!(-1), json!("WITHSCORES")], None).unwrap();
+        let expected = json!([{"member": "ann", "score": 12.5}, {"member": "bob", "score": "inf"}]);
+        assert_eq!(scored.decode(redis::Value::Array(vec![bulk("ann"), bulk("12.5"), bulk("bob"), bulk("inf")])), expected);
+        let resp3 = redis::Value::Array(vec![
+            redis::Value::Array(vec![bulk("ann"), redis::Value::Double(12.5)]),
+            redis::Value::Array(vec![bulk("bob"), bulk("inf")]),
+        ]);
+        assert_eq!(scored.decode(resp3), expected);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::compiler::diagnostics::Diagnostic;
+use crate::validation::{compile_schema, schema_errors, ValidationError};
+use jsonschema::JSONSchema;
+use proto::models::RouteDefinition;
+use serde_json::Value;
+
+/// A route's `response_schema`, compiled once and checked against what its logic returns
+#[derive(Debug, Default)]
+pub struct ResponseValidator {
+    schema: Option<JSONSchema>,
+}
+
+impl ResponseValidator {
+    pub fn compile(route: &RouteDefinition) -> Result<Self, Diagnostic> {
+        let schema = match &route.response_schema {
+            Some(schema) => Some(compile_schema(schema).map_err(|e| Diagnostic::error("response_schema", e))?),
+            None => None,
+        };
+        Ok(Self { schema })
+    }
+
+    /// Every way the returned value breaks the schema; empty when the route has none
+    pub fn check(&self, result: &Value) -> Vec<ValidationError> {
+        match &self.schema {
+            Some(schema) => schema_errors(schema, response_value(result))
+                .into_iter()
+                .map(|mut e| {
+                    // JSON pointer "/items/0" → "response.items.0"
+                    e.path = format!("response{}", e.path.replace('/', "."));
+                    e
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+/// The value a client receives: `value` of an enhanced return
+/// (`{value, status, headers, raw}`), otherwise the result itself
+pub fn response_value(result: &Value) -> &Value {
+    match result.get("value") {
+        Some(value) if result.is_object() => value,
+        _ => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::models::HttpMethod;
+    use serde_json::json;
+
+    fn route(response_schema: Option<Value>) -> RouteDefinition {
+        RouteDefinition {
+            id: "r".to_string(),
+            name: "r".to_string(),
+            description: String::new(),
+            path: "/users".to_string(),
+            method: HttpMethod::GET,
+            route_type: Default::default(),
+            logic: vec![],
+            ws_hooks: None,
+            parameters: vec![],
+            request_schema: None,
+            response_schema,
+            response_schema_policy: Default::default(),
+            auth_required: false,
+            required_scopes: vec![],
+            required_roles: vec![],
+            rate_limit: None,
+            rate_limit_key: None,
+            limits: None,
+            optimize: true,
+            enabled: true,
+            version: "1.0.0".to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            created_by: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_checks_returned_value() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"users": {"type": "array", "items": {"type": "string"}}},
+            "required": ["users"]
+        });
+        let validator = ResponseValidator::compile(&route(Some(schema))).unwrap();
+
+        assert!(validator.check(&json!({"users": ["a"]})).is_empty());
+        assert!(validator.check(&json!({"value": {"users": []}, "status": 201})).is_empty());
+
+        let errors = validator.check(&json!({"value": {"users": ["a", 2]}, "status": 200}));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, "response.users.1");
+        assert_eq!(validator.check(&json!({"count": 1})).len(), 1);
+    }
+
+    #[test]
+    fn test_without_schema() {
+        let validator = ResponseValidator::compile(&route(None)).unwrap();
+        assert!(validator.check(&json!(42)).is_empty());
+        assert_eq!(
+            ResponseValidator::compile(&route(Some(json!({"type": 5})))).unwrap_err().path,
+            "response_schema"
+        );
+    }
+}
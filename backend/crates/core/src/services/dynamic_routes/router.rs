@@ -0,0 +1,451 @@
+//! Compiled path router for dynamic routes
+//!
+//! Route paths are split on `/` and inserted into a segment tree. Supported segments:
+//! - `users`          static segment, matched literally
+//! - `{id}`           named parameter, matches any single segment
+//! - `{id:int}`       typed parameter, only matches when the segment parses as the type
+//! - `*rest`          trailing wildcard, captures the remaining path (one or more segments)
+//!
+//! Lookup precedence is deterministic at every level: static beats typed param,
+//! typed param beats plain param, and param beats wildcard. Lookup backtracks, so
+//! `/users/{id}` is still reachable when a static sibling like `/users/me/...` fails deeper.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Type constraint for a `{name:type}` path parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ParamType {
+    Int,
+    Uint,
+    Float,
+    Bool,
+    Uuid,
+    Str,
+}
+
+impl ParamType {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "int" | "i64" => Ok(ParamType::Int),
+            "uint" | "u64" => Ok(ParamType::Uint),
+            "float" | "number" | "f64" => Ok(ParamType::Float),
+            "bool" => Ok(ParamType::Bool),
+            "uuid" => Ok(ParamType::Uuid),
+            "str" | "string" => Ok(ParamType::Str),
+            other => Err(format!("Unknown path parameter type '{}'", other)),
+        }
+    }
+
+    fn accepts(&self, segment: &str) -> bool {
+        match self {
+            ParamType::Int => segment.parse::<i64>().is_ok(),
+            ParamType::Uint => segment.parse::<u64>().is_ok(),
+            ParamType::Float => segment.parse::<f64>().is_ok_and(|f| f.is_finite()),
+            ParamType::Bool => segment == "true" || segment == "false",
+            ParamType::Uuid => uuid::Uuid::parse_str(segment).is_ok(),
+            ParamType::Str => true,
+        }
+    }
+}
+
+/// A single parsed segment of a route path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Static(String),
+    Param { name: String, param_type: ParamType },
+    Wildcard(String),
+}
+
+/// A route path parsed into segments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPattern {
+    pub segments: Vec<PathSegment>,
+}
+
+impl PathPattern {
+    /// Parse a route path such as `/users/{id:int}/files/*rest`
+    pub fn parse(path: &str) -> Result<Self, String> {
+        if !path.starts_with('/') {
+            return Err("Route path must start with '/'".to_string());
+        }
+
+        let raw_segments: Vec<&str> = split_path(path).collect();
+        let mut segments = Vec::with_capacity(raw_segments.len());
+        let mut seen_names = BTreeSet::new();
+
+        for (i, raw) in raw_segments.iter().enumerate() {
+            let segment = if let Some(inner) = raw.strip_prefix('{') {
+                let inner = inner.strip_suffix('}')
+                    .ok_or_else(|| format!("Unterminated parameter segment '{}' in '{}'", raw, path))?;
+                let (name, param_type) = match inner.split_once(':') {
+                    Some((name, ty)) => (name.trim(), ParamType::parse(ty.trim())?),
+                    None => (inner.trim(), ParamType::Str),
+                };
+                validate_param_name(name, path)?;
+                PathSegment::Param { name: name.to_string(), param_type }
+            } else if let Some(name) = raw.strip_prefix('*') {
+                if i != raw_segments.len() - 1 {
+                    return Err(format!("Wildcard '{}' must be the last segment in '{}'", raw, path));
+                }
+                validate_param_name(name, path)?;
+                PathSegment::Wildcard(name.to_string())
+            } else {
+                if raw.contains('{') || raw.contains('}') {
+                    return Err(format!("Parameters must span a whole segment, got '{}' in '{}'", raw, path));
+                }
+                PathSegment::Static(raw.to_string())
+            };
+
+            if let PathSegment::Param { name, .. } | PathSegment::Wildcard(name) = &segment {
+                if !seen_names.insert(name.clone()) {
+                    return Err(format!("Duplicate path parameter '{}' in '{}'", name, path));
+                }
+            }
+            segments.push(segment);
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Names of the parameters and wildcard captured by this pattern
+    pub fn param_names(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|segment| match segment {
+            PathSegment::Param { name, .. } | PathSegment::Wildcard(name) => Some(name.as_str()),
+            PathSegment::Static(_) => None,
+        })
+    }
+}
+
+fn validate_param_name(name: &str, path: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid path parameter name '{}' in '{}'", name, path))
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+/// A successful route lookup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteMatch {
+    pub route_id: String,
+    pub params: HashMap<String, String>,
+}
+
+/// Outcome of resolving a method and path against the router
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteLookup {
+    Matched(RouteMatch),
+    /// The path exists but not for this method; carries the methods that are allowed
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    // Kept sorted by precedence: typed params first, then plain params
+    params: Vec<ParamEdge>,
+    wildcards: Vec<WildcardEdge>,
+    // method -> route id
+    endpoints: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+struct ParamEdge {
+    name: String,
+    param_type: ParamType,
+    node: Node,
+}
+
+#[derive(Debug)]
+struct WildcardEdge {
+    name: String,
+    endpoints: BTreeMap<String, String>,
+}
+
+/// Segment tree router compiled from the registered dynamic routes
+#[derive(Debug, Default)]
+pub struct DynamicRouter {
+    root: Node,
+    len: usize,
+}
+
+impl DynamicRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a route. Fails on an invalid path or if the same path shape is
+    /// already registered for this method.
+    pub fn insert(&mut self, path: &str, method: &str, route_id: &str) -> Result<(), String> {
+        let pattern = PathPattern::parse(path)?;
+        if let Some(existing) = Self::find_shape(&self.root, &pattern.segments, method).filter(|id| *id != route_id) {
+            return Err(format!("Route conflict: {} {} is already handled by route '{}'", method, path, existing));
+        }
+        let mut node = &mut self.root;
+
+        for segment in &pattern.segments {
+            match segment {
+                PathSegment::Static(s) => {
+                    node = node.statics.entry(s.clone()).or_default();
+                }
+                PathSegment::Param { name, param_type } => {
+                    let pos = match node.params.iter().position(|e| e.name == *name && e.param_type == *param_type) {
+                        Some(pos) => pos,
+                        None => {
+                            node.params.push(ParamEdge { name: name.clone(), param_type: *param_type, node: Node::default() });
+                            node.params.sort_by(|a, b| (a.param_type, &a.name).cmp(&(b.param_type, &b.name)));
+                            node.params.iter().position(|e| e.name == *name && e.param_type == *param_type).unwrap()
+                        }
+                    };
+                    node = &mut node.params[pos].node;
+                }
+                PathSegment::Wildcard(name) => {
+                    let pos = match node.wildcards.iter().position(|e| e.name == *name) {
+                        Some(pos) => pos,
+                        None => {
+                            node.wildcards.push(WildcardEdge { name: name.clone(), endpoints: BTreeMap::new() });
+                            node.wildcards.sort_by(|a, b| a.name.cmp(&b.name));
+                            node.wildcards.iter().position(|e| e.name == *name).unwrap()
+                        }
+                    };
+                    return Self::insert_endpoint(&mut node.wildcards[pos].endpoints, path, method, route_id)
+                        .map(|_| self.len += 1);
+                }
+            }
+        }
+
+        Self::insert_endpoint(&mut node.endpoints, path, method, route_id).map(|_| self.len += 1)
+    }
+
+    /// The route serving a path shape for a method. Parameter and wildcard names don't
+    /// change which requests a path matches, so `/a/{id}` and `/a/{name}` are one shape.
+    fn find_shape<'a>(node: &'a Node, segments: &[PathSegment], method: &str) -> Option<&'a str> {
+        let Some((first, rest)) = segments.split_first() else {
+            return node.endpoints.get(method).map(|s| s.as_str());
+        };
+        match first {
+            PathSegment::Static(s) => node.statics.get(s).and_then(|child| Self::find_shape(child, rest, method)),
+            PathSegment::Param { param_type, .. } => node.params.iter()
+                .filter(|e| e.param_type == *param_type)
+                .find_map(|e| Self::find_shape(&e.node, rest, method)),
+            PathSegment::Wildcard(_) => node.wildcards.iter()
+                .find_map(|e| e.endpoints.get(method))
+                .map(|s| s.as_str()),
+        }
+    }
+
+    fn insert_endpoint(endpoints: &mut BTreeMap<String, String>, path: &str, method: &str, route_id: &str) -> Result<(), String> {
+        if let Some(existing) = endpoints.get(method) {
+            if existing != route_id {
+                return Err(format!("Route conflict: {} {} is already handled by route '{}'", method, path, existing));
+            }
+        }
+        endpoints.insert(method.to_string(), route_id.to_string());
+        Ok(())
+    }
+
+    /// Resolve a request method and path
+    pub fn lookup(&self, method: &str, path: &str) -> RouteLookup {
+        let segments: Vec<&str> = split_path(path).collect();
+
+        let mut params = Vec::new();
+        if let Some(route_id) = Self::find(&self.root, &segments, method, &mut params) {
+            return RouteLookup::Matched(RouteMatch {
+                route_id: route_id.to_string(),
+                params: params.into_iter().collect(),
+            });
+        }
+
+        let mut allowed = BTreeSet::new();
+        Self::collect_allowed(&self.root, &segments, &mut allowed);
+        if allowed.is_empty() {
+            RouteLookup::NotFound
+        } else {
+            RouteLookup::MethodNotAllowed(allowed.into_iter().collect())
+        }
+    }
+
+    fn find<'a>(node: &'a Node, segments: &[&str], method: &str, params: &mut Vec<(String, String)>) -> Option<&'a str> {
+        let Some((first, rest)) = segments.split_first() else {
+            return node.endpoints.get(method).map(|s| s.as_str());
+        };
+
+        if let Some(child) = node.statics.get(*first) {
+            if let Some(found) = Self::find(child, rest, method, params) {
+                return Some(found);
+            }
+        }
+
+        for edge in &node.params {
+            if edge.param_type.accepts(first) {
+                params.push((edge.name.clone(), first.to_string()));
+                if let Some(found) = Self::find(&edge.node, rest, method, params) {
+                    return Some(found);
+                }
+                params.pop();
+            }
+        }
+
+        for edge in &node.wildcards {
+            if let Some(route_id) = edge.endpoints.get(method) {
+                params.push((edge.name.clone(), segments.join("/")));
+                return Some(route_id.as_str());
+            }
+        }
+
+        None
+    }
+
+    fn collect_allowed(node: &Node, segments: &[&str], allowed: &mut BTreeSet<String>) {
+        let Some((first, rest)) = segments.split_first() else {
+            allowed.extend(node.endpoints.keys().cloned());
+            return;
+        };
+
+        if let Some(child) = node.statics.get(*first) {
+            Self::collect_allowed(child, rest, allowed);
+        }
+        for edge in node.params.iter().filter(|e| e.param_type.accepts(first)) {
+            Self::collect_allowed(&edge.node, rest, allowed);
+        }
+        for edge in &node.wildcards {
+            allowed.extend(edge.endpoints.keys().cloned());
+        }
+    }
+
+    /// Number of registered (path, method) endpoints
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(routes: &[(&str, &str, &str)]) -> DynamicRouter {
+        let mut router = DynamicRouter::new();
+        for (path, method, id) in routes {
+            router.insert(path, method, id).unwrap();
+        }
+        router
+    }
+
+    fn matched(lookup: RouteLookup) -> RouteMatch {
+        match lookup {
+            RouteLookup::Matched(m) => m,
+            other => panic!("Expected match, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_static_route() {
+        let r = router(&[("/api/users", "GET", "list")]);
+        assert_eq!(matched(r.lookup("GET", "/api/users")).route_id, "list");
+        assert_eq!(matched(r.lookup("GET", "/api/users/")).route_id, "list");
+        assert_eq!(r.lookup("GET", "/api/users/1"), RouteLookup::NotFound);
+    }
+
+    #[test]
+    fn test_param_extraction() {
+        let r = router(&[("/users/{id}/posts/{post_id}", "GET", "post")]);
+        let m = matched(r.lookup("GET", "/users/42/posts/abc"));
+        assert_eq!(m.route_id, "post");
+        assert_eq!(m.params.get("id").unwrap(), "42");
+        assert_eq!(m.params.get("post_id").unwrap(), "abc");
+    }
+
+    #[test]
+    fn test_typed_param() {
+        let r = router(&[("/users/{id:int}", "GET", "by_id"), ("/users/{name}", "GET", "by_name")]);
+        assert_eq!(matched(r.lookup("GET", "/users/7")).route_id, "by_id");
+        let m = matched(r.lookup("GET", "/users/alice"));
+        assert_eq!(m.route_id, "by_name");
+        assert_eq!(m.params.get("name").unwrap(), "alice");
+    }
+
+    #[test]
+    fn test_typed_param_rejects_mismatch() {
+        let r = router(&[("/orders/{id:uuid}", "GET", "order")]);
+        assert_eq!(r.lookup("GET", "/orders/123"), RouteLookup::NotFound);
+        let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        assert_eq!(matched(r.lookup("GET", &format!("/orders/{}", id))).params.get("id").unwrap(), id);
+    }
+
+    #[test]
+    fn test_precedence_static_param_wildcard() {
+        let r = router(&[
+            ("/files/*rest", "GET", "wild"),
+            ("/files/{name}", "GET", "param"),
+            ("/files/readme", "GET", "static"),
+        ]);
+        assert_eq!(matched(r.lookup("GET", "/files/readme")).route_id, "static");
+        assert_eq!(matched(r.lookup("GET", "/files/other")).route_id, "param");
+        let m = matched(r.lookup("GET", "/files/a/b/c"));
+        assert_eq!(m.route_id, "wild");
+        assert_eq!(m.params.get("rest").unwrap(), "a/b/c");
+    }
+
+    #[test]
+    fn test_backtracking_from_static_branch() {
+        let r = router(&[("/users/me/settings", "GET", "settings"), ("/users/{id}/profile", "GET", "profile")]);
+        let m = matched(r.lookup("GET", "/users/me/profile"));
+        assert_eq!(m.route_id, "profile");
+        assert_eq!(m.params.get("id").unwrap(), "me");
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let r = router(&[("/items/{id}", "GET", "get"), ("/items/{id}", "DELETE", "delete")]);
+        assert_eq!(
+            r.lookup("POST", "/items/1"),
+            RouteLookup::MethodNotAllowed(vec!["DELETE".to_string(), "GET".to_string()])
+        );
+        assert_eq!(r.lookup("POST", "/nothing"), RouteLookup::NotFound);
+    }
+
+    #[test]
+    fn test_conflict_detection() {
+        let mut r = router(&[("/a/{id}", "GET", "one")]);
+        assert!(r.insert("/a/{id}", "GET", "two").unwrap_err().contains("conflict"));
+        assert!(r.insert("/a/{id}", "GET", "one").is_ok());
+    }
+
+    #[test]
+    fn test_conflict_ignores_param_names() {
+        let mut r = router(&[("/a/{id}", "GET", "one"), ("/b/{n:int}/x", "GET", "two"), ("/c/*rest", "GET", "three")]);
+        assert!(r.insert("/a/{name}", "GET", "four").unwrap_err().contains("route 'one'"));
+        assert!(r.insert("/b/{id:int}/x", "GET", "four").unwrap_err().contains("route 'two'"));
+        assert!(r.insert("/c/*path", "GET", "four").unwrap_err().contains("route 'three'"));
+
+        // Other shapes and methods are still free
+        assert!(r.insert("/a/{name}", "POST", "four").is_ok());
+        assert!(r.insert("/b/{id:uuid}/x", "GET", "five").is_ok());
+        assert!(r.insert("/b/{id:int}/y", "GET", "six").is_ok());
+        let m = matched(r.lookup("GET", "/b/7/y"));
+        assert_eq!(m.route_id, "six");
+        assert_eq!(m.params.get("id").unwrap(), "7");
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!(PathPattern::parse("/a/*rest/b").is_err());
+        assert!(PathPattern::parse("/a/{id:color}").is_err());
+        assert!(PathPattern::parse("/a/{id}/{id}").is_err());
+        assert!(PathPattern::parse("/a/x{id}").is_err());
+        assert!(PathPattern::parse("/a/{}").is_err());
+        assert!(PathPattern::parse("a").is_err());
+    }
+}
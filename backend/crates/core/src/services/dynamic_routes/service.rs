@@ -1,19 +1,37 @@
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::cmp::Reverse;
+use std::future::Future;
 use proto::models::{
     RouteDefinition, LogicOperation, RouteTestRequest, RouteTestResponse,
-    DynamicRouteExecutionContext, LoopControl, FunctionDef, FunctionDefinition, SwitchCase,
+    DynamicRouteExecutionContext, LoopControl, FunctionDef, FunctionDefinition, SwitchCase, QuarantinedRoute, SqlParams, DatasourceStatus, RedisCommand,
 };
 use serde_json::Value;
 use regex;
 use super::execution::execute_logic_extended;
-use super::cache::ExecutionPlan;
+use super::cache::{self, ExecutionPlan, PersistedPlan};
+use super::sql;
+use super::router::{DynamicRouter, PathPattern, RouteLookup};
+use super::params::{RequestValidator, ValidatedRequest};
+use super::response::ResponseValidator;
+use crate::rate_limit::ClientKey;
+use crate::budget::{self, Limits};
+use crate::trace::{self, RouteTrace, TraceStore};
+use crate::debugger::{self, DebugClient, DebugEvent};
+use crate::datasource::{DatasourceRegistry, DEFAULT_DATASOURCE};
+use crate::validation::{ValidationError, ValidationResult};
 use crate::compiler::lowerer::LogicCompiler;
+use crate::compiler::optimizer::Optimizer;
+use crate::compiler::diagnostics::{error_summary, Diagnostic};
+use crate::compiler::symbol_table::SymbolTable;
+use crate::vm::instructions::Program;
 use crate::vm::machine::VirtualMachine;
 use crate::vm::memory::ExecutionMemory;
 use crate::websocket::WebSocketManager;
 
+/// Global functions up to this many operations long are inlined at their call sites
+const INLINE_MAX_OPERATIONS: usize = 8;
+
 pub struct DynamicRouteService {
     // In production, this would be a repository
     routes: Arc<std::sync::RwLock<HashMap<String, RouteDefinition>>>,
@@ -21,13 +39,21 @@ pub struct DynamicRouteService {
     global_functions: Arc<std::sync::RwLock<HashMap<String, FunctionDef>>>,
     // Hot routes cache for optimized execution
     hot_routes_cache: Arc<std::sync::RwLock<HashMap<String, Arc<ExecutionPlan>>>>,
+    // Compiled path router, rebuilt whenever the route set changes
+    router: Arc<std::sync::RwLock<DynamicRouter>>,
     data_dir: String,
     // WebSocket manager for real-time connections
     ws_manager: Arc<WebSocketManager>,
-    // Database pool for SQL operations
-    db_pool: Option<Arc<sqlx::Pool<sqlx::Sqlite>>>,
+    // Databases SQL operations run on, by name
+    datasources: Arc<DatasourceRegistry>,
     // Redis pool for caching operations
     redis_pool: Option<Arc<deadpool_redis::Pool>>,
+    // Traces of sampled live executions
+    traces: Arc<TraceStore>,
+    // Debug sessions waiting for their client to attach
+    debug_sessions: Arc<std::sync::Mutex<HashMap<String, DebugClient>>>,
+    // Persisted routes that failed to load on startup
+    quarantine: Arc<std::sync::RwLock<Vec<QuarantinedRoute>>>,
 }
 
 impl Default for DynamicRouteService {
@@ -61,24 +87,44 @@ impl DynamicRouteService {
             routes: Arc::new(std::sync::RwLock::new(HashMap::new())),
             global_functions: Arc::new(std::sync::RwLock::new(HashMap::new())),
             hot_routes_cache: Arc::new(std::sync::RwLock::new(HashMap::new())),
+            router: Arc::new(std::sync::RwLock::new(DynamicRouter::new())),
             data_dir,
             ws_manager: Arc::new(WebSocketManager::new()),
-            db_pool: None,
+            datasources: Arc::new(DatasourceRegistry::default()),
             redis_pool: None,
+            traces: Arc::new(TraceStore::from_env()),
+            debug_sessions: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            quarantine: Arc::new(std::sync::RwLock::new(Vec::new())),
         };
         // Load persisted data
         let _ = service.load_persisted_data();
+        service.rebuild_router();
         service
     }
     
+    /// Directory routes, plans and functions are persisted in
+    pub fn data_dir(&self) -> &str {
+        &self.data_dir
+    }
+    
     /// Get WebSocket manager
     pub fn get_ws_manager(&self) -> Option<WebSocketManager> {
         Some((*self.ws_manager).clone())
     }
     
-    /// Get database pool
+    /// Get the pool of the default datasource
     pub fn get_db_pool(&self) -> Option<sqlx::Pool<sqlx::Sqlite>> {
-        self.db_pool.as_ref().map(|p| (**p).clone())
+        self.datasources.get(DEFAULT_DATASOURCE).cloned()
+    }
+
+    /// Pools of all datasources, by name
+    pub fn datasource_pools(&self) -> HashMap<String, sqlx::Pool<sqlx::Sqlite>> {
+        self.datasources.pools()
+    }
+
+    /// Settings and health of each datasource
+    pub async fn datasource_health(&self) -> Vec<DatasourceStatus> {
+        self.datasources.health().await
     }
     
     /// Get Redis pool
@@ -86,9 +132,14 @@ impl DynamicRouteService {
         self.redis_pool.as_ref().map(|p| (**p).clone())
     }
     
-    /// Set database pool
+    /// Set the pool of the default datasource
     pub fn set_db_pool(&mut self, pool: sqlx::Pool<sqlx::Sqlite>) {
-        self.db_pool = Some(Arc::new(pool));
+        Arc::make_mut(&mut self.datasources).insert(DEFAULT_DATASOURCE, pool, false);
+    }
+
+    /// Set the datasources routes can use
+    pub fn set_datasources(&mut self, datasources: DatasourceRegistry) {
+        self.datasources = Arc::new(datasources);
     }
     
     /// Set Redis pool
@@ -100,13 +151,14 @@ impl DynamicRouteService {
     pub async fn register_route(&self, mut route: RouteDefinition) -> Result<String, String> {
         // Validate route
         self.validate_route(&route)?;
+        self.check_lint_errors(&route)?;
         
         // Generate ID if not provided
         if route.id.is_empty() {
             route.id = uuid::Uuid::new_v4().to_string();
         }
         
-        // Inline global function calls for zero-cost abstraction
+        // Inline calls to small global functions; the others are called
         route.logic = self.inline_logic(&route.logic, 0)?;
         
         // Set timestamps
@@ -116,15 +168,12 @@ impl DynamicRouteService {
         
         // Pre-compile execution plan for hot cache
         let execution_plan = self.compile_execution_plan(&route)?;
+        let stored_plan = self.persisted_plan(&route, &execution_plan);
         
-        // Store route
-        let mut routes = self.routes.write().unwrap();
+        // Store route, unless its path and method are already taken by another route
         let route_id = route.id.clone();
-        routes.insert(route_id.clone(), route.clone());
-        
-        // Cache the execution plan
-        let mut cache = self.hot_routes_cache.write().unwrap();
-        cache.insert(route_id.clone(), Arc::new(execution_plan));
+        self.store_route(route.clone(), execution_plan)?;
+        self.release_from_quarantine(&route_id);
         
         // Persist to disk asynchronously
         let route_clone = route.clone();
@@ -133,6 +182,8 @@ impl DynamicRouteService {
         tokio::spawn(async move {
             if let Err(e) = Self::save_route_async(&route_clone, &data_dir_clone).await {
                 eprintln!("[ERROR] Failed to persist route {}: {}", route_id_for_log, e);
+            } else if let Err(e) = Self::save_plan_async(&route_id_for_log, &stored_plan, &data_dir_clone).await {
+                eprintln!("[ERROR] Failed to persist plan of route {}: {}", route_id_for_log, e);
             }
         });
         
@@ -154,17 +205,52 @@ impl DynamicRouteService {
     /// Compile an execution plan for a route (pre-computation for hot cache)
     fn compile_execution_plan(&self, route: &RouteDefinition) -> Result<ExecutionPlan, String> {
         // Compile to bytecode for VM execution
-        let (bytecode, symbol_table) = self.compile_logic(&route.logic)?;
+        let logic = self.optimize_logic(route, &route.logic);
+        let (bytecode, symbol_table) = self.compile_logic(&logic)?;
+        
+        // WebSocket hooks are compiled per connection, but must be executable too
+        if let Some(hooks) = &route.ws_hooks {
+            for (name, hook) in Self::ws_hook_blocks(hooks) {
+                LogicCompiler::new().compile_root(name, hook)?;
+            }
+        }
         
+        Self::assemble_plan(route, Arc::new(logic), bytecode.map(Arc::new), symbol_table.map(Arc::new))
+    }
+
+    /// Complete an execution plan around compiled logic, with the route's validators and budget
+    fn assemble_plan(
+        route: &RouteDefinition,
+        logic: Arc<Vec<LogicOperation>>,
+        bytecode: Option<Arc<Program>>,
+        symbol_table: Option<Arc<SymbolTable>>,
+    ) -> Result<ExecutionPlan, String> {
         Ok(ExecutionPlan {
-            logic: Arc::new(route.logic.clone()),
+            logic,
             enabled: route.enabled,
             version: route.version.clone(),
-            bytecode: bytecode.map(Arc::new),
-            symbol_table: symbol_table.map(Arc::new),
+            bytecode,
+            symbol_table,
+            validator: Arc::new(Self::compile_validator(route)?),
+            response_validator: Arc::new(ResponseValidator::compile(route).map_err(|d| d.to_string())?),
+            limits: Limits::for_route(route),
         })
     }
 
+    /// The plan as it is stored next to the route, stamped with what it was compiled from
+    fn persisted_plan(&self, route: &RouteDefinition, plan: &ExecutionPlan) -> PersistedPlan {
+        PersistedPlan::new(plan, cache::source_hash(route, &self.global_functions.read().unwrap()))
+    }
+
+    fn plan_path(data_dir: &str, route_id: &str) -> std::path::PathBuf {
+        std::path::Path::new(data_dir).join("routes").join(format!("{}.plan", route_id))
+    }
+
+    fn compile_validator(route: &RouteDefinition) -> Result<RequestValidator, String> {
+        RequestValidator::compile(route)
+            .map_err(|diagnostics| error_summary(&diagnostics).unwrap_or_default())
+    }
+
     /// Save a route to disk asynchronously
     async fn save_route_async(route: &RouteDefinition, data_dir: &str) -> Result<(), String> {
         use tokio::fs;
@@ -193,19 +279,36 @@ impl DynamicRouteService {
         Ok(())
     }
 
-    /// Delete a route file from disk asynchronously
+    /// Save a route's compiled plan next to the route file
+    async fn save_plan_async(route_id: &str, plan: &PersistedPlan, data_dir: &str) -> Result<(), String> {
+        let file_path = Self::plan_path(data_dir, route_id);
+        let content = tokio::task::spawn_blocking({
+            let plan = plan.clone();
+            move || serde_json::to_string(&plan)
+        })
+        .await
+        .map_err(|e| format!("Serialization task failed: {}", e))?
+        .map_err(|e| format!("Failed to serialize plan: {}", e))?;
+
+        tokio::fs::write(&file_path, content)
+            .await
+            .map_err(|e| format!("Failed to write plan file {}: {}", file_path.display(), e))
+    }
+
+    /// Delete a route file, and its stored plan, from disk asynchronously
     async fn delete_route_file_async(route_id: &str, data_dir: &str) -> Result<(), String> {
         use tokio::fs;
         use std::path::Path;
 
         let routes_dir = Path::new(data_dir).join("routes");
-        let file_path = routes_dir.join(format!("{}.json", route_id));
         
         // Check existence and remove using tokio::fs
-        if tokio::fs::try_exists(&file_path).await.unwrap_or(false) {
-            fs::remove_file(&file_path)
-                .await
-                .map_err(|e| format!("Failed to delete route file {}: {}", file_path.display(), e))?;
+        for file_path in [routes_dir.join(format!("{}.json", route_id)), Self::plan_path(data_dir, route_id)] {
+            if tokio::fs::try_exists(&file_path).await.unwrap_or(false) {
+                fs::remove_file(&file_path)
+                    .await
+                    .map_err(|e| format!("Failed to delete route file {}: {}", file_path.display(), e))?;
+            }
         }
 
         Ok(())
@@ -214,19 +317,20 @@ impl DynamicRouteService {
     /// Update an existing route
     pub async fn update_route(&self, route_id: &str, mut route: RouteDefinition) -> Result<(), String> {
         self.validate_route(&route)?;
+        self.check_lint_errors(&route)?;
         
-        let mut routes = self.routes.write().unwrap();
-        if !routes.contains_key(route_id) {
+        if !self.routes.read().unwrap().contains_key(route_id) {
             return Err("Route not found".to_string());
         }
         
+        route.id = route_id.to_string();
         route.updated_at = chrono::Utc::now().to_rfc3339();
-        routes.insert(route_id.to_string(), route.clone());
         
         // Pre-compile and cache the execution plan
         let execution_plan = self.compile_execution_plan(&route)?;
-        let mut cache = self.hot_routes_cache.write().unwrap();
-        cache.insert(route_id.to_string(), Arc::new(execution_plan));
+        let stored_plan = self.persisted_plan(&route, &execution_plan);
+        self.store_route(route.clone(), execution_plan)?;
+        self.release_from_quarantine(route_id);
         
         // Persist to disk asynchronously
         let route_clone = route.clone();
@@ -235,6 +339,8 @@ impl DynamicRouteService {
         tokio::spawn(async move {
             if let Err(e) = Self::save_route_async(&route_clone, &data_dir_clone).await {
                 eprintln!("[ERROR] Failed to persist route update {}: {}", route_id_clone, e);
+            } else if let Err(e) = Self::save_plan_async(&route_id_clone, &stored_plan, &data_dir_clone).await {
+                eprintln!("[ERROR] Failed to persist plan of route {}: {}", route_id_clone, e);
             }
         });
         
@@ -243,14 +349,13 @@ impl DynamicRouteService {
 
     /// Delete a route
     pub async fn delete_route(&self, route_id: &str) -> Result<(), String> {
-        let mut routes = self.routes.write().unwrap();
-        if routes.remove(route_id).is_none() {
+        if self.routes.write().unwrap().remove(route_id).is_none() {
             return Err("Route not found".to_string());
         }
         
         // Invalidate cache
-        let mut cache = self.hot_routes_cache.write().unwrap();
-        cache.remove(route_id);
+        self.hot_routes_cache.write().unwrap().remove(route_id);
+        self.rebuild_router();
 
         // Delete from disk asynchronously
         let route_id_clone = route_id.to_string();
@@ -277,9 +382,10 @@ impl DynamicRouteService {
         };
         
         steps.push(format!("Route found: {} ({})", route.name, route.path));
+        let plan = self.plan_for(&route)?;
         
         // Create execution context
-        let context = DynamicRouteExecutionContext {
+        let mut context = DynamicRouteExecutionContext {
             route_id: request.route_id.clone(),
             variables: HashMap::new(),
             request_payload: request.test_payload,
@@ -294,16 +400,28 @@ impl DynamicRouteService {
         
         steps.push("Execution context created".to_string());
         
-        // Execute logic
-        match self.execute_logic(&route.logic, context, &mut steps).await {
+        // Execute logic the way live requests do, tracing every operation
+        let execution = self.run_plan(&plan, &mut context);
+        let (outcome, trace) = trace::collect(budget::run(plan.limits, execution)).await;
+        steps.push(format!("Executed on the {}", if plan.bytecode.is_some() { "VM" } else { "interpreter" }));
+        match outcome {
             Ok(result) => {
                 let execution_time = start_time.elapsed().as_millis() as u64;
+                let schema_errors: Vec<String> = self.check_response(&route, &result)
+                    .into_iter()
+                    .map(|e| format!("{}: {}", e.path, e.message))
+                    .collect();
+                if route.response_schema.is_some() {
+                    steps.push(format!("Response schema checked: {} error(s)", schema_errors.len()));
+                }
                 Ok(RouteTestResponse {
-                    success: true,
+                    success: schema_errors.is_empty(),
                     result: Some(result),
-                    error: None,
+                    error: (!schema_errors.is_empty()).then(|| "Result does not match response_schema".to_string()),
                     execution_time_ms: execution_time,
                     steps_executed: steps,
+                    schema_errors,
+                    trace,
                 })
             },
             Err(e) => {
@@ -314,6 +432,8 @@ impl DynamicRouteService {
                     error: Some(e),
                     execution_time_ms: execution_time,
                     steps_executed: steps,
+                    schema_errors: vec![],
+                    trace,
                 })
             }
         }
@@ -343,7 +463,7 @@ impl DynamicRouteService {
                     .ok_or_else(|| "Route not found".to_string())?;
                 
                 // Critical Optimization: Flatten logic tree (inline functions) before caching
-                let optimized_logic = self.inline_logic(&route.logic, 0)?;
+                let optimized_logic = self.optimize_logic(route, &self.inline_logic(&route.logic, 0)?);
                 
                 // Compile to bytecode for VM execution
                 let (bytecode, symbol_table) = self.compile_logic(&optimized_logic)?;
@@ -354,6 +474,9 @@ impl DynamicRouteService {
                     version: route.version.clone(),
                     bytecode: bytecode.map(Arc::new),
                     symbol_table: symbol_table.map(Arc::new),
+                    validator: Arc::new(Self::compile_validator(route)?),
+                    response_validator: Arc::new(ResponseValidator::compile(route).map_err(|d| d.to_string())?),
+                    limits: Limits::for_route(route),
                 })
             }
         };
@@ -362,46 +485,136 @@ impl DynamicRouteService {
             return Err("Route is disabled".to_string());
         }
         
-        // Execute using VM if bytecode is available, otherwise fallback to interpreter
-        let result = if let (Some(bytecode), Some(symbol_table)) = (&plan.bytecode, &plan.symbol_table) {
-            // VM execution path
-            let memory = ExecutionMemory::new();
-            let mut vm = VirtualMachine::new(memory, (**symbol_table).clone());
-            
-            // Bridge request data to VM memory
-            self.inject_request_data_into_vm(&mut vm, &payload, &path_params, &query_params, symbol_table)?;
-            
-            // Execute bytecode
-            vm.execute(bytecode).await?
-        } else {
-            // Fallback to interpreter (not measured for telemetry as it's legacy path)
-            let mut context = DynamicRouteExecutionContext {
-                route_id: route_id.to_string(),
-                variables: HashMap::new(),
-                request_payload: payload,
-                path_params,
-                query_params,
-                functions: HashMap::new(),
-                loop_control: LoopControl::default(),
-                error_context: None,
+        let mut context = DynamicRouteExecutionContext {
+            route_id: route_id.to_string(),
+            variables: HashMap::new(),
+            request_payload: payload,
+            path_params,
+            query_params,
+            functions: HashMap::new(),
+            loop_control: LoopControl::default(),
+            error_context: None,
+        };
+        let execution = budget::run(plan.limits, self.run_plan(&plan, &mut context));
+        self.sampled(route_id, execution).await
+    }
+
+    /// Run an execution, tracing it when it is picked for sampling
+    async fn sampled(&self, route_id: &str, execution: impl Future<Output = Result<Value, String>>) -> Result<Value, String> {
+        if !self.traces.should_sample() {
+            return execution.await;
+        }
+        let started = std::time::Instant::now();
+        let (result, records) = trace::collect(execution).await;
+        self.traces.store(RouteTrace {
+            route_id: route_id.to_string(),
+            recorded_at: chrono::Utc::now().to_rfc3339(),
+            duration_us: started.elapsed().as_micros() as u64,
+            error: result.as_ref().err().cloned(),
+            records,
+        });
+        result
+    }
+
+    /// Trace one in `every` live executions (0 turns sampling off)
+    pub fn set_trace_sampling(&self, every: u64) {
+        self.traces.set_sample_every(every);
+    }
+
+    /// Sampled traces of a route's live executions, newest first
+    pub fn recent_traces(&self, route_id: &str) -> Vec<RouteTrace> {
+        self.traces.for_route(route_id)
+    }
+
+    /// Start a `test_route`-style execution on the VM under the debugger, paused
+    /// before its first operation until a client attaches. Returns the session id.
+    pub async fn start_debug_session(&self, request: RouteTestRequest) -> Result<String, String> {
+        let plan = {
+            let cache = self.hot_routes_cache.read().unwrap();
+            cache.get(&request.route_id).cloned()
+                .ok_or_else(|| "Route not found".to_string())?
+        };
+        let (Some(bytecode), Some(symbol_table)) = (plan.bytecode.clone(), plan.symbol_table.clone()) else {
+            return Err("Route logic does not compile to bytecode and cannot be debugged".to_string());
+        };
+
+        let context = DynamicRouteExecutionContext {
+            route_id: request.route_id.clone(),
+            variables: HashMap::new(),
+            request_payload: request.test_payload,
+            path_params: HashMap::new(),
+            query_params: request.test_params.iter()
+                .map(|(k, v)| (k.clone(), v.to_string()))
+                .collect(),
+            functions: HashMap::new(),
+            loop_control: LoopControl::default(),
+            error_context: None,
+        };
+        let mut vm = self.route_vm(&symbol_table);
+        Self::inject_request_data_into_vm(&mut vm, &context, &symbol_table);
+
+        let (debugger, client) = debugger::session();
+        let events = debugger.events();
+        vm.attach_debugger(debugger);
+
+        let session_id = uuid::Uuid::new_v4().to_string();
+        self.debug_sessions.lock().unwrap().insert(session_id.clone(), client);
+
+        // A paused session must outlive the route's timeout, but not forever
+        let limits = Limits { timeout: debugger::SESSION_TIMEOUT, ..plan.limits };
+        let sessions = self.debug_sessions.clone();
+        let id = session_id.clone();
+        tokio::spawn(async move {
+            let (result, error) = match budget::run(limits, vm.execute(&bytecode)).await {
+                Ok(result) => (Some(result), None),
+                Err(e) => (None, Some(e)),
             };
+            let _ = events.send(DebugEvent::Finished { result, error });
+            sessions.lock().unwrap().remove(&id);
+        });
+
+        Ok(session_id)
+    }
+
+    /// Take the client end of a debug session; a session has a single client
+    pub fn attach_debugger(&self, session_id: &str) -> Option<DebugClient> {
+        self.debug_sessions.lock().unwrap().remove(session_id)
+    }
+
+    /// Run a route's plan on the VM. Logic that could not be lowered to
+    /// bytecode falls back to the interpreter.
+    async fn run_plan(&self, plan: &ExecutionPlan, context: &mut DynamicRouteExecutionContext) -> Result<Value, String> {
+        let (Some(bytecode), Some(symbol_table)) = (&plan.bytecode, &plan.symbol_table) else {
+            // Inject global functions into context for execution
+            let global_functions = self.global_functions.read().unwrap().clone();
+            for (name, func_def) in global_functions.iter() {
+                context.functions.insert(name.clone(), FunctionDefinition {
+                    params: func_def.params.clone(),
+                    body: func_def.logic.clone(),
+                });
+            }
             let mut steps = Vec::new();
-            execute_logic_extended(&plan.logic, &mut context, &mut steps).await?
+            return execute_logic_extended(&plan.logic, context, &mut steps).await;
         };
-        Ok(result)
+
+        let mut vm = self.route_vm(symbol_table);
+        Self::inject_request_data_into_vm(&mut vm, context, symbol_table);
+        vm.execute(bytecode).await
     }
 
-    /// Legacy execute logic (now uses extended version)
-    fn execute_logic<'a>(
-        &'a self,
-        operations: &'a [LogicOperation],
-        mut context: DynamicRouteExecutionContext,
-        steps: &'a mut Vec<String>,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Value, String>> + Send + 'a>> {
-        Box::pin(async move {
-            let result = execute_logic_extended(operations, &mut context, steps).await?;
-            Ok(result)
-        })
+    /// A VM for a route's bytecode, connected to the service's pools
+    fn route_vm(&self, symbol_table: &SymbolTable) -> VirtualMachine {
+        let mut vm = VirtualMachine::with_all(ExecutionMemory::new(), symbol_table.clone(), self.get_db_pool(), self.get_redis_pool(), None, None);
+        vm.set_datasources(self.datasource_pools());
+        vm
+    }
+
+    /// The route's cached plan, or a freshly compiled one for a route that isn't cached
+    fn plan_for(&self, route: &RouteDefinition) -> Result<Arc<ExecutionPlan>, String> {
+        match self.cached_plan(&route.id) {
+            Some(plan) => Ok(plan),
+            None => self.compile_execution_plan(route).map(Arc::new),
+        }
     }
 
     /// Validate route definition
@@ -418,6 +631,9 @@ impl DynamicRouteService {
             return Err("Route path must start with '/'".to_string());
         }
         
+        // Path must be a valid router pattern ({param}, {param:type}, trailing *wildcard)
+        PathPattern::parse(&route.path)?;
+        
         if route.logic.is_empty() {
             return Err("Route logic cannot be empty".to_string());
         }
@@ -427,6 +643,176 @@ impl DynamicRouteService {
 
 
 
+    /// Compile the route's logic, hooks and parameter rules and report every problem found.
+    /// Runs on the logic as written, before global function calls are inlined.
+    pub fn lint_route(&self, route: &RouteDefinition) -> Vec<Diagnostic> {
+        let global_functions: Vec<String> = self.global_functions.read().unwrap().keys().cloned().collect();
+        let mut inputs: Vec<String> = route.parameters.iter().map(|p| p.name.clone()).collect();
+        if let Ok(pattern) = PathPattern::parse(&route.path) {
+            inputs.extend(pattern.param_names().map(str::to_string));
+        }
+
+        let mut compiler = LogicCompiler::new()
+            .with_functions(global_functions.clone())
+            .with_inputs(inputs.clone());
+        let _ = compiler.compile_root("logic", &route.logic);
+        let mut diagnostics = compiler.diagnostics().to_vec();
+
+        if let Some(hooks) = &route.ws_hooks {
+            for (name, hook) in Self::ws_hook_blocks(hooks) {
+                let mut compiler = LogicCompiler::new()
+                    .with_functions(global_functions.clone())
+                    .with_inputs(inputs.clone());
+                let _ = compiler.compile_root(name, hook);
+                diagnostics.extend_from_slice(compiler.diagnostics());
+            }
+        }
+
+        if let Err(errors) = RequestValidator::compile(route) {
+            diagnostics.extend(errors);
+        }
+        if let Err(error) = ResponseValidator::compile(route) {
+            diagnostics.push(error);
+        }
+        if route.rate_limit == Some(0) {
+            diagnostics.push(Diagnostic::error("rate_limit", "Rate limit must allow at least 1 request per minute"));
+        }
+        if let Err(e) = ClientKey::parse(route.rate_limit_key.as_deref()) {
+            diagnostics.push(Diagnostic::error("rate_limit_key", e));
+        }
+        if let Some(limits) = &route.limits {
+            let zero = [
+                ("limits.max_fuel", limits.max_fuel == Some(0)),
+                ("limits.timeout_ms", limits.timeout_ms == Some(0)),
+                ("limits.max_value_bytes", limits.max_value_bytes == Some(0)),
+                ("limits.max_parallel", limits.max_parallel == Some(0)),
+                ("limits.max_call_depth", limits.max_call_depth == Some(0)),
+            ];
+            for (path, is_zero) in zero {
+                if is_zero {
+                    diagnostics.push(Diagnostic::error(path, "Limit must be greater than 0"));
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Check a request against the route's parameter declarations and request schema.
+    /// Uses the validator compiled into the route's cached plan when there is one.
+    pub fn validate_request(
+        &self,
+        route: &RouteDefinition,
+        path_params: &HashMap<String, String>,
+        query_params: &HashMap<String, String>,
+        body: Value,
+    ) -> Result<ValidatedRequest, ValidationResult> {
+        let validator = match self.cached_plan(&route.id) {
+            Some(plan) => plan.validator.clone(),
+            None => Arc::new(RequestValidator::compile(route).map_err(|diagnostics| {
+                ValidationResult::from_errors(diagnostics.into_iter().map(|d| ValidationError {
+                    path: d.path,
+                    message: d.message,
+                    schema_path: String::new(),
+                }).collect())
+            })?),
+        };
+        validator.validate(path_params, query_params, body)
+    }
+
+    /// Check what a route returned against its `response_schema`.
+    /// Errors are reported whatever the route's policy; callers decide what to do with them.
+    pub fn check_response(&self, route: &RouteDefinition, result: &Value) -> Vec<ValidationError> {
+        if route.response_schema.is_none() {
+            return vec![];
+        }
+        let validator = match self.cached_plan(&route.id) {
+            Some(plan) => plan.response_validator.clone(),
+            None => match ResponseValidator::compile(route) {
+                Ok(validator) => Arc::new(validator),
+                Err(d) => {
+                    return vec![ValidationError { path: d.path, message: d.message, schema_path: String::new() }];
+                }
+            },
+        };
+        validator.check(result)
+    }
+
+    /// The plan a registered route runs, with its logic as optimized and its bytecode
+    pub fn cached_plan(&self, route_id: &str) -> Option<Arc<ExecutionPlan>> {
+        self.hot_routes_cache.read().unwrap().get(route_id).cloned()
+    }
+
+    /// Reject a route whose lint reports errors
+    fn check_lint_errors(&self, route: &RouteDefinition) -> Result<(), String> {
+        match error_summary(&self.lint_route(route)) {
+            Some(errors) => Err(format!("Route has errors: {}", errors)),
+            None => Ok(()),
+        }
+    }
+
+    fn ws_hook_blocks(hooks: &proto::models::WebSocketHooks) -> [(&'static str, &[LogicOperation]); 3] {
+        [
+            ("ws_hooks.on_connect", &hooks.on_connect),
+            ("ws_hooks.on_message", &hooks.on_message),
+            ("ws_hooks.on_disconnect", &hooks.on_disconnect),
+        ]
+    }
+
+    /// Fail if another enabled route already serves the same path shape and method
+    pub(crate) fn check_route_conflict(&self, route: &RouteDefinition) -> Result<(), String> {
+        if !route.enabled {
+            return Ok(());
+        }
+        let routes = self.routes.read().unwrap();
+        let mut router = DynamicRouter::new();
+        for existing in routes.values().filter(|r| r.enabled && r.id != route.id) {
+            let _ = router.insert(&existing.path, existing.method.as_str(), &existing.id);
+        }
+        router.insert(&route.path, route.method.as_str(), &route.id)
+    }
+
+    /// Store a route with its plan and route requests to it. Fails, storing nothing, if
+    /// another enabled route already serves the same path shape and method; the route set
+    /// stays locked from that check to the insert, so two writers can't both pass it.
+    fn store_route(&self, route: RouteDefinition, execution_plan: ExecutionPlan) -> Result<(), String> {
+        let mut routes = self.routes.write().unwrap();
+        let mut router = Self::build_router(routes.values().filter(|r| r.id != route.id));
+        if route.enabled {
+            router.insert(&route.path, route.method.as_str(), &route.id)?;
+        }
+        self.hot_routes_cache.write().unwrap().insert(route.id.clone(), Arc::new(execution_plan));
+        routes.insert(route.id.clone(), route);
+        *self.router.write().unwrap() = router;
+        Ok(())
+    }
+
+    /// Rebuild the compiled router from the current route set
+    fn rebuild_router(&self) {
+        let routes = self.routes.read().unwrap();
+        *self.router.write().unwrap() = Self::build_router(routes.values());
+    }
+
+    /// A router over the enabled routes among these
+    fn build_router<'a>(routes: impl Iterator<Item = &'a RouteDefinition>) -> DynamicRouter {
+        // Deterministic insertion order so conflicting legacy routes always resolve the same way
+        let mut enabled: Vec<&RouteDefinition> = routes.filter(|r| r.enabled).collect();
+        enabled.sort_by(|a, b| (&a.created_at, &a.id).cmp(&(&b.created_at, &b.id)));
+
+        let mut router = DynamicRouter::new();
+        for route in enabled {
+            if let Err(e) = router.insert(&route.path, route.method.as_str(), &route.id) {
+                eprintln!("[WARN] Route {} not added to router: {}", route.id, e);
+            }
+        }
+        router
+    }
+
+    /// Resolve an incoming request to a registered route
+    pub async fn match_route(&self, method: &str, path: &str) -> RouteLookup {
+        self.router.read().unwrap().lookup(method, path)
+    }
+
     /// Export route as JSON
     pub async fn export_route(&self, route_id: &str) -> Result<String, String> {
         let routes = self.routes.read().unwrap();
@@ -445,7 +831,7 @@ impl DynamicRouteService {
         self.register_route(route).await
     }
 
-    /// Define a global function for zero-cost inlining
+    /// Define a global function, callable from any route
     pub async fn define_global_function(&self, func: FunctionDef) -> Result<(), String> {
         let mut functions = self.global_functions.write().unwrap();
         functions.insert(func.name.clone(), func.clone());
@@ -475,10 +861,19 @@ impl DynamicRouteService {
         functions.get(name).cloned()
     }
 
-    /// Inline global function calls in logic operations for zero-cost abstraction
-    /// This function processes call_function operations BEFORE execution phase (during route creation/registration)
+    /// Inline calls to small global functions in logic operations, during route
+    /// registration. Calls to other functions are left for the VM, which runs them
+    /// in call frames of their own; see [`Self::is_inlinable`].
     /// The algorithm is recursive with depth limiting for security
     pub fn inline_logic(&self, logic: &[LogicOperation], depth: usize) -> Result<Vec<LogicOperation>, String> {
+        let mut local = HashSet::new();
+        Self::collect_defined_functions(logic, &mut local);
+        self.inline_block(logic, depth, &local)
+    }
+
+    /// Inline a block; `local` are the functions the route defines itself, which
+    /// shadow global ones of the same name
+    fn inline_block(&self, logic: &[LogicOperation], depth: usize, local: &HashSet<String>) -> Result<Vec<LogicOperation>, String> {
         // Security Check: Prevent Stack Overflow
         if depth > 50 {
             return Err("Maximum recursion depth exceeded (50) - possible circular function calls".to_string());
@@ -490,18 +885,14 @@ impl DynamicRouteService {
         for operation in logic {
             match operation {
                 LogicOperation::DefineFunction { name, params, body } => {
-                    // Register the function in the global registry
-                    let mut functions = self.global_functions.write().unwrap();
-                    functions.insert(name.clone(), FunctionDef {
+                    result.push(LogicOperation::DefineFunction {
                         name: name.clone(),
                         params: params.clone(),
-                        logic: body.clone(),
+                        body: self.inline_block(body, depth + 1, local)?,
                     });
-                    // Don't add to result - function definition is processed at registration time
                 },
                 LogicOperation::CallFunction { name, args, output_var } => {
-                    // Look up the function logic from the global store
-                    if let Some(func_def) = functions.get(name) {
+                    if let Some(func_def) = functions.get(name).filter(|f| !local.contains(name) && Self::is_inlinable(f)) {
                         // Extract variables from the function being called for proper scoping
                         let function_variables = self.extract_function_variables(func_def);
                         
@@ -532,14 +923,14 @@ impl DynamicRouteService {
                             value: serde_json::Value::String(format!("${{{}}}", scoped_result)),
                         });
                     } else {
-                        return Err(format!("Function '{}' not found in global registry", name));
+                        result.push(operation.clone());
                     }
                 },
                 // Handle nested operations recursively
                 LogicOperation::If { condition, then, otherwise } => {
-                    let flattened_then = self.inline_logic(then, depth + 1)?;
+                    let flattened_then = self.inline_block(then, depth + 1, local)?;
                     let flattened_otherwise = if let Some(otherwise_ops) = otherwise {
-                        Some(self.inline_logic(otherwise_ops, depth + 1)?)
+                        Some(self.inline_block(otherwise_ops, depth + 1, local)?)
                     } else {
                         None
                     };
@@ -550,7 +941,7 @@ impl DynamicRouteService {
                     });
                 },
                 LogicOperation::Loop { collection, var, body } => {
-                    let flattened_body = self.inline_logic(body, depth + 1)?;
+                    let flattened_body = self.inline_block(body, depth + 1, local)?;
                     result.push(LogicOperation::Loop {
                         collection: collection.clone(),
                         var: var.clone(),
@@ -561,12 +952,12 @@ impl DynamicRouteService {
                     let flattened_cases = cases.iter().map(|case| {
                         Ok(SwitchCase {
                             value: case.value.clone(),
-                            operations: self.inline_logic(&case.operations, depth + 1)?,
+                            operations: self.inline_block(&case.operations, depth + 1, local)?,
                         })
                     }).collect::<Result<Vec<_>, String>>()?;
 
                     let flattened_default = if let Some(default_ops) = default {
-                        Some(self.inline_logic(default_ops, depth + 1)?)
+                        Some(self.inline_block(default_ops, depth + 1, local)?)
                     } else {
                         None
                     };
@@ -578,7 +969,7 @@ impl DynamicRouteService {
                     });
                 },
                 LogicOperation::While { condition, body, max_iterations } => {
-                    let flattened_body = self.inline_logic(body, depth + 1)?;
+                    let flattened_body = self.inline_block(body, depth + 1, local)?;
                     result.push(LogicOperation::While {
                         condition: condition.clone(),
                         body: flattened_body,
@@ -586,10 +977,10 @@ impl DynamicRouteService {
                     });
                 },
                 LogicOperation::Try { body, catch, finally } => {
-                    let flattened_body = self.inline_logic(body, depth + 1)?;
-                    let flattened_catch = self.inline_logic(catch, depth + 1)?;
+                    let flattened_body = self.inline_block(body, depth + 1, local)?;
+                    let flattened_catch = self.inline_block(catch, depth + 1, local)?;
                     let flattened_finally = if let Some(finally_ops) = finally {
-                        Some(self.inline_logic(finally_ops, depth + 1)?)
+                        Some(self.inline_block(finally_ops, depth + 1, local)?)
                     } else {
                         None
                     };
@@ -599,9 +990,17 @@ impl DynamicRouteService {
                         finally: flattened_finally,
                     });
                 },
+                LogicOperation::Transaction { body, savepoint, datasource } => {
+                    let flattened_body = self.inline_block(body, depth + 1, local)?;
+                    result.push(LogicOperation::Transaction {
+                        body: flattened_body,
+                        savepoint: *savepoint,
+                        datasource: datasource.clone(),
+                    });
+                },
                 LogicOperation::Parallel { tasks, max_concurrent } => {
                     let flattened_tasks = tasks.iter()
-                        .map(|task| self.inline_logic(task, depth + 1))
+                        .map(|task| self.inline_block(task, depth + 1, local))
                         .collect::<Result<Vec<_>, String>>()?;
                     result.push(LogicOperation::Parallel {
                         tasks: flattened_tasks,
@@ -616,6 +1015,75 @@ impl DynamicRouteService {
         Ok(result)
     }
 
+    /// Whether calls to a global function are inlined rather than run in a call
+    /// frame: a short body without calls, definitions or control transfers whose
+    /// value is the `result` it sets last, as the inlined code reads it from there
+    fn is_inlinable(function: &FunctionDef) -> bool {
+        fn size(logic: &[LogicOperation]) -> Option<usize> {
+            logic.iter().try_fold(0, |total, op| {
+                let nested = match op {
+                    LogicOperation::CallFunction { .. }
+                    | LogicOperation::DefineFunction { .. }
+                    | LogicOperation::Return { .. }
+                    | LogicOperation::Break
+                    | LogicOperation::Continue
+                    | LogicOperation::Parallel { .. } => return None,
+                    LogicOperation::If { then, otherwise, .. } => size(then)? + size(otherwise.as_deref().unwrap_or_default())?,
+                    LogicOperation::Loop { body, .. }
+                    | LogicOperation::While { body, .. }
+                    | LogicOperation::Transaction { body, .. } => size(body)?,
+                    LogicOperation::Switch { cases, default, .. } => {
+                        cases.iter().map(|case| size(&case.operations)).sum::<Option<usize>>()?
+                            + size(default.as_deref().unwrap_or_default())?
+                    },
+                    LogicOperation::Try { body, catch, finally } => {
+                        size(body)? + size(catch)? + size(finally.as_deref().unwrap_or_default())?
+                    },
+                    _ => 0,
+                };
+                Some(total + 1 + nested)
+            })
+        }
+        let sets_result = matches!(function.logic.last(), Some(LogicOperation::Set { var, .. }) if var == "result");
+        sets_result && size(&function.logic).is_some_and(|size| size <= INLINE_MAX_OPERATIONS)
+    }
+
+    /// Names of the functions the logic defines, wherever they appear
+    fn collect_defined_functions(logic: &[LogicOperation], names: &mut HashSet<String>) {
+        for op in logic {
+            match op {
+                LogicOperation::DefineFunction { name, body, .. } => {
+                    names.insert(name.clone());
+                    Self::collect_defined_functions(body, names);
+                },
+                LogicOperation::If { then, otherwise, .. } => {
+                    Self::collect_defined_functions(then, names);
+                    Self::collect_defined_functions(otherwise.as_deref().unwrap_or_default(), names);
+                },
+                LogicOperation::Loop { body, .. }
+                | LogicOperation::While { body, .. }
+                | LogicOperation::Transaction { body, .. } => Self::collect_defined_functions(body, names),
+                LogicOperation::Switch { cases, default, .. } => {
+                    for case in cases {
+                        Self::collect_defined_functions(&case.operations, names);
+                    }
+                    Self::collect_defined_functions(default.as_deref().unwrap_or_default(), names);
+                },
+                LogicOperation::Try { body, catch, finally } => {
+                    Self::collect_defined_functions(body, names);
+                    Self::collect_defined_functions(catch, names);
+                    Self::collect_defined_functions(finally.as_deref().unwrap_or_default(), names);
+                },
+                LogicOperation::Parallel { tasks, .. } => {
+                    for task in tasks {
+                        Self::collect_defined_functions(task, names);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
     /// Helper method to inline logic with variable scoping
     fn inline_scoped_logic(&self, logic: &[LogicOperation], scope_prefix: &str, depth: usize, variables: &[String]) -> Result<Vec<LogicOperation>, String> {
         if depth > 50 {
@@ -712,24 +1180,58 @@ impl DynamicRouteService {
                         args: scoped_args,
                     });
                 },
-                LogicOperation::SqlOp { query, args, output_var } => {
-                    // Scope all args
+                LogicOperation::SqlOp { query, args, params, datasource, output_var } => {
+                    // `:name` parameters may read variables implicitly, so bind them
+                    // positionally first and scope every argument
+                    let (query, args, params) = match sql::positional_args(query, args, params.as_ref()) {
+                        Ok((query, args)) => (query, args, None),
+                        Err(_) => (query.clone(), args.clone(), params.clone()),
+                    };
                     let scoped_args = args.iter()
                         .map(|arg| self.scope_value_references(arg, scope_prefix, variables))
                         .collect();
+                    let scoped_params = params.map(|params| params.iter()
+                        .map(|(name, value)| (name.clone(), self.scope_value_references(value, scope_prefix, variables)))
+                        .collect());
                     // Scope output variable
                     let scoped_output = format!("{}{}", scope_prefix, output_var);
                     result.push(LogicOperation::SqlOp {
-                        query: query.clone(),
+                        query,
                         args: scoped_args,
+                        params: scoped_params,
+                        datasource: datasource.clone(),
                         output_var: scoped_output,
                     });
                 },
-                LogicOperation::RedisOp { command, key, value, ttl_seconds, output_var } => {
-                    // Scope key and value strings
+                LogicOperation::QueryDb { query, params, datasource } => {
+                    let (args, named) = sql::split_params(params);
+                    let (query, args) = sql::positional_args(query, args, named)
+                        .unwrap_or_else(|_| (query.clone(), args.to_vec()));
+                    let scoped_args = args.iter()
+                        .map(|arg| self.scope_value_references(arg, scope_prefix, variables))
+                        .collect();
+                    result.push(LogicOperation::QueryDb {
+                        query,
+                        params: SqlParams::Positional(scoped_args),
+                        datasource: datasource.clone(),
+                    });
+                },
+                LogicOperation::RedisOp { command, key, args, ttl_seconds, commands, output_var } => {
+                    // Scope keys and arguments, including each pipelined command's
                     let scoped_key = self.scope_string_references(key, scope_prefix, variables);
-                    let scoped_value = value.as_ref()
-                        .map(|v| self.scope_string_references(v, scope_prefix, variables));
+                    let scoped_args = args.iter()
+                        .map(|arg| self.scope_value_references(arg, scope_prefix, variables))
+                        .collect();
+                    let scoped_commands = commands.iter()
+                        .map(|c| RedisCommand {
+                            command: c.command.clone(),
+                            key: self.scope_string_references(&c.key, scope_prefix, variables),
+                            args: c.args.iter()
+                                .map(|arg| self.scope_value_references(arg, scope_prefix, variables))
+                                .collect(),
+                            ttl_seconds: c.ttl_seconds,
+                        })
+                        .collect();
                     // Scope output variable if provided
                     let scoped_output = output_var.as_ref()
                         .map(|var| format!("{}{}", scope_prefix, var));
@@ -737,8 +1239,9 @@ impl DynamicRouteService {
                     result.push(LogicOperation::RedisOp {
                         command: command.clone(),
                         key: scoped_key,
-                        value: scoped_value,
+                        args: scoped_args,
                         ttl_seconds: *ttl_seconds,
+                        commands: scoped_commands,
                         output_var: scoped_output,
                     });
                 },
@@ -859,6 +1362,9 @@ impl DynamicRouteService {
                         self.scan_logic_for_variables(finally_ops, variables);
                     }
                 },
+                LogicOperation::Transaction { body, .. } => {
+                    self.scan_logic_for_variables(body, variables);
+                },
                 LogicOperation::Parallel { tasks, .. } => {
                     for task in tasks {
                         self.scan_logic_for_variables(task, variables);
@@ -1010,27 +1516,19 @@ impl DynamicRouteService {
         }
     }
 
-    /// Execute route logic با context
+    /// Execute route logic with context, within the route's execution budget
     pub async fn execute_route_logic(
         &self,
-        logic: &[LogicOperation],
+        route: &RouteDefinition,
         context: &mut DynamicRouteExecutionContext,
     ) -> Result<Value, String> {
-        // Inject global functions into context for execution
-        let global_functions = self.global_functions.read().unwrap().clone();
-        for (name, func_def) in global_functions.iter() {
-            context.functions.insert(name.clone(), FunctionDefinition {
-                params: func_def.params.clone(),
-                body: func_def.logic.clone(),
-            });
-        }
-        
-        let mut steps = Vec::new();
-        let result = execute_logic_extended(logic, context, &mut steps).await?;
-        Ok(result)
+        let plan = self.plan_for(route)?;
+        let execution = budget::run(plan.limits, self.run_plan(&plan, context));
+        self.sampled(&route.id, execution).await
     }
 
-    /// Load persisted routes and functions from disk
+    /// Load persisted functions and routes from disk. A route that can't be
+    /// read, parsed or compiled is quarantined instead of stopping the rest.
     fn load_persisted_data(&self) -> Result<(), String> {
         use std::fs;
         use std::path::Path;
@@ -1038,46 +1536,49 @@ impl DynamicRouteService {
         let routes_dir = Path::new(&self.data_dir).join("routes");
         let functions_dir = Path::new(&self.data_dir).join("functions");
 
-        // Load routes
-        if routes_dir.exists() {
-            let entries = fs::read_dir(&routes_dir)
-                .map_err(|e| format!("Failed to read routes dir: {}", e))?;
-            for entry in entries {
-                let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+        // Load functions first; routes are compiled against them
+        if functions_dir.exists() {
+            let entries = fs::read_dir(&functions_dir)
+                .map_err(|e| format!("Failed to read functions dir: {}", e))?;
+            for entry in entries.flatten() {
                 let path = entry.path();
                 if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                    let content = fs::read_to_string(&path)
-                        .map_err(|e| format!("Failed to read route file {}: {}", path.display(), e))?;
-                    let route: RouteDefinition = serde_json::from_str(&content)
-                        .map_err(|e| format!("Failed to parse route from {}: {}", path.display(), e))?;
-                    
-                    // Pre-compile execution plan for hot cache
-                    let execution_plan = self.compile_execution_plan(&route)?;
-                    
-                    let mut routes = self.routes.write().unwrap();
-                    routes.insert(route.id.clone(), route.clone());
-                    
-                    // Cache the execution plan
-                    let mut cache = self.hot_routes_cache.write().unwrap();
-                    cache.insert(route.id.clone(), Arc::new(execution_plan));
+                    let func = fs::read_to_string(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|content| serde_json::from_str::<FunctionDef>(&content).map_err(|e| e.to_string()));
+                    match func {
+                        Ok(func) => {
+                            self.global_functions.write().unwrap().insert(func.name.clone(), func);
+                        },
+                        Err(e) => eprintln!("[WARN] Skipping function file {}: {}", path.display(), e),
+                    }
                 }
             }
         }
 
-        // Load functions
-        if functions_dir.exists() {
-            let entries = fs::read_dir(&functions_dir)
-                .map_err(|e| format!("Failed to read functions dir: {}", e))?;
-            for entry in entries {
-                let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+        // Load routes
+        if routes_dir.exists() {
+            let entries = fs::read_dir(&routes_dir)
+                .map_err(|e| format!("Failed to read routes dir: {}", e))?;
+            for entry in entries.flatten() {
                 let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                    let content = fs::read_to_string(&path)
-                        .map_err(|e| format!("Failed to read function file {}: {}", path.display(), e))?;
-                    let func: FunctionDef = serde_json::from_str(&content)
-                        .map_err(|e| format!("Failed to parse function from {}: {}", path.display(), e))?;
-                    let mut functions = self.global_functions.write().unwrap();
-                    functions.insert(func.name.clone(), func);
+                if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                    continue;
+                }
+                match self.load_route_file(&path) {
+                    Ok((route, execution_plan)) => {
+                        self.hot_routes_cache.write().unwrap().insert(route.id.clone(), Arc::new(execution_plan));
+                        self.routes.write().unwrap().insert(route.id.clone(), route);
+                    },
+                    Err((route_id, error)) => {
+                        eprintln!("[WARN] Quarantined route file {}: {}", path.display(), error);
+                        self.quarantine.write().unwrap().push(QuarantinedRoute {
+                            file: path.display().to_string(),
+                            route_id,
+                            error,
+                            quarantined_at: chrono::Utc::now().to_rfc3339(),
+                        });
+                    },
                 }
             }
         }
@@ -1085,6 +1586,45 @@ impl DynamicRouteService {
         Ok(())
     }
 
+    /// Read a route file and its execution plan: the stored one when it is
+    /// still current, or else a freshly compiled one, which is then stored.
+    /// Errors carry the route id when the file could be parsed.
+    fn load_route_file(&self, path: &std::path::Path) -> Result<(RouteDefinition, ExecutionPlan), (Option<String>, String)> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| (None, format!("Failed to read route file: {}", e)))?;
+        let route: RouteDefinition = serde_json::from_str(content.trim_start_matches('\u{feff}'))
+            .map_err(|e| (None, format!("Failed to parse route: {}", e)))?;
+
+        let source_hash = cache::source_hash(&route, &self.global_functions.read().unwrap());
+        let plan_path = Self::plan_path(&self.data_dir, &route.id);
+        let stored = std::fs::read_to_string(&plan_path).ok()
+            .and_then(|content| serde_json::from_str::<PersistedPlan>(&content).ok())
+            .filter(|stored| stored.is_current(&source_hash));
+        let execution_plan = match stored {
+            Some(stored) => Self::assemble_plan(&route, stored.logic, stored.bytecode, stored.symbol_table),
+            None => self.compile_execution_plan(&route).inspect(|plan| {
+                let stored = serde_json::to_string(&PersistedPlan::new(plan, source_hash))
+                    .map_err(|e| e.to_string())
+                    .and_then(|content| std::fs::write(&plan_path, content).map_err(|e| e.to_string()));
+                if let Err(e) = stored {
+                    eprintln!("[WARN] Failed to store plan of route {}: {}", route.id, e);
+                }
+            }),
+        };
+        execution_plan
+            .map(|plan| (route.clone(), plan))
+            .map_err(|e| (Some(route.id.clone()), e))
+    }
+
+    /// Persisted routes that failed to load on startup
+    pub fn quarantined_routes(&self) -> Vec<QuarantinedRoute> {
+        self.quarantine.read().unwrap().clone()
+    }
+
+    fn release_from_quarantine(&self, route_id: &str) {
+        self.quarantine.write().unwrap().retain(|entry| entry.route_id.as_deref() != Some(route_id));
+    }
+
     /// Save a function to disk asynchronously
     async fn save_function_async(func: &FunctionDef, data_dir: &str) -> Result<(), String> {
         use tokio::fs;
@@ -1103,10 +1643,20 @@ impl DynamicRouteService {
         Ok(())
     }
 
-    /// Compile logic operations to VM bytecode
-    fn compile_logic(&self, logic: &[LogicOperation]) -> Result<(Option<Vec<crate::vm::instructions::OptimizedOperation>>, Option<crate::compiler::symbol_table::SymbolTable>), String> {
-        let mut compiler = LogicCompiler::new();
-        let bytecode = compiler.compile(logic);
+    /// Run the optimizer over a route's (inlined) logic, unless the route turns it off
+    fn optimize_logic(&self, route: &RouteDefinition, logic: &[LogicOperation]) -> Vec<LogicOperation> {
+        if !route.optimize {
+            return logic.to_vec();
+        }
+        let global_functions: Vec<FunctionDef> = self.global_functions.read().unwrap().values().cloned().collect();
+        Optimizer::new().with_global_functions(global_functions).optimize(logic)
+    }
+
+    /// Compile logic operations to VM bytecode, with the global functions it calls
+    fn compile_logic(&self, logic: &[LogicOperation]) -> Result<(Option<Program>, Option<SymbolTable>), String> {
+        let global_functions: Vec<FunctionDef> = self.global_functions.read().unwrap().values().cloned().collect();
+        let mut compiler = LogicCompiler::new().with_global_functions(global_functions);
+        let bytecode = compiler.compile(logic)?;
         if bytecode.is_empty() {
             Ok((None, None))
         } else {
@@ -1114,17 +1664,15 @@ impl DynamicRouteService {
         }
     }
 
-    /// Inject request data into VM memory for execution
+    /// Inject request data into VM memory for execution. Variables already in
+    /// the context (validated parameters, `auth`, `request`) win over raw input.
     fn inject_request_data_into_vm(
-        &self,
         vm: &mut VirtualMachine,
-        payload: &Option<Value>,
-        path_params: &HashMap<String, String>,
-        query_params: &HashMap<String, String>,
-        symbol_table: &Arc<crate::compiler::symbol_table::SymbolTable>,
-    ) -> Result<(), String> {
+        context: &DynamicRouteExecutionContext,
+        symbol_table: &SymbolTable,
+    ) {
         // Inject payload fields as top-level variables
-        if let Some(Value::Object(obj)) = payload {
+        if let Some(Value::Object(obj)) = &context.request_payload {
             for (key, value) in obj {
                 if let Some(index) = symbol_table.get_index(key) {
                     vm.memory.set(index, value.clone());
@@ -1132,27 +1680,20 @@ impl DynamicRouteService {
             }
         }
 
-        // Inject path parameters
-        for (key, value_str) in path_params {
+        // Path and query parameters are parsed as JSON when they can be, otherwise kept as strings
+        for (key, value_str) in context.path_params.iter().chain(&context.query_params) {
             if let Some(index) = symbol_table.get_index(key) {
-                // Try to parse as JSON, otherwise keep as string
                 let value = serde_json::from_str(value_str)
                     .unwrap_or(Value::String(value_str.clone()));
                 vm.memory.set(index, value);
             }
         }
 
-        // Inject query parameters
-        for (key, value_str) in query_params {
+        for (key, value) in &context.variables {
             if let Some(index) = symbol_table.get_index(key) {
-                // Try to parse as JSON, otherwise keep as JSON string
-                let value = serde_json::from_str(value_str)
-                    .unwrap_or(Value::String(value_str.clone()));
-                vm.memory.set(index, value);
+                vm.memory.set(index, value.clone());
             }
         }
-
-        Ok(())
     }
 }
 
@@ -1320,7 +1861,7 @@ mod tests {
     }
 
     #[test]
-    fn test_inline_logic_recursive_function_calls() {
+    fn test_inline_logic_leaves_calls_to_functions_that_call_others() {
         let service = create_test_service();
         
         // Define both functions
@@ -1341,19 +1882,16 @@ mod tests {
 
         let result = service.inline_logic(&logic, 0).unwrap();
 
-        // Should have parameter bindings for multiply_and_add, then inlined add_numbers call, then result mapping
-        assert!(result.len() > 3); // At least: 3 param sets + 2 param sets for inner call + 1 result set + 1 final result set
-        
-        // Check that we have multiple Set operations for parameters
-        let set_operations: Vec<_> = result.iter().filter(|op| matches!(op, LogicOperation::Set { .. })).collect();
-        assert!(set_operations.len() >= 5); // x, y, z for outer function, a, b for inner function
+        // The function calls another one, so the VM calls it instead
+        assert_eq!(result.len(), 1);
+        assert!(matches!(&result[0], LogicOperation::CallFunction { name, .. } if name == "multiply_and_add"));
     }
 
     #[test]
     fn test_inline_logic_depth_limiting() {
         let service = create_test_service();
 
-        // Create a function that calls itself (would cause infinite recursion)
+        // Create a function that calls itself
         let recursive_func = FunctionDef {
             name: "recursive".to_string(),
             params: vec!["n".to_string()],
@@ -1376,8 +1914,12 @@ mod tests {
             }
         ];
 
+        // Recursion is left to the VM's call frames, however deep the caller is
+        let result = service.inline_logic(&logic, 49).unwrap();
+        assert!(matches!(&result[0], LogicOperation::CallFunction { .. }));
+
         // Should fail with depth error
-        let result = service.inline_logic(&logic, 49); // Start close to limit
+        let result = service.inline_logic(&logic, 51);
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Maximum recursion depth exceeded"));
     }
@@ -1385,18 +1927,34 @@ mod tests {
     #[test]
     fn test_inline_logic_function_not_found() {
         let service = create_test_service();
+        service.global_functions.write().unwrap().insert("add_numbers".to_string(), create_simple_function());
 
         let logic = vec![
             LogicOperation::CallFunction {
                 name: "nonexistent_function".to_string(),
                 args: vec![Value::Number(1.into())],
                 output_var: "result".to_string(),
-            }
+            },
+            // A function the route defines shadows the global one
+            LogicOperation::DefineFunction {
+                name: "add_numbers".to_string(),
+                params: vec![],
+                body: vec![LogicOperation::Set { var: "result".to_string(), value: Value::Number(0.into()) }],
+            },
+            LogicOperation::CallFunction {
+                name: "add_numbers".to_string(),
+                args: vec![],
+                output_var: "sum".to_string(),
+            },
         ];
 
-        let result = service.inline_logic(&logic, 0);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("not found in global registry"));
+        // Unknown functions are left for the compiler to reject
+        let result = service.inline_logic(&logic, 0).unwrap();
+        assert_eq!(result.len(), 3);
+        assert!(matches!(&result[0], LogicOperation::CallFunction { .. }));
+        assert!(matches!(&result[1], LogicOperation::DefineFunction { .. }));
+        assert!(matches!(&result[2], LogicOperation::CallFunction { .. }));
+        assert!(service.global_functions.read().unwrap()["add_numbers"].params.len() == 2, "route functions stay local");
     }
 
     #[test]
@@ -1514,4 +2072,205 @@ mod tests {
             _ => panic!("Expected Return operation"),
         }
     }
+
+    fn lint_test_route(logic: Vec<LogicOperation>) -> RouteDefinition {
+        RouteDefinition {
+            id: String::new(),
+            name: "lint".to_string(),
+            description: String::new(),
+            path: "/lint/{id}".to_string(),
+            method: proto::models::HttpMethod::GET,
+            route_type: proto::models::RouteType::Http,
+            logic,
+            ws_hooks: None,
+            parameters: vec![proto::models::RouteParameter {
+                name: "code".to_string(),
+                param_type: "query".to_string(),
+                data_type: "string".to_string(),
+                required: false,
+                default_value: None,
+                validation: Some("[a-z".to_string()),
+            }],
+            request_schema: None,
+            response_schema: None,
+            response_schema_policy: Default::default(),
+            auth_required: false,
+            required_scopes: vec![],
+            required_roles: vec![],
+            rate_limit: None,
+            rate_limit_key: None,
+            limits: None,
+            optimize: true,
+            enabled: true,
+            version: "1.0.0".to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            created_by: "test".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lint_route_reports_and_register_rejects_errors() {
+        let service = create_test_service();
+        let route = lint_test_route(vec![
+            LogicOperation::Return {
+                value: Value::String("{{id}} {{code}} {{cde}}".to_string()),
+                status: None,
+                headers: None,
+                raw: None,
+            },
+        ]);
+
+        let diagnostics = service.lint_route(&route);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].path, "logic[0]");
+        assert!(!diagnostics[0].is_error());
+        assert!(diagnostics[0].message.contains("'cde'"));
+        assert_eq!(diagnostics[1].path, "parameters[0].validation");
+        assert!(diagnostics[1].is_error());
+
+        let err = service.register_route(route).await.unwrap_err();
+        assert!(err.contains("parameters[0].validation"), "unexpected error: {}", err);
+    }
+    #[tokio::test]
+    async fn test_registered_routes_run_optimized_logic_unless_turned_off() {
+        let service = create_test_service();
+        let logic = vec![
+            LogicOperation::Comment { text: "greeting".to_string() },
+            LogicOperation::Set { var: "greeting".to_string(), value: Value::String(String::new()) },
+            LogicOperation::If {
+                condition: "${2 > 1}".to_string(),
+                then: vec![LogicOperation::Set { var: "greeting".to_string(), value: Value::String("${'hi' | upper}".to_string()) }],
+                otherwise: None,
+            },
+            LogicOperation::Return { value: Value::String("{{greeting}}".to_string()), status: None, headers: None, raw: None },
+        ];
+        let mut route = lint_test_route(logic.clone());
+        route.parameters.clear();
+
+        let optimized = service.register_route(route.clone()).await.unwrap();
+        let plan = service.cached_plan(&optimized).unwrap();
+        assert_eq!(serde_json::to_value(plan.logic.as_ref()).unwrap(), serde_json::json!([
+            {"set": {"var": "greeting", "value": ""}},
+            {"set": {"var": "greeting", "value": "HI"}},
+            {"return": {"value": "{{greeting}}"}},
+        ]));
+        let result = service.execute_route(&optimized, None, HashMap::new(), HashMap::new()).await.unwrap();
+        assert_eq!(result, Value::String("HI".to_string()));
+
+        route.path = "/plain/{id}".to_string();
+        route.optimize = false;
+        let plain = service.register_route(route).await.unwrap();
+        assert_eq!(service.cached_plan(&plain).unwrap().logic.len(), logic.len());
+        let result = service.execute_route(&plain, None, HashMap::new(), HashMap::new()).await.unwrap();
+        assert_eq!(result, Value::String("HI".to_string()));
+    }
+
+    fn stored_route(id: &str, result: i64) -> RouteDefinition {
+        let mut route = lint_test_route(vec![
+            LogicOperation::Return { value: serde_json::json!(result), status: None, headers: None, raw: None },
+        ]);
+        route.id = id.to_string();
+        route.path = format!("/{}", id);
+        route.parameters.clear();
+        route
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_routes_of_one_shape_are_never_both_registered() {
+        let data_dir = std::env::temp_dir().join(format!("worpen_test_{}", uuid::Uuid::new_v4()));
+        let service = Arc::new(DynamicRouteService::with_data_dir(data_dir.display().to_string()));
+        let registrations: Vec<_> = ["id", "name", "slug", "key"].iter().enumerate()
+            .map(|(i, param)| {
+                let mut route = stored_route(&format!("shape_{}", i), i as i64);
+                route.path = format!("/shape/{{{}}}", param);
+                let service = service.clone();
+                tokio::spawn(async move { service.register_route(route).await })
+            })
+            .collect();
+        let mut errors = Vec::new();
+        for registration in registrations {
+            if let Err(e) = registration.await.unwrap() {
+                errors.push(e);
+            }
+        }
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors.iter().all(|e| e.contains("Route conflict")), "{:?}", errors);
+        assert_eq!(service.list_routes().await.unwrap().len(), 1);
+
+        // Moving another route onto the taken shape is refused and leaves it where it was
+        let other = stored_route("shape_other", 0);
+        service.register_route(other.clone()).await.unwrap();
+        let mut moved = other.clone();
+        moved.path = "/shape/{other}".to_string();
+        assert!(service.update_route("shape_other", moved).await.unwrap_err().contains("Route conflict"));
+        assert_eq!(service.get_route("shape_other").await.unwrap().unwrap().path, other.path);
+        assert!(matches!(service.match_route("GET", "/shape_other").await, RouteLookup::Matched(_)));
+        std::fs::remove_dir_all(data_dir).ok();
+    }
+
+    fn write_json(path: std::path::PathBuf, value: &impl serde::Serialize) {
+        std::fs::write(path, serde_json::to_string(value).unwrap()).unwrap();
+    }
+
+    fn read_plan(path: &std::path::Path) -> PersistedPlan {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_startup_uses_stored_plans_and_quarantines_broken_routes() {
+        let data_dir = std::env::temp_dir().join(format!("worpen_test_{}", uuid::Uuid::new_v4()));
+        let routes_dir = data_dir.join("routes");
+        std::fs::create_dir_all(&routes_dir).unwrap();
+        let data_dir_str = data_dir.to_str().unwrap().to_string();
+        write_json(routes_dir.join("one.json"), &stored_route("one", 1));
+        write_json(routes_dir.join("two.json"), &stored_route("two", 2));
+        let mut bad = stored_route("bad", 0);
+        bad.parameters = lint_test_route(vec![]).parameters;
+        write_json(routes_dir.join("bad.json"), &bad);
+        std::fs::write(routes_dir.join("broken.json"), "{ not a route").unwrap();
+
+        // Broken files don't keep the others from loading, and plans are stored for the rest
+        let service = DynamicRouteService::with_data_dir(data_dir_str.clone());
+        assert_eq!(service.execute_route("one", None, HashMap::new(), HashMap::new()).await.unwrap(), serde_json::json!(1));
+        let mut quarantined: Vec<Option<String>> = service.quarantined_routes().into_iter().map(|q| q.route_id).collect();
+        quarantined.sort();
+        assert_eq!(quarantined, vec![None, Some("bad".to_string())]);
+        assert!(!routes_dir.join("bad.plan").exists());
+        let one = read_plan(&routes_dir.join("one.plan"));
+        assert!(one.is_current(&one.source_hash));
+
+        // A current plan is run as stored, without recompiling the route
+        let mut swapped = read_plan(&routes_dir.join("two.plan"));
+        swapped.source_hash = one.source_hash.clone();
+        write_json(routes_dir.join("one.plan"), &swapped);
+        let service = DynamicRouteService::with_data_dir(data_dir_str.clone());
+        assert_eq!(service.execute_route("one", None, HashMap::new(), HashMap::new()).await.unwrap(), serde_json::json!(2));
+
+        // A stale one is compiled again and replaced
+        swapped.source_hash = "stale".to_string();
+        write_json(routes_dir.join("one.plan"), &swapped);
+        let service = DynamicRouteService::with_data_dir(data_dir_str.clone());
+        assert_eq!(service.execute_route("one", None, HashMap::new(), HashMap::new()).await.unwrap(), serde_json::json!(1));
+        assert_eq!(read_plan(&routes_dir.join("one.plan")).source_hash, one.source_hash);
+
+        // Registering a route stores a plan the next start accepts, and ends its quarantine
+        bad.parameters.clear();
+        service.register_route(bad).await.unwrap();
+        assert_eq!(service.quarantined_routes().len(), 1);
+        let plan_path = routes_dir.join("bad.plan");
+        for _ in 0..200 {
+            if plan_path.exists() && serde_json::from_str::<PersistedPlan>(&std::fs::read_to_string(&plan_path).unwrap()).is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let registered = read_plan(&plan_path);
+        let service = DynamicRouteService::with_data_dir(data_dir_str);
+        assert_eq!(service.quarantined_routes().len(), 1);
+        assert_eq!(read_plan(&plan_path).source_hash, registered.source_hash);
+        assert_eq!(service.execute_route("bad", None, HashMap::new(), HashMap::new()).await.unwrap(), serde_json::json!(0));
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
 }
@@ -0,0 +1,236 @@
+use crate::sql_params::NamedQuery;
+use base64::Engine;
+use proto::models::SqlParams;
+use serde_json::{json, Value};
+use sqlx::sqlite::{SqliteArguments, SqliteRow};
+use sqlx::{Column, Executor, Row, Sqlite, TypeInfo, ValueRef};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Key of the object a BLOB is written as in route logic and results: `{"$blob": "<base64>"}`
+pub const BLOB_KEY: &str = "$blob";
+
+/// A resolved `sql_op` or `query_db` argument, bound with the SQLite storage class of its value
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlArg {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+    /// An array or object, bound as its JSON text
+    Json(String),
+}
+
+impl SqlArg {
+    /// Booleans are bound as 0 and 1, which is how SQLite stores them
+    pub fn from_value(value: Value) -> Result<Self, String> {
+        Ok(match value {
+            Value::Null => Self::Null,
+            Value::Bool(b) => Self::Integer(b as i64),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => Self::Integer(i),
+                None => Self::Real(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(s) => Self::Text(s),
+            Value::Object(map) if map.len() == 1 && map.contains_key(BLOB_KEY) => match &map[BLOB_KEY] {
+                Value::String(encoded) => Self::Blob(base64::engine::general_purpose::STANDARD.decode(encoded)
+                    .map_err(|e| format!("Invalid {} argument: {}", BLOB_KEY, e))?),
+                other => return Err(format!("{} argument must be a base64 string, got {}", BLOB_KEY, other)),
+            },
+            other => Self::Json(other.to_string()),
+        })
+    }
+
+    fn bind<'q>(self, query: sqlx::query::Query<'q, Sqlite, SqliteArguments<'q>>) -> sqlx::query::Query<'q, Sqlite, SqliteArguments<'q>> {
+        match self {
+            Self::Null => query.bind(None::<String>),
+            Self::Integer(i) => query.bind(i),
+            Self::Real(f) => query.bind(f),
+            Self::Text(s) | Self::Json(s) => query.bind(s),
+            Self::Blob(bytes) => query.bind(bytes),
+        }
+    }
+}
+
+/// Number of arguments a statement takes. A plain `?` is numbered one past the
+/// highest parameter so far, `?NNN` takes that number, and each distinct
+/// `:name`, `@name` or `$name` counts once. Literals and comments are skipped.
+pub fn parameter_count(query: &str) -> usize {
+    let chars: Vec<char> = query.chars().collect();
+    let mut highest = 0;
+    let mut names: Vec<String> = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            quote @ ('\'' | '"' | '`') => {
+                i += 1;
+                while i < chars.len() && chars[i] != quote {
+                    i += 1;
+                }
+            },
+            '[' => {
+                while i < chars.len() && chars[i] != ']' {
+                    i += 1;
+                }
+            },
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            },
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i += 1;
+            },
+            '?' => {
+                let digits: String = chars[i + 1..].iter().take_while(|c| c.is_ascii_digit()).collect();
+                i += digits.len();
+                highest = match digits.parse::<usize>() {
+                    Ok(number) => highest.max(number),
+                    Err(_) => highest + 1,
+                };
+            },
+            ':' | '@' | '$' if chars.get(i + 1).is_some_and(|c| c.is_alphabetic() || *c == '_') => {
+                let name: String = chars[i + 1..].iter().take_while(|c| c.is_alphanumeric() || **c == '_').collect();
+                i += name.len();
+                if !names.contains(&name) {
+                    names.push(name);
+                    highest += 1;
+                }
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+    highest
+}
+
+/// A statement and its arguments in positional form. `:name` placeholders
+/// become `?`, bound to `params[name]`, or else to the route variable of that
+/// name (as `{{name}}`); `?` placeholders are bound to `args` in order.
+pub fn positional_args(query: &str, args: &[Value], params: Option<&HashMap<String, Value>>) -> Result<(String, Vec<Value>), String> {
+    let named = NamedQuery::parse(query);
+    if named.param_names.is_empty() {
+        if params.is_some_and(|params| !params.is_empty()) {
+            return Err("Query has no :name parameters for `params`".to_string());
+        }
+        return Ok((query.to_string(), args.to_vec()));
+    }
+    if !args.is_empty() {
+        return Err("Query takes :name parameters; give them in `params`, not `args`".to_string());
+    }
+    let mut distinct = named.param_names.clone();
+    distinct.sort();
+    distinct.dedup();
+    if parameter_count(query) != distinct.len() {
+        return Err("Query mixes :name parameters with other placeholders".to_string());
+    }
+    let args = named.param_names.iter()
+        .map(|name| params.and_then(|params| params.get(name)).cloned()
+            .unwrap_or_else(|| Value::String(format!("{{{{{}}}}}", name))))
+        .collect();
+    Ok((named.query, args))
+}
+
+/// `query_db` parameters as `positional_args` takes them
+pub fn split_params(params: &SqlParams) -> (&[Value], Option<&HashMap<String, Value>>) {
+    match params {
+        SqlParams::Positional(args) => (args, None),
+        SqlParams::Named(params) => (&[], Some(params)),
+    }
+}
+
+/// A transaction statements run in, shared by the parallel tasks inside it.
+/// Dropping the last handle of one still open rolls it back.
+pub type SharedTransaction = Arc<tokio::sync::Mutex<sqlx::Transaction<'static, Sqlite>>>;
+
+/// Run a statement with its bound arguments, on a pool or on a transaction's
+/// connection, and return the rows it yields
+pub async fn fetch_all<'c, E>(executor: E, query: &str, args: Vec<SqlArg>) -> Result<Value, String>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let query = args.into_iter().fold(sqlx::query(query), |query, arg| arg.bind(query));
+    let rows = query.fetch_all(executor).await
+        .map_err(|e| format!("SQL execution error: {}", e))?;
+    Ok(Value::Array(rows.iter().map(row_to_json).collect()))
+}
+
+/// Run a statement without arguments on a transaction, such as `SAVEPOINT sp_1`
+pub async fn execute_in(transaction: &SharedTransaction, statement: &str) -> Result<(), String> {
+    let mut transaction = transaction.lock().await;
+    sqlx::query(statement).execute(&mut **transaction).await
+        .map(|_| ())
+        .map_err(|e| format!("SQL execution error: {}", e))
+}
+
+/// A row as a JSON object keyed by column name
+pub fn row_to_json(row: &SqliteRow) -> Value {
+    let mut obj = serde_json::Map::new();
+    for (i, col) in row.columns().iter().enumerate() {
+        // Try to get the value as different types
+        let val = if row.try_get_raw(i).map_or(true, |raw| raw.is_null()) {
+            Value::Null
+        } else if let Some(v) = (col.type_info().name() == "BOOLEAN").then(|| row.try_get::<bool, _>(i).ok()).flatten() {
+            // SQLite stores booleans as integers; the declared type tells them apart
+            Value::Bool(v)
+        } else if let Ok(v) = row.try_get::<String, _>(i) {
+            Value::String(v)
+        } else if let Ok(v) = row.try_get::<i64, _>(i) {
+            Value::Number(v.into())
+        } else if let Ok(v) = row.try_get::<f64, _>(i) {
+            Value::Number(serde_json::Number::from_f64(v).unwrap_or(0.into()))
+        } else if let Ok(v) = row.try_get::<bool, _>(i) {
+            Value::Bool(v)
+        } else if let Ok(v) = row.try_get::<Vec<u8>, _>(i) {
+            json!({ BLOB_KEY: base64::engine::general_purpose::STANDARD.encode(v) })
+        } else {
+            Value::Null
+        };
+        obj.insert(col.name().to_string(), val);
+    }
+    Value::Object(obj)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args_keep_their_type() {
+        assert_eq!(SqlArg::from_value(json!(null)).unwrap(), SqlArg::Null);
+        assert_eq!(SqlArg::from_value(json!(true)).unwrap(), SqlArg::Integer(1));
+        assert_eq!(SqlArg::from_value(json!(42)).unwrap(), SqlArg::Integer(42));
+        assert_eq!(SqlArg::from_value(json!(1.5)).unwrap(), SqlArg::Real(1.5));
+        assert_eq!(SqlArg::from_value(json!("42")).unwrap(), SqlArg::Text("42".to_string()));
+        assert_eq!(SqlArg::from_value(json!({"$blob": "AAEC"})).unwrap(), SqlArg::Blob(vec![0, 1, 2]));
+        assert_eq!(SqlArg::from_value(json!({"a": [1]})).unwrap(), SqlArg::Json("{\"a\":[1]}".to_string()));
+        assert!(SqlArg::from_value(json!({"$blob": "not base64!"})).is_err());
+    }
+
+    #[test]
+    fn test_named_parameters_become_positional() {
+        let params: HashMap<String, Value> = [("min".to_string(), json!("${limit - 1}"))].into();
+        let (query, args) = positional_args("SELECT * FROM t WHERE a > :min AND b = :name OR c = :min", &[], Some(&params)).unwrap();
+        assert_eq!(query, "SELECT * FROM t WHERE a > ? AND b = ? OR c = ?");
+        assert_eq!(args, vec![json!("${limit - 1}"), json!("{{name}}"), json!("${limit - 1}")]);
+
+        assert_eq!(positional_args("SELECT ?", &[json!(1)], None).unwrap(), ("SELECT ?".to_string(), vec![json!(1)]));
+        assert!(positional_args("SELECT :a", &[json!(1)], None).is_err());
+        assert!(positional_args("SELECT :a, ?", &[], None).is_err());
+        assert!(positional_args("SELECT ?", &[json!(1)], Some(&params)).is_err());
+    }
+
+    #[test]
+    fn test_parameter_count() {
+        assert_eq!(parameter_count("SELECT 1"), 0);
+        assert_eq!(parameter_count("INSERT INTO t VALUES (?, ?, ?)"), 3);
+        assert_eq!(parameter_count("SELECT * FROM t WHERE a = ?2 OR b = ?1 OR c = ?"), 3);
+        assert_eq!(parameter_count("SELECT * FROM t WHERE a = :id OR b = :id OR c = @name"), 2);
+        assert_eq!(parameter_count("SELECT '?', \"a?\" FROM t -- is it?\nWHERE a = ? /* or ? */"), 1);
+    }
+}
@@ -1,10 +1,16 @@
 use serde_json::Value;
+use regex::Regex;
 use proto::models::DynamicRouteExecutionContext;
 use super::utils::resolve_string;
 
 pub fn handle_string_op(operation: &str, input: &str, args: &[Value], context: &DynamicRouteExecutionContext) -> Value {
     let input_str = resolve_string(input, context);
-    
+    compute_string_op(operation, input_str, context.variables.get(input), args)
+}
+
+/// Apply a string operation to a resolved input (shared by interpreter and VM).
+/// `input_var` is the raw variable named by `input`, used by `join`.
+pub fn compute_string_op(operation: &str, input_str: String, input_var: Option<&Value>, args: &[Value]) -> Value {
     match operation {
         "split" => {
             let delimiter = args.first().and_then(|v| v.as_str()).unwrap_or(",");
@@ -13,7 +19,7 @@ pub fn handle_string_op(operation: &str, input: &str, args: &[Value], context: &
         },
         "join" => {
             let delimiter = args.first().and_then(|v| v.as_str()).unwrap_or("");
-            if let Some(Value::Array(arr)) = context.variables.get(input) {
+            if let Some(Value::Array(arr)) = input_var {
                 let joined = arr.iter().filter_map(|v| v.as_str()).collect::<Vec<&str>>().join(delimiter);
                 Value::String(joined)
             } else {
@@ -28,6 +34,12 @@ pub fn handle_string_op(operation: &str, input: &str, args: &[Value], context: &
             let to = args.get(1).and_then(|v| v.as_str()).unwrap_or("");
             Value::String(input_str.replace(from, to))
         },
+        "regex_match" => {
+            let pattern = args.first().and_then(|v| v.as_str()).unwrap_or("");
+            Regex::new(pattern)
+                .map(|re| Value::Bool(re.is_match(&input_str)))
+                .unwrap_or(Value::Null)
+        },
         _ => Value::Null,
     }
 }
@@ -2,6 +2,7 @@ use proto::models::DynamicRouteExecutionContext;
 use serde_json::Value;
 use regex::Regex;
 use once_cell::sync::Lazy;
+use crate::expression::CompiledExpression;
 
 // Static Regex compilation to avoid overhead on every call
 static EXPR_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$\{([^}]+)\}").unwrap());
@@ -175,69 +176,47 @@ pub fn resolve_variables(value: &Value, context: &DynamicRouteExecutionContext)
     }
 }
 
-// Enhanced condition evaluation
-pub fn evaluate_condition(condition: &str, context: &DynamicRouteExecutionContext) -> bool {
-    // Optimization: Check for simple boolean literals before resolving string
-    if condition == "true" { return true; }
-    if condition == "false" { return false; }
-
-    let resolved = resolve_string(condition, context);
-    
-    // Support multiple operators
-    if resolved.contains("==") {
-        let parts: Vec<&str> = resolved.split("==").map(|s| s.trim()).collect();
-        if parts.len() == 2 {
-            return parts[0] == parts[1];
-        }
-    }
-    
-    if resolved.contains("!=") {
-        let parts: Vec<&str> = resolved.split("!=").map(|s| s.trim()).collect();
-        if parts.len() == 2 {
-            return parts[0] != parts[1];
-        }
-    }
-    
-    if resolved.contains(">=") {
-        let parts: Vec<&str> = resolved.split(">=").collect();
-        if parts.len() == 2 {
-            if let (Ok(left), Ok(right)) = (parts[0].trim().parse::<f64>(), parts[1].trim().parse::<f64>()) {
-                return left >= right;
-            }
-        }
-    }
-    
-    if resolved.contains("<=") {
-        let parts: Vec<&str> = resolved.split("<=").collect();
-        if parts.len() == 2 {
-            if let (Ok(left), Ok(right)) = (parts[0].trim().parse::<f64>(), parts[1].trim().parse::<f64>()) {
-                return left <= right;
+/// Look up a variable or nested path (`order.status`) the way templates do:
+/// error context first, then variables, then top-level payload fields
+pub fn lookup_path(path: &str, context: &DynamicRouteExecutionContext) -> Option<Value> {
+    if let Some(error_ctx) = &context.error_context {
+        match path {
+            "error.message" => return Some(Value::String(error_ctx.message.clone())),
+            "error.code" => {
+                let code = error_ctx.code.clone().unwrap_or_else(|| "UNKNOWN_ERROR".to_string());
+                return Some(Value::String(code));
             }
+            _ => {}
         }
     }
-    
-    if resolved.contains(">") {
-        let parts: Vec<&str> = resolved.split(">").collect();
-        if parts.len() == 2 {
-            if let (Ok(left), Ok(right)) = (parts[0].trim().parse::<f64>(), parts[1].trim().parse::<f64>()) {
-                return left > right;
-            }
-        }
+
+    let (root, rest) = match path.split_once('.') {
+        Some((root, rest)) => (root, Some(rest)),
+        None => (path, None),
+    };
+    let root_value = context.variables.get(root)
+        .or_else(|| context.request_payload.as_ref().and_then(|p| p.get(root)))?;
+
+    match rest {
+        Some(rest) => match get_json_path(root_value, rest) {
+            Value::Null => None,
+            nested => Some(nested),
+        },
+        None => Some(root_value.clone()),
     }
-    
-    if resolved.contains("<") {
-        let parts: Vec<&str> = resolved.split("<").collect();
-        if parts.len() == 2 {
-            if let (Ok(left), Ok(right)) = (parts[0].trim().parse::<f64>(), parts[1].trim().parse::<f64>()) {
-                return left < right;
-            }
-        }
+}
+
+// Condition evaluation through the expression engine (same rules as the VM)
+pub fn evaluate_condition(condition: &str, context: &DynamicRouteExecutionContext) -> Result<bool, String> {
+    CompiledExpression::cached(condition)?.is_true(|path| lookup_path(path, context))
+}
+
+/// A switch case matches on equal values, or on the subject's text for string cases
+pub fn switch_case_matches(case_value: &Value, subject: &Value) -> bool {
+    match (case_value, subject) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (Value::String(text), Value::String(s)) => text == s,
+        (Value::String(text), other) => text.as_str() == other.to_string().as_str(),
+        _ => case_value == subject,
     }
-    
-    // Boolean literals
-    if resolved == "true" { return true; }
-    if resolved == "false" { return false; }
-    
-    // Default
-    true
 }
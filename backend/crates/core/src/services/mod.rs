@@ -5,6 +5,7 @@ pub mod incident;
 pub mod automation;
 pub mod pipelines;
 pub mod dynamic_routes;
+pub mod models;
 
 
 pub use agent_service::AgentService;
@@ -14,3 +15,4 @@ pub use incident::IncidentService;
 pub use automation::AutomationService;
 pub use pipelines::PipelineService;
 pub use dynamic_routes::DynamicRouteService;
+pub use models::ModelService;
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use proto::models::{
+    DataModel, ModelApplyResult, ModelMigration, ModelPlan, ModelRouteDiff, RegisteredModel, RouteDefinition, RouteDiffStatus,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::SqlitePool;
+use crate::data_model::{self, crud, migration};
+use crate::datasource::DEFAULT_DATASOURCE;
+use super::dynamic_routes::cache;
+use super::dynamic_routes::DynamicRouteService;
+
+/// Route fields that change on every registration rather than with the model
+const BOOKKEEPING_FIELDS: [&str; 4] = ["id", "created_at", "updated_at", "created_by"];
+
+/// What registering a model may do beyond the safe changes
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ApplyOptions {
+    /// Run a migration that drops or retypes columns
+    #[serde(default)]
+    pub allow_data_loss: bool,
+    /// Replace generated routes that were edited since
+    #[serde(default)]
+    pub overwrite_edited: bool,
+}
+
+/// Declarative data models: keeps each model's table migrated and its CRUD routes registered
+pub struct ModelService {
+    routes: Arc<DynamicRouteService>,
+    models: std::sync::RwLock<HashMap<String, RegisteredModel>>,
+    // One model change at a time, so migrations never interleave
+    apply_lock: tokio::sync::Mutex<()>,
+}
+
+impl ModelService {
+    /// Models are persisted next to the routes they generate
+    pub fn new(routes: Arc<DynamicRouteService>) -> Self {
+        let service = Self {
+            routes,
+            models: std::sync::RwLock::new(HashMap::new()),
+            apply_lock: tokio::sync::Mutex::new(()),
+        };
+        service.load_persisted_models();
+        service
+    }
+
+    pub fn list_models(&self) -> Vec<RegisteredModel> {
+        let mut models: Vec<RegisteredModel> = self.models.read().unwrap().values().cloned().collect();
+        models.sort_by(|a, b| a.model.name.cmp(&b.model.name));
+        models
+    }
+
+    pub fn get_model(&self, name: &str) -> Option<RegisteredModel> {
+        self.models.read().unwrap().get(name).cloned()
+    }
+
+    /// What registering the model would change, without changing anything
+    pub async fn plan(&self, model: &DataModel) -> Result<ModelPlan, String> {
+        data_model::validate(model)?;
+        self.plan_against(model, self.get_model(&model.name).as_ref()).await
+    }
+
+    /// Register a model, or a new version of one: migrate its table, then
+    /// (re)generate its routes. Routes edited since they were generated are
+    /// left alone unless `overwrite_edited` is set.
+    pub async fn apply(&self, model: DataModel, options: ApplyOptions) -> Result<ModelApplyResult, String> {
+        data_model::validate(&model)?;
+        let _guard = self.apply_lock.lock().await;
+        let previous = self.get_model(&model.name);
+        let plan = self.plan_against(&model, previous.as_ref()).await?;
+        if plan.destructive && !options.allow_data_loss {
+            return Err(format!(
+                "Migrating model '{}' drops or retypes columns and loses their data; allow data loss to apply it",
+                model.name,
+            ));
+        }
+
+        let pool = self.pool(&model)?;
+        let table = data_model::table_name(&model);
+        if previous.is_none() && table_exists(&pool, &table).await? {
+            return Err(format!("Table '{}' already exists and isn't managed by a model", table));
+        }
+
+        // Routes are checked before the table changes, so a clash doesn't leave a half-applied model
+        let (replace, skipped): (Vec<&ModelRouteDiff>, Vec<&ModelRouteDiff>) = plan.routes.iter()
+            .filter(|diff| diff.status != RouteDiffStatus::UpToDate)
+            .partition(|diff| diff.status != RouteDiffStatus::Edited || options.overwrite_edited);
+        for diff in &replace {
+            self.routes.check_route_conflict(&diff.generated)?;
+        }
+
+        let mut record = previous.unwrap_or_else(|| RegisteredModel {
+            model: model.clone(),
+            version: 0,
+            migrations: vec![],
+            generated_routes: HashMap::new(),
+            updated_at: String::new(),
+        });
+        if !plan.statements.is_empty() {
+            let version = record.version + 1;
+            migrate(&pool, &model.name, version, &plan.statements).await?;
+            record.version = version;
+            record.migrations.push(ModelMigration {
+                version,
+                statements: plan.statements.clone(),
+                destructive: plan.destructive,
+                applied_at: chrono::Utc::now().to_rfc3339(),
+            });
+        }
+        record.model = model.clone();
+        record.updated_at = chrono::Utc::now().to_rfc3339();
+        self.store(&record).await?;
+
+        for diff in plan.routes.iter().filter(|diff| diff.status == RouteDiffStatus::UpToDate) {
+            record.generated_routes.insert(diff.route_id.clone(), route_hash(&diff.generated));
+        }
+        for diff in replace {
+            let route = diff.generated.clone();
+            let registered = match diff.status {
+                RouteDiffStatus::Missing => self.routes.register_route(route).await.map(|_| ()),
+                _ => self.routes.update_route(&diff.route_id, route).await,
+            };
+            registered.map_err(|e| format!(
+                "Model '{}' is at version {}, but its route '{}' failed to register: {}",
+                model.name, record.version, diff.route_id, e,
+            ))?;
+            if let Some(stored) = self.routes.get_route(&diff.route_id).await? {
+                record.generated_routes.insert(diff.route_id.clone(), route_hash(&stored));
+            }
+        }
+        self.store(&record).await?;
+
+        let skipped_routes = skipped.iter().map(|diff| diff.route_id.clone()).collect();
+        Ok(ModelApplyResult { model: record, plan, skipped_routes })
+    }
+
+    /// Forget a model and remove the routes generated from it that weren't
+    /// edited. The table and its rows stay. Returns the removed route ids.
+    pub async fn delete_model(&self, name: &str) -> Result<Vec<String>, String> {
+        let _guard = self.apply_lock.lock().await;
+        let record = self.get_model(name).ok_or_else(|| format!("Model '{}' not found", name))?;
+        let mut removed = Vec::new();
+        for action in crud::ACTIONS {
+            let route_id = crud::route_id(name, action);
+            let Some(route) = self.routes.get_route(&route_id).await? else { continue };
+            if record.generated_routes.get(&route_id) == Some(&route_hash(&route)) {
+                self.routes.delete_route(&route_id).await?;
+                removed.push(route_id);
+            }
+        }
+
+        self.models.write().unwrap().remove(name);
+        let path = Self::model_path(self.routes.data_dir(), name);
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            tokio::fs::remove_file(&path).await
+                .map_err(|e| format!("Failed to delete model file {}: {}", path.display(), e))?;
+        }
+        Ok(removed)
+    }
+
+    async fn plan_against(&self, model: &DataModel, previous: Option<&RegisteredModel>) -> Result<ModelPlan, String> {
+        let migration = migration::plan(previous.map(|record| &record.model), model)?;
+        let mut routes = Vec::new();
+        for generated in crud::generate_routes(model) {
+            let current = self.routes.get_route(&generated.id).await?;
+            routes.push(route_diff(generated, current.as_ref(), previous));
+        }
+        Ok(ModelPlan { statements: migration.statements, destructive: migration.destructive, routes })
+    }
+
+    fn pool(&self, model: &DataModel) -> Result<SqlitePool, String> {
+        let datasource = model.datasource.as_deref().unwrap_or(DEFAULT_DATASOURCE);
+        self.routes.datasource_pools().remove(datasource)
+            .ok_or_else(|| format!("Unknown datasource '{}'", datasource))
+    }
+
+    fn model_path(data_dir: &str, name: &str) -> std::path::PathBuf {
+        std::path::Path::new(data_dir).join("models").join(format!("{}.json", name))
+    }
+
+    /// Keep a model in memory and on disk
+    async fn store(&self, record: &RegisteredModel) -> Result<(), String> {
+        self.models.write().unwrap().insert(record.model.name.clone(), record.clone());
+        let path = Self::model_path(self.routes.data_dir(), &record.model.name);
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await
+                .map_err(|e| format!("Failed to create models dir: {}", e))?;
+        }
+        let content = serde_json::to_string_pretty(record)
+            .map_err(|e| format!("Failed to serialize model: {}", e))?;
+        tokio::fs::write(&path, content).await
+            .map_err(|e| format!("Failed to write model file {}: {}", path.display(), e))
+    }
+
+    fn load_persisted_models(&self) {
+        let dir = std::path::Path::new(self.routes.data_dir()).join("models");
+        let Ok(entries) = std::fs::read_dir(&dir) else { return };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                continue;
+            }
+            let record = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|content| serde_json::from_str::<RegisteredModel>(&content).map_err(|e| e.to_string()));
+            match record {
+                Ok(record) => {
+                    self.models.write().unwrap().insert(record.model.name.clone(), record);
+                },
+                Err(e) => eprintln!("[WARN] Skipping model file {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+/// How a registered route compares to the one the model generates now
+fn route_diff(generated: RouteDefinition, current: Option<&RouteDefinition>, previous: Option<&RegisteredModel>) -> ModelRouteDiff {
+    let (status, changes) = match current {
+        None => (RouteDiffStatus::Missing, vec![]),
+        Some(current) => {
+            let changes = changed_fields(current, &generated);
+            let unedited = previous
+                .and_then(|record| record.generated_routes.get(&generated.id))
+                .is_some_and(|hash| *hash == route_hash(current));
+            let status = if changes.is_empty() {
+                RouteDiffStatus::UpToDate
+            } else if unedited {
+                RouteDiffStatus::Outdated
+            } else {
+                RouteDiffStatus::Edited
+            };
+            (status, changes)
+        },
+    };
+    ModelRouteDiff { route_id: generated.id.clone(), status, changes, generated }
+}
+
+/// A route's content, without the fields registration fills in
+fn route_content(route: &RouteDefinition) -> serde_json::Map<String, Value> {
+    let mut content = match serde_json::to_value(route) {
+        Ok(Value::Object(content)) => content,
+        _ => serde_json::Map::new(),
+    };
+    for field in BOOKKEEPING_FIELDS {
+        content.remove(field);
+    }
+    content
+}
+
+fn route_hash(route: &RouteDefinition) -> String {
+    cache::fingerprint(&Value::Object(route_content(route)).to_string())
+}
+
+fn changed_fields(current: &RouteDefinition, generated: &RouteDefinition) -> Vec<String> {
+    let (current, generated) = (route_content(current), route_content(generated));
+    generated.iter()
+        .filter(|(field, value)| current.get(*field) != Some(value))
+        .map(|(field, _)| field.clone())
+        .collect()
+}
+
+async fn table_exists(pool: &SqlitePool, table: &str) -> Result<bool, String> {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+        .bind(table)
+        .fetch_one(pool).await
+        .map(|count| count > 0)
+        .map_err(|e| format!("Failed to look up table '{}': {}", table, e))
+}
+
+/// Run a migration's statements in one transaction and record it in the history table
+async fn migrate(pool: &SqlitePool, model: &str, version: u32, statements: &[String]) -> Result<(), String> {
+    sqlx::query(migration::HISTORY_DDL).execute(pool).await
+        .map_err(|e| format!("Failed to create {}: {}", migration::HISTORY_TABLE, e))?;
+    let mut transaction = pool.begin().await
+        .map_err(|e| format!("Failed to start migration: {}", e))?;
+    for statement in statements {
+        sqlx::query(statement).execute(&mut *transaction).await
+            .map_err(|e| format!("Migration {} of model '{}' failed at `{}`: {}", version, model, statement, e))?;
+    }
+    sqlx::query(&format!("INSERT INTO {} (model, version, statements, applied_at) VALUES (?, ?, ?, ?)", migration::HISTORY_TABLE))
+        .bind(model)
+        .bind(version as i64)
+        .bind(serde_json::to_string(statements).unwrap_or_default())
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(&mut *transaction).await
+        .map_err(|e| format!("Failed to record migration {} of model '{}': {}", version, model, e))?;
+    transaction.commit().await
+        .map_err(|e| format!("Failed to commit migration {} of model '{}': {}", version, model, e))
+}
@@ -42,6 +42,32 @@ impl NamedQuery {
         let mut chars = sql.chars().peekable();
         
         while let Some(ch) = chars.next() {
+            // Quoted text and comments are copied as they are
+            let end = match ch {
+                '\'' | '"' | '`' => Some(ch),
+                '[' => Some(']'),
+                '-' if chars.peek() == Some(&'-') => Some('\n'),
+                '/' if chars.peek() == Some(&'*') => Some('/'),
+                _ => None,
+            };
+            if let Some(end) = end {
+                query.push(ch);
+                let mut prev = ch;
+                if end == '/' {
+                    // Skip the '*' that opened the comment, so `/*/` doesn't close it
+                    prev = chars.next().unwrap();
+                    query.push(prev);
+                }
+                for next in chars.by_ref() {
+                    query.push(next);
+                    if next == end && (end != '/' || prev == '*') {
+                        break;
+                    }
+                    prev = next;
+                }
+                continue;
+            }
+            
             if ch == ':' {
                 // Check if next char is alphanumeric (start of parameter name)
                 if let Some(&next_ch) = chars.peek() {
@@ -267,6 +293,15 @@ mod tests {
         assert!(named.query.contains("Time: 10:30"));
     }
     
+    #[test]
+    fn test_parse_skips_literals_and_comments() {
+        let sql = "SELECT ':skip', \"a:b\" FROM t -- :note\nWHERE id = :id /* :old */ AND x = :x";
+        let named = NamedQuery::parse(sql);
+        
+        assert_eq!(named.param_names, vec!["id", "x"]);
+        assert_eq!(named.query, "SELECT ':skip', \"a:b\" FROM t -- :note\nWHERE id = ? /* :old */ AND x = ?");
+    }
+    
     #[test]
     fn test_bind_params_simple() {
         let query = NamedQuery::parse("SELECT * FROM users WHERE id = :id");
@@ -0,0 +1,218 @@
+//! Structured execution traces
+//!
+//! While a trace is being collected, the VM and the interpreter record one
+//! [`TraceRecord`] per operation they run: where it sits in the logic tree, its
+//! resolved inputs, its output, how long it took and the error it failed with.
+//! The collector lives in a task-local, like the execution budget, so parallel
+//! tasks and function calls record into the same trace.
+
+pub use proto::models::{RouteTrace, TraceRecord};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// A trace stops growing here; a runaway loop shouldn't take the server's memory with it
+const MAX_RECORDS: usize = 10_000;
+
+/// Sampled traces kept in memory, across all routes
+const STORED_TRACES: usize = 100;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Inputs under a key containing one of these are never recorded
+const SENSITIVE_KEYS: &[&str] = &["password", "secret", "token", "authorization", "api_key", "apikey", "cookie", "credential"];
+
+struct Collector {
+    started: Instant,
+    records: Arc<Mutex<Vec<TraceRecord>>>,
+}
+
+tokio::task_local! {
+    static TRACE: Collector;
+}
+
+/// Whether the current execution is being traced
+pub fn enabled() -> bool {
+    TRACE.try_with(|_| ()).is_ok()
+}
+
+/// Run `execution` and return what it recorded. Inside another trace, the
+/// records go to that one instead.
+pub async fn collect<T, F>(execution: F) -> (T, Vec<TraceRecord>)
+where
+    F: Future<Output = T>,
+{
+    if enabled() {
+        return (execution.await, vec![]);
+    }
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let collector = Collector { started: Instant::now(), records: records.clone() };
+    let output = TRACE.scope(collector, execution).await;
+    let records = std::mem::take(&mut *records.lock().unwrap());
+    (output, records)
+}
+
+/// An operation that started running; [`Span::finish`] records it
+pub struct Span {
+    path: String,
+    op: String,
+    inputs: Value,
+    started: Instant,
+}
+
+pub fn span(path: String, op: &str, inputs: Value) -> Span {
+    Span { path, op: op.to_string(), inputs, started: Instant::now() }
+}
+
+impl Span {
+    pub fn finish(self, outcome: Result<&Value, &String>) {
+        let _ = TRACE.try_with(|collector| {
+            let mut records = collector.records.lock().unwrap();
+            if records.len() >= MAX_RECORDS {
+                return;
+            }
+            let (output, error) = match outcome {
+                Ok(output) => (output.clone(), None),
+                Err(e) => (Value::Null, Some(e.clone())),
+            };
+            records.push(TraceRecord {
+                path: self.path,
+                op: self.op,
+                inputs: self.inputs,
+                output,
+                started_us: self.started.duration_since(collector.started).as_micros() as u64,
+                duration_us: self.started.elapsed().as_micros() as u64,
+                error,
+            });
+        });
+    }
+}
+
+/// Replace values under sensitive keys (passwords, tokens, auth headers...) with a marker
+pub fn redact(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(fields.into_iter()
+            .map(|(key, item)| {
+                let lower = key.to_ascii_lowercase();
+                if SENSITIVE_KEYS.iter().any(|s| lower.contains(s)) {
+                    (key, Value::String(REDACTED.to_string()))
+                } else {
+                    (key, redact(item))
+                }
+            })
+            .collect()),
+        Value::Array(items) => Value::Array(items.into_iter().map(redact).collect()),
+        other => other,
+    }
+}
+
+/// Traces of sampled live requests, newest last
+#[derive(Default)]
+pub struct TraceStore {
+    /// Trace one in this many executions; 0 turns sampling off
+    sample_every: AtomicU64,
+    seen: AtomicU64,
+    recent: Mutex<VecDeque<RouteTrace>>,
+}
+
+impl TraceStore {
+    /// Sampling set by `ROUTE_TRACE_SAMPLE_EVERY`, off when unset
+    pub fn from_env() -> Self {
+        let store = Self::default();
+        let every = std::env::var("ROUTE_TRACE_SAMPLE_EVERY").ok().and_then(|v| v.parse().ok()).unwrap_or(0);
+        store.set_sample_every(every);
+        store
+    }
+
+    pub fn set_sample_every(&self, every: u64) {
+        self.sample_every.store(every, Ordering::Relaxed);
+    }
+
+    /// Whether the execution starting now should be traced
+    pub fn should_sample(&self) -> bool {
+        match self.sample_every.load(Ordering::Relaxed) {
+            0 => false,
+            every => self.seen.fetch_add(1, Ordering::Relaxed).is_multiple_of(every),
+        }
+    }
+
+    pub fn store(&self, trace: RouteTrace) {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == STORED_TRACES {
+            recent.pop_front();
+        }
+        recent.push_back(trace);
+    }
+
+    /// The route's stored traces, newest first
+    pub fn for_route(&self, route_id: &str) -> Vec<RouteTrace> {
+        self.recent.lock().unwrap().iter()
+            .rev()
+            .filter(|trace| trace.route_id == route_id)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_collects_records_only_inside_collect() {
+        span("logic[0]".to_string(), "set", json!({})).finish(Ok(&json!(1)));
+
+        let ((), records) = collect(async {
+            assert!(enabled());
+            span("logic[0]".to_string(), "set", json!({"var": "x"})).finish(Ok(&json!(1)));
+            span("logic[1]".to_string(), "throw", json!({})).finish(Err(&"boom".to_string()));
+        }).await;
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].path, "logic[0]");
+        assert_eq!(records[0].output, json!(1));
+        assert_eq!(records[1].error.as_deref(), Some("boom"));
+        assert!(!enabled());
+    }
+
+    #[test]
+    fn test_redacts_sensitive_keys() {
+        let inputs = json!({
+            "url": "https://api.example.com",
+            "headers": {"Authorization": "Bearer abc", "Accept": "json"},
+            "body": [{"user": "ana", "password": "hunter2", "refresh_token": "t"}]
+        });
+        assert_eq!(redact(inputs), json!({
+            "url": "https://api.example.com",
+            "headers": {"Authorization": REDACTED, "Accept": "json"},
+            "body": [{"user": "ana", "password": REDACTED, "refresh_token": REDACTED}]
+        }));
+    }
+
+    #[test]
+    fn test_store_samples_and_keeps_recent() {
+        let store = TraceStore::default();
+        assert!(!store.should_sample());
+
+        store.set_sample_every(2);
+        let sampled: Vec<bool> = (0..4).map(|_| store.should_sample()).collect();
+        assert_eq!(sampled, vec![true, false, true, false]);
+
+        for i in 0..STORED_TRACES + 1 {
+            store.store(RouteTrace {
+                route_id: if i % 2 == 0 { "a" } else { "b" }.to_string(),
+                recorded_at: i.to_string(),
+                duration_us: 0,
+                error: None,
+                records: vec![],
+            });
+        }
+        let a = store.for_route("a");
+        assert_eq!(a.len(), STORED_TRACES / 2);
+        assert_eq!(a[0].recorded_at, STORED_TRACES.to_string());
+    }
+}
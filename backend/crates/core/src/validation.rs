@@ -4,6 +4,7 @@
 //! defined in YAML routes.
 
 use jsonschema::{Draft, JSONSchema};
+use serde::Serialize;
 use serde_json::Value;
 
 /// Validate input data against a JSON Schema
@@ -69,52 +70,51 @@ pub fn validate_input(data: &Value, schema: &Value) -> Result<(), String> {
 /// 
 /// Similar to `validate_input` but returns structured error information
 pub fn validate_with_details(data: &Value, schema: &Value) -> ValidationResult {
-    let compiled = match JSONSchema::options()
-        .with_draft(Draft::Draft7)
-        .compile(schema)
-    {
-        Ok(schema) => schema,
-        Err(e) => {
-            return ValidationResult {
-                valid: false,
-                errors: vec![ValidationError {
-                    path: String::new(),
-                    message: format!("Invalid schema: {}", e),
-                    schema_path: String::new(),
-                }],
-            };
+    let compiled = match compile_schema(schema) {
+        Ok(compiled) => compiled,
+        Err(message) => {
+            return ValidationResult::from_errors(vec![ValidationError {
+                path: String::new(),
+                message,
+                schema_path: String::new(),
+            }]);
         }
     };
     
-    let validation_result = compiled.validate(data);
-    
-    match validation_result {
-        Ok(_) => ValidationResult {
-            valid: true,
-            errors: vec![],
-        },
-        Err(errors) => ValidationResult {
-            valid: false,
-            errors: errors
-                .map(|e| ValidationError {
-                    path: e.instance_path.to_string(),
-                    message: e.to_string(),
-                    schema_path: e.schema_path.to_string(),
-                })
-                .collect(),
-        },
+    ValidationResult::from_errors(schema_errors(&compiled, data))
+}
+
+/// Compile a Draft 7 schema once so it can be checked against many inputs
+pub fn compile_schema(schema: &Value) -> Result<JSONSchema, String> {
+    JSONSchema::options()
+        .with_draft(Draft::Draft7)
+        .compile(schema)
+        .map_err(|e| format!("Invalid schema: {}", e))
+}
+
+/// Every way `data` violates an already compiled schema
+pub fn schema_errors(compiled: &JSONSchema, data: &Value) -> Vec<ValidationError> {
+    match compiled.validate(data) {
+        Ok(_) => vec![],
+        Err(errors) => errors
+            .map(|e| ValidationError {
+                path: e.instance_path.to_string(),
+                message: e.to_string(),
+                schema_path: e.schema_path.to_string(),
+            })
+            .collect(),
     }
 }
 
 /// Structured validation result
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ValidationResult {
     pub valid: bool,
     pub errors: Vec<ValidationError>,
 }
 
 /// Validation error details
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ValidationError {
     pub path: String,
     pub message: String,
@@ -122,6 +122,10 @@ pub struct ValidationError {
 }
 
 impl ValidationResult {
+    pub fn from_errors(errors: Vec<ValidationError>) -> Self {
+        Self { valid: errors.is_empty(), errors }
+    }
+
     /// Convert to Result type
     pub fn to_result(&self) -> Result<(), String> {
         if self.valid {
@@ -1,13 +1,17 @@
 use serde::{Deserialize, Serialize};
+use crate::compiler::symbol_table::SymbolTable;
+use crate::expression::CompiledExpression;
+use crate::vm::template::{CompiledValue, Template};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum OptimizedOperation {
     // Data Operations
     #[serde(rename = "return")]
     Return { 
-        value: Value,
+        value: CompiledValue,
         #[serde(skip_serializing_if = "Option::is_none")]
         status: Option<u16>,
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -19,44 +23,44 @@ pub enum OptimizedOperation {
     #[serde(rename = "comment")]
     Comment { text: String },
 
+    /// `:name` parameters are already turned into positional `args`
     #[serde(rename = "query_db")]
-    QueryDb { query: String, params: Vec<Value> },
+    QueryDb { query: String, args: Vec<CompiledValue>, datasource: Option<String> },
     
     #[serde(rename = "sql_op")]
-    SqlOp { query: String, arg_indices: Vec<usize>, output_var_index: usize },
+    SqlOp { query: String, args: Vec<CompiledValue>, datasource: Option<String>, output_var_index: usize },
     
     #[serde(rename = "redis_op")]
+    /// One command, or a PIPELINE's commands sent in one round trip
     RedisOp {
-        command: String,  // "GET", "SET", "DEL", "EXPIRE", "INCR", "DECR"
-        key: String,      // Template string with {{vars}}
-        value: Option<String>, // Template string with {{vars}}
-        ttl_seconds: Option<u64>,
+        commands: Vec<RedisCommand>,
+        pipeline: bool,
         output_var_index: Option<usize> // Where to store result
     },
     
     #[serde(rename = "ws_op")]
     WsOp {
         command: String,  // "send" | "broadcast"
-        message: String,  // Template string with {{vars}}
-        channel: Option<String>, // Optional channel
+        message: Template,
+        channel: Option<Template>, // Optional channel
     },
 
     #[serde(rename = "http_request")]
-    HttpRequest { url: String, method: String, body: Option<Value>, headers: Option<HashMap<String, String>>, timeout_ms: Option<u64> },
+    HttpRequest { url: Template, method: String, body: Option<CompiledValue>, headers: Option<HashMap<String, Template>>, timeout_ms: Option<u64> },
 
     // Control Flow - Basic
     #[serde(rename = "if")]
-    If { condition: String, then: Vec<OptimizedOperation>, otherwise: Option<Vec<OptimizedOperation>> },
+    If { condition: CompiledExpression, then: Vec<OptimizedOperation>, otherwise: Option<Vec<OptimizedOperation>> },
 
     #[serde(rename = "loop")]
-    Loop { collection: String, var_index: usize, body: Vec<OptimizedOperation> },
+    Loop { collection: Template, var_index: usize, body: Vec<OptimizedOperation> },
 
     // Control Flow - Advanced
     #[serde(rename = "switch")]
-    Switch { value: String, cases: Vec<OptimizedSwitchCase>, default: Option<Vec<OptimizedOperation>> },
+    Switch { value: CompiledExpression, cases: Vec<OptimizedSwitchCase>, default: Option<Vec<OptimizedOperation>> },
 
     #[serde(rename = "while")]
-    While { condition: String, body: Vec<OptimizedOperation>, max_iterations: Option<u32> },
+    While { condition: CompiledExpression, body: Vec<OptimizedOperation>, max_iterations: Option<u32> },
 
     #[serde(rename = "break")]
     Break,
@@ -69,70 +73,262 @@ pub enum OptimizedOperation {
     Try { body: Vec<OptimizedOperation>, catch: Vec<OptimizedOperation>, finally: Option<Vec<OptimizedOperation>> },
 
     #[serde(rename = "throw")]
-    Throw { message: String, code: Option<String> },
+    Throw { message: Template, code: Option<String> },
+
+    // Transactions
+    #[serde(rename = "transaction")]
+    Transaction { body: Vec<OptimizedOperation>, savepoint: bool, datasource: Option<String> },
 
     // Parallel Execution
     #[serde(rename = "parallel")]
     Parallel { tasks: Vec<Vec<OptimizedOperation>>, max_concurrent: Option<usize> },
 
-    #[serde(rename = "await_all")]
-    AwaitAll { task_ids: Vec<String> },
-
     // Function Operations
     #[serde(rename = "define_function")]
-    DefineFunction { name: String, param_indices: Vec<usize>, body: Vec<OptimizedOperation> },
+    DefineFunction { function: Arc<CompiledFunction> },
 
     #[serde(rename = "call_function")]
-    CallFunction { name: String, args: Vec<Value>, output_var_index: usize },
-
-    // Data Transformations
-    #[serde(rename = "map")]
-    Map { input: String, transform: String },
-
-    #[serde(rename = "filter")]
-    Filter { input: String, condition: String },
-
-    #[serde(rename = "aggregate")]
-    Aggregate { input: String, operation: String }, // sum, count, avg, min, max
+    CallFunction { name: String, args: Vec<CompiledValue>, output_var_index: usize },
 
     // Variable Operations
     #[serde(rename = "set")]
-    Set { var_index: usize, value: Value },
+    Set { var_index: usize, value: CompiledValue },
 
     #[serde(rename = "get")]
     Get { var_index: usize },
 
     // Helper Functions
     #[serde(rename = "string_op")]
-    StringOp { operation: String, input: String, args: Vec<Value> }, // split, join, upper, lower, trim, replace, regex_match
+    StringOp { operation: String, input: Template, args: Vec<Value> }, // split, join, upper, lower, trim, replace, regex_match
 
     #[serde(rename = "math_op")]
-    MathOp { operation: String, args: Vec<Value> }, // sum, avg, min, max, round, ceil, floor, abs, pow, sqrt
+    MathOp { operation: String, args: Vec<CompiledValue> }, // sum, avg, min, max, round, ceil, floor, abs, pow, sqrt
 
     #[serde(rename = "date_op")]
     DateOp { operation: String, args: Vec<Value> }, // now, parse, format, add, diff
 
     #[serde(rename = "json_op")]
-    JsonOp { operation: String, input: String, args: Vec<Value> }, // parse, stringify, merge, get_path, set_path
+    JsonOp { operation: String, input: Template, args: Vec<Value> }, // parse, stringify, merge, get_path, set_path
 
     // Logging & Debugging
     #[serde(rename = "log")]
-    Log { level: String, message: String },
+    Log { level: String, message: Template },
 
     #[serde(rename = "sleep")]
     Sleep { duration_ms: u64 },
+}
 
-    // Custom Script Execution
-    #[serde(rename = "execute_script")]
-    ExecuteScript { language: String, code: String },
-    
-    // Custom Operations (for user-defined extensions)
-    #[serde(untagged)]
-    CustomOp(HashMap<String, Value>),
+impl OptimizedOperation {
+    /// The name of the operation this was compiled from
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OptimizedOperation::Return { .. } => "return",
+            OptimizedOperation::Comment { .. } => "comment",
+            OptimizedOperation::QueryDb { .. } => "query_db",
+            OptimizedOperation::SqlOp { .. } => "sql_op",
+            OptimizedOperation::RedisOp { .. } => "redis_op",
+            OptimizedOperation::WsOp { .. } => "ws_op",
+            OptimizedOperation::HttpRequest { .. } => "http_request",
+            OptimizedOperation::If { .. } => "if",
+            OptimizedOperation::Loop { .. } => "loop",
+            OptimizedOperation::Switch { .. } => "switch",
+            OptimizedOperation::While { .. } => "while",
+            OptimizedOperation::Break => "break",
+            OptimizedOperation::Continue => "continue",
+            OptimizedOperation::Try { .. } => "try",
+            OptimizedOperation::Transaction { .. } => "transaction",
+            OptimizedOperation::Throw { .. } => "throw",
+            OptimizedOperation::Parallel { .. } => "parallel",
+            OptimizedOperation::DefineFunction { .. } => "define_function",
+            OptimizedOperation::CallFunction { .. } => "call_function",
+            OptimizedOperation::Set { .. } => "set",
+            OptimizedOperation::Get { .. } => "get",
+            OptimizedOperation::StringOp { .. } => "string_op",
+            OptimizedOperation::MathOp { .. } => "math_op",
+            OptimizedOperation::DateOp { .. } => "date_op",
+            OptimizedOperation::JsonOp { .. } => "json_op",
+            OptimizedOperation::Log { .. } => "log",
+            OptimizedOperation::Sleep { .. } => "sleep",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OptimizedSwitchCase {
     pub value: Value,
     pub operations: Vec<OptimizedOperation>,
-}
\ No newline at end of file
+}
+
+/// A `redis_op` command with its key and arguments compiled; `command` is upper case
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedisCommand {
+    pub command: String,
+    pub key: Template,
+    pub args: Vec<CompiledValue>,
+    pub ttl_seconds: Option<u64>,
+}
+
+/// A function compiled as a unit of its own: its parameters and the variables
+/// its body uses have slots in its own symbol table, parameters first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompiledFunction {
+    pub name: String,
+    pub param_indices: Vec<usize>,
+    pub symbols: Arc<SymbolTable>,
+    pub body: Vec<OptimizedOperation>,
+}
+
+/// A compiled program: operations laid out in one instruction stream, with
+/// nested blocks reached through jumps so the VM runs it in a single loop
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Program {
+    pub code: Vec<Instruction>,
+    /// Where each instruction came from, in step with `code`; `None` for the
+    /// jumps and block ends the compiler adds
+    pub locations: Vec<Option<Location>>,
+    /// The catch and finally blocks of each `try`
+    pub handlers: Vec<Handler>,
+    /// The functions whose bodies are laid out after the program's own `Halt`
+    pub functions: Vec<ProgramFunction>,
+}
+
+impl Program {
+    /// True when there is nothing to run besides the final `Halt`
+    pub fn is_empty(&self) -> bool {
+        self.locations.iter().all(Option::is_none)
+    }
+}
+
+/// The operation an instruction was compiled from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Location {
+    /// Path in the logic, e.g. `logic[2].then[0]`; function bodies are relative
+    /// to the call, e.g. `function[0]`
+    pub path: String,
+    /// Blocks the operation is nested in
+    pub depth: usize,
+}
+
+/// A function body in a program, run in a call frame of its own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgramFunction {
+    pub name: String,
+    pub param_indices: Vec<usize>,
+    pub symbols: Arc<SymbolTable>,
+    /// Where the body starts; it runs until its `Halt`
+    pub entry: usize,
+    /// A global function is callable from the start; one the logic defines
+    /// only once its `define_function` ran
+    pub global: bool,
+}
+
+/// Where a `try` goes on an error, and the finally block it runs either way
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handler {
+    pub catch: usize,
+    pub finally: Option<usize>,
+}
+
+/// One step of a program. Targets are indices into `Program::code`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Instruction {
+    /// An operation without nested blocks
+    #[serde(rename = "op")]
+    Op(OptimizedOperation),
+
+    #[serde(rename = "jump")]
+    Jump { target: usize },
+
+    /// Enter the then block (the next instruction) or `otherwise`, or skip past `end`
+    #[serde(rename = "if")]
+    If { condition: CompiledExpression, otherwise: Option<usize>, end: usize },
+
+    /// Enter the first matching case or `default`, or skip past `end`
+    #[serde(rename = "switch")]
+    Switch { value: CompiledExpression, cases: Vec<(Value, usize)>, default: Option<usize>, end: usize },
+
+    /// Open a loop frame over `collection`; `next` is the loop's `Next`
+    #[serde(rename = "loop")]
+    Loop { collection: Template, var_index: usize, next: usize },
+
+    /// Open a loop frame that runs while `condition` holds
+    #[serde(rename = "while")]
+    While { condition: CompiledExpression, max_iterations: Option<u32>, next: usize },
+
+    /// Start the innermost loop's next iteration at `body`, or close its frame
+    #[serde(rename = "next")]
+    Next { body: usize },
+
+    /// Leave the innermost loop
+    #[serde(rename = "break")]
+    Break,
+
+    /// Skip to the innermost loop's next iteration
+    #[serde(rename = "continue")]
+    Continue,
+
+    /// Open a frame for a try whose body follows; errors go to `handlers[handler]`
+    #[serde(rename = "try")]
+    Try { handler: usize },
+
+    /// Start of the innermost try's finally block
+    #[serde(rename = "finally")]
+    Finally,
+
+    /// Open a frame for a transaction whose body follows, or for a savepoint
+    /// inside the running one
+    #[serde(rename = "transaction")]
+    Transaction { savepoint: bool, datasource: Option<String> },
+
+    /// Close the frame of the innermost if, switch, try or transaction;
+    /// a transaction commits
+    #[serde(rename = "end")]
+    End,
+
+    /// Run each task, starting at the given instructions, on its own copy of the state
+    #[serde(rename = "parallel")]
+    Parallel { tasks: Vec<usize>, max_concurrent: Option<usize>, end: usize },
+
+    /// Make `Program::functions[function]` callable by its name
+    #[serde(rename = "define_function")]
+    DefineFunction { function: usize },
+
+    /// Open a call frame and run the body of the function registered as `name`
+    #[serde(rename = "call_function")]
+    CallFunction { name: String, args: Vec<CompiledValue>, output_var_index: usize },
+
+    /// Leave the running function body from a `return` nested in it, closing
+    /// the blocks it is in
+    #[serde(rename = "exit")]
+    Exit,
+
+    /// End of the program, a function body or a parallel task
+    #[serde(rename = "halt")]
+    Halt,
+}
+
+impl Instruction {
+    /// The name of the operation this was compiled from
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Instruction::Op(op) => op.kind(),
+            Instruction::Jump { .. } => "jump",
+            Instruction::If { .. } => "if",
+            Instruction::Switch { .. } => "switch",
+            Instruction::Loop { .. } => "loop",
+            Instruction::While { .. } => "while",
+            Instruction::Next { .. } => "next",
+            Instruction::Break => "break",
+            Instruction::Continue => "continue",
+            Instruction::Try { .. } => "try",
+            Instruction::Transaction { .. } => "transaction",
+            Instruction::Finally => "finally",
+            Instruction::End => "end",
+            Instruction::Parallel { .. } => "parallel",
+            Instruction::DefineFunction { .. } => "define_function",
+            Instruction::CallFunction { .. } => "call_function",
+            Instruction::Exit => "exit",
+            Instruction::Halt => "halt",
+        }
+    }
+}
@@ -1,54 +1,155 @@
 use crate::vm::memory::ExecutionMemory;
 use crate::compiler::symbol_table::SymbolTable;
-use crate::vm::instructions::OptimizedOperation;
+use crate::vm::instructions::{CompiledFunction, Instruction, Location, OptimizedOperation, Program, RedisCommand};
+use crate::vm::template::{CompiledValue, Segment, Template, VariableRef};
 use crate::websocket::WebSocketManager;
-use proto::models::LoopControl;
+use crate::services::dynamic_routes::{date, io, json, math, sql, string};
+use crate::services::dynamic_routes::sql::SqlArg;
+use crate::services::dynamic_routes::redis::RedisRequest;
+use crate::services::dynamic_routes::utils::{get_json_path, switch_case_matches};
+use crate::expression::CompiledExpression;
+use crate::budget;
+use crate::trace;
+use crate::debugger::Debugger;
+use crate::datasource::DEFAULT_DATASOURCE;
+use proto::models::{ErrorContext, LoopControl};
 use serde_json::Value;
-use regex::Regex;
-use sqlx::{Row, Column};
-use redis::AsyncCommands;
+use futures::stream::{self, StreamExt};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+mod tree;
+
+/// A function callable by name, once defined or as a global
+#[derive(Debug, Clone)]
+enum Callable {
+    /// Index into the running program's functions
+    Program(usize),
+    /// A body for the tree walker
+    Tree(Arc<CompiledFunction>),
+}
+
+/// A block the VM is inside of, with the trace span of the operation that opened it
+struct Frame {
+    kind: FrameKind,
+    span: Option<trace::Span>,
+}
+
+enum FrameKind {
+    /// The running branch of an if or switch
+    Branch,
+    Loop(LoopFrame),
+    Try { handler: usize, stage: TryStage },
+    Transaction(TransactionFrame),
+    Call(Box<CallFrame>),
+}
+
+struct LoopFrame {
+    /// The loop's `Next` instruction
+    next: usize,
+    /// Set by `break`; the next `Next` closes the frame
+    done: bool,
+    state: LoopState,
+}
+
+enum LoopState {
+    Each {
+        items: std::vec::IntoIter<Value>,
+        position: usize,
+        var_index: usize,
+        index_slot: Option<usize>,
+        /// Values the loop variables had before the loop, restored once it ends
+        prev_var: Option<Value>,
+        prev_index: Option<Value>,
+    },
+    While {
+        /// The `While` instruction, which holds the condition
+        at: usize,
+        iterations: u32,
+    },
+}
+
+/// A transaction and the datasource it is on
+#[derive(Clone)]
+struct OpenTransaction {
+    datasource: Option<String>,
+    shared: sql::SharedTransaction,
+}
+
+/// What a transaction block opened, and so what ending it commits or rolls back
+enum TransactionFrame {
+    /// A transaction of its own
+    Outermost,
+    /// Nothing: the block runs in the enclosing transaction
+    Joined,
+    /// Savepoint `sp_N` inside the enclosing transaction
+    Savepoint(usize),
+}
+
+enum TryStage {
+    Body,
+    Catch,
+    /// Running the finally block; its value is discarded for the one saved here
+    Finally(Value),
+}
+
+/// The caller's state, put back when the function body halts: the body runs on
+/// memory of its own and only the return value flows back
+struct CallFrame {
+    return_to: usize,
+    output_var_index: usize,
+    memory: ExecutionMemory,
+    symbol_table: Arc<SymbolTable>,
+    functions: HashMap<String, Callable>,
+    error_context: Option<ErrorContext>,
+    call_path: Option<String>,
+    depth: usize,
+}
 
 pub struct VirtualMachine {
     pub memory: ExecutionMemory,
-    symbol_table: SymbolTable,
+    /// Slots of the running function body, or of the program outside of calls
+    symbol_table: Arc<SymbolTable>,
     db_pool: Option<sqlx::Pool<sqlx::Sqlite>>,
+    /// Pools of the named datasources operations may pick
+    datasources: HashMap<String, sqlx::Pool<sqlx::Sqlite>>,
+    /// The open transaction SQL operations on its datasource run in, if any
+    transaction: Option<OpenTransaction>,
+    /// Savepoints open in it
+    savepoints: usize,
     redis_pool: Option<deadpool_redis::Pool>,
     ws_manager: Option<WebSocketManager>,
     ws_connection_id: Option<String>,
+    functions: HashMap<String, Callable>,
+    /// Function calls the running body is nested in
+    call_depth: usize,
+    /// Pending break/continue of the tree walker
+    loop_control: LoopControl,
+    /// Set by a `return` in a function body the tree walker runs; ends every
+    /// block up to the call
+    returning: bool,
+    error_context: Option<ErrorContext>,
+    /// Path of the call whose body is running, while traced or debugged;
+    /// function body locations are relative to it
+    call_path: Option<String>,
+    /// Blocks the running function body is nested in
+    depth: usize,
+    debugger: Option<Arc<tokio::sync::Mutex<Debugger>>>,
 }
 
 impl VirtualMachine {
     pub fn new(memory: ExecutionMemory, symbol_table: SymbolTable) -> Self {
-        Self { 
-            memory, 
-            symbol_table,
-            db_pool: None,
-            redis_pool: None,
-            ws_manager: None,
-            ws_connection_id: None,
-        }
+        Self::with_all(memory, symbol_table, None, None, None, None)
     }
     
     pub fn with_db_pool(memory: ExecutionMemory, symbol_table: SymbolTable, db_pool: sqlx::Pool<sqlx::Sqlite>) -> Self {
-        Self { 
-            memory, 
-            symbol_table,
-            db_pool: Some(db_pool),
-            redis_pool: None,
-            ws_manager: None,
-            ws_connection_id: None,
-        }
+        Self::with_all(memory, symbol_table, Some(db_pool), None, None, None)
     }
     
     pub fn with_redis_pool(memory: ExecutionMemory, symbol_table: SymbolTable, redis_pool: deadpool_redis::Pool) -> Self {
-        Self {
-            memory,
-            symbol_table,
-            db_pool: None,
-            redis_pool: Some(redis_pool),
-            ws_manager: None,
-            ws_connection_id: None,
-        }
+        Self::with_all(memory, symbol_table, None, Some(redis_pool), None, None)
     }
     
     pub fn with_both_pools(
@@ -57,14 +158,7 @@ impl VirtualMachine {
         db_pool: sqlx::Pool<sqlx::Sqlite>,
         redis_pool: deadpool_redis::Pool
     ) -> Self {
-        Self {
-            memory,
-            symbol_table,
-            db_pool: Some(db_pool),
-            redis_pool: Some(redis_pool),
-            ws_manager: None,
-            ws_connection_id: None,
-        }
+        Self::with_all(memory, symbol_table, Some(db_pool), Some(redis_pool), None, None)
     }
     
     pub fn with_websocket(
@@ -73,14 +167,7 @@ impl VirtualMachine {
         ws_manager: WebSocketManager,
         connection_id: String,
     ) -> Self {
-        Self {
-            memory,
-            symbol_table,
-            db_pool: None,
-            redis_pool: None,
-            ws_manager: Some(ws_manager),
-            ws_connection_id: Some(connection_id),
-        }
+        Self::with_all(memory, symbol_table, None, None, Some(ws_manager), Some(connection_id))
     }
     
     pub fn with_all(
@@ -93,385 +180,949 @@ impl VirtualMachine {
     ) -> Self {
         Self {
             memory,
-            symbol_table,
+            symbol_table: Arc::new(symbol_table),
             db_pool,
+            datasources: HashMap::new(),
+            transaction: None,
+            savepoints: 0,
             redis_pool,
             ws_manager,
             ws_connection_id: connection_id,
+            functions: HashMap::new(),
+            call_depth: 0,
+            loop_control: LoopControl::default(),
+            returning: false,
+            error_context: None,
+            call_path: None,
+            depth: 0,
+            debugger: None,
+        }
+    }
+
+    /// Independent copy of the current state, used for parallel tasks
+    fn fork(&self) -> Self {
+        Self {
+            memory: self.memory.clone(),
+            symbol_table: self.symbol_table.clone(),
+            db_pool: self.db_pool.clone(),
+            datasources: self.datasources.clone(),
+            transaction: self.transaction.clone(),
+            savepoints: self.savepoints,
+            redis_pool: self.redis_pool.clone(),
+            ws_manager: self.ws_manager.clone(),
+            ws_connection_id: self.ws_connection_id.clone(),
+            functions: self.functions.clone(),
+            call_depth: self.call_depth,
+            loop_control: LoopControl::default(),
+            returning: false,
+            error_context: self.error_context.clone(),
+            call_path: self.call_path.clone(),
+            depth: self.depth,
+            debugger: self.debugger.clone(),
         }
     }
 
-    pub async fn execute(&mut self, program: &[OptimizedOperation]) -> Result<Value, String> {
-        let mut result = Value::Null;
-        let mut loop_control = LoopControl::default();
+    /// Pools operations with a `datasource` run on; the others use the database pool
+    pub fn set_datasources(&mut self, datasources: HashMap<String, sqlx::Pool<sqlx::Sqlite>>) {
+        self.datasources = datasources;
+    }
+
+    /// Pause before operations as the debugger says; parallel tasks and
+    /// function calls share it
+    pub fn attach_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(Arc::new(tokio::sync::Mutex::new(debugger)));
+    }
 
-        for op in program.iter() {
-            // Handle loop control
-            if loop_control.should_break {
-                break;
+    pub async fn execute(&mut self, program: &Program) -> Result<Value, String> {
+        for (index, function) in program.functions.iter().enumerate() {
+            if function.global {
+                self.functions.insert(function.name.clone(), Callable::Program(index));
             }
-            if loop_control.should_continue {
-                loop_control.should_continue = false;
-                continue;
+        }
+        self.run(program, 0).await
+    }
+
+    /// Run from `entry` to its `Halt`: the whole program, or one parallel task
+    fn run<'a>(
+        &'a mut self,
+        program: &'a Program,
+        entry: usize,
+    ) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut frames = Vec::new();
+            let mut result = Value::Null;
+            let mut pc = entry;
+            loop {
+                pc = match self.step(program, pc, &mut frames, &mut result).await {
+                    Ok(Some(next)) => next,
+                    Ok(None) => return Ok(result),
+                    Err(e) => self.unwind(program, &mut frames, &mut result, e).await?,
+                };
             }
+        })
+    }
 
-            match op {
-                OptimizedOperation::Return { value, status, headers, raw } => {
-                    // Resolve the return value
-                    let mut resolved = self.resolve_value(value)?;
-                    
-                    // Build enhanced return with metadata if any custom fields are set
-                    if status.is_some() || headers.is_some() || raw.is_some() {
-                        let mut return_obj = serde_json::Map::new();
-                        return_obj.insert("value".to_string(), resolved);
-                        
-                        if let Some(s) = status {
-                            return_obj.insert("status".to_string(), Value::Number((*s).into()));
-                        }
-                        if let Some(h) = headers {
-                            return_obj.insert("headers".to_string(), serde_json::to_value(h).unwrap_or(Value::Null));
-                        }
-                        if let Some(r) = raw {
-                            return_obj.insert("raw".to_string(), Value::Bool(*r));
-                        }
-                        
-                        resolved = Value::Object(return_obj);
+    /// Run the instruction at `pc`; returns where to go on, `None` once the run halts
+    async fn step(
+        &mut self,
+        program: &Program,
+        pc: usize,
+        frames: &mut Vec<Frame>,
+        result: &mut Value,
+    ) -> Result<Option<usize>, String> {
+        let instruction = &program.code[pc];
+        let mut span = None;
+        // Jumps and block ends the compiler added are not operations
+        if let Some(location) = &program.locations[pc] {
+            budget::charge(1)?;
+            if let Some(debugger) = self.debugger.clone() {
+                let path = self.path(location);
+                let depth = self.depth + location.depth;
+                debugger.lock().await.before(&path, instruction.kind(), depth, &mut self.memory, &self.symbol_table).await?;
+            }
+            if trace::enabled() {
+                span = Some(trace::span(self.path(location), instruction.kind(), self.trace_inputs(instruction)));
+            }
+        }
+        let outcome = self.dispatch(program, pc, frames, result, &mut span).await;
+        // Operations that open a frame hand their span to it
+        if let Some(span) = span {
+            span.finish(outcome.as_ref().map(|_| &*result));
+        }
+        outcome
+    }
+
+    async fn dispatch(
+        &mut self,
+        program: &Program,
+        pc: usize,
+        frames: &mut Vec<Frame>,
+        result: &mut Value,
+        span: &mut Option<trace::Span>,
+    ) -> Result<Option<usize>, String> {
+        match &program.code[pc] {
+            Instruction::Op(op) => {
+                self.execute_operation(op, result).await?;
+            },
+            Instruction::Jump { target } => return Ok(Some(*target)),
+            Instruction::If { condition, otherwise, end } => {
+                let start = if self.evaluate_condition(condition)? { Some(pc + 1) } else { *otherwise };
+                return Ok(Some(match start {
+                    Some(start) => {
+                        Self::enter(frames, FrameKind::Branch, span, result);
+                        start
+                    },
+                    None => end + 1,
+                }));
+            },
+            Instruction::Switch { value, cases, default, end } => {
+                let switch_value = value.evaluate(|path| self.lookup_in(value.scope(), path))?;
+                let start = cases.iter()
+                    .find(|(case, _)| switch_case_matches(case, &switch_value))
+                    .map(|(_, start)| *start)
+                    .or(*default);
+                return Ok(Some(match start {
+                    Some(start) => {
+                        Self::enter(frames, FrameKind::Branch, span, result);
+                        start
+                    },
+                    None => end + 1,
+                }));
+            },
+            Instruction::Loop { collection, var_index, next } => {
+                let Value::Array(items) = self.resolve_collection(collection) else {
+                    return Ok(Some(next + 1));
+                };
+                // Loop variables are restored afterwards so they don't leak out of the loop
+                let index_slot = self.symbol_table.get_index("index");
+                let state = LoopState::Each {
+                    items: items.into_iter(),
+                    position: 0,
+                    var_index: *var_index,
+                    index_slot,
+                    prev_var: self.memory.get(*var_index).cloned(),
+                    prev_index: index_slot.and_then(|idx| self.memory.get(idx).cloned()),
+                };
+                frames.push(Frame { kind: FrameKind::Loop(LoopFrame { next: *next, done: false, state }), span: span.take() });
+                return Ok(Some(*next));
+            },
+            Instruction::While { next, .. } => {
+                let state = LoopState::While { at: pc, iterations: 0 };
+                frames.push(Frame { kind: FrameKind::Loop(LoopFrame { next: *next, done: false, state }), span: span.take() });
+                return Ok(Some(*next));
+            },
+            Instruction::Next { body } => {
+                let Some(Frame { kind: FrameKind::Loop(frame), .. }) = frames.last_mut() else {
+                    return Err("'next' outside of a loop frame".to_string());
+                };
+                if !frame.done && self.next_iteration(program, &mut frame.state)? {
+                    budget::charge(1)?;
+                    *result = Value::Null;
+                    return Ok(Some(*body));
+                }
+                if let Some(frame) = frames.pop() {
+                    self.close(frame, result).await?;
+                }
+            },
+            Instruction::Break | Instruction::Continue => {
+                // Blocks between here and the loop end as they are, like at their end
+                loop {
+                    match frames.last_mut() {
+                        Some(Frame { kind: FrameKind::Loop(frame), .. }) => {
+                            frame.done |= matches!(program.code[pc], Instruction::Break);
+                            return Ok(Some(frame.next));
+                        },
+                        Some(Frame { kind: FrameKind::Call(_), .. }) | None => {
+                            return Err(format!("'{}' outside of a loop", program.code[pc].kind()));
+                        },
+                        Some(_) => {
+                            if let Some(frame) = frames.pop() {
+                                self.close(frame, result).await?;
+                            }
+                        },
                     }
-                    
-                    result = resolved;
-                    break;
-                },
-                OptimizedOperation::Comment { .. } => {
-                    // Comment is a no-op, skip execution
-                    continue;
-                },
-                OptimizedOperation::Set { var_index, value } => {
-                    let resolved = self.resolve_value(value)?;
-                    self.memory.set(*var_index, resolved);
-                },
-                OptimizedOperation::Get { var_index } => {
-                    result = self.memory.get(*var_index).cloned().unwrap_or(Value::Null);
-                },
-                OptimizedOperation::MathOp { operation, args } => {
-                    let resolved_args: Result<Vec<Value>, String> = args.iter()
-                        .map(|arg| self.resolve_value(arg))
-                        .collect();
-                    let resolved_args = resolved_args?;
-                    result = self.execute_math_op(operation, &resolved_args)?;
-                },
-                OptimizedOperation::If { condition, then, otherwise } => {
-                    let resolved_condition = self.resolve_string(condition)?;
-                    let condition_result = self.evaluate_condition(&resolved_condition)?;
-                    if condition_result {
-                        result = Box::pin(self.execute(then)).await?;
-                    } else if let Some(else_ops) = otherwise {
-                        result = Box::pin(self.execute(else_ops)).await?;
+                }
+            },
+            Instruction::Try { handler } => {
+                Self::enter(frames, FrameKind::Try { handler: *handler, stage: TryStage::Body }, span, result);
+            },
+            Instruction::Finally => {
+                if let Some(Frame { kind: FrameKind::Try { stage, .. }, .. }) = frames.last_mut() {
+                    *stage = TryStage::Finally(std::mem::take(result));
+                }
+            },
+            Instruction::Transaction { savepoint, datasource } => {
+                let transaction = self.begin_transaction(*savepoint, datasource.as_deref()).await?;
+                Self::enter(frames, FrameKind::Transaction(transaction), span, result);
+            },
+            Instruction::End => {
+                if let Some(frame) = frames.pop() {
+                    self.close(frame, result).await?;
+                }
+            },
+            Instruction::Parallel { tasks, max_concurrent, end } => {
+                // Each task runs on its own copy of the state; failed tasks are dropped
+                // unless they ran out of budget, which aborts the whole execution
+                budget::check_parallel(tasks.len())?;
+                let futures: Vec<_> = tasks.iter().map(|start| {
+                    let mut task_vm = self.fork();
+                    async move { task_vm.run(program, *start).await }
+                }).collect();
+
+                // At most `max_concurrent` tasks run at once; results keep the tasks' order
+                let limit = max_concurrent.unwrap_or(tasks.len()).max(1);
+                let results: Vec<_> = stream::iter(futures).buffered(limit).collect().await;
+                if let Some(e) = budget::limit_error() {
+                    return Err(e);
+                }
+                *result = Value::Array(results.into_iter().filter_map(Result::ok).collect());
+                return Ok(Some(*end));
+            },
+            Instruction::DefineFunction { function } => {
+                self.functions.insert(program.functions[*function].name.clone(), Callable::Program(*function));
+            },
+            Instruction::CallFunction { name, args, output_var_index } => {
+                let Some(Callable::Program(index)) = self.functions.get(name).cloned() else {
+                    return Err(format!("Function '{}' not defined", name));
+                };
+                let function = &program.functions[index];
+                budget::check_call_depth(self.call_depth + 1)?;
+
+                let memory = self.call_memory(&function.symbols, &function.param_indices, args);
+                let location = program.locations[pc].as_ref();
+                let call_path = match location {
+                    Some(location) if span.is_some() || self.debugger.is_some() => Some(self.path(location)),
+                    _ => None,
+                };
+                let caller = CallFrame {
+                    return_to: pc + 1,
+                    output_var_index: *output_var_index,
+                    memory: std::mem::replace(&mut self.memory, memory),
+                    symbol_table: std::mem::replace(&mut self.symbol_table, function.symbols.clone()),
+                    functions: self.functions.clone(),
+                    error_context: self.error_context.clone(),
+                    call_path: std::mem::replace(&mut self.call_path, call_path),
+                    depth: self.depth,
+                };
+                self.depth += location.map_or(0, |location| location.depth) + 1;
+                self.call_depth += 1;
+                Self::enter(frames, FrameKind::Call(Box::new(caller)), span, result);
+                return Ok(Some(function.entry));
+            },
+            Instruction::Exit => {
+                // Blocks the return is nested in end as they are, like at a break
+                while frames.last().is_some_and(|frame| !matches!(frame.kind, FrameKind::Call(_))) {
+                    if let Some(frame) = frames.pop() {
+                        self.close(frame, result).await?;
+                    }
+                }
+                return self.halt(frames, result);
+            },
+            Instruction::Halt => return self.halt(frames, result),
+        }
+        Ok(Some(pc + 1))
+    }
+
+    /// End a run, or a function body by returning its value to the caller
+    fn halt(&mut self, frames: &mut Vec<Frame>, result: &mut Value) -> Result<Option<usize>, String> {
+        let Some(frame) = frames.pop() else { return Ok(None) };
+        let FrameKind::Call(caller) = frame.kind else {
+            return Err("'halt' inside an open block".to_string());
+        };
+        let value = std::mem::take(result);
+        let (return_to, output_var_index) = (caller.return_to, caller.output_var_index);
+        self.return_to_caller(*caller);
+        let stored = self.store(output_var_index, value.clone());
+        if let Some(span) = frame.span {
+            span.finish(stored.as_ref().map(|_| &value));
+        }
+        stored?;
+        *result = value;
+        Ok(Some(return_to))
+    }
+
+    /// Memory for a call: the arguments in the parameters' slots, and a copy of
+    /// whatever the caller's outermost variables of the same names hold in the others
+    fn call_memory(&self, symbols: &SymbolTable, param_indices: &[usize], args: &[CompiledValue]) -> ExecutionMemory {
+        let mut memory = ExecutionMemory::with_capacity(symbols.len());
+        for index in 0..symbols.len() {
+            let caller_slot = symbols.get_name(index).and_then(|name| self.symbol_table.get_index(name));
+            if let Some(value) = caller_slot.and_then(|slot| self.memory.get(slot)) {
+                memory.set(index, value.clone());
+            }
+        }
+        for (param_index, arg) in param_indices.iter().zip(args) {
+            memory.set(*param_index, self.resolve_value(arg));
+        }
+        memory
+    }
+
+    /// Open a frame for a block about to run; a block starts out with no value
+    fn enter(frames: &mut Vec<Frame>, kind: FrameKind, span: &mut Option<trace::Span>, result: &mut Value) {
+        frames.push(Frame { kind, span: span.take() });
+        *result = Value::Null;
+    }
+
+    /// Move a loop to its next iteration; false once it is over
+    fn next_iteration(&mut self, program: &Program, state: &mut LoopState) -> Result<bool, String> {
+        match state {
+            LoopState::Each { items, position, var_index, index_slot, .. } => {
+                let Some(item) = items.next() else { return Ok(false) };
+                self.memory.set(*var_index, item);
+                if let Some(idx) = index_slot {
+                    self.memory.set(*idx, Value::Number((*position).into()));
+                }
+                *position += 1;
+                Ok(true)
+            },
+            LoopState::While { at, iterations } => {
+                let Instruction::While { condition, max_iterations, .. } = &program.code[*at] else {
+                    return Err("loop frame does not point at a 'while'".to_string());
+                };
+                if *iterations >= max_iterations.unwrap_or(1000) || !self.evaluate_condition(condition)? {
+                    return Ok(false);
+                }
+                *iterations += 1;
+                Ok(true)
+            },
+        }
+    }
+
+    /// Close a frame whose block ran to its end (or was left by break/continue);
+    /// a transaction commits
+    async fn close(&mut self, frame: Frame, result: &mut Value) -> Result<(), String> {
+        let mut outcome = Ok(());
+        match frame.kind {
+            FrameKind::Loop(LoopFrame { state: LoopState::Each { var_index, index_slot, prev_var, prev_index, .. }, .. }) => {
+                self.memory.set(var_index, prev_var.unwrap_or(Value::Null));
+                if let Some(idx) = index_slot {
+                    self.memory.set(idx, prev_index.unwrap_or(Value::Null));
+                }
+            },
+            FrameKind::Try { stage: TryStage::Finally(saved), .. } => *result = saved,
+            FrameKind::Transaction(transaction) => outcome = self.commit(transaction).await,
+            _ => {},
+        }
+        if let Some(span) = frame.span {
+            span.finish(outcome.as_ref().map(|_| &*result));
+        }
+        outcome
+    }
+
+    /// Open a transaction for a block, or inside the running one a savepoint
+    /// if asked for
+    async fn begin_transaction(&mut self, savepoint: bool, datasource: Option<&str>) -> Result<TransactionFrame, String> {
+        let Some(transaction) = &self.transaction else {
+            let pool = self.pool(datasource, "transaction")?;
+            let transaction = pool.begin().await
+                .map_err(|e| format!("Failed to begin transaction: {}", e))?;
+            self.transaction = Some(OpenTransaction {
+                datasource: datasource.map(str::to_string),
+                shared: Arc::new(tokio::sync::Mutex::new(transaction)),
+            });
+            return Ok(TransactionFrame::Outermost);
+        };
+        if !same_datasource(transaction.datasource.as_deref(), datasource) {
+            return Err(format!(
+                "A transaction on datasource '{}' can't be nested in one on '{}'",
+                datasource.unwrap_or(DEFAULT_DATASOURCE),
+                transaction.datasource.as_deref().unwrap_or(DEFAULT_DATASOURCE),
+            ));
+        }
+        if !savepoint {
+            return Ok(TransactionFrame::Joined);
+        }
+        sql::execute_in(&transaction.shared, &format!("SAVEPOINT sp_{}", self.savepoints + 1)).await?;
+        self.savepoints += 1;
+        Ok(TransactionFrame::Savepoint(self.savepoints))
+    }
+
+    /// Keep what a transaction block did
+    async fn commit(&mut self, transaction: TransactionFrame) -> Result<(), String> {
+        match transaction {
+            TransactionFrame::Joined => Ok(()),
+            TransactionFrame::Savepoint(n) => {
+                self.savepoints = n - 1;
+                let transaction = self.transaction.as_ref().ok_or("No transaction to release a savepoint of")?;
+                sql::execute_in(&transaction.shared, &format!("RELEASE sp_{}", n)).await
+            },
+            TransactionFrame::Outermost => {
+                let transaction = self.take_transaction()?;
+                transaction.commit().await.map_err(|e| format!("Failed to commit transaction: {}", e))
+            },
+        }
+    }
+
+    /// Undo what a transaction block did; the error that ended the block is
+    /// what gets reported, so a failed rollback is only logged
+    async fn roll_back(&mut self, transaction: TransactionFrame) {
+        let outcome = match transaction {
+            TransactionFrame::Joined => Ok(()),
+            TransactionFrame::Savepoint(n) => {
+                self.savepoints = n - 1;
+                match self.transaction.as_ref().map(|transaction| &transaction.shared) {
+                    Some(shared) => match sql::execute_in(shared, &format!("ROLLBACK TO sp_{}", n)).await {
+                        Ok(()) => sql::execute_in(shared, &format!("RELEASE sp_{}", n)).await,
+                        Err(e) => Err(e),
+                    },
+                    None => Ok(()),
+                }
+            },
+            TransactionFrame::Outermost => match self.take_transaction() {
+                Ok(transaction) => transaction.rollback().await.map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            },
+        };
+        if let Err(e) = outcome {
+            eprintln!("[WARN] Failed to roll back transaction: {}", e);
+        }
+    }
+
+    /// The open transaction, once no parallel task holds it any more
+    fn take_transaction(&mut self) -> Result<sqlx::Transaction<'static, sqlx::Sqlite>, String> {
+        let transaction = self.transaction.take().ok_or("No transaction open")?;
+        Arc::try_unwrap(transaction.shared)
+            .map(tokio::sync::Mutex::into_inner)
+            .map_err(|_| "Transaction is still in use".to_string())
+    }
+
+    /// Put back the state of the caller a function body ran for
+    fn return_to_caller(&mut self, caller: CallFrame) {
+        self.memory = caller.memory;
+        self.symbol_table = caller.symbol_table;
+        self.functions = caller.functions;
+        self.call_depth -= 1;
+        self.error_context = caller.error_context;
+        self.call_path = caller.call_path;
+        self.depth = caller.depth;
+    }
+
+    /// Close frames until a try whose body is running takes the error, rolling
+    /// back the transactions on the way; returns where its catch block starts
+    async fn unwind(&mut self, program: &Program, frames: &mut Vec<Frame>, result: &mut Value, mut error: String) -> Result<usize, String> {
+        while let Some(frame) = frames.pop() {
+            match frame.kind {
+                // Running out of budget isn't the logic's error to handle
+                FrameKind::Try { handler, stage: TryStage::Body } if budget::exceeded().is_none() => {
+                    match self.set_named("error", serde_json::json!({
+                        "message": error,
+                    })) {
+                        Ok(()) => {
+                            self.error_context = Some(ErrorContext {
+                                message: error,
+                                code: None,
+                                stack: vec![],
+                            });
+                            Self::enter(frames, FrameKind::Try { handler, stage: TryStage::Catch }, &mut { frame.span }, result);
+                            return Ok(program.handlers[handler].catch);
+                        },
+                        // An error too big to keep runs the execution out of memory instead
+                        Err(e) => error = e,
                     }
                 },
-                OptimizedOperation::SqlOp { query, arg_indices, output_var_index } => {
-                    // Resolve all args from memory
-                    let resolved_args: Vec<sqlx::Either<String, i64>> = arg_indices.iter()
-                        .map(|idx| {
-                            if let Some(val) = self.memory.get(*idx) {
-                                // Convert Value to sqlx compatible type
-                                match val {
-                                    Value::Number(n) => {
-                                        if let Some(i) = n.as_i64() {
-                                            Ok(sqlx::Either::Right(i))
-                                        } else if let Some(f) = n.as_f64() {
-                                            Ok(sqlx::Either::Left(f.to_string()))
-                                        } else {
-                                            Ok(sqlx::Either::Left(n.to_string()))
-                                        }
-                                    },
-                                    Value::String(s) => Ok(sqlx::Either::Left(s.clone())),
-                                    _ => Ok(sqlx::Either::Left(val.to_string())),
-                                }
-                            } else {
-                                Err(format!("Variable at index {} not set", idx))
-                            }
+                FrameKind::Transaction(transaction) => self.roll_back(transaction).await,
+                FrameKind::Call(caller) => self.return_to_caller(*caller),
+                _ => {},
+            }
+            if let Some(span) = frame.span {
+                span.finish(Err(&error));
+            }
+        }
+        Err(error)
+    }
+
+    /// Run an operation without nested blocks, leaving its value in `result`;
+    /// `Ok(true)` ends the block
+    async fn execute_operation(&mut self, op: &OptimizedOperation, result: &mut Value) -> Result<bool, String> {
+        match op {
+            OptimizedOperation::Return { value, status, headers, raw } => {
+                // Resolve the return value
+                let mut resolved = self.resolve_value(value);
+                
+                // Build enhanced return with metadata if any custom fields are set
+                if status.is_some() || headers.is_some() || raw.is_some() {
+                    let mut return_obj = serde_json::Map::new();
+                    return_obj.insert("value".to_string(), resolved);
+                    
+                    if let Some(s) = status {
+                        return_obj.insert("status".to_string(), Value::Number((*s).into()));
+                    }
+                    if let Some(h) = headers {
+                        return_obj.insert("headers".to_string(), serde_json::to_value(h).unwrap_or(Value::Null));
+                    }
+                    if let Some(r) = raw {
+                        return_obj.insert("raw".to_string(), Value::Bool(*r));
+                    }
+                    
+                    resolved = Value::Object(return_obj);
+                }
+                
+                *result = resolved;
+                return Ok(true);
+            },
+            OptimizedOperation::Comment { .. } => {
+                // Comment is a no-op, skip execution
+            },
+            OptimizedOperation::Set { var_index, value } => {
+                let resolved = self.resolve_value(value);
+                self.store(*var_index, resolved.clone())?;
+                *result = resolved;
+            },
+            OptimizedOperation::Get { var_index } => {
+                *result = self.memory.get(*var_index).cloned().unwrap_or(Value::Null);
+            },
+            OptimizedOperation::MathOp { operation, args } => {
+                let resolved_args: Vec<Value> = args.iter()
+                    .map(|arg| self.resolve_value(arg))
+                    .collect();
+                *result = math::compute_math_op(operation, &resolved_args);
+                self.set_named("math_result", result.clone())?;
+            },
+            OptimizedOperation::SqlOp { query, args, datasource, output_var_index } => {
+                let resolved_args = args.iter()
+                    .map(|arg| self.resolve_arg(arg).and_then(SqlArg::from_value))
+                    .collect::<Result<Vec<_>, String>>()?;
+                
+                *result = self.fetch_rows(query, resolved_args, datasource.as_deref(), "SqlOp").await?;
+                self.store(*output_var_index, result.clone())?;
+            },
+            OptimizedOperation::RedisOp { commands, pipeline, output_var_index } => {
+                if let Some(redis_pool) = &self.redis_pool {
+                    // Resolve each command's key and arguments before taking a connection
+                    let requests = commands.iter()
+                        .map(|command| {
+                            let args = command.args.iter()
+                                .map(|arg| self.resolve_arg(arg))
+                                .collect::<Result<Vec<_>, String>>()?;
+                            RedisRequest::new(&command.command, &self.render(&command.key), args, command.ttl_seconds)
                         })
                         .collect::<Result<Vec<_>, String>>()?;
                     
-                    // Execute SQL query
-                    if let Some(pool) = &self.db_pool {
-                        let mut query_builder = sqlx::query(query);
-                        
-                        // Bind all arguments
-                        for arg in resolved_args {
-                            match arg {
-                                sqlx::Either::Left(s) => {
-                                    query_builder = query_builder.bind(s);
-                                },
-                                sqlx::Either::Right(i) => {
-                                    query_builder = query_builder.bind(i);
-                                },
-                            }
-                        }
-                        
-                        // Execute and fetch results
-                        let rows = query_builder.fetch_all(pool).await
-                            .map_err(|e| format!("SQL execution error: {}", e))?;
-                        
-                        // Convert rows to JSON array
-                        let json_rows: Vec<Value> = rows.iter()
-                            .map(|row| {
-                                let mut obj = serde_json::Map::new();
-                                for (i, col) in row.columns().iter().enumerate() {
-                                    let col_name = col.name().to_string();
-                                    
-                                    // Try to get the value as different types
-                                    let val = if let Ok(v) = row.try_get::<String, _>(i) {
-                                        Value::String(v)
-                                    } else if let Ok(v) = row.try_get::<i64, _>(i) {
-                                        Value::Number(v.into())
-                                    } else if let Ok(v) = row.try_get::<f64, _>(i) {
-                                        Value::Number(serde_json::Number::from_f64(v).unwrap_or(0.into()))
-                                    } else if let Ok(v) = row.try_get::<bool, _>(i) {
-                                        Value::Bool(v)
-                                    } else {
-                                        Value::Null
-                                    };
-                                    
-                                    obj.insert(col_name, val);
-                                }
-                                Value::Object(obj)
-                            })
-                            .collect();
-                        
-                        // Store result in memory
-                        self.memory.set(*output_var_index, Value::Array(json_rows.clone()));
-                        result = Value::Array(json_rows);
+                    // Get Redis connection
+                    let mut conn = redis_pool.get().await
+                        .map_err(|e| format!("Failed to get Redis connection: {}", e))?;
+                    let mut replies = RedisRequest::send_all(&mut conn, &requests).await?;
+                    let redis_result = if *pipeline {
+                        Value::Array(replies)
                     } else {
-                        return Err("Database pool not available for SqlOp".to_string());
+                        replies.pop().unwrap_or(Value::Null)
+                    };
+                    
+                    // Store result in memory if output variable specified
+                    if let Some(index) = output_var_index {
+                        self.store(*index, redis_result.clone())?;
                     }
-                },
-                OptimizedOperation::RedisOp { command, key, value, ttl_seconds, output_var_index } => {
-                    if let Some(redis_pool) = &self.redis_pool {
-                        // Resolve key template
-                        let resolved_key = self.resolve_string(key)?;
-                        
-                        // Get Redis connection
-                        let mut conn = redis_pool.get().await
-                            .map_err(|e| format!("Failed to get Redis connection: {}", e))?;
-                        
-                        // Execute Redis command
-                        let redis_result: Value = match command.as_str() {
-                            "GET" => {
-                                let value: Option<String> = conn.get(&resolved_key).await
-                                    .map_err(|e| format!("Redis GET error: {}", e))?;
-                                value.map(Value::String).unwrap_or(Value::Null)
-                            },
-                            "SET" => {
-                                if let Some(val) = value {
-                                    let resolved_value = self.resolve_string(val)?;
-                                    
-                                    if let Some(ttl) = ttl_seconds {
-                                        // SET with TTL
-                                        let _: () = conn.set_ex(&resolved_key, &resolved_value, *ttl).await
-                                            .map_err(|e| format!("Redis SET with TTL error: {}", e))?;
-                                    } else {
-                                        // SET without TTL
-                                        let _: () = conn.set(&resolved_key, &resolved_value).await
-                                            .map_err(|e| format!("Redis SET error: {}", e))?;
-                                    }
-                                    Value::String("OK".to_string())
-                                } else {
-                                    return Err("SET command requires a value".to_string());
-                                }
-                            },
-                            "DEL" => {
-                                let count: i32 = conn.del(&resolved_key).await
-                                    .map_err(|e| format!("Redis DEL error: {}", e))?;
-                                Value::Number(count.into())
-                            },
-                            "EXPIRE" => {
-                                if let Some(ttl) = ttl_seconds {
-                                    let success: bool = conn.expire(&resolved_key, *ttl as i64).await
-                                        .map_err(|e| format!("Redis EXPIRE error: {}", e))?;
-                                    Value::Bool(success)
-                                } else {
-                                    return Err("EXPIRE command requires ttl_seconds".to_string());
-                                }
-                            },
-                            "INCR" => {
-                                let new_value: i64 = conn.incr(&resolved_key, 1).await
-                                    .map_err(|e| format!("Redis INCR error: {}", e))?;
-                                Value::Number(new_value.into())
-                            },
-                            "DECR" => {
-                                let new_value: i64 = conn.decr(&resolved_key, 1).await
-                                    .map_err(|e| format!("Redis DECR error: {}", e))?;
-                                Value::Number(new_value.into())
-                            },
-                            _ => {
-                                return Err(format!("Unsupported Redis command: {}", command));
+                    *result = redis_result;
+                } else {
+                    return Err("Redis pool not available for RedisOp".to_string());
+                }
+            },
+            OptimizedOperation::WsOp { command, message, channel } => {
+                if let Some(ws_manager) = &self.ws_manager {
+                    // Resolve message template
+                    let resolved_message = self.render(message);
+                    
+                    // Execute WebSocket command
+                    match command.as_str() {
+                        "send" => {
+                            // Send to current connection only
+                            if let Some(conn_id) = &self.ws_connection_id {
+                                ws_manager.send_to(conn_id, resolved_message)
+                                    .map_err(|e| format!("WebSocket send error: {}", e))?;
+                                *result = Value::String("sent".to_string());
+                            } else {
+                                return Err("No active WebSocket connection for send command".to_string());
                             }
-                        };
-                        
-                        // Store result in memory if output variable specified
-                        if let Some(index) = output_var_index {
-                            self.memory.set(*index, redis_result.clone());
-                        }
-                        result = redis_result;
-                    } else {
-                        return Err("Redis pool not available for RedisOp".to_string());
-                    }
-                },
-                OptimizedOperation::WsOp { command, message, channel } => {
-                    if let Some(ws_manager) = &self.ws_manager {
-                        // Resolve message template
-                        let resolved_message = self.resolve_string(message)?;
-                        
-                        // Execute WebSocket command
-                        match command.as_str() {
-                            "send" => {
-                                // Send to current connection only
-                                if let Some(conn_id) = &self.ws_connection_id {
-                                    ws_manager.send_to(conn_id, resolved_message)
-                                        .map_err(|e| format!("WebSocket send error: {}", e))?;
-                                    result = Value::String("sent".to_string());
-                                } else {
-                                    return Err("No active WebSocket connection for send command".to_string());
-                                }
-                            },
-                            "broadcast" => {
-                                // Broadcast to all or to specific channel
-                                if let Some(ch) = channel {
-                                    let resolved_channel = self.resolve_string(ch)?;
-                                    ws_manager.broadcast_to_channel(&resolved_channel, resolved_message)
-                                        .map_err(|e| format!("WebSocket broadcast error: {}", e))?;
-                                    result = Value::String(format!("broadcast to channel {}", resolved_channel));
-                                } else {
-                                    ws_manager.broadcast(resolved_message)
-                                        .map_err(|e| format!("WebSocket broadcast error: {}", e))?;
-                                    result = Value::String("broadcast to all".to_string());
-                                }
-                            },
-                            _ => {
-                                return Err(format!("Unsupported WebSocket command: {}", command));
+                        },
+                        "broadcast" => {
+                            // Broadcast to all or to specific channel
+                            if let Some(ch) = channel {
+                                let resolved_channel = self.render(ch);
+                                ws_manager.broadcast_to_channel(&resolved_channel, resolved_message)
+                                    .map_err(|e| format!("WebSocket broadcast error: {}", e))?;
+                                *result = Value::String(format!("broadcast to channel {}", resolved_channel));
+                            } else {
+                                ws_manager.broadcast(resolved_message)
+                                    .map_err(|e| format!("WebSocket broadcast error: {}", e))?;
+                                *result = Value::String("broadcast to all".to_string());
                             }
+                        },
+                        _ => {
+                            return Err(format!("Unsupported WebSocket command: {}", command));
                         }
-                    } else {
-                        return Err("WebSocket manager not available for WsOp".to_string());
                     }
-                },
-                // Add more operations as needed
-                _ => {
-                    // For now, skip unimplemented operations
+                } else {
+                    return Err("WebSocket manager not available for WsOp".to_string());
                 }
-            }
+            },
+            OptimizedOperation::QueryDb { query, args, datasource } => {
+                let resolved_args = args.iter()
+                    .map(|arg| self.resolve_arg(arg).and_then(SqlArg::from_value))
+                    .collect::<Result<Vec<_>, String>>()?;
+                let rows = self.fetch_rows(query, resolved_args, datasource.as_deref(), "query_db").await?;
+                *result = io::query_db_result(query, rows);
+                self.set_named("db_result", result.clone())?;
+            },
+            OptimizedOperation::HttpRequest { url, method, body, headers, timeout_ms } => {
+                let resolved_url = self.render(url);
+                let resolved_headers: Vec<(String, String)> = headers.iter()
+                    .flatten()
+                    .map(|(key, value)| (key.clone(), self.render(value)))
+                    .collect();
+                let body_string = match body {
+                    Some(b) => Some(io::http_body_string(self.resolve_value(b))?),
+                    None => None,
+                };
+                *result = io::send_http_request(&resolved_url, method, &resolved_headers, body_string, *timeout_ms).await?;
+                budget::check_value(result)?;
+                self.set_named("http_response", result.clone())?;
+            },
+            OptimizedOperation::Throw { message, code: _ } => {
+                return Err(self.render(message));
+            },
+            OptimizedOperation::StringOp { operation, input, args } => {
+                let input_str = self.render(input);
+                let input_var = self.lookup_in(input.scope(), input.source());
+                *result = string::compute_string_op(operation, input_str, input_var.as_ref(), args);
+                self.set_named("string_result", result.clone())?;
+            },
+            OptimizedOperation::DateOp { operation, args: _ } => {
+                *result = date::handle_date_op(operation);
+                self.set_named("date_result", result.clone())?;
+            },
+            OptimizedOperation::JsonOp { operation, input, args } => {
+                let input_val = self.lookup_in(input.scope(), input.source())
+                    .unwrap_or_else(|| serde_json::from_str(input.source()).unwrap_or(Value::Null));
+                *result = json::compute_json_op(operation, input_val, args);
+            },
+            OptimizedOperation::Log { level, message } => {
+                let resolved_msg = self.render(message);
+                io::emit_log(level, &resolved_msg);
+            },
+            OptimizedOperation::Sleep { duration_ms } => {
+                tokio::time::sleep(tokio::time::Duration::from_millis(*duration_ms)).await;
+            },
+            // Compiled to control flow instructions, or run by the tree walker
+            OptimizedOperation::If { .. }
+            | OptimizedOperation::Loop { .. }
+            | OptimizedOperation::Switch { .. }
+            | OptimizedOperation::While { .. }
+            | OptimizedOperation::Break
+            | OptimizedOperation::Continue
+            | OptimizedOperation::Try { .. }
+            | OptimizedOperation::Transaction { .. }
+            | OptimizedOperation::Parallel { .. }
+            | OptimizedOperation::DefineFunction { .. }
+            | OptimizedOperation::CallFunction { .. } => {
+                return Err(format!("'{}' has nested blocks and is not a single operation", op.kind()));
+            },
+        }
+
+        Ok(false)
+    }
+
+    /// Run a statement in the open transaction when it is on the same
+    /// datasource, or else on the datasource's pool
+    async fn fetch_rows(&self, query: &str, args: Vec<SqlArg>, datasource: Option<&str>, operation: &str) -> Result<Value, String> {
+        match &self.transaction {
+            Some(transaction) if same_datasource(transaction.datasource.as_deref(), datasource) => {
+                sql::fetch_all(&mut **transaction.shared.lock().await, query, args).await
+            },
+            _ => sql::fetch_all(self.pool(datasource, operation)?, query, args).await,
         }
+    }
 
-        Ok(result)
+    /// The pool of a named datasource, or the database pool
+    fn pool(&self, datasource: Option<&str>, operation: &str) -> Result<&sqlx::Pool<sqlx::Sqlite>, String> {
+        match datasource {
+            Some(name) => self.datasources.get(name).ok_or_else(|| format!("Unknown datasource '{}'", name)),
+            None => self.db_pool.as_ref().ok_or_else(|| format!("Database pool not available for {}", operation)),
+        }
     }
 
-    fn resolve_value(&self, value: &Value) -> Result<Value, String> {
+    fn resolve_value(&self, value: &CompiledValue) -> Value {
         match value {
-            Value::String(s) => {
-                let re = Regex::new(r"\{\{([^}]+)\}\}").unwrap();
-                let mut result = s.clone();
-
-                for cap in re.captures_iter(s) {
-                    if let (Some(full_match), Some(var_match)) = (cap.get(0), cap.get(1)) {
-                        let var_name = var_match.as_str();
-                        if let Some(index) = self.symbol_table.get_index(var_name) {
-                            if let Some(val) = self.memory.get(index) {
-                                let val_str = val.to_string();
-                                result = result.replace(full_match.as_str(), &val_str);
-                            } else {
-                                return Err(format!("Variable '{}' not set", var_name));
-                            }
-                        } else {
-                            return Err(format!("Unknown variable '{}'", var_name));
-                        }
-                    }
-                }
+            CompiledValue::Constant(value) => value.clone(),
+            CompiledValue::Template(template) => self.resolve_template(template),
+            CompiledValue::Array(items) => {
+                Value::Array(items.iter().map(|item| self.resolve_value(item)).collect())
+            },
+            CompiledValue::Object(fields) => {
+                Value::Object(fields.iter().map(|(key, item)| (key.clone(), self.resolve_value(item))).collect())
+            },
+        }
+    }
 
-                // Try to parse as JSON, otherwise keep as string
-                if let Ok(parsed) = serde_json::from_str(&result) {
-                    Ok(parsed)
-                } else {
-                    Ok(Value::String(result))
+    /// A single placeholder yields its typed value; anything else is rendered
+    /// and read back as JSON when it parses, otherwise kept as text
+    fn resolve_template(&self, template: &Template) -> Value {
+        match template.single_placeholder() {
+            Some(Segment::Variable(var)) => {
+                if let Some(value) = self.read_variable(var) {
+                    return value;
                 }
             },
-            Value::Array(arr) => {
-                let resolved: Result<Vec<Value>, String> = arr.iter()
-                    .map(|v| self.resolve_value(v))
-                    .collect();
-                Ok(Value::Array(resolved?))
-            },
-            Value::Object(obj) => {
-                let resolved: Result<serde_json::Map<String, Value>, String> = obj.iter()
-                    .map(|(k, v)| {
-                        self.resolve_value(v).map(|rv| (k.clone(), rv))
-                    })
-                    .collect();
-                Ok(Value::Object(resolved?))
+            Some(Segment::Expression { expr, .. }) => {
+                if let Ok(value) = expr.evaluate_strict(|path| self.lookup_in(template.scope(), path)) {
+                    return value;
+                }
             },
-            _ => Ok(value.clone()),
+            _ => {},
+        }
+
+        let rendered = self.render(template);
+        serde_json::from_str(&rendered).unwrap_or(Value::String(rendered))
+    }
+
+    /// Render a template to text; strings are inserted without quotes and
+    /// placeholders that can't be resolved are left in place, matching the interpreter
+    fn render(&self, template: &Template) -> String {
+        let mut out = String::new();
+        for segment in template.segments() {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Variable(var) => match self.read_variable(var) {
+                    Some(Value::String(s)) => out.push_str(&s),
+                    Some(other) => out.push_str(&other.to_string()),
+                    None => out.push_str(&var.raw),
+                },
+                Segment::Expression { expr, raw } => match expr.evaluate_strict(|path| self.lookup_in(template.scope(), path)) {
+                    Ok(Value::String(s)) => out.push_str(&s),
+                    Ok(other) => out.push_str(&other.to_string()),
+                    Err(_) => out.push_str(raw),
+                },
+            }
         }
+        out
     }
 
-    fn resolve_string(&self, s: &str) -> Result<String, String> {
-        let re = Regex::new(r"\{\{([^}]+)\}\}").unwrap();
-        let mut result = s.to_string();
+    /// SQL and Redis arguments must be set: a placeholder that doesn't resolve is
+    /// an error, not text. A single placeholder keeps its value's type; text
+    /// around placeholders always makes a string.
+    fn resolve_arg(&self, arg: &CompiledValue) -> Result<Value, String> {
+        let CompiledValue::Template(template) = arg else {
+            return Ok(self.resolve_value(arg));
+        };
+        match template.single_placeholder() {
+            Some(Segment::Variable(var)) => self.read_variable(var)
+                .ok_or_else(|| format!("Variable '{}' not set", var.raw.trim_matches(['{', '}']))),
+            Some(Segment::Expression { expr, raw }) => expr.evaluate_strict(|path| self.lookup_in(template.scope(), path))
+                .map_err(|e| format!("Argument {}: {}", raw, e)),
+            _ => self.render_strict(template).map(Value::String),
+        }
+    }
 
-        for cap in re.captures_iter(s) {
-            if let (Some(full_match), Some(var_match)) = (cap.get(0), cap.get(1)) {
-                let var_name = var_match.as_str();
-                if let Some(index) = self.symbol_table.get_index(var_name) {
-                    if let Some(val) = self.memory.get(index) {
-                        let val_str = val.to_string();
-                        result = result.replace(full_match.as_str(), &val_str);
-                    } else {
-                        return Err(format!("Variable '{}' not set", var_name));
-                    }
-                } else {
-                    return Err(format!("Unknown variable '{}'", var_name));
-                }
+    /// Render a template, failing on the first placeholder that doesn't resolve
+    fn render_strict(&self, template: &Template) -> Result<String, String> {
+        let mut out = String::new();
+        for segment in template.segments() {
+            let value = match segment {
+                Segment::Text(text) => {
+                    out.push_str(text);
+                    continue;
+                },
+                Segment::Variable(var) => self.read_variable(var)
+                    .ok_or_else(|| format!("Variable '{}' not set", var.raw.trim_matches(['{', '}'])))?,
+                Segment::Expression { expr, raw } => expr.evaluate_strict(|path| self.lookup_in(template.scope(), path))
+                    .map_err(|e| format!("{}: {}", raw, e))?,
+            };
+            match value {
+                Value::String(s) => out.push_str(&s),
+                other => out.push_str(&other.to_string()),
             }
         }
+        Ok(out)
+    }
+
+    /// Read a compiled `{{path}}` straight from its memory slot
+    fn read_variable(&self, var: &VariableRef) -> Option<Value> {
+        if var.root == "error" && var.rest.as_deref() == Some("code") && self.error_context.is_some() {
+            return self.lookup_in(SymbolTable::OUTERMOST, "error.code");
+        }
 
-        Ok(result)
+        // Templates that were deserialized rather than compiled read the outermost scope
+        let slot = var.slot.or_else(|| self.symbol_table.get_index(&var.root))?;
+        let root_value = self.memory.get(slot)?;
+        match &var.rest {
+            Some(rest) => match get_json_path(root_value, rest) {
+                Value::Null => None,
+                nested => Some(nested),
+            },
+            None => Some(root_value.clone()),
+        }
     }
 
-    fn evaluate_condition(&self, condition: &str) -> Result<bool, String> {
-        // Simple condition evaluation - for now, assume it's a boolean string or comparison
-        // This is a placeholder; in real implementation, you'd parse and evaluate expressions
-        match condition.trim() {
-            "true" => Ok(true),
-            "false" => Ok(false),
-            _ => {
-                // Try to parse as JSON boolean
-                if let Ok(val) = serde_json::from_str::<Value>(condition) {
-                    match val {
-                        Value::Bool(b) => Ok(b),
-                        _ => Err(format!("Cannot evaluate condition: {}", condition)),
-                    }
-                } else {
-                    Err(format!("Cannot evaluate condition: {}", condition))
+    /// Look up a variable or a nested path such as `user.name`, as seen from `scope`
+    fn lookup_in(&self, scope: usize, path: &str) -> Option<Value> {
+        match path.split_once('.') {
+            Some(("error", "code")) if self.error_context.is_some() => {
+                let code = self.error_context.as_ref().and_then(|ctx| ctx.code.clone());
+                Some(Value::String(code.unwrap_or_else(|| "UNKNOWN_ERROR".to_string())))
+            },
+            Some((root, rest)) => {
+                let root_value = self.memory.get(self.symbol_table.resolve(scope, root)?)?;
+                match get_json_path(root_value, rest) {
+                    Value::Null => None,
+                    nested => Some(nested),
                 }
-            }
+            },
+            None => self.memory.get(self.symbol_table.resolve(scope, path)?).cloned(),
         }
     }
 
-    fn execute_math_op(&self, operation: &str, args: &[Value]) -> Result<Value, String> {
-        match operation {
-            "add" => {
-                if args.len() == 2 {
-                    match (&args[0], &args[1]) {
-                        (Value::Number(a), Value::Number(b)) => {
-                            let sum = a.as_f64().unwrap_or(0.0) + b.as_f64().unwrap_or(0.0);
-                            Ok(Value::Number(serde_json::Number::from_f64(sum).unwrap_or(serde_json::Number::from(0))))
-                        },
-                        _ => Err("Add operation requires two numbers".to_string()),
-                    }
-                } else {
-                    Err("Add operation requires exactly two arguments".to_string())
+    /// What an operation is about to work on, for its trace record
+    fn trace_inputs(&self, instruction: &Instruction) -> Value {
+        use serde_json::json;
+        let values = |args: &[CompiledValue]| args.iter().map(|arg| self.resolve_value(arg)).collect::<Vec<_>>();
+        let inputs = match instruction {
+            Instruction::Op(op) => self.operation_inputs(op),
+            Instruction::If { condition, .. } | Instruction::While { condition, .. } => {
+                json!({"condition": self.evaluate_condition(condition).ok()})
+            },
+            Instruction::Loop { collection, .. } => json!({"collection": self.resolve_collection(collection)}),
+            Instruction::Switch { value, .. } => json!({"value": value.evaluate(|path| self.lookup_in(value.scope(), path)).ok()}),
+            Instruction::CallFunction { name, args, .. } => json!({"name": name, "args": values(args)}),
+            _ => json!({}),
+        };
+        trace::redact(inputs)
+    }
+
+    fn operation_inputs(&self, op: &OptimizedOperation) -> Value {
+        use serde_json::json;
+        let var = |index: &usize| self.symbol_table.get_name(*index).unwrap_or_default().to_string();
+        let values = |args: &[CompiledValue]| args.iter().map(|arg| self.resolve_value(arg)).collect::<Vec<_>>();
+        match op {
+            OptimizedOperation::Return { value, status, .. } => json!({"value": self.resolve_value(value), "status": status}),
+            OptimizedOperation::Set { var_index, value } => json!({"var": var(var_index), "value": self.resolve_value(value)}),
+            OptimizedOperation::Get { var_index } => json!({"var": var(var_index)}),
+            OptimizedOperation::QueryDb { query, args, datasource } | OptimizedOperation::SqlOp { query, args, datasource, .. } => {
+                json!({"query": query, "args": values(args), "datasource": datasource})
+            },
+            OptimizedOperation::RedisOp { commands, pipeline, .. } => {
+                let command = |c: &RedisCommand| json!({
+                    "command": c.command,
+                    "key": self.render(&c.key),
+                    "args": values(&c.args),
+                    "ttl_seconds": c.ttl_seconds,
+                });
+                match commands.as_slice() {
+                    [single] if !pipeline => command(single),
+                    _ => json!({"command": "PIPELINE", "commands": commands.iter().map(command).collect::<Vec<_>>()}),
                 }
             },
-            _ => Err(format!("Unsupported math operation: {}", operation)),
+            OptimizedOperation::WsOp { command, message, channel } => json!({
+                "command": command,
+                "message": self.render(message),
+                "channel": channel.as_ref().map(|ch| self.render(ch)),
+            }),
+            OptimizedOperation::HttpRequest { url, method, body, headers, .. } => json!({
+                "method": method,
+                "url": self.render(url),
+                "headers": headers.as_ref().map(|h| h.iter().map(|(k, v)| (k.clone(), self.render(v))).collect::<HashMap<_, _>>()),
+                "body": body.as_ref().map(|b| self.resolve_value(b)),
+            }),
+            OptimizedOperation::Throw { message, code } => json!({"message": self.render(message), "code": code}),
+            OptimizedOperation::StringOp { operation, input, args } => json!({"operation": operation, "input": self.render(input), "args": args}),
+            OptimizedOperation::MathOp { operation, args } => json!({"operation": operation, "args": values(args)}),
+            OptimizedOperation::DateOp { operation, .. } => json!({"operation": operation}),
+            OptimizedOperation::JsonOp { operation, input, .. } => json!({"operation": operation, "input": input}),
+            OptimizedOperation::Log { level, message } => json!({"level": level, "message": self.render(message)}),
+            OptimizedOperation::Sleep { duration_ms } => json!({"duration_ms": duration_ms}),
+            _ => json!({}),
         }
     }
-}
\ No newline at end of file
+
+    /// Path of an operation in the logic, for traces and the debugger
+    fn path(&self, location: &Location) -> String {
+        match &self.call_path {
+            Some(call) => format!("{}.{}", call, location.path),
+            None => location.path.clone(),
+        }
+    }
+
+    /// Write a value the logic produced, unless it is over the size budget
+    fn store(&mut self, index: usize, value: Value) -> Result<(), String> {
+        budget::check_value(&value)?;
+        self.memory.set(index, value);
+        Ok(())
+    }
+
+    /// Store a value in a variable the interpreter writes implicitly (e.g.
+    /// `math_result`); it counts against the size budget like any other, also
+    /// when the logic never reads it
+    fn set_named(&mut self, name: &str, value: Value) -> Result<(), String> {
+        budget::check_value(&value)?;
+        if let Some(index) = self.symbol_table.get_index(name) {
+            self.memory.set(index, value);
+        }
+        Ok(())
+    }
+
+    /// Resolve a loop collection: {{var}}, a bare variable name or a JSON array literal
+    fn resolve_collection(&self, collection: &Template) -> Value {
+        let scope = collection.scope();
+        let collection = collection.source();
+        let var_name = if collection.starts_with("{{") && collection.ends_with("}}") {
+            collection.trim_start_matches("{{").trim_end_matches("}}").trim()
+        } else {
+            collection
+        };
+
+        if let Some(value) = self.lookup_in(scope, var_name) {
+            value
+        } else if collection.starts_with('[') {
+            serde_json::from_str(collection).unwrap_or(Value::Array(vec![]))
+        } else {
+            Value::Array(vec![])
+        }
+    }
+
+    fn evaluate_condition(&self, condition: &CompiledExpression) -> Result<bool, String> {
+        condition.is_true(|path| self.lookup_in(condition.scope(), path))
+    }
+}
+
+/// Whether two operations' `datasource`s name the same database; none is the default one
+fn same_datasource(a: Option<&str>, b: Option<&str>) -> bool {
+    a.unwrap_or(DEFAULT_DATASOURCE) == b.unwrap_or(DEFAULT_DATASOURCE)
+}
@@ -0,0 +1,201 @@
+//! The recursive tree walker the VM ran before programs were flattened
+//!
+//! Routes don't run on it: it runs a lowered operation tree directly, recursing
+//! into nested blocks through a boxed future each, and stays as the baseline
+//! the flat VM is benchmarked against. It neither traces nor debugs.
+
+use super::{Callable, VirtualMachine};
+use crate::budget;
+use crate::services::dynamic_routes::utils::switch_case_matches;
+use crate::vm::instructions::OptimizedOperation;
+use futures::stream::{self, StreamExt};
+use proto::models::ErrorContext;
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+
+impl VirtualMachine {
+    /// Run a lowered operation tree
+    pub async fn execute_tree(&mut self, program: &[OptimizedOperation]) -> Result<Value, String> {
+        self.walk(program).await
+    }
+
+    fn walk<'a>(
+        &'a mut self,
+        block: &'a [OptimizedOperation],
+    ) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut result = Value::Null;
+            for op in block {
+                // A pending break/continue ends the current block, the enclosing loop clears it;
+                // a return in a function body ends every block up to the call
+                if self.loop_control.should_break || self.loop_control.should_continue || self.returning {
+                    break;
+                }
+                budget::charge(1)?;
+                if self.walk_operation(op, &mut result).await? {
+                    break;
+                }
+            }
+            Ok(result)
+        })
+    }
+
+    async fn walk_operation(&mut self, op: &OptimizedOperation, result: &mut Value) -> Result<bool, String> {
+        match op {
+            OptimizedOperation::If { condition, then, otherwise } => {
+                let condition_result = self.evaluate_condition(condition)?;
+                if condition_result {
+                    *result = self.walk(then).await?;
+                } else if let Some(else_ops) = otherwise {
+                    *result = self.walk(else_ops).await?;
+                }
+            },
+            OptimizedOperation::Loop { collection, var_index, body } => {
+                if let Value::Array(items) = self.resolve_collection(collection) {
+                    // Loop variables are restored afterwards so they don't leak out of the loop
+                    let index_slot = self.symbol_table.get_index("index");
+                    let prev_var = self.memory.get(*var_index).cloned();
+                    let prev_index = index_slot.and_then(|idx| self.memory.get(idx).cloned());
+                    
+                    for (i, item) in items.into_iter().enumerate() {
+                        budget::charge(1)?;
+                        self.memory.set(*var_index, item);
+                        if let Some(idx) = index_slot {
+                            self.memory.set(idx, Value::Number(i.into()));
+                        }
+                        
+                        *result = self.walk(body).await?;
+                        
+                        if self.loop_control.should_break || self.returning {
+                            self.loop_control.should_break = false;
+                            break;
+                        }
+                        self.loop_control.should_continue = false;
+                    }
+                    
+                    self.memory.set(*var_index, prev_var.unwrap_or(Value::Null));
+                    if let Some(idx) = index_slot {
+                        self.memory.set(idx, prev_index.unwrap_or(Value::Null));
+                    }
+                }
+            },
+            OptimizedOperation::Switch { value, cases, default } => {
+                let switch_value = value.evaluate(|path| self.lookup_in(value.scope(), path))?;
+                match cases.iter().position(|case| switch_case_matches(&case.value, &switch_value)) {
+                    Some(i) => {
+                        *result = self.walk(&cases[i].operations).await?;
+                    },
+                    None => {
+                        if let Some(default_ops) = default {
+                            *result = self.walk(default_ops).await?;
+                        }
+                    },
+                }
+            },
+            OptimizedOperation::While { condition, body, max_iterations } => {
+                let max_iter = max_iterations.unwrap_or(1000);
+                let mut iterations = 0;
+                
+                while iterations < max_iter && self.evaluate_condition(condition)? {
+                    iterations += 1;
+                    budget::charge(1)?;
+                    
+                    *result = self.walk(body).await?;
+                    
+                    if self.loop_control.should_break || self.returning {
+                        self.loop_control.should_break = false;
+                        break;
+                    }
+                    self.loop_control.should_continue = false;
+                }
+            },
+            OptimizedOperation::Break => {
+                self.loop_control.should_break = true;
+            },
+            OptimizedOperation::Continue => {
+                self.loop_control.should_continue = true;
+            },
+            OptimizedOperation::Try { body, catch, finally } => {
+                match self.walk(body).await {
+                    Ok(value) => {
+                        *result = value;
+                    },
+                    // Running out of budget isn't the logic's error to handle
+                    Err(e) if budget::exceeded().is_some() => return Err(e),
+                    Err(e) => {
+                        self.set_named("error", serde_json::json!({
+                            "message": e,
+                        }))?;
+                        self.error_context = Some(ErrorContext {
+                            message: e,
+                            code: None,
+                            stack: vec![],
+                        });
+                        
+                        *result = self.walk(catch).await?;
+                    }
+                }
+                
+                if let Some(finally_ops) = finally {
+                    self.walk(finally_ops).await?;
+                }
+            },
+            OptimizedOperation::Transaction { body, savepoint, datasource } => {
+                let transaction = self.begin_transaction(*savepoint, datasource.as_deref()).await?;
+                match self.walk(body).await {
+                    Ok(value) => {
+                        self.commit(transaction).await?;
+                        *result = value;
+                    },
+                    Err(e) => {
+                        self.roll_back(transaction).await;
+                        return Err(e);
+                    },
+                }
+            },
+            OptimizedOperation::Parallel { tasks, max_concurrent } => {
+                // Each task runs on its own copy of the state; failed tasks are dropped
+                // unless they ran out of budget, which aborts the whole execution
+                budget::check_parallel(tasks.len())?;
+                let futures: Vec<_> = tasks.iter().map(|task| {
+                    let mut task_vm = self.fork();
+                    async move { task_vm.walk(task).await }
+                }).collect();
+                
+                // At most `max_concurrent` tasks run at once; results keep the tasks' order
+                let limit = max_concurrent.unwrap_or(tasks.len()).max(1);
+                let results: Vec<_> = stream::iter(futures).buffered(limit).collect().await;
+                if let Some(e) = budget::limit_error() {
+                    return Err(e);
+                }
+                *result = Value::Array(results.into_iter().filter_map(Result::ok).collect());
+            },
+            OptimizedOperation::DefineFunction { function } => {
+                self.functions.insert(function.name.clone(), Callable::Tree(function.clone()));
+            },
+            OptimizedOperation::CallFunction { name, args, output_var_index } => {
+                let Some(Callable::Tree(function)) = self.functions.get(name).cloned() else {
+                    return Err(format!("Function '{}' not defined", name));
+                };
+                budget::check_call_depth(self.call_depth + 1)?;
+                
+                // The body runs on memory of its own; only the return value flows back
+                let mut callee = self.fork();
+                callee.memory = self.call_memory(&function.symbols, &function.param_indices, args);
+                callee.symbol_table = function.symbols.clone();
+                callee.call_depth += 1;
+                
+                let value = callee.walk(&function.body).await?;
+                self.store(*output_var_index, value.clone())?;
+                *result = value;
+            },
+            OptimizedOperation::Return { .. } => {
+                self.returning = self.call_depth > 0;
+                return self.execute_operation(op, result).await;
+            },
+            other => return self.execute_operation(other, result).await,
+        }
+        Ok(false)
+    }
+}
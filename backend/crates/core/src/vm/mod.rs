@@ -1,3 +1,4 @@
 pub mod memory;
 pub mod instructions;
-pub mod machine;
\ No newline at end of file
+pub mod machine;
+pub mod template;
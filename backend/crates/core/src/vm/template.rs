@@ -0,0 +1,348 @@
+use crate::compiler::symbol_table::SymbolTable;
+use crate::expression::condition::is_path;
+use crate::expression::CompiledExpression;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// A templated string lowered ahead of time into literal text, variable slots
+/// and expressions, so the VM never scans or re-parses it at run time.
+///
+///   "Hello {{user.name}}"   → Text("Hello "), Variable(user → slot, path "name")
+///   "{{ items | length }}"  → Expression
+///   "${price * qty}"        → Expression
+///
+/// Serialized as its source text, along with the scope and slots it was
+/// compiled with when there are any, so a stored program runs as compiled.
+#[derive(Debug, Clone)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+    /// Symbol table scope its variables are looked up in by name
+    scope: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum Segment {
+    Text(String),
+    Variable(VariableRef),
+    /// `raw` is the placeholder as written, kept when evaluation fails
+    Expression { expr: CompiledExpression, raw: String },
+}
+
+/// A `{{path}}` placeholder bound to the memory slot of its root variable
+#[derive(Debug, Clone)]
+pub struct VariableRef {
+    /// Unbound when the template was deserialized rather than compiled
+    pub slot: Option<usize>,
+    pub root: String,
+    /// Nested path below the root, e.g. `address.city` in `{{user.address.city}}`
+    pub rest: Option<String>,
+    pub raw: String,
+}
+
+impl Template {
+    /// Split a template into segments without binding variables to slots
+    pub fn parse(source: &str) -> Self {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut rest = source;
+
+        while !rest.is_empty() {
+            let placeholder = if let Some(body) = rest.strip_prefix("{{") {
+                placeholder_end(body, "}}").map(|end| (&body[..end], 2 + end + 2, false))
+            } else if let Some(body) = rest.strip_prefix("${") {
+                placeholder_end(body, "}").map(|end| (&body[..end], 2 + end + 1, true))
+            } else {
+                None
+            };
+
+            match placeholder {
+                Some((inner, len, is_expression)) => {
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::placeholder(inner, &rest[..len], is_expression));
+                    rest = &rest[len..];
+                }
+                None => {
+                    let c = rest.chars().next().unwrap_or_default();
+                    text.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Self { source: source.to_string(), segments, scope: SymbolTable::OUTERMOST }
+    }
+
+    /// Parse and bind every variable the template reads to a slot in `symbols`
+    pub fn compile(source: &str, symbols: &mut SymbolTable) -> Self {
+        let mut template = Self::parse(source);
+        template.scope = symbols.scope();
+        for segment in &mut template.segments {
+            match segment {
+                Segment::Variable(var) => {
+                    var.slot = Some(symbols.register(var.root.clone()));
+                }
+                Segment::Expression { expr, .. } => {
+                    for path in expr.variable_paths() {
+                        let root = path.split('.').next().unwrap_or(path);
+                        symbols.register(root.to_string());
+                    }
+                }
+                Segment::Text(_) => {}
+            }
+        }
+        template
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Scope the template was compiled in, for variables read by name (expressions, `join` inputs)
+    pub fn scope(&self) -> usize {
+        self.scope
+    }
+
+    /// Root names of the variables the template reads
+    pub fn variable_roots(&self) -> Vec<&str> {
+        let mut roots = Vec::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Variable(var) => roots.push(var.root.as_str()),
+                Segment::Expression { expr, .. } => {
+                    roots.extend(expr.variable_paths().map(|path| path.split('.').next().unwrap_or(path)));
+                }
+                Segment::Text(_) => {}
+            }
+        }
+        roots
+    }
+
+    /// True when the template has no placeholders
+    pub fn is_literal(&self) -> bool {
+        self.segments.iter().all(|segment| matches!(segment, Segment::Text(_)))
+    }
+
+    /// The only segment, when the whole template is a single placeholder
+    pub fn single_placeholder(&self) -> Option<&Segment> {
+        match self.segments.as_slice() {
+            [segment @ (Segment::Variable(_) | Segment::Expression { .. })] => Some(segment),
+            _ => None,
+        }
+    }
+}
+
+impl Segment {
+    fn placeholder(inner: &str, raw: &str, is_expression: bool) -> Self {
+        let inner = inner.trim();
+        if !is_expression && is_path(inner) {
+            // a[0].b reads the same as a.0.b
+            let path = inner.replace('[', ".").replace(']', "");
+            let (root, rest) = match path.split_once('.') {
+                Some((root, rest)) => (root.to_string(), Some(rest.to_string())),
+                None => (path, None),
+            };
+            return Segment::Variable(VariableRef { slot: None, root, rest, raw: raw.to_string() });
+        }
+
+        match CompiledExpression::compile(inner) {
+            Ok(expr) => Segment::Expression { expr, raw: raw.to_string() },
+            // Not an expression (e.g. stray braces in text): render as written
+            Err(_) => Segment::Text(raw.to_string()),
+        }
+    }
+}
+
+/// Offset of the closing delimiter; the placeholder body is non-empty and has no '}'
+fn placeholder_end(body: &str, close: &str) -> Option<usize> {
+    let end = body.find('}')?;
+    (end > 0 && body[end..].starts_with(close)).then_some(end)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredTemplate {
+    Source(String),
+    Compiled { source: String, scope: usize, slots: Vec<Option<usize>> },
+}
+
+impl Serialize for Template {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let slots: Vec<Option<usize>> = self.segments.iter()
+            .filter_map(|segment| match segment {
+                Segment::Variable(var) => Some(var.slot),
+                _ => None,
+            })
+            .collect();
+        if self.scope == SymbolTable::OUTERMOST && slots.iter().all(Option::is_none) {
+            return serializer.serialize_str(&self.source);
+        }
+        StoredTemplate::Compiled { source: self.source.clone(), scope: self.scope, slots }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match StoredTemplate::deserialize(deserializer)? {
+            StoredTemplate::Source(source) => Ok(Self::parse(&source)),
+            StoredTemplate::Compiled { source, scope, slots } => {
+                let mut template = Self::parse(&source);
+                template.scope = scope;
+                let mut slots = slots.into_iter();
+                for segment in &mut template.segments {
+                    if let Segment::Variable(var) = segment {
+                        var.slot = slots.next().flatten();
+                    }
+                }
+                Ok(template)
+            }
+        }
+    }
+}
+
+/// A JSON value with its templated strings compiled.
+/// Subtrees without placeholders are kept as constants; plain strings are
+/// pre-parsed as JSON the way the VM used to do on every evaluation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompiledValue {
+    Constant(Value),
+    Template(Template),
+    Array(Vec<CompiledValue>),
+    Object(Vec<(String, CompiledValue)>),
+}
+
+impl CompiledValue {
+    pub fn compile(value: &Value, symbols: &mut SymbolTable) -> Self {
+        Self::lower(value, &mut |s| Template::compile(s, symbols))
+    }
+
+    /// Compile without binding variables to slots
+    pub fn parse(value: &Value) -> Self {
+        Self::lower(value, &mut Template::parse)
+    }
+
+    fn lower(value: &Value, template: &mut dyn FnMut(&str) -> Template) -> Self {
+        match value {
+            Value::String(s) => {
+                let compiled = template(s);
+                if compiled.is_literal() {
+                    Self::Constant(serde_json::from_str(s).unwrap_or_else(|_| value.clone()))
+                } else {
+                    Self::Template(compiled)
+                }
+            }
+            Value::Array(items) => {
+                let items: Vec<Self> = items.iter().map(|item| Self::lower(item, template)).collect();
+                if items.iter().all(|item| matches!(item, Self::Constant(_))) {
+                    Self::Constant(Value::Array(items.into_iter().map(Self::into_source).collect()))
+                } else {
+                    Self::Array(items)
+                }
+            }
+            Value::Object(map) => {
+                let fields: Vec<(String, Self)> = map.iter()
+                    .map(|(key, item)| (key.clone(), Self::lower(item, template)))
+                    .collect();
+                if fields.iter().all(|(_, item)| matches!(item, Self::Constant(_))) {
+                    Self::Constant(Value::Object(fields.into_iter().map(|(k, item)| (k, item.into_source())).collect()))
+                } else {
+                    Self::Object(fields)
+                }
+            }
+            other => Self::Constant(other.clone()),
+        }
+    }
+
+    /// Every template in the value, depth first
+    pub fn templates(&self) -> Vec<&Template> {
+        match self {
+            Self::Constant(_) => vec![],
+            Self::Template(template) => vec![template],
+            Self::Array(items) => items.iter().flat_map(Self::templates).collect(),
+            Self::Object(fields) => fields.iter().flat_map(|(_, item)| item.templates()).collect(),
+        }
+    }
+
+    /// The value as written in the route definition (plain strings already parsed)
+    pub fn source(&self) -> Value {
+        self.clone().into_source()
+    }
+
+    fn into_source(self) -> Value {
+        match self {
+            Self::Constant(value) => value,
+            Self::Template(template) => Value::String(template.source),
+            Self::Array(items) => Value::Array(items.into_iter().map(Self::into_source).collect()),
+            Self::Object(fields) => Value::Object(fields.into_iter().map(|(k, item)| (k, item.into_source())).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_segments() {
+        let mut symbols = SymbolTable::new();
+        let template = Template::compile("Hi {{user.name}}, total ${price * qty}!", &mut symbols);
+        match template.segments() {
+            [Segment::Text(a), Segment::Variable(var), Segment::Text(b), Segment::Expression { .. }, Segment::Text(c)] => {
+                assert_eq!((a.as_str(), b.as_str(), c.as_str()), ("Hi ", ", total ", "!"));
+                assert_eq!(var.slot, symbols.get_index("user"));
+                assert_eq!(var.rest.as_deref(), Some("name"));
+            }
+            other => panic!("unexpected segments: {:?}", other),
+        }
+        assert!(symbols.get_index("price").is_some());
+        assert!(symbols.get_index("qty").is_some());
+    }
+
+    #[test]
+    fn test_single_placeholder() {
+        assert!(Template::parse("{{ count }}").single_placeholder().is_some());
+        assert!(Template::parse("{{ items | length }}").single_placeholder().is_some());
+        assert!(Template::parse("n={{count}}").single_placeholder().is_none());
+        assert!(Template::parse("{{}} and {x}").is_literal());
+    }
+
+    #[test]
+    fn test_compiled_value_constants() {
+        let value = CompiledValue::parse(&json!({"a": "123", "b": ["x", true]}));
+        assert!(matches!(&value, CompiledValue::Constant(v) if *v == json!({"a": 123, "b": ["x", true]})));
+
+        let value = CompiledValue::parse(&json!({"id": "{{id}}", "tag": "static"}));
+        assert!(matches!(value, CompiledValue::Object(_)));
+        assert_eq!(value.source(), json!({"id": "{{id}}", "tag": "static"}));
+    }
+
+    #[test]
+    fn test_compiled_templates_round_trip_with_their_slots() {
+        let mut symbols = SymbolTable::new();
+        symbols.declare("other".to_string());
+        symbols.enter_scope();
+        let value = CompiledValue::compile(&json!({"greeting": "Hi {{user.name}} ${n + 1}", "n": 2}), &mut symbols);
+
+        let stored = serde_json::to_value(&value).unwrap();
+        assert_eq!(stored["object"][0][1]["template"], json!({"source": "Hi {{user.name}} ${n + 1}", "scope": 1, "slots": [Some(1)]}));
+        let loaded: CompiledValue = serde_json::from_value(stored).unwrap();
+        let CompiledValue::Object(fields) = &loaded else { panic!("expected an object: {:?}", loaded) };
+        let CompiledValue::Template(template) = &fields[0].1 else { panic!("expected a template") };
+        assert_eq!(template.scope(), 1);
+        assert!(matches!(&template.segments()[1], Segment::Variable(var) if var.slot == Some(1)));
+
+        // Templates that were never compiled stay plain text
+        assert_eq!(serde_json::to_value(Template::parse("{{x}}")).unwrap(), json!("{{x}}"));
+    }
+}
@@ -0,0 +1,212 @@
+use worpen_core::data_model;
+use worpen_core::services::dynamic_routes::service::DynamicRouteService;
+use worpen_core::services::dynamic_routes::RouteLookup;
+use worpen_core::services::models::{ApplyOptions, ModelService};
+use proto::models::{DynamicRouteExecutionContext, LogicOperation, LoopControl, RouteDiffStatus};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const PRODUCT: &str = r#"
+name: product
+table: products
+max_page_size: 2
+fields:
+  - name: title
+    type: string
+    required: true
+    max_length: 20
+  - name: price
+    type: number
+    min: 0
+  - name: status
+    type: string
+    enum: [draft, published]
+    default: draft
+indexes:
+  - fields: [status]
+"#;
+
+struct Fixture {
+    routes: Arc<DynamicRouteService>,
+    models: ModelService,
+    pool: SqlitePool,
+    data_dir: std::path::PathBuf,
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.data_dir).ok();
+    }
+}
+
+async fn fixture() -> Fixture {
+    let data_dir = std::env::temp_dir().join(format!("worpen_models_{}", uuid::Uuid::new_v4()));
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let mut routes = DynamicRouteService::with_data_dir(data_dir.display().to_string());
+    routes.set_db_pool(pool.clone());
+    let routes = Arc::new(routes);
+    let models = ModelService::new(routes.clone());
+    Fixture { routes, models, pool, data_dir }
+}
+
+/// Route a request the way the HTTP fallback does: match, validate, execute
+async fn request(routes: &DynamicRouteService, method: &str, path: &str, query: &[(&str, &str)], body: Value) -> (u16, Value) {
+    let RouteLookup::Matched(matched) = routes.match_route(method, path).await else {
+        return (404, Value::Null);
+    };
+    let route = routes.get_route(&matched.route_id).await.unwrap().unwrap();
+    let query: HashMap<String, String> = query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    let validated = match routes.validate_request(&route, &matched.params, &query, body) {
+        Ok(validated) => validated,
+        Err(result) => return (400, json!(result.errors.iter().map(|e| e.path.clone()).collect::<Vec<_>>())),
+    };
+    let mut context = DynamicRouteExecutionContext {
+        route_id: route.id.clone(),
+        variables: validated.params.into_iter().collect(),
+        request_payload: Some(validated.body),
+        path_params: matched.params,
+        query_params: query,
+        functions: HashMap::new(),
+        loop_control: LoopControl::default(),
+        error_context: None,
+    };
+    match routes.execute_route_logic(&route, &mut context).await {
+        Ok(Value::Object(response)) if response.contains_key("value") => {
+            (response["status"].as_u64().unwrap_or(200) as u16, response["value"].clone())
+        },
+        Ok(value) => (200, value),
+        Err(e) => (500, Value::String(e)),
+    }
+}
+
+fn titles(page: &Value) -> Vec<&str> {
+    page["items"].as_array().unwrap().iter().map(|item| item["title"].as_str().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn test_model_generates_working_crud_routes() {
+    let f = fixture().await;
+    let result = f.models.apply(data_model::parse(PRODUCT).unwrap(), ApplyOptions::default()).await.unwrap();
+    assert_eq!(result.model.version, 1);
+    assert!(result.plan.routes.iter().all(|diff| diff.status == RouteDiffStatus::Missing));
+    let r = f.routes.as_ref();
+
+    let (status, pen) = request(r, "POST", "/api/products", &[], json!({"title": "pen", "price": 1.5})).await;
+    assert_eq!(status, 201);
+    assert_eq!((&pen["id"], &pen["status"], &pen["price"]), (&json!(1), &json!("draft"), &json!(1.5)));
+    for (title, price, state) in [("ink", 4.0, "published"), ("pad", 2.5, "published")] {
+        let (status, _) = request(r, "POST", "/api/products", &[], json!({"title": title, "price": price, "status": state})).await;
+        assert_eq!(status, 201);
+    }
+
+    // Constraints are checked before the logic runs
+    let (status, errors) = request(r, "POST", "/api/products", &[], json!({"price": -1, "status": "sold"})).await;
+    assert_eq!(status, 400);
+    assert!(errors.as_array().unwrap().contains(&json!("body.title")), "{}", errors);
+
+    // Filtering, sorting and pagination, with the page size capped by the model
+    let (_, page) = request(r, "GET", "/api/products", &[("sort", "-price"), ("limit", "10")], Value::Null).await;
+    assert_eq!((titles(&page), &page["total"], &page["limit"]), (vec!["ink", "pad"], &json!(3), &json!(2)));
+    let (_, page) = request(r, "GET", "/api/products", &[("sort", "-price"), ("offset", "2")], Value::Null).await;
+    assert_eq!(titles(&page), vec!["pen"]);
+    let (_, page) = request(r, "GET", "/api/products", &[("status", "published"), ("price_max", "3")], Value::Null).await;
+    assert_eq!((titles(&page), &page["total"]), (vec!["pad"], &json!(1)));
+    let (status, _) = request(r, "GET", "/api/products", &[("sort", "title; DROP TABLE products")], Value::Null).await;
+    assert_eq!(status, 400);
+
+    let (status, found) = request(r, "GET", "/api/products/2", &[], Value::Null).await;
+    assert_eq!((status, &found["title"]), (200, &json!("ink")));
+    let (status, updated) = request(r, "PUT", "/api/products/2", &[], json!({"title": "ink", "price": 5})).await;
+    assert_eq!((status, &updated["price"], &updated["status"]), (200, &json!(5.0), &json!("draft")));
+    let (status, _) = request(r, "DELETE", "/api/products/2", &[], Value::Null).await;
+    assert_eq!(status, 200);
+    for (method, body) in [("GET", Value::Null), ("PUT", json!({"title": "x"})), ("DELETE", Value::Null)] {
+        let (status, error) = request(r, method, "/api/products/2", &[], body).await;
+        assert_eq!((status, error), (404, json!({"error": "product 2 not found"})), "{}", method);
+    }
+
+    let history: Vec<(String, i64)> = sqlx::query_as("SELECT model, version FROM _worpen_model_migrations")
+        .fetch_all(&f.pool).await.unwrap();
+    assert_eq!(history, vec![("product".to_string(), 1)]);
+}
+
+#[tokio::test]
+async fn test_model_changes_migrate_and_regenerate_unedited_routes() {
+    let f = fixture().await;
+    let first = data_model::parse(PRODUCT).unwrap();
+    f.models.apply(first.clone(), ApplyOptions::default()).await.unwrap();
+    request(&f.routes, "POST", "/api/products", &[], json!({"title": "pen", "price": 1})).await;
+
+    // Someone edits the get route by hand
+    let mut get = f.routes.get_route("model-product-get").await.unwrap().unwrap();
+    get.logic.insert(0, LogicOperation::Comment { text: "audited".to_string() });
+    f.routes.update_route("model-product-get", get).await.unwrap();
+
+    let mut second = first.clone();
+    second.fields.push(data_model::parse("name: x\nfields: [{name: stock, type: integer, required: true, default: 0}]").unwrap().fields.remove(0));
+    let plan = f.models.plan(&second).await.unwrap();
+    assert_eq!(plan.statements, vec![
+        "ALTER TABLE \"products\" ADD COLUMN \"stock\" INTEGER NOT NULL DEFAULT 0",
+    ]);
+    let statuses: Vec<(&str, RouteDiffStatus)> = plan.routes.iter().map(|d| (d.route_id.as_str(), d.status)).collect();
+    assert_eq!(statuses, vec![
+        ("model-product-list", RouteDiffStatus::Outdated),
+        ("model-product-get", RouteDiffStatus::Edited),
+        ("model-product-create", RouteDiffStatus::Outdated),
+        ("model-product-update", RouteDiffStatus::Outdated),
+        ("model-product-delete", RouteDiffStatus::UpToDate),
+    ]);
+    assert_eq!(plan.routes[0].changes, vec!["logic", "parameters"]);
+    assert_eq!(plan.routes[1].changes, vec!["logic"]);
+
+    let result = f.models.apply(second.clone(), ApplyOptions::default()).await.unwrap();
+    assert_eq!((result.model.version, result.skipped_routes.clone()), (2, vec!["model-product-get".to_string()]));
+    let (_, page) = request(&f.routes, "GET", "/api/products", &[("stock", "0")], Value::Null).await;
+    assert_eq!(titles(&page), vec!["pen"]);
+    let get = f.routes.get_route("model-product-get").await.unwrap().unwrap();
+    assert!(matches!(get.logic[0], LogicOperation::Comment { .. }));
+
+    // Applying the same model again changes nothing; overwriting replaces the edited route
+    let again = f.models.apply(second.clone(), ApplyOptions::default()).await.unwrap();
+    assert!(again.plan.statements.is_empty() && again.model.version == 2);
+    f.models.apply(second.clone(), ApplyOptions { overwrite_edited: true, ..Default::default() }).await.unwrap();
+    let plan = f.models.plan(&second).await.unwrap();
+    assert!(plan.routes.iter().all(|diff| diff.status == RouteDiffStatus::UpToDate));
+
+    // Dropping a field needs consent
+    let err = f.models.apply(first.clone(), ApplyOptions::default()).await.unwrap_err();
+    assert!(err.contains("loses their data"), "{}", err);
+    let result = f.models.apply(first, ApplyOptions { allow_data_loss: true, ..Default::default() }).await.unwrap();
+    assert_eq!(result.model.migrations.len(), 3);
+    let (status, pen) = request(&f.routes, "GET", "/api/products/1", &[], Value::Null).await;
+    assert_eq!((status, pen.get("stock"), &pen["title"]), (200, None, &json!("pen")));
+
+    // Models outlive a restart, and deleting one keeps the table
+    let models = ModelService::new(f.routes.clone());
+    assert_eq!(models.get_model("product").unwrap().version, 3);
+    assert_eq!(models.delete_model("product").await.unwrap().len(), 5);
+    assert!(f.routes.get_route("model-product-list").await.unwrap().is_none());
+    let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM products").fetch_one(&f.pool).await.unwrap();
+    assert_eq!(rows, 1);
+}
+
+#[tokio::test]
+async fn test_model_refuses_unmanaged_tables_and_taken_paths() {
+    let f = fixture().await;
+    sqlx::query("CREATE TABLE products (id INTEGER PRIMARY KEY)").execute(&f.pool).await.unwrap();
+    let err = f.models.apply(data_model::parse(PRODUCT).unwrap(), ApplyOptions::default()).await.unwrap_err();
+    assert_eq!(err, "Table 'products' already exists and isn't managed by a model");
+
+    let mut model = data_model::parse(PRODUCT).unwrap();
+    model.name = "item".to_string();
+    model.table = None;
+    model.path = Some("/api/products".to_string());
+    f.models.apply(model.clone(), ApplyOptions::default()).await.unwrap();
+    model.name = "other".to_string();
+    let err = f.models.apply(model, ApplyOptions::default()).await.unwrap_err();
+    assert!(err.contains("/api/products"), "{}", err);
+    let tables: Vec<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE name = 'other'").fetch_all(&f.pool).await.unwrap();
+    assert!(tables.is_empty());
+}
@@ -28,9 +28,16 @@ fn test_hot_routes_cache() {
                 LogicOperation::Return { value: Value::String("Success".to_string()), status: None, headers: None, raw: None }
             ],
             parameters: vec![],
+            request_schema: None,
             response_schema: None,
+            response_schema_policy: Default::default(),
             auth_required: false,
+            required_scopes: vec![],
+            required_roles: vec![],
             rate_limit: None,
+            rate_limit_key: None,
+            limits: None,
+            optimize: true,
             enabled: true,
             version: "1.0.0".to_string(),
             route_type: proto::models::RouteType::Http,
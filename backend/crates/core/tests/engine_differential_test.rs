@@ -0,0 +1,358 @@
+//! Runs every route fixture in the repository through both engines and reports
+//! where the VM and the interpreter disagree. The VM is the engine routes run on;
+//! until the interpreter is removed, a fixture that starts diverging fails here.
+
+use worpen_core::budget::{self, Limits};
+use worpen_core::compiler::lowerer::LogicCompiler;
+use worpen_core::compiler::optimizer::Optimizer;
+use worpen_core::compiler::symbol_table::SymbolTable;
+use worpen_core::parsers::parse_route;
+use worpen_core::services::dynamic_routes::execution::execute_logic_extended;
+use worpen_core::services::dynamic_routes::service::DynamicRouteService;
+use worpen_core::vm::instructions::Program;
+use worpen_core::vm::machine::VirtualMachine;
+use worpen_core::vm::memory::ExecutionMemory;
+use proto::models::{
+    DynamicRouteExecutionContext, HttpMethod, LogicOperation, LoopControl, RouteDefinition, RouteTestRequest,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+fn limits() -> Limits {
+    Limits {
+        max_fuel: 100_000,
+        timeout: Duration::from_secs(2),
+        max_value_bytes: 1024 * 1024,
+        max_parallel: 16,
+        max_call_depth: 64,
+    }
+}
+
+/// Route files under `backend/` and the YAML test routes at the repository root
+fn fixtures() -> Vec<PathBuf> {
+    let backend = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+    let mut files = vec![];
+    for (dir, extensions) in [(backend.clone(), &["json", "yaml"][..]), (backend.join(".."), &["yaml"][..])] {
+        for entry in std::fs::read_dir(&dir).unwrap().flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()).is_some_and(|e| extensions.contains(&e)) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+fn name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().to_string()
+}
+
+/// What an engine produced; both failing counts as agreement, whatever the messages
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Value(Value),
+    Failed,
+}
+
+async fn run_vm(logic: &[LogicOperation]) -> Result<Outcome, String> {
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(logic)?;
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), compiler.get_symbol_table().clone());
+    Ok(match budget::run(limits(), vm.execute(&program)).await {
+        Ok(value) => Outcome::Value(value),
+        Err(_) => Outcome::Failed,
+    })
+}
+
+async fn run_interpreter(logic: &[LogicOperation]) -> Outcome {
+    let mut context = DynamicRouteExecutionContext {
+        route_id: "differential".to_string(),
+        variables: HashMap::new(),
+        request_payload: None,
+        path_params: HashMap::new(),
+        query_params: HashMap::new(),
+        functions: HashMap::new(),
+        loop_control: LoopControl::default(),
+        error_context: None,
+    };
+    let mut steps = vec![];
+    match budget::run(limits(), execute_logic_extended(logic, &mut context, &mut steps)).await {
+        Ok(value) => Outcome::Value(value),
+        Err(_) => Outcome::Failed,
+    }
+}
+
+/// Fixtures the engines are known to disagree on, and why. The interpreter is
+/// the one that is off in each case; a fixture leaves this list once it agrees.
+const KNOWN_DIVERGENCES: &[(&str, &str)] = &[
+    ("advanced_test.yaml", "interpreter renders numbers as strings"),
+    ("array_processing_test.yaml", "interpreter renders numbers as strings"),
+    ("game_action_api.json", "interpreter renders numbers as strings"),
+    ("nested_test.yaml", "interpreter renders numbers and null as strings"),
+    ("performance_demo.json", "interpreter does not evaluate {{now()}}"),
+    ("sequential_test.yaml", "interpreter renders numbers and null as strings"),
+    ("simple_while_test.json", "interpreter renders numbers as strings"),
+    ("test_foreach.json", "interpreter renders numbers as strings"),
+    ("test_mock.json", "interpreter leaves unset variables as '{{name}}'"),
+    ("test_yaml_route.yaml", "interpreter does not evaluate {{now()}}"),
+    ("ultimate_test.json", "interpreter renders numbers as strings and leaves unset variables"),
+    ("ultra_complex_test.yaml", "interpreter renders numbers and booleans as strings"),
+];
+
+/// Fixtures that aren't a single route the compiler accepts, and why. Every
+/// other fixture must be compared; a fixture leaves this list once it loads.
+const EXPECTED_SKIPS: &[(&str, &str)] = &[
+    ("advanced_stress_test.yaml", "query_db passes arguments its query has no placeholders for"),
+    ("api_demo_with_comments.json", "uses `comment` operations"),
+    ("complex_test.yaml", "condition uses JavaScript's `===`"),
+    ("helper_functions_demo.json", "written in the `steps`/`action` format"),
+    ("html_demo_with_comments.json", "uses `comment` operations"),
+    ("loop_break_continue_demo.json", "written in the `steps`/`action` format"),
+    ("loop_metadata_demo.json", "written in the `steps`/`action` format"),
+    ("loop_nested_demo.json", "written in the `steps`/`action` format"),
+    ("loop_until_demo.json", "written in the `steps`/`action` format"),
+    ("loop_while_demo.json", "written in the `steps`/`action` format"),
+    ("medium_stress_test.yaml", "query_db passes arguments its query has no placeholders for"),
+    ("order_create_named_params.json", "written in the `steps`/`action` format"),
+    ("physics_api.json", "uses snake_case operation names"),
+    ("physics_api_simple.json", "uses snake_case operation names"),
+    ("physics_api_v2.json", "uses snake_case operation names"),
+    ("redis_example.yaml", "several YAML documents of examples"),
+    ("sample_function.json", "a UTF-16 function definition"),
+    ("sample_functions.json", "a list of function definitions"),
+    ("sample_inlined_route.json", "JSON with comments"),
+    ("sample_route_with_functions.json", "calls functions defined in another file"),
+    ("sql_example.yaml", "several YAML documents of examples"),
+    ("test_route.json", "uses PascalCase operation names"),
+    ("test_route_fixed.json", "uses a `loop` operation"),
+    ("user_profile_update_named_params.json", "written in the `steps`/`action` format"),
+    ("user_registration_named_params.json", "written in the `steps`/`action` format"),
+];
+
+/// Where two results differ, as `path: <label> <value>, <label> <value>`
+fn differences(vm: &Value, interpreter: &Value, path: &str, labels: (&str, &str), out: &mut Vec<String>) {
+    match (vm, interpreter) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let nested = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                differences(a.get(key).unwrap_or(&Value::Null), b.get(key).unwrap_or(&Value::Null), &nested, labels, out);
+            }
+        },
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            for (i, (x, y)) in a.iter().zip(b).enumerate() {
+                differences(x, y, &format!("{}[{}]", path, i), labels, out);
+            }
+        },
+        _ if vm != interpreter => {
+            let short = |v: &Value| v.to_string().chars().take(60).collect::<String>();
+            let path = if path.is_empty() { "result" } else { path };
+            out.push(format!("{}: {} {}, {} {}", path, labels.0, short(vm), labels.1, short(interpreter)));
+        },
+        _ => {},
+    }
+}
+
+fn describe(vm: &Outcome, interpreter: &Outcome, labels: (&str, &str)) -> Vec<String> {
+    match (vm, interpreter) {
+        (Outcome::Value(a), Outcome::Value(b)) => {
+            let mut out = vec![];
+            differences(a, b, "", labels, &mut out);
+            out
+        },
+        _ => vec![format!("{} {:?}, {} {:?}", labels.0, vm, labels.1, interpreter)],
+    }
+}
+
+#[tokio::test]
+async fn test_engines_agree_on_route_fixtures() {
+    let mut report = vec![];
+    let mut compared = vec![];
+    let mut skipped = vec![];
+    let mut divergent = HashMap::new();
+    for path in fixtures() {
+        let file = name(&path);
+        let content = String::from_utf8_lossy(&std::fs::read(&path).unwrap()).trim_start_matches('\u{feff}').to_string();
+        let route = match parse_route(&content) {
+            Ok(route) => route,
+            Err(e) => {
+                skipped.push((file, format!("not a single route ({})", e.lines().next().unwrap_or(""))));
+                continue;
+            }
+        };
+        // Logic the compiler rejects can't be registered, so only the interpreter could run it
+        let vm = match run_vm(&route.logic).await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                skipped.push((file, format!("does not compile ({})", e.chars().take(100).collect::<String>())));
+                continue;
+            }
+        };
+        let interpreter = run_interpreter(&route.logic).await;
+        if vm != interpreter {
+            let details = describe(&vm, &interpreter, ("vm", "interpreter"));
+            report.push(format!("DIVERGED {}:\n    {}", file, details.join("\n    ")));
+            divergent.insert(file.clone(), details);
+        }
+        compared.push(file);
+    }
+    println!("Engine differential over {} fixtures:\n{}", compared.len(), report.join("\n"));
+    assert!(compared.len() >= 20, "fixtures not found: {:?}", compared);
+
+    let unexpected: Vec<String> = skipped.iter()
+        .filter(|(file, _)| !EXPECTED_SKIPS.iter().any(|(expected, _)| expected == file))
+        .map(|(file, reason)| format!("{}: {}", file, reason))
+        .collect();
+    assert!(unexpected.is_empty(), "fixtures no longer load; fix them or add them to EXPECTED_SKIPS:\n{}", unexpected.join("\n"));
+    let loaded: Vec<&str> = EXPECTED_SKIPS.iter()
+        .map(|(file, _)| *file)
+        .filter(|file| !skipped.iter().any(|(skipped, _)| skipped == file))
+        .collect();
+    assert!(loaded.is_empty(), "{:?} are compared now or gone; remove them from EXPECTED_SKIPS", loaded);
+    let uncompared: Vec<&str> = KNOWN_DIVERGENCES.iter()
+        .map(|(file, _)| *file)
+        .filter(|file| !compared.iter().any(|c| c == file))
+        .collect();
+    assert!(uncompared.is_empty(), "{:?} in KNOWN_DIVERGENCES are never compared", uncompared);
+
+    let known = |file: &str| KNOWN_DIVERGENCES.iter().any(|(known, _)| *known == file);
+    let new: Vec<String> = divergent.iter()
+        .filter(|(file, _)| !known(file))
+        .map(|(file, details)| format!("{}:\n    {}", file, details.join("\n    ")))
+        .collect();
+    assert!(new.is_empty(), "engines diverge on:\n{}", new.join("\n"));
+
+    let fixed: Vec<&str> = KNOWN_DIVERGENCES.iter()
+        .map(|(file, _)| *file)
+        .filter(|file| compared.iter().any(|c| c == file) && !divergent.contains_key(*file))
+        .collect();
+    assert!(fixed.is_empty(), "engines now agree on {:?}; remove them from KNOWN_DIVERGENCES", fixed);
+}
+
+/// Fixtures whose results change from run to run, so the optimizer can't be compared on them
+const NONDETERMINISTIC: &[&str] = &["performance_demo.json", "test_yaml_route.yaml", "ultimate_test.json"];
+
+#[tokio::test]
+async fn test_optimizer_keeps_fixture_results() {
+    let mut compared = 0;
+    let mut changed = vec![];
+    for path in fixtures() {
+        let file = name(&path);
+        let content = String::from_utf8_lossy(&std::fs::read(&path).unwrap()).trim_start_matches('\u{feff}').to_string();
+        let Ok(route) = parse_route(&content) else { continue };
+        let Ok(written) = run_vm(&route.logic).await else { continue };
+        if NONDETERMINISTIC.contains(&file.as_str()) {
+            continue;
+        }
+        let optimized = run_vm(&Optimizer::new().optimize(&route.logic)).await
+            .unwrap_or_else(|e| panic!("{} no longer compiles once optimized: {}", file, e));
+        if optimized != written {
+            changed.push(format!("{}:\n    {}", file, describe(&optimized, &written, ("optimized", "as written")).join("\n    ")));
+        }
+        compared += 1;
+    }
+    assert!(compared >= 20, "fixtures not found");
+    assert!(changed.is_empty(), "optimized logic answers differently on:\n{}", changed.join("\n"));
+}
+
+/// Run a program after a round trip through its stored form, as a persisted plan is loaded
+async fn run_stored(logic: &[LogicOperation]) -> Outcome {
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(logic).unwrap();
+    let stored = serde_json::to_string(&(&program, compiler.get_symbol_table())).unwrap();
+    let (program, symbols): (Program, SymbolTable) = serde_json::from_str(&stored).unwrap();
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), symbols);
+    match budget::run(limits(), vm.execute(&program)).await {
+        Ok(value) => Outcome::Value(value),
+        Err(_) => Outcome::Failed,
+    }
+}
+
+#[tokio::test]
+async fn test_stored_programs_keep_fixture_results() {
+    let mut compared = 0;
+    let mut changed = vec![];
+    for path in fixtures() {
+        let file = name(&path);
+        let content = String::from_utf8_lossy(&std::fs::read(&path).unwrap()).trim_start_matches('\u{feff}').to_string();
+        let Ok(route) = parse_route(&content) else { continue };
+        let Ok(compiled) = run_vm(&route.logic).await else { continue };
+        if NONDETERMINISTIC.contains(&file.as_str()) {
+            continue;
+        }
+        let stored = run_stored(&route.logic).await;
+        if stored != compiled {
+            changed.push(format!("{}:\n    {}", file, describe(&stored, &compiled, ("stored", "compiled")).join("\n    ")));
+        }
+        compared += 1;
+    }
+    assert!(compared >= 20, "fixtures not found");
+    assert!(changed.is_empty(), "stored programs answer differently on:\n{}", changed.join("\n"));
+}
+
+#[tokio::test]
+async fn test_every_entry_point_runs_on_the_vm() {
+    let temp_dir_path = std::env::temp_dir().join(format!("worpen_test_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&temp_dir_path).unwrap();
+    let service = DynamicRouteService::with_data_dir(temp_dir_path.to_str().unwrap().to_string());
+
+    let route = RouteDefinition {
+        id: "typed".to_string(),
+        name: "typed".to_string(),
+        description: "".to_string(),
+        path: "/typed".to_string(),
+        method: HttpMethod::GET,
+        route_type: Default::default(),
+        logic: vec![
+            LogicOperation::MathOp { operation: "sum".to_string(), args: vec![json!(1), json!(2)] },
+            LogicOperation::Return {
+                value: json!({"sum": "{{math_result}}"}),
+                status: None,
+                headers: None,
+                raw: None,
+            },
+        ],
+        ws_hooks: None,
+        parameters: vec![],
+        request_schema: None,
+        response_schema: None,
+        response_schema_policy: Default::default(),
+        auth_required: false,
+        required_scopes: vec![],
+        required_roles: vec![],
+        rate_limit: None,
+        rate_limit_key: None,
+        limits: None,
+        optimize: true,
+        enabled: true,
+        version: "1.0.0".to_string(),
+        created_at: "".to_string(),
+        updated_at: "".to_string(),
+        created_by: "system".to_string(),
+    };
+    let route_id = service.register_route(route.clone()).await.unwrap();
+    let expected = json!({"sum": 3.0});
+
+    // The interpreter would answer {"sum": "3.0"}
+    let mut context = DynamicRouteExecutionContext {
+        route_id: route_id.clone(),
+        variables: HashMap::new(),
+        request_payload: None,
+        path_params: HashMap::new(),
+        query_params: HashMap::new(),
+        functions: HashMap::new(),
+        loop_control: LoopControl::default(),
+        error_context: None,
+    };
+    assert_eq!(service.execute_route_logic(&route, &mut context).await.unwrap(), expected);
+    assert_eq!(service.execute_route(&route_id, None, HashMap::new(), HashMap::new()).await.unwrap(), expected);
+    let test = service.test_route(RouteTestRequest { route_id, test_payload: None, test_params: HashMap::new() }).await.unwrap();
+    assert_eq!(test.result, Some(expected));
+
+    let _ = std::fs::remove_dir_all(&temp_dir_path);
+}
@@ -0,0 +1,248 @@
+use worpen_core::budget::{self, LimitKind, Limits};
+use worpen_core::compiler::lowerer::LogicCompiler;
+use worpen_core::services::dynamic_routes::execution::execute_logic_extended;
+use worpen_core::services::dynamic_routes::service::DynamicRouteService;
+use worpen_core::vm::machine::VirtualMachine;
+use worpen_core::vm::memory::ExecutionMemory;
+use proto::models::{
+    DynamicRouteExecutionContext, ExecutionLimits, FunctionDef, HttpMethod, LogicOperation, LoopControl, RouteDefinition,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+
+fn limits() -> Limits {
+    Limits {
+        max_fuel: 1_000,
+        timeout: Duration::from_secs(5),
+        max_value_bytes: 1_024,
+        max_parallel: 4,
+        max_call_depth: 64,
+    }
+}
+
+/// What each engine returned and the limit it ran into, in (VM, interpreter) order
+async fn run_both_engines(logic: &[LogicOperation]) -> [(Result<Value, String>, Option<LimitKind>); 2] {
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(logic).unwrap();
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), compiler.get_symbol_table().clone());
+    let vm_outcome = budget::watch(budget::run(limits(), vm.execute(&program))).await;
+
+    let mut context = DynamicRouteExecutionContext {
+        route_id: "budget".to_string(),
+        variables: HashMap::new(),
+        request_payload: None,
+        path_params: HashMap::new(),
+        query_params: HashMap::new(),
+        functions: HashMap::new(),
+        loop_control: LoopControl::default(),
+        error_context: None,
+    };
+    let mut steps = vec![];
+    let interpreter_outcome = budget::watch(budget::run(limits(), execute_logic_extended(logic, &mut context, &mut steps))).await;
+    [vm_outcome, interpreter_outcome]
+}
+
+/// The limit each engine ran into, in (VM, interpreter) order
+async fn limit_hit_by_both_engines(logic: &[LogicOperation]) -> (Option<LimitKind>, Option<LimitKind>) {
+    let [(_, vm_limit), (_, interpreter_limit)] = run_both_engines(logic).await;
+    (vm_limit, interpreter_limit)
+}
+
+fn endless_loop() -> LogicOperation {
+    LogicOperation::While {
+        condition: "true".to_string(),
+        body: vec![LogicOperation::Set { var: "x".to_string(), value: json!(1) }],
+        max_iterations: Some(u32::MAX),
+    }
+}
+
+#[tokio::test]
+async fn test_fuel_stops_runaway_loop_even_inside_try() {
+    let logic = vec![
+        LogicOperation::Try {
+            body: vec![endless_loop()],
+            catch: vec![LogicOperation::Return { value: json!("swallowed"), status: None, headers: None, raw: None }],
+            finally: None,
+        },
+    ];
+    assert_eq!(limit_hit_by_both_engines(&logic).await, (Some(LimitKind::Fuel), Some(LimitKind::Fuel)));
+}
+
+#[tokio::test]
+async fn test_value_size_and_parallel_fan_out() {
+    let big = vec![LogicOperation::Set { var: "blob".to_string(), value: json!("x".repeat(2_000)) }];
+    assert_eq!(limit_hit_by_both_engines(&big).await, (Some(LimitKind::Memory), Some(LimitKind::Memory)));
+
+    let task = || vec![LogicOperation::Return { value: json!(1), status: None, headers: None, raw: None }];
+    let wide = vec![LogicOperation::Parallel { tasks: (0..5).map(|_| task()).collect(), max_concurrent: None }];
+    assert_eq!(limit_hit_by_both_engines(&wide).await, (Some(LimitKind::Parallelism), Some(LimitKind::Parallelism)));
+
+    // A task out of budget isn't dropped like an ordinary failed task
+    let spinning = vec![LogicOperation::Parallel { tasks: vec![task(), vec![endless_loop()]], max_concurrent: None }];
+    assert_eq!(limit_hit_by_both_engines(&spinning).await, (Some(LimitKind::Fuel), Some(LimitKind::Fuel)));
+}
+
+#[tokio::test]
+async fn test_thrown_limit_message_is_an_ordinary_error() {
+    let logic = vec![
+        LogicOperation::Try {
+            body: vec![LogicOperation::Throw { message: "Execution limit exceeded (fuel): not really".to_string(), code: None }],
+            catch: vec![LogicOperation::Return { value: json!("{{error.message}}"), status: None, headers: None, raw: None }],
+            finally: None,
+        },
+    ];
+    for (result, limit) in run_both_engines(&logic).await {
+        assert_eq!(result, Ok(json!("Execution limit exceeded (fuel): not really")));
+        assert_eq!(limit, None);
+    }
+
+    // Uncaught, it fails the execution without counting as an abort
+    let logic = vec![LogicOperation::Throw { message: "Execution limit exceeded (memory): not really".to_string(), code: None }];
+    for (result, limit) in run_both_engines(&logic).await {
+        assert!(result.is_err());
+        assert_eq!(limit, None);
+    }
+}
+
+#[tokio::test]
+async fn test_implicit_result_variables_count_against_value_size() {
+    // `string_result` would be ~2 KB, over the 1 KB cap, even though nothing reads it
+    let logic = vec![LogicOperation::Try {
+        body: vec![LogicOperation::StringOp {
+            operation: "replace".to_string(),
+            input: "a".repeat(100),
+            args: vec![json!("a"), json!("b".repeat(20))],
+        }],
+        catch: vec![LogicOperation::Return { value: json!("swallowed"), status: None, headers: None, raw: None }],
+        finally: None,
+    }];
+    assert_eq!(limit_hit_by_both_engines(&logic).await, (Some(LimitKind::Memory), Some(LimitKind::Memory)));
+}
+
+#[tokio::test]
+async fn test_route_limits_apply_to_execute_route() {
+    let temp_dir_path = std::env::temp_dir().join(format!("worpen_test_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&temp_dir_path).unwrap();
+    let service = DynamicRouteService::with_data_dir(temp_dir_path.to_str().unwrap().to_string());
+
+    let route = RouteDefinition {
+        id: "spin".to_string(),
+        name: "spin".to_string(),
+        description: "".to_string(),
+        path: "/spin".to_string(),
+        method: HttpMethod::GET,
+        route_type: Default::default(),
+        logic: vec![endless_loop()],
+        ws_hooks: None,
+        parameters: vec![],
+        request_schema: None,
+        response_schema: None,
+        response_schema_policy: Default::default(),
+        auth_required: false,
+        required_scopes: vec![],
+        required_roles: vec![],
+        rate_limit: None,
+        rate_limit_key: None,
+        limits: Some(ExecutionLimits { max_fuel: Some(100), ..Default::default() }),
+        optimize: true,
+        enabled: true,
+        version: "1.0.0".to_string(),
+        created_at: "".to_string(),
+        updated_at: "".to_string(),
+        created_by: "system".to_string(),
+    };
+    let zero = RouteDefinition {
+        id: "zero".to_string(),
+        path: "/zero".to_string(),
+        limits: Some(ExecutionLimits { max_parallel: Some(0), ..Default::default() }),
+        ..route.clone()
+    };
+    let route_id = service.register_route(route).await.unwrap();
+
+    let before = budget::abort_counts().fuel;
+    let (result, exceeded) = budget::watch(service.execute_route(&route_id, None, HashMap::new(), HashMap::new())).await;
+    let error = result.unwrap_err();
+    assert_eq!(exceeded, Some(LimitKind::Fuel), "{}", error);
+    assert!(error.contains("100 operations"), "{}", error);
+    assert!(budget::abort_counts().fuel > before);
+
+    let error = service.register_route(zero).await.unwrap_err();
+    assert!(error.contains("limits.max_parallel"), "{}", error);
+
+    let _ = std::fs::remove_dir_all(&temp_dir_path);
+}
+
+#[tokio::test]
+async fn test_call_depth_stops_runaway_recursion() {
+    let temp_dir_path = std::env::temp_dir().join(format!("worpen_test_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&temp_dir_path).unwrap();
+    let service = DynamicRouteService::with_data_dir(temp_dir_path.to_str().unwrap().to_string());
+
+    // Counts down to 0, or forever when it starts below 0
+    service.define_global_function(FunctionDef {
+        name: "countdown".to_string(),
+        params: vec!["n".to_string()],
+        logic: vec![
+            LogicOperation::If {
+                condition: "{{n}} == 0".to_string(),
+                then: vec![LogicOperation::Return { value: json!("done"), status: None, headers: None, raw: None }],
+                otherwise: None,
+            },
+            LogicOperation::CallFunction { name: "countdown".to_string(), args: vec![json!("${n - 1}")], output_var: "rest".to_string() },
+            LogicOperation::Return { value: json!("{{rest}}"), status: None, headers: None, raw: None },
+        ],
+    }).await.unwrap();
+
+    let route = RouteDefinition {
+        id: "recurse".to_string(),
+        name: "recurse".to_string(),
+        description: "".to_string(),
+        path: "/recurse".to_string(),
+        method: HttpMethod::GET,
+        route_type: Default::default(),
+        logic: vec![LogicOperation::Try {
+            body: vec![
+                LogicOperation::CallFunction { name: "countdown".to_string(), args: vec![json!("{{from}}")], output_var: "out".to_string() },
+                LogicOperation::Return { value: json!("{{out}}"), status: None, headers: None, raw: None },
+            ],
+            catch: vec![LogicOperation::Return { value: json!("swallowed"), status: None, headers: None, raw: None }],
+            finally: None,
+        }],
+        ws_hooks: None,
+        parameters: vec![],
+        request_schema: None,
+        response_schema: None,
+        response_schema_policy: Default::default(),
+        auth_required: false,
+        required_scopes: vec![],
+        required_roles: vec![],
+        rate_limit: None,
+        rate_limit_key: None,
+        limits: Some(ExecutionLimits { max_call_depth: Some(30), ..Default::default() }),
+        optimize: true,
+        enabled: true,
+        version: "1.0.0".to_string(),
+        created_at: "".to_string(),
+        updated_at: "".to_string(),
+        created_by: "system".to_string(),
+    };
+    let route_id = service.register_route(route).await.unwrap();
+    // The recursive call is left to the VM instead of being inlined
+    let stored = service.get_route(&route_id).await.unwrap().unwrap();
+    let LogicOperation::Try { body, .. } = &stored.logic[0] else { panic!("{:?}", stored.logic) };
+    assert!(matches!(body[0], LogicOperation::CallFunction { .. }));
+
+    let from = |n: i32| HashMap::from([("from".to_string(), n.to_string())]);
+    let result = service.execute_route(&route_id, None, HashMap::new(), from(25)).await.unwrap();
+    assert_eq!(result, json!("done"));
+
+    let before = budget::abort_counts().call_depth;
+    let (result, exceeded) = budget::watch(service.execute_route(&route_id, None, HashMap::new(), from(-1))).await;
+    let error = result.unwrap_err();
+    assert_eq!(exceeded, Some(LimitKind::CallDepth), "{}", error);
+    assert_eq!(LimitKind::CallDepth.status_code(), 508);
+    assert!(budget::abort_counts().call_depth > before);
+
+    let _ = std::fs::remove_dir_all(&temp_dir_path);
+}
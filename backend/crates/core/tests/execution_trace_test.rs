@@ -0,0 +1,145 @@
+use worpen_core::compiler::lowerer::LogicCompiler;
+use worpen_core::services::dynamic_routes::execution::execute_logic_extended;
+use worpen_core::services::dynamic_routes::service::DynamicRouteService;
+use worpen_core::trace::{self, TraceRecord};
+use worpen_core::vm::machine::VirtualMachine;
+use worpen_core::vm::memory::ExecutionMemory;
+use proto::models::{
+    DynamicRouteExecutionContext, HttpMethod, LogicOperation, LoopControl, RouteDefinition, RouteTestRequest,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+fn ret(value: Value) -> LogicOperation {
+    LogicOperation::Return { value, status: None, headers: None, raw: None }
+}
+
+fn logic() -> Vec<LogicOperation> {
+    vec![
+        LogicOperation::Set { var: "credentials".to_string(), value: json!({"user": "ana", "password": "hunter2"}) },
+        LogicOperation::Set { var: "n".to_string(), value: json!(2) },
+        // Declared at the top so it outlives the catch block that sets it
+        LogicOperation::Set { var: "caught".to_string(), value: Value::Null },
+        LogicOperation::If {
+            condition: "{{n}} > 1".to_string(),
+            then: vec![LogicOperation::Try {
+                body: vec![LogicOperation::Throw { message: "n is {{n}}".to_string(), code: None }],
+                catch: vec![LogicOperation::Set { var: "caught".to_string(), value: json!("{{error.message}}") }],
+                finally: None,
+            }],
+            otherwise: None,
+        },
+        ret(json!({"caught": "{{caught}}"})),
+    ]
+}
+
+async fn traced_by_both_engines(logic: &[LogicOperation]) -> (Vec<TraceRecord>, Vec<TraceRecord>) {
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(logic).unwrap();
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), compiler.get_symbol_table().clone());
+    let (vm_result, vm_trace) = trace::collect(vm.execute(&program)).await;
+    vm_result.unwrap();
+
+    let mut context = DynamicRouteExecutionContext {
+        route_id: "trace".to_string(),
+        variables: HashMap::new(),
+        request_payload: None,
+        path_params: HashMap::new(),
+        query_params: HashMap::new(),
+        functions: HashMap::new(),
+        loop_control: LoopControl::default(),
+        error_context: None,
+    };
+    let mut steps = vec![];
+    let (interpreter_result, interpreter_trace) = trace::collect(execute_logic_extended(logic, &mut context, &mut steps)).await;
+    interpreter_result.unwrap();
+
+    (vm_trace, interpreter_trace)
+}
+
+#[tokio::test]
+async fn test_both_engines_record_every_operation() {
+    let (vm_trace, interpreter_trace) = traced_by_both_engines(&logic()).await;
+
+    // Nested operations finish, and are recorded, before the operation around them
+    let expected = vec![
+        ("logic[0]", "set"),
+        ("logic[1]", "set"),
+        ("logic[2]", "set"),
+        ("logic[3].then[0].body[0]", "throw"),
+        ("logic[3].then[0].catch[0]", "set"),
+        ("logic[3].then[0]", "try"),
+        ("logic[3]", "if"),
+        ("logic[4]", "return"),
+    ];
+    for trace in [&vm_trace, &interpreter_trace] {
+        let found: Vec<(&str, &str)> = trace.iter().map(|r| (r.path.as_str(), r.op.as_str())).collect();
+        assert_eq!(found, expected);
+
+        assert_eq!(trace[0].inputs["value"], json!({"user": "ana", "password": "[REDACTED]"}));
+        assert_eq!(trace[3].inputs["message"], json!("n is 2"));
+        assert_eq!(trace[3].error.as_deref(), Some("n is 2"));
+        assert!(trace[5].error.is_none(), "the try caught the error");
+        assert_eq!(trace[6].inputs["condition"], json!(true));
+        assert_eq!(trace[7].output, json!({"caught": "n is 2"}));
+        assert!(trace[6].started_us <= trace[3].started_us);
+    }
+}
+
+#[tokio::test]
+async fn test_route_test_and_sampled_live_traces() {
+    let temp_dir_path = std::env::temp_dir().join(format!("worpen_test_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&temp_dir_path).unwrap();
+    let service = DynamicRouteService::with_data_dir(temp_dir_path.to_str().unwrap().to_string());
+
+    let route_id = service.register_route(RouteDefinition {
+        id: "traced".to_string(),
+        name: "traced".to_string(),
+        description: "".to_string(),
+        path: "/traced".to_string(),
+        method: HttpMethod::GET,
+        route_type: Default::default(),
+        logic: logic(),
+        ws_hooks: None,
+        parameters: vec![],
+        request_schema: None,
+        response_schema: None,
+        response_schema_policy: Default::default(),
+        auth_required: false,
+        required_scopes: vec![],
+        required_roles: vec![],
+        rate_limit: None,
+        rate_limit_key: None,
+        limits: None,
+        optimize: true,
+        enabled: true,
+        version: "1.0.0".to_string(),
+        created_at: "".to_string(),
+        updated_at: "".to_string(),
+        created_by: "system".to_string(),
+    }).await.unwrap();
+
+    let response = service.test_route(RouteTestRequest {
+        route_id: route_id.clone(),
+        test_payload: None,
+        test_params: HashMap::new(),
+    }).await.unwrap();
+    assert!(response.success);
+    // Registered logic is optimized first, which drops the unread `credentials`
+    assert_eq!(response.trace.len(), 7);
+    assert_eq!(response.trace[0].path, "logic[0]");
+    assert_eq!(response.trace[0].inputs["var"], json!("n"));
+
+    // Live executions are only traced when sampled
+    service.execute_route(&route_id, None, HashMap::new(), HashMap::new()).await.unwrap();
+    assert!(service.recent_traces(&route_id).is_empty());
+
+    service.set_trace_sampling(1);
+    service.execute_route(&route_id, None, HashMap::new(), HashMap::new()).await.unwrap();
+    let traces = service.recent_traces(&route_id);
+    assert_eq!(traces.len(), 1);
+    assert_eq!(traces[0].records.len(), 7);
+    assert!(traces[0].error.is_none());
+
+    let _ = std::fs::remove_dir_all(&temp_dir_path);
+}
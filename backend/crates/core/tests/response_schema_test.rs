@@ -0,0 +1,71 @@
+use worpen_core::services::dynamic_routes::service::DynamicRouteService;
+use proto::models::{RouteDefinition, RouteTestRequest, HttpMethod, LogicOperation, ResponseSchemaPolicy};
+use serde_json::json;
+use std::collections::HashMap;
+
+fn route(id: &str, returned: serde_json::Value) -> RouteDefinition {
+    RouteDefinition {
+        id: id.to_string(),
+        name: id.to_string(),
+        description: "".to_string(),
+        path: format!("/{}", id),
+        method: HttpMethod::GET,
+        route_type: Default::default(),
+        logic: vec![LogicOperation::Return {
+            value: returned,
+            status: None,
+            headers: None,
+            raw: None,
+        }],
+        ws_hooks: None,
+        parameters: vec![],
+        request_schema: None,
+        response_schema: Some(json!({
+            "type": "object",
+            "properties": {"id": {"type": "number"}},
+            "required": ["id"]
+        })),
+        // test_route reports mismatches even when the route doesn't check them
+        response_schema_policy: ResponseSchemaPolicy::Off,
+        auth_required: false,
+        required_scopes: vec![],
+        required_roles: vec![],
+        rate_limit: None,
+        rate_limit_key: None,
+        limits: None,
+        optimize: true,
+        enabled: true,
+        version: "1.0.0".to_string(),
+        created_at: "".to_string(),
+        updated_at: "".to_string(),
+        created_by: "system".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_route_reports_response_schema_errors() {
+    let temp_dir_path = std::env::temp_dir().join(format!("worpen_test_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&temp_dir_path).unwrap();
+    let service = DynamicRouteService::with_data_dir(temp_dir_path.to_str().unwrap().to_string());
+
+    let good = service.register_route(route("good_contract", json!({"id": 1}))).await.unwrap();
+    let bad = service.register_route(route("bad_contract", json!({"id": "one"}))).await.unwrap();
+
+    let test = |route_id: String| RouteTestRequest {
+        route_id,
+        test_payload: None,
+        test_params: HashMap::new(),
+    };
+
+    let response = service.test_route(test(good)).await.unwrap();
+    assert!(response.success);
+    assert!(response.schema_errors.is_empty());
+
+    let response = service.test_route(test(bad)).await.unwrap();
+    assert!(!response.success);
+    assert_eq!(response.result, Some(json!({"id": "one"})));
+    assert_eq!(response.schema_errors.len(), 1);
+    assert!(response.schema_errors[0].starts_with("response.id:"), "{:?}", response.schema_errors);
+
+    let _ = std::fs::remove_dir_all(&temp_dir_path);
+}
@@ -0,0 +1,104 @@
+use worpen_core::debugger::{DebugClient, DebugCommand, DebugEvent};
+use worpen_core::services::dynamic_routes::service::DynamicRouteService;
+use proto::models::{HttpMethod, LogicOperation, RouteDefinition, RouteTestRequest};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+fn set(var: &str, value: Value) -> LogicOperation {
+    LogicOperation::Set { var: var.to_string(), value }
+}
+
+fn route() -> RouteDefinition {
+    RouteDefinition {
+        id: "debugged".to_string(),
+        name: "debugged".to_string(),
+        description: "".to_string(),
+        path: "/debugged".to_string(),
+        method: HttpMethod::GET,
+        route_type: Default::default(),
+        logic: vec![
+            set("n", json!(1)),
+            set("msg", json!("")),
+            LogicOperation::If {
+                condition: "{{n}} > 0".to_string(),
+                then: vec![set("msg", json!("n is {{n}}")), set("done", json!(true))],
+                otherwise: None,
+            },
+            LogicOperation::Return { value: json!("{{msg}}"), status: None, headers: None, raw: None },
+        ],
+        ws_hooks: None,
+        parameters: vec![],
+        request_schema: None,
+        response_schema: None,
+        response_schema_policy: Default::default(),
+        auth_required: false,
+        required_scopes: vec![],
+        required_roles: vec![],
+        rate_limit: None,
+        rate_limit_key: None,
+        limits: None,
+        optimize: true,
+        enabled: true,
+        version: "1.0.0".to_string(),
+        created_at: "".to_string(),
+        updated_at: "".to_string(),
+        created_by: "system".to_string(),
+    }
+}
+
+async fn send(client: &mut DebugClient, command: DebugCommand) -> DebugEvent {
+    client.commands.send(command).unwrap();
+    client.events.recv().await.unwrap()
+}
+
+fn paused_at(event: DebugEvent) -> (String, usize, bool) {
+    match event {
+        DebugEvent::Paused { path, depth, breakpoint, .. } => (path, depth, breakpoint),
+        other => panic!("expected a pause, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_debug_session_breakpoints_steps_and_variables() {
+    let temp_dir_path = std::env::temp_dir().join(format!("worpen_test_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&temp_dir_path).unwrap();
+    let service = DynamicRouteService::with_data_dir(temp_dir_path.to_str().unwrap().to_string());
+    let route_id = service.register_route(route()).await.unwrap();
+    let request = RouteTestRequest { route_id, test_payload: None, test_params: HashMap::new() };
+
+    let session_id = service.start_debug_session(request.clone()).await.unwrap();
+    let mut client = service.attach_debugger(&session_id).unwrap();
+    assert!(service.attach_debugger(&session_id).is_none(), "a session has a single client");
+
+    // Paused before the first operation
+    assert_eq!(paused_at(client.events.recv().await.unwrap()), ("logic[0]".to_string(), 0, false));
+    let breakpoints = send(&mut client, DebugCommand::SetBreakpoints { paths: vec!["logic[2].then[0]".to_string()] }).await;
+    assert_eq!(breakpoints, DebugEvent::Breakpoints { paths: vec!["logic[2].then[0]".to_string()] });
+
+    let event = send(&mut client, DebugCommand::Continue).await;
+    assert_eq!(paused_at(event), ("logic[2].then[0]".to_string(), 1, true));
+    let event = send(&mut client, DebugCommand::Inspect { var: None }).await;
+    let DebugEvent::Variables { values } = event else { panic!("{:?}", event) };
+    assert_eq!(values["n"], json!(1));
+    assert_eq!(values["msg"], json!(""));
+    send(&mut client, DebugCommand::SetVariable { var: "n".to_string(), value: json!(7) }).await;
+
+    let event = send(&mut client, DebugCommand::StepOver).await;
+    assert_eq!(paused_at(event), ("logic[2].then[1]".to_string(), 1, false));
+    let event = send(&mut client, DebugCommand::StepOut).await;
+    assert_eq!(paused_at(event), ("logic[3]".to_string(), 0, false));
+    let event = send(&mut client, DebugCommand::Inspect { var: Some("msg".to_string()) }).await;
+    assert_eq!(event, DebugEvent::Variables { values: HashMap::from([("msg".to_string(), json!("n is 7"))]) });
+
+    let event = send(&mut client, DebugCommand::Continue).await;
+    assert_eq!(event, DebugEvent::Finished { result: Some(json!("n is 7")), error: None });
+
+    // Stopping aborts the execution
+    let session_id = service.start_debug_session(request).await.unwrap();
+    let mut client = service.attach_debugger(&session_id).unwrap();
+    client.events.recv().await.unwrap();
+    let event = send(&mut client, DebugCommand::Stop).await;
+    assert!(matches!(event, DebugEvent::Finished { result: None, error: Some(_) }), "{:?}", event);
+
+    let _ = std::fs::remove_dir_all(&temp_dir_path);
+}
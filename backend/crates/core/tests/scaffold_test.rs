@@ -0,0 +1,133 @@
+use worpen_core::data_model::scaffold;
+use worpen_core::parsers::parse_route;
+use worpen_core::services::dynamic_routes::service::DynamicRouteService;
+use worpen_core::services::dynamic_routes::RouteLookup;
+use proto::models::{ColumnInfo, DynamicRouteExecutionContext, ForeignKeyInfo, LoopControl, TableInfo};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+fn column(name: &str, declared_type: &str, not_null: bool, default: Option<&str>, primary_key: u32) -> ColumnInfo {
+    ColumnInfo { name: name.to_string(), declared_type: declared_type.to_string(), not_null, default: default.map(str::to_string), primary_key }
+}
+
+/// The seed migration's `orders` table, as introspection reads it
+fn orders() -> TableInfo {
+    TableInfo {
+        name: "orders".to_string(),
+        columns: vec![
+            column("id", "TEXT", true, None, 1),
+            column("user_id", "TEXT", true, None, 0),
+            column("status", "TEXT", true, Some("'pending'"), 0),
+            column("total_amount", "REAL", true, None, 0),
+            column("last_processed_at", "TEXT", false, None, 0),
+        ],
+        foreign_keys: vec![],
+    }
+}
+
+fn order_items() -> TableInfo {
+    TableInfo {
+        name: "order_items".to_string(),
+        columns: vec![
+            column("id", "INTEGER", false, None, 1),
+            column("order_id", "TEXT", true, None, 0),
+            column("quantity", "INTEGER", true, None, 0),
+        ],
+        foreign_keys: vec![ForeignKeyInfo { column: "order_id".to_string(), references_table: "orders".to_string(), references_column: Some("id".to_string()) }],
+    }
+}
+
+async fn service() -> (DynamicRouteService, std::path::PathBuf) {
+    let data_dir = std::env::temp_dir().join(format!("worpen_scaffold_{}", uuid::Uuid::new_v4()));
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    sqlx::query(
+        "CREATE TABLE orders (id TEXT PRIMARY KEY NOT NULL, user_id TEXT NOT NULL, status TEXT NOT NULL DEFAULT 'pending', \
+         total_amount REAL NOT NULL, last_processed_at TEXT)",
+    ).execute(&pool).await.unwrap();
+    sqlx::query(
+        "CREATE TABLE order_items (id INTEGER PRIMARY KEY, order_id TEXT NOT NULL REFERENCES orders(id), quantity INTEGER NOT NULL)",
+    ).execute(&pool).await.unwrap();
+    let mut routes = DynamicRouteService::with_data_dir(data_dir.display().to_string());
+    routes.set_db_pool(pool);
+    for table in [orders(), order_items()] {
+        for route in scaffold::scaffold(table, "default").routes {
+            routes.register_route(route).await.unwrap();
+        }
+    }
+    (routes, data_dir)
+}
+
+/// Route a request the way the HTTP fallback does: match, validate, execute
+async fn request(routes: &DynamicRouteService, method: &str, path: &str, query: &[(&str, &str)], body: Value) -> (u16, Value) {
+    let RouteLookup::Matched(matched) = routes.match_route(method, path).await else {
+        return (404, Value::Null);
+    };
+    let route = routes.get_route(&matched.route_id).await.unwrap().unwrap();
+    let query: HashMap<String, String> = query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    let validated = match routes.validate_request(&route, &matched.params, &query, body) {
+        Ok(validated) => validated,
+        Err(result) => return (400, json!(result.errors.iter().map(|e| e.path.clone()).collect::<Vec<_>>())),
+    };
+    let mut context = DynamicRouteExecutionContext {
+        route_id: route.id.clone(),
+        variables: validated.params.into_iter().collect(),
+        request_payload: Some(validated.body),
+        path_params: matched.params,
+        query_params: query,
+        functions: HashMap::new(),
+        loop_control: LoopControl::default(),
+        error_context: None,
+    };
+    match routes.execute_route_logic(&route, &mut context).await {
+        Ok(Value::Object(response)) if response.contains_key("value") => {
+            (response["status"].as_u64().unwrap_or(200) as u16, response["value"].clone())
+        },
+        Ok(value) => (200, value),
+        Err(e) => (500, Value::String(e)),
+    }
+}
+
+#[tokio::test]
+async fn test_scaffolded_routes_serve_an_existing_table() {
+    let (routes, data_dir) = service().await;
+    let r = &routes;
+
+    let (status, order) = request(r, "POST", "/api/orders", &[], json!({"id": "ORD-1", "user_id": "U-1", "total_amount": 10.5})).await;
+    assert_eq!((status, &order["status"], &order["last_processed_at"]), (201, &json!("pending"), &Value::Null));
+    request(r, "POST", "/api/orders", &[], json!({"id": "ORD-2", "user_id": "U-2", "status": "shipped", "total_amount": 3})).await;
+    let (status, errors) = request(r, "POST", "/api/orders", &[], json!({"id": "ORD-3"})).await;
+    assert_eq!(status, 400);
+    assert!(errors.as_array().unwrap().contains(&json!("body.user_id")), "{}", errors);
+
+    let (status, item) = request(r, "POST", "/api/order_items", &[], json!({"order_id": "ORD-1", "quantity": 2})).await;
+    assert_eq!((status, &item["id"]), (201, &json!(1)));
+
+    let (_, page) = request(r, "GET", "/api/orders", &[("status", "pending")], Value::Null).await;
+    assert_eq!((&page["items"][0]["id"], &page["total"]), (&json!("ORD-1"), &json!(1)));
+    let (_, page) = request(r, "GET", "/api/orders", &[("limit", "1"), ("offset", "1")], Value::Null).await;
+    assert_eq!((&page["items"][0]["id"], &page["total"]), (&json!("ORD-2"), &json!(2)));
+
+    // Updates keep the columns the body leaves out
+    let (status, updated) = request(r, "PATCH", "/api/orders/ORD-2", &[], json!({"status": "delivered"})).await;
+    assert_eq!((status, &updated["status"], &updated["user_id"]), (200, &json!("delivered"), &json!("U-2")));
+    let (status, found) = request(r, "GET", "/api/order_items/1", &[], Value::Null).await;
+    assert_eq!((status, &found["quantity"]), (200, &json!(2)));
+
+    let (status, deleted) = request(r, "DELETE", "/api/orders/ORD-2", &[], Value::Null).await;
+    assert_eq!((status, deleted), (200, json!({"id": "ORD-2", "deleted": true})));
+    let (status, error) = request(r, "GET", "/api/orders/ORD-2", &[], Value::Null).await;
+    assert_eq!((status, error), (404, json!({"error": "orders ORD-2 not found"})));
+
+    std::fs::remove_dir_all(data_dir).ok();
+}
+
+#[test]
+fn test_scaffolded_routes_round_trip_through_yaml() {
+    // The way worpen-convert writes them, so they can be registered as YAML
+    for route in scaffold::scaffold(order_items(), "default").routes {
+        let yaml = serde_yaml::to_string(&serde_json::to_value(&route).unwrap()).unwrap();
+        let parsed = parse_route(&yaml).unwrap_or_else(|e| panic!("{}: {}", route.id, e));
+        assert_eq!((parsed.path, parsed.logic.len(), parsed.parameters.len()), (route.path, route.logic.len(), route.parameters.len()));
+    }
+}
@@ -28,15 +28,17 @@ fn test_redis_set_and_get() {
             LogicOperation::RedisOp {
                 command: "SET".to_string(),
                 key: "test_key_simple".to_string(),
-                value: Some("test_value_123".to_string()),
+                args: vec![json!("test_value_123")],
                 ttl_seconds: None,
+                commands: vec![],
                 output_var: Some("status".to_string()),
             },
             LogicOperation::RedisOp {
                 command: "GET".to_string(),
                 key: "test_key_simple".to_string(),
-                value: None,
+                args: vec![],
                 ttl_seconds: None,
+                commands: vec![],
                 output_var: Some("result".to_string()),
             },
             LogicOperation::Return { value: Value::String("{{result}}".to_string()), status: None, headers: None, raw: None },
@@ -44,7 +46,7 @@ fn test_redis_set_and_get() {
         
         // Compile and execute
         let mut compiler = LogicCompiler::new();
-        let optimized = compiler.compile(&logic);
+        let optimized = compiler.compile(&logic).unwrap();
         let symbol_table = compiler.get_symbol_table();
         
         let memory = ExecutionMemory::new();
@@ -84,22 +86,24 @@ fn test_redis_with_variables() {
             LogicOperation::RedisOp {
                 command: "SET".to_string(),
                 key: "user:{{user_id}}:name".to_string(),
-                value: Some("{{user_name}}".to_string()),
+                args: vec![json!("{{user_name}}")],
                 ttl_seconds: None,
+                commands: vec![],
                 output_var: Some("status".to_string()),
             },
             LogicOperation::RedisOp {
                 command: "GET".to_string(),
                 key: "user:42:name".to_string(),
-                value: None,
+                args: vec![],
                 ttl_seconds: None,
+                commands: vec![],
                 output_var: Some("result".to_string()),
             },
             LogicOperation::Return { value: Value::String("{{result}}".to_string()), status: None, headers: None, raw: None },
         ];
         
         let mut compiler = LogicCompiler::new();
-        let optimized = compiler.compile(&logic);
+        let optimized = compiler.compile(&logic).unwrap();
         let symbol_table = compiler.get_symbol_table();
         
         let memory = ExecutionMemory::new();
@@ -137,36 +141,40 @@ fn test_redis_incr() {
             LogicOperation::RedisOp {
                 command: "SET".to_string(),
                 key: "counter_key".to_string(),
-                value: Some("10".to_string()),
+                args: vec![json!("10")],
                 ttl_seconds: None,
+                commands: vec![],
                 output_var: Some("status".to_string()),
             },
             LogicOperation::RedisOp {
                 command: "INCR".to_string(),
                 key: "counter_key".to_string(),
-                value: None,
+                args: vec![],
                 ttl_seconds: None,
+                commands: vec![],
                 output_var: Some("counter".to_string()),
             },
             LogicOperation::RedisOp {
                 command: "INCR".to_string(),
                 key: "counter_key".to_string(),
-                value: None,
+                args: vec![],
                 ttl_seconds: None,
+                commands: vec![],
                 output_var: Some("counter".to_string()),
             },
             LogicOperation::RedisOp {
                 command: "DECR".to_string(),
                 key: "counter_key".to_string(),
-                value: None,
+                args: vec![],
                 ttl_seconds: None,
+                commands: vec![],
                 output_var: Some("counter".to_string()),
             },
             LogicOperation::Return { value: Value::String("{{counter}}".to_string()), status: None, headers: None, raw: None },
         ];
         
         let mut compiler = LogicCompiler::new();
-        let optimized = compiler.compile(&logic);
+        let optimized = compiler.compile(&logic).unwrap();
         let symbol_table = compiler.get_symbol_table();
         
         let memory = ExecutionMemory::new();
@@ -182,3 +190,64 @@ fn test_redis_incr() {
         }
     });
 }
+
+#[test]
+fn test_redis_structures_and_pipeline() {
+    let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+    
+    let redis_pool = match deadpool_redis::Pool::builder(
+        deadpool_redis::Manager::new(redis_url.as_str()).expect("Failed to create Redis manager")
+    ).build() {
+        Ok(pool) => pool,
+        Err(_) => {
+            println!("Failed to create Redis pool, skipping test");
+            return;
+        }
+    };
+    
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        // Hashes, sorted sets and sets in one pipeline, with typed results
+        let logic: Vec<LogicOperation> = serde_json::from_value(json!([
+            {"set": {"var": "profile", "value": {"name": "Ann", "level": 3}}},
+            {"redis_op": {"command": "DEL", "key": "test:user", "args": ["test:board", "test:tags"]}},
+            {"redis_op": {"command": "PIPELINE", "commands": [
+                {"command": "HSET", "key": "test:user", "args": ["{{profile}}"], "ttl_seconds": 60},
+                {"command": "ZADD", "key": "test:board", "args": [12.5, "ann", 7, "bob"]},
+                {"command": "SADD", "key": "test:tags", "args": ["a", "b"]},
+            ], "output_var": "written"}},
+            {"redis_op": {"command": "HGETALL", "key": "test:user", "output_var": "user"}},
+            {"redis_op": {"command": "ZREVRANGE", "key": "test:board", "args": [0, -1, "WITHSCORES"], "output_var": "board"}},
+            {"redis_op": {"command": "SISMEMBER", "key": "test:tags", "args": ["b"], "output_var": "tagged"}},
+            {"return": {"value": {"written": "{{written}}", "user": "{{user}}", "board": "{{board}}", "tagged": "{{tagged}}"}}},
+        ])).unwrap();
+        
+        let mut compiler = LogicCompiler::new();
+        let optimized = compiler.compile(&logic).unwrap();
+        let symbol_table = compiler.get_symbol_table();
+        
+        let memory = ExecutionMemory::new();
+        let mut vm = VirtualMachine::with_redis_pool(memory, symbol_table.clone(), redis_pool.clone());
+        
+        match vm.execute(&optimized).await {
+            Ok(val) => assert_eq!(val, json!({
+                "written": [2, 2, 2],
+                "user": {"name": "Ann", "level": 3},
+                "board": [{"member": "ann", "score": 12.5}, {"member": "bob", "score": 7}],
+                "tagged": true,
+            })),
+            Err(e) if e.contains("Failed to get Redis connection") => {
+                println!("Redis not available: {}", e);
+            },
+            Err(e) => panic!("Unexpected error: {}", e),
+        }
+    });
+}
+
+#[test]
+fn test_redis_value_reads_as_args() {
+    // Routes written before `args` keep working
+    let op: LogicOperation = serde_yaml::from_str("redis_op:\n  command: SET\n  key: greeting\n  value: hello\n").unwrap();
+    let LogicOperation::RedisOp { args, commands, .. } = op else { panic!("not a redis_op") };
+    assert_eq!((args, commands.len()), (vec![json!("hello")], 0));
+}
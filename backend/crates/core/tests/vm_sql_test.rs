@@ -1,8 +1,8 @@
 use worpen_core::compiler::lowerer::LogicCompiler;
 use worpen_core::vm::machine::VirtualMachine;
 use worpen_core::vm::memory::ExecutionMemory;
-use proto::models::LogicOperation;
-use serde_json::Value;
+use proto::models::{LogicOperation, SqlParams};
+use serde_json::{json, Value};
 use tokio::runtime::Runtime;
 use sqlx::SqlitePool;
 
@@ -32,13 +32,15 @@ fn test_vm_sql() {
                     Value::String("{{user_name}}".to_string()),
                     Value::String("{{user_age}}".to_string()),
                 ],
+                params: None,
+                datasource: None,
                 output_var: "insert_result".to_string(),
             },
         ];
         
         // Compile and execute
         let mut compiler = LogicCompiler::new();
-        let optimized = compiler.compile(&logic);
+        let optimized = compiler.compile(&logic).unwrap();
         let symbol_table = compiler.get_symbol_table();
         
         let memory = ExecutionMemory::new();
@@ -54,6 +56,8 @@ fn test_vm_sql() {
                 args: vec![
                     Value::String("{{search_id}}".to_string()),
                 ],
+                params: None,
+                datasource: None,
                 output_var: "query_result".to_string(),
             },
             LogicOperation::Return { value: Value::String("{{query_result}}".to_string()), status: None, headers: None, raw: None },
@@ -61,7 +65,7 @@ fn test_vm_sql() {
         
         // Compile and execute
         let mut compiler = LogicCompiler::new();
-        let optimized = compiler.compile(&logic);
+        let optimized = compiler.compile(&logic).unwrap();
         let symbol_table = compiler.get_symbol_table();
         
         let memory = ExecutionMemory::new();
@@ -95,11 +99,15 @@ fn test_vm_sql() {
                     Value::String("{{user2_name}}".to_string()),
                     Value::String("{{user2_age}}".to_string()),
                 ],
+                params: None,
+                datasource: None,
                 output_var: "insert_result2".to_string(),
             },
             LogicOperation::SqlOp { 
                 query: "SELECT id, name, age FROM users ORDER BY id".to_string(),
                 args: vec![],
+                params: None,
+                datasource: None,
                 output_var: "all_users".to_string(),
             },
             LogicOperation::Return { value: Value::String("{{all_users}}".to_string()), status: None, headers: None, raw: None },
@@ -107,7 +115,7 @@ fn test_vm_sql() {
         
         // Compile and execute
         let mut compiler = LogicCompiler::new();
-        let optimized = compiler.compile(&logic);
+        let optimized = compiler.compile(&logic).unwrap();
         let symbol_table = compiler.get_symbol_table();
         
         let memory = ExecutionMemory::new();
@@ -139,3 +147,168 @@ fn test_vm_sql() {
         }
     });
 }
+
+#[tokio::test]
+async fn test_vm_sql_binds_args_with_their_type() {
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let logic = vec![
+        LogicOperation::Set { var: "name".to_string(), value: json!("Alice") },
+        LogicOperation::Set { var: "price".to_string(), value: json!(9.5) },
+        LogicOperation::Set { var: "tags".to_string(), value: json!(["a", "b"]) },
+        LogicOperation::SqlOp {
+            query: "SELECT typeof(?) AS a, typeof(?) AS b, typeof(?) AS c, typeof(?) AS d, typeof(?) AS e, typeof(?) AS f, typeof(?) AS g, typeof(?) AS h".to_string(),
+            args: vec![
+                json!(null),
+                json!(true),
+                json!(42),
+                json!("${price * 2}"),
+                json!("{{name}}"),
+                json!("Dr. {{name}}"),
+                json!({"$blob": "AAEC"}),
+                json!("{{tags}}"),
+            ],
+            params: None,
+            datasource: None,
+            output_var: "types".to_string(),
+        },
+        LogicOperation::SqlOp {
+            query: "SELECT ? AS null_value, ? AS flag, ? AS total, ? AS title, ? AS bytes, json_extract(?, '$[1]') AS tag".to_string(),
+            args: vec![json!(null), json!(true), json!("${price * 2}"), json!("Dr. {{name}}"), json!({"$blob": "AAEC"}), json!("{{tags}}")],
+            params: None,
+            datasource: None,
+            output_var: "values".to_string(),
+        },
+        LogicOperation::Return { value: json!({"types": "{{types}}", "values": "{{values}}"}), status: None, headers: None, raw: None },
+    ];
+
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let mut vm = VirtualMachine::with_db_pool(ExecutionMemory::new(), compiler.get_symbol_table().clone(), pool);
+    let result = vm.execute(&program).await.unwrap();
+
+    assert_eq!(result["types"], json!([{
+        "a": "null", "b": "integer", "c": "integer", "d": "real",
+        "e": "text", "f": "text", "g": "blob", "h": "text",
+    }]));
+    assert_eq!(result["values"], json!([{
+        "null_value": null, "flag": 1, "total": 19.0, "title": "Dr. Alice", "bytes": {"$blob": "AAEC"}, "tag": "b",
+    }]));
+}
+
+#[tokio::test]
+async fn test_vm_sql_rejects_unset_args_and_miscounted_placeholders() {
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let logic = vec![LogicOperation::SqlOp {
+        query: "SELECT ?".to_string(),
+        args: vec![json!("Dr. {{missing}}")],
+        params: None,
+        datasource: None,
+        output_var: "rows".to_string(),
+    }];
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let mut vm = VirtualMachine::with_db_pool(ExecutionMemory::new(), compiler.get_symbol_table().clone(), pool);
+    assert_eq!(vm.execute(&program).await.unwrap_err(), "Variable 'missing' not set");
+
+    let logic = vec![LogicOperation::SqlOp { query: "SELECT ?, ?".to_string(), args: vec![json!(1)], params: None, datasource: None, output_var: "rows".to_string() }];
+    let err = LogicCompiler::new().compile(&logic).unwrap_err();
+    assert!(err.contains("Query takes 2 argument(s) but 1 given"), "unexpected error: {}", err);
+}
+
+#[tokio::test]
+async fn test_vm_sql_binds_named_parameters() {
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    sqlx::query("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, active BOOLEAN, score REAL)").execute(&pool).await.unwrap();
+    sqlx::query("INSERT INTO users VALUES (1, 'Alice', 1, 9.5), (2, 'Bob', 0, 7.0), (3, 'Carol', 1, 8.0)").execute(&pool).await.unwrap();
+
+    let logic = vec![
+        LogicOperation::Set { var: "active".to_string(), value: json!(true) },
+        LogicOperation::Set { var: "limit".to_string(), value: json!(3) },
+        // `:active` has no entry in `params`, so it reads the route variable
+        LogicOperation::SqlOp {
+            query: "SELECT id, name, active, score FROM users WHERE active = :active AND id < :max ORDER BY id".to_string(),
+            args: vec![],
+            params: Some([("max".to_string(), json!("${limit + 1}"))].into()),
+            datasource: None,
+            output_var: "users".to_string(),
+        },
+        LogicOperation::QueryDb {
+            query: "SELECT name FROM users WHERE id = :id".to_string(),
+            params: SqlParams::Named([("id".to_string(), json!(2))].into()),
+            datasource: None,
+        },
+        LogicOperation::Return { value: json!({"users": "{{users}}", "db_result": "{{db_result}}"}), status: None, headers: None, raw: None },
+    ];
+
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let mut vm = VirtualMachine::with_db_pool(ExecutionMemory::new(), compiler.get_symbol_table().clone(), pool);
+    let result = vm.execute(&program).await.unwrap();
+
+    assert_eq!(result["users"], json!([
+        {"id": 1, "name": "Alice", "active": true, "score": 9.5},
+        {"id": 3, "name": "Carol", "active": true, "score": 8.0},
+    ]));
+    assert_eq!(result["db_result"], json!({
+        "rows": [{"name": "Bob"}], "count": 1, "query": "SELECT name FROM users WHERE id = ?",
+    }));
+}
+
+#[tokio::test]
+async fn test_vm_query_db_needs_a_pool() {
+    let logic = vec![LogicOperation::QueryDb { query: "SELECT 1".to_string(), params: SqlParams::default(), datasource: None }];
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), compiler.get_symbol_table().clone());
+    assert_eq!(vm.execute(&program).await.unwrap_err(), "Database pool not available for query_db");
+}
+
+#[tokio::test]
+async fn test_vm_sql_runs_on_the_named_datasource() {
+    let main = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    let shop = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    sqlx::query("CREATE TABLE orders (id INTEGER)").execute(&main).await.unwrap();
+    sqlx::query("CREATE TABLE orders (id INTEGER)").execute(&shop).await.unwrap();
+
+    let insert = |id: i64, datasource: Option<&str>| LogicOperation::SqlOp {
+        query: "INSERT INTO orders (id) VALUES (?)".to_string(),
+        args: vec![json!(id)],
+        params: None,
+        datasource: datasource.map(str::to_string),
+        output_var: "rows".to_string(),
+    };
+    let transaction = |body: Vec<LogicOperation>, datasource: Option<&str>| LogicOperation::Transaction {
+        body,
+        savepoint: false,
+        datasource: datasource.map(str::to_string),
+    };
+    let run = |logic: Vec<LogicOperation>| {
+        let (main, shop) = (main.clone(), shop.clone());
+        async move {
+            let mut compiler = LogicCompiler::new();
+            let program = compiler.compile(&logic)?;
+            let mut vm = VirtualMachine::with_db_pool(ExecutionMemory::new(), compiler.get_symbol_table().clone(), main);
+            vm.set_datasources([("shop".to_string(), shop)].into());
+            vm.execute(&program).await
+        }
+    };
+    let ids = |pool: SqlitePool| async move {
+        sqlx::query_scalar::<_, i64>("SELECT id FROM orders ORDER BY id").fetch_all(&pool).await.unwrap()
+    };
+
+    // The default database's statements run outside a transaction on another datasource
+    let err = run(vec![transaction(vec![
+        insert(1, Some("shop")),
+        insert(2, None),
+        LogicOperation::Throw { message: "rejected".to_string(), code: None },
+    ], Some("shop"))]).await.unwrap_err();
+    assert_eq!(err, "rejected");
+    assert_eq!((ids(main.clone()).await, ids(shop.clone()).await), (vec![2], vec![]));
+
+    run(vec![insert(3, Some("shop"))]).await.unwrap();
+    assert_eq!(ids(shop.clone()).await, vec![3]);
+
+    assert_eq!(run(vec![insert(4, Some("archive"))]).await.unwrap_err(), "Unknown datasource 'archive'");
+    let err = run(vec![transaction(vec![transaction(vec![], None)], Some("shop"))]).await.unwrap_err();
+    assert_eq!(err, "A transaction on datasource 'default' can't be nested in one on 'shop'");
+}
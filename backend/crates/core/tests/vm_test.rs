@@ -60,7 +60,7 @@ fn test_logic_compiler() {
         LogicOperation::MathOp { operation: "add".to_string(), args: vec![Value::String("{{x}}".to_string()), Value::String("{{y}}".to_string())] },
     ];
 
-    let optimized = compiler.compile(&logic);
+    let optimized = compiler.lower(&logic).unwrap();
 
     // Check symbol table
     let symbol_table = compiler.get_symbol_table();
@@ -72,14 +72,14 @@ fn test_logic_compiler() {
     match &optimized[0] {
         OptimizedOperation::Set { var_index, value } => {
             assert_eq!(*var_index, 0);
-            assert_eq!(value, &Value::Number(10.into()));
+            assert_eq!(value.source(), Value::Number(10.into()));
         }
         _ => panic!("Expected Set"),
     }
     match &optimized[1] {
         OptimizedOperation::Set { var_index, value } => {
             assert_eq!(*var_index, 1);
-            assert_eq!(value, &Value::Number(20.into()));
+            assert_eq!(value.source(), Value::Number(20.into()));
         }
         _ => panic!("Expected Set"),
     }
@@ -87,8 +87,8 @@ fn test_logic_compiler() {
         OptimizedOperation::MathOp { operation, args } => {
             assert_eq!(operation, "add");
             assert_eq!(args.len(), 2);
-            assert_eq!(args[0], Value::String("{{x}}".to_string()));
-            assert_eq!(args[1], Value::String("{{y}}".to_string()));
+            assert_eq!(args[0].source(), Value::String("{{x}}".to_string()));
+            assert_eq!(args[1].source(), Value::String("{{y}}".to_string()));
         }
         _ => panic!("Expected MathOp"),
     }
@@ -111,7 +111,7 @@ fn test_vm_execution() {
 
     // Compile
     let mut compiler = LogicCompiler::new();
-    let optimized = compiler.compile(&logic);
+    let optimized = compiler.compile(&logic).unwrap();
     let symbol_table = compiler.get_symbol_table();
 
     // Create VM
@@ -124,4 +124,450 @@ fn test_vm_execution() {
 
     // Assert
     assert_eq!(result, Value::Number(5.into()));
-}
\ No newline at end of file
+}
+/// Tests whose logic the two engines don't agree on, with the reason
+const KNOWN_DIVERGENCES: &[(&str, &str)] = &[
+    ("test_vm_while_switch_and_math", "interpreter renders numbers as strings"),
+    ("test_vm_expression_conditions", "interpreter renders booleans as strings"),
+];
+
+/// Run the same logic through the VM and the interpreter. Their results must be
+/// equal, unless the test is a known divergence; then they must still differ.
+fn run_both_engines(test: &str, logic: &[proto::models::LogicOperation]) -> (Value, Value) {
+    let (vm_result, interpreter_result) = run_engines(logic);
+    match KNOWN_DIVERGENCES.iter().find(|(known, _)| *known == test) {
+        Some((_, reason)) => assert_ne!(vm_result, interpreter_result, "engines now agree ({}); remove {} from KNOWN_DIVERGENCES", reason, test),
+        None => assert_eq!(vm_result, interpreter_result, "engines diverge on {}", test),
+    }
+    (vm_result, interpreter_result)
+}
+
+fn run_engines(logic: &[proto::models::LogicOperation]) -> (Value, Value) {
+    use worpen_core::compiler::lowerer::LogicCompiler;
+    use worpen_core::vm::machine::VirtualMachine;
+    use worpen_core::services::dynamic_routes::execute_logic_extended;
+    use proto::models::{DynamicRouteExecutionContext, LoopControl};
+    use std::collections::HashMap;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut compiler = LogicCompiler::new();
+        let program = compiler.compile(logic).unwrap();
+        let mut vm = VirtualMachine::new(ExecutionMemory::new(), compiler.get_symbol_table().clone());
+        let vm_result = vm.execute(&program).await.unwrap();
+
+        let mut context = DynamicRouteExecutionContext {
+            route_id: "parity".to_string(),
+            variables: HashMap::new(),
+            request_payload: None,
+            path_params: HashMap::new(),
+            query_params: HashMap::new(),
+            functions: HashMap::new(),
+            loop_control: LoopControl::default(),
+            error_context: None,
+        };
+        let mut steps = Vec::new();
+        let interpreter_result = execute_logic_extended(logic, &mut context, &mut steps).await.unwrap();
+
+        (vm_result, interpreter_result)
+    })
+}
+
+#[test]
+fn test_vm_loop_with_break_and_continue() {
+    use proto::models::LogicOperation;
+    use serde_json::json;
+
+    // Collect items until "stop", skipping "skip"
+    let logic = vec![
+        LogicOperation::Set { var: "items".to_string(), value: json!(["a", "skip", "b", "stop", "c"]) },
+        LogicOperation::Set { var: "out".to_string(), value: json!("") },
+        LogicOperation::Loop {
+            collection: "{{items}}".to_string(),
+            var: "item".to_string(),
+            body: vec![
+                LogicOperation::If {
+                    condition: "{{item}} == skip".to_string(),
+                    then: vec![LogicOperation::Continue],
+                    otherwise: None,
+                },
+                LogicOperation::If {
+                    condition: "{{item}} == stop".to_string(),
+                    then: vec![LogicOperation::Break],
+                    otherwise: None,
+                },
+                LogicOperation::Set { var: "out".to_string(), value: json!("{{out}}{{item}}") },
+            ],
+        },
+        LogicOperation::Return { value: json!({"out": "{{out}}"}), status: None, headers: None, raw: None },
+    ];
+
+    let (vm_result, interpreter_result) = run_both_engines("test_vm_loop_with_break_and_continue", &logic);
+    assert_eq!(vm_result, json!({"out": "ab"}));
+    assert_eq!(interpreter_result, json!({"out": "ab"}));
+}
+
+#[test]
+fn test_vm_while_switch_and_math() {
+    use proto::models::{LogicOperation, SwitchCase};
+    use serde_json::json;
+
+    let logic = vec![
+        LogicOperation::Set { var: "n".to_string(), value: json!(0) },
+        LogicOperation::While {
+            condition: "{{n}} < 3".to_string(),
+            body: vec![
+                LogicOperation::MathOp { operation: "add".to_string(), args: vec![json!("{{n}}"), json!(1)] },
+                LogicOperation::Set { var: "n".to_string(), value: json!("{{math_result}}") },
+            ],
+            max_iterations: Some(10),
+        },
+        LogicOperation::StringOp { operation: "upper".to_string(), input: "gold".to_string(), args: vec![] },
+        LogicOperation::Set { var: "tier".to_string(), value: Value::Null },
+        LogicOperation::Switch {
+            value: "{{string_result}}".to_string(),
+            cases: vec![
+                SwitchCase { value: json!("SILVER"), operations: vec![LogicOperation::Set { var: "tier".to_string(), value: json!(2) }] },
+                SwitchCase { value: json!("GOLD"), operations: vec![LogicOperation::Set { var: "tier".to_string(), value: json!(1) }] },
+            ],
+            default: Some(vec![LogicOperation::Set { var: "tier".to_string(), value: json!(3) }]),
+        },
+        LogicOperation::Return { value: json!({"n": "{{n}}", "tier": "{{tier}}"}), status: None, headers: None, raw: None },
+    ];
+
+    let (vm_result, interpreter_result) = run_both_engines("test_vm_while_switch_and_math", &logic);
+    assert_eq!(vm_result, json!({"n": 3.0, "tier": 1}));
+    assert_eq!(interpreter_result, json!({"n": "3.0", "tier": "1"}));
+}
+
+#[test]
+fn test_vm_try_catch_and_functions() {
+    use proto::models::LogicOperation;
+    use serde_json::json;
+
+    let logic = vec![
+        LogicOperation::DefineFunction {
+            name: "greet".to_string(),
+            params: vec!["who".to_string()],
+            body: vec![
+                LogicOperation::Set { var: "leaked".to_string(), value: json!(true) },
+                LogicOperation::Return { value: json!("hello {{who}}"), status: None, headers: None, raw: None },
+            ],
+        },
+        LogicOperation::CallFunction { name: "greet".to_string(), args: vec![json!("bob")], output_var: "greeting".to_string() },
+        LogicOperation::Set { var: "caught".to_string(), value: Value::Null },
+        LogicOperation::Try {
+            body: vec![LogicOperation::Throw { message: "boom for {{greeting}}".to_string(), code: None }],
+            catch: vec![LogicOperation::Set { var: "caught".to_string(), value: json!("{{error.message}}") }],
+            finally: None,
+        },
+        LogicOperation::Return {
+            value: json!({"greeting": "{{greeting}}", "caught": "{{caught}}", "leaked": "{{leaked}}"}),
+            status: None,
+            headers: None,
+            raw: None,
+        },
+    ];
+
+    let (vm_result, interpreter_result) = run_both_engines("test_vm_try_catch_and_functions", &logic);
+    assert_eq!(vm_result["greeting"], json!("hello bob"));
+    assert_eq!(vm_result["caught"], json!("boom for hello bob"));
+    assert_eq!(interpreter_result["caught"], json!("boom for hello bob"));
+    // Function bodies must not leak variables into the caller
+    assert_ne!(vm_result["leaked"], json!(true));
+}
+
+#[test]
+fn test_compile_rejects_unsupported_operations() {
+    use worpen_core::compiler::lowerer::LogicCompiler;
+    use proto::models::LogicOperation;
+    use std::collections::HashMap;
+
+    let script = vec![LogicOperation::ExecuteScript { language: "python".to_string(), code: "print(1)".to_string() }];
+    let err = LogicCompiler::new().compile(&script).unwrap_err();
+    assert!(err.contains("execute_script"), "unexpected error: {}", err);
+
+    let mut custom = HashMap::new();
+    custom.insert("frobnicate".to_string(), Value::Null);
+    let nested = vec![LogicOperation::If {
+        condition: "true".to_string(),
+        then: vec![LogicOperation::CustomOp(custom)],
+        otherwise: None,
+    }];
+    let err = LogicCompiler::new().compile(&nested).unwrap_err();
+    assert!(err.contains("frobnicate"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_vm_expression_conditions() {
+    use proto::models::LogicOperation;
+    use serde_json::json;
+
+    let logic = vec![
+        LogicOperation::Set { var: "user".to_string(), value: json!({"age": 21, "name": "Ann"}) },
+        LogicOperation::Set { var: "adult".to_string(), value: Value::Null },
+        LogicOperation::If {
+            condition: "{{user.age}} > 18 && {{ user.name | lower }} == 'ann'".to_string(),
+            then: vec![LogicOperation::Set { var: "adult".to_string(), value: json!(true) }],
+            otherwise: Some(vec![LogicOperation::Set { var: "adult".to_string(), value: json!(false) }]),
+        },
+        LogicOperation::Return { value: json!({"adult": "{{adult}}"}), status: None, headers: None, raw: None },
+    ];
+
+    let (vm_result, interpreter_result) = run_both_engines("test_vm_expression_conditions", &logic);
+    assert_eq!(vm_result, json!({"adult": true}));
+    // The interpreter renders templated values as text
+    assert_eq!(interpreter_result, json!({"adult": "true"}));
+
+    let invalid = vec![LogicOperation::While { condition: "{{n}} <".to_string(), body: vec![], max_iterations: None }];
+    assert!(worpen_core::compiler::lowerer::LogicCompiler::new().compile(&invalid).is_err());
+}
+
+#[test]
+fn test_vm_compiled_templates() {
+    use worpen_core::compiler::lowerer::LogicCompiler;
+    use worpen_core::vm::machine::VirtualMachine;
+    use proto::models::LogicOperation;
+    use serde_json::json;
+
+    let logic = vec![
+        LogicOperation::Set { var: "n".to_string(), value: json!(4) },
+        LogicOperation::Set { var: "user".to_string(), value: json!({"tags": ["a", "b"], "name": "Ann"}) },
+        LogicOperation::Return {
+            value: json!({
+                "tags": "{{user.tags}}",
+                "text": "{{user.name}} has {{n}}",
+                "next": "{{ n + 1 }}",
+                "doubled": "${n * 2}",
+                "missing": "{{nope}}",
+                "literal": "42",
+            }),
+            status: None,
+            headers: None,
+            raw: None,
+        },
+    ];
+
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), compiler.get_symbol_table().clone());
+    let result = tokio::runtime::Runtime::new().unwrap().block_on(vm.execute(&program)).unwrap();
+
+    assert_eq!(result, json!({
+        "tags": ["a", "b"],
+        "text": "Ann has 4",
+        "next": 5.0,
+        "doubled": 8.0,
+        "missing": "{{nope}}",
+        "literal": 42,
+    }));
+}
+
+#[test]
+fn test_flat_program_matches_tree_walker() {
+    use worpen_core::compiler::lowerer::LogicCompiler;
+    use worpen_core::vm::machine::VirtualMachine;
+    use proto::models::LogicOperation;
+    use serde_json::json;
+
+    let set = |var: &str, value: Value| LogicOperation::Set { var: var.to_string(), value };
+    let ret = |value: Value| LogicOperation::Return { value, status: None, headers: None, raw: None };
+    let logic = vec![
+        set("log", json!("")),
+        set("item", json!("outer")),
+        LogicOperation::Loop {
+            collection: "[1, 2, 3, 4]".to_string(),
+            var: "item".to_string(),
+            body: vec![
+                LogicOperation::Try {
+                    body: vec![
+                        LogicOperation::If {
+                            condition: "{{item}} == 2".to_string(),
+                            then: vec![LogicOperation::Throw { message: "two".to_string(), code: None }],
+                            otherwise: None,
+                        },
+                        LogicOperation::If { condition: "{{item}} == 4".to_string(), then: vec![LogicOperation::Break], otherwise: None },
+                    ],
+                    catch: vec![set("log", json!("{{log}}[{{error.message}}]")), LogicOperation::Continue],
+                    finally: Some(vec![set("log", json!("{{log}}f"))]),
+                },
+                set("log", json!("{{log}}{{item}}")),
+            ],
+        },
+        set("n", json!(0)),
+        LogicOperation::While {
+            condition: "{{n}} < 5".to_string(),
+            body: vec![set("n", json!("${n + 1}")), LogicOperation::If { condition: "{{n}} == 3".to_string(), then: vec![ret(json!("early"))], otherwise: None }],
+            max_iterations: None,
+        },
+        LogicOperation::DefineFunction {
+            name: "double".to_string(),
+            params: vec!["x".to_string()],
+            body: vec![set("local", json!(1)), ret(json!("${x * 2}"))],
+        },
+        LogicOperation::CallFunction { name: "double".to_string(), args: vec![json!("{{n}}")], output_var: "doubled".to_string() },
+        LogicOperation::Parallel { tasks: vec![vec![set("a", json!(1))], vec![LogicOperation::Throw { message: "dropped".to_string(), code: None }], vec![set("b", json!(2))]], max_concurrent: None },
+        ret(json!({"log": "{{log}}", "item": "{{item}}", "n": "{{n}}", "doubled": "{{doubled}}", "local": "{{local}}"})),
+    ];
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let tree = compiler.lower(&logic).unwrap();
+    let symbols = compiler.get_symbol_table().clone();
+
+    let mut flat_vm = VirtualMachine::new(ExecutionMemory::new(), symbols.clone());
+    let flat = rt.block_on(flat_vm.execute(&program)).unwrap();
+    let mut tree_vm = VirtualMachine::new(ExecutionMemory::new(), symbols);
+    let walked = rt.block_on(tree_vm.execute_tree(&tree)).unwrap();
+
+    // Break skips the finally block, a return only ends its own block, and the
+    // loop variable shadows the outer one; a function's locals aren't even
+    // variables of the caller
+    assert_eq!(flat, json!({"log": "f1[two]f3", "item": "outer", "n": 5.0, "doubled": 10.0, "local": "{{local}}"}));
+    assert_eq!(flat, walked);
+}
+
+#[test]
+fn test_recursive_functions_run_in_call_frames() {
+    use worpen_core::compiler::lowerer::LogicCompiler;
+    use worpen_core::vm::machine::VirtualMachine;
+    use proto::models::{FunctionDef, LogicOperation};
+    use serde_json::json;
+
+    let set = |var: &str, value: Value| LogicOperation::Set { var: var.to_string(), value };
+    let ret = |value: Value| LogicOperation::Return { value, status: None, headers: None, raw: None };
+    let call = |name: &str, arg: &str, output: &str| LogicOperation::CallFunction {
+        name: name.to_string(),
+        args: vec![json!(arg)],
+        output_var: output.to_string(),
+    };
+    let logic = vec![
+        set("sub", json!("caller's")),
+        LogicOperation::DefineFunction {
+            name: "fact".to_string(),
+            params: vec!["n".to_string()],
+            body: vec![
+                // A return nested in the body ends the call, not just the if
+                LogicOperation::If { condition: "{{n}} <= 1".to_string(), then: vec![ret(json!(1))], otherwise: None },
+                call("fact", "${n - 1}", "sub"),
+                ret(json!("${n * sub}")),
+            ],
+        },
+        call("fact", "6", "six"),
+        call("fib", "10", "fib"),
+        ret(json!({"six": "{{six}}", "fib": "{{fib}}", "sub": "{{sub}}"})),
+    ];
+    let fib = FunctionDef {
+        name: "fib".to_string(),
+        params: vec!["n".to_string()],
+        logic: vec![
+            LogicOperation::If { condition: "{{n}} < 2".to_string(), then: vec![ret(json!("{{n}}"))], otherwise: None },
+            call("fib", "${n - 1}", "a"),
+            call("fib", "${n - 2}", "b"),
+            ret(json!("${a + b}")),
+        ],
+    };
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut compiler = LogicCompiler::new().with_global_functions([fib]);
+    let program = compiler.compile(&logic).unwrap();
+    // Global functions are compiled in once, however often they are called
+    assert_eq!(program.functions.iter().map(|f| (f.name.as_str(), f.global)).collect::<Vec<_>>(), vec![("fact", false), ("fib", true)]);
+
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), compiler.get_symbol_table().clone());
+    let result = rt.block_on(vm.execute(&program)).unwrap();
+    assert_eq!(result, json!({"six": 720.0, "fib": 55.0, "sub": "caller's"}));
+
+    // The tree walker runs route functions the same way
+    let tree = compiler.lower(&logic[..3]).unwrap();
+    let mut tree_vm = VirtualMachine::new(ExecutionMemory::new(), compiler.get_symbol_table().clone());
+    rt.block_on(tree_vm.execute_tree(&tree)).unwrap();
+    let six = compiler.get_symbol_table().get_index("six").unwrap();
+    assert_eq!(tree_vm.memory.get(six), Some(&json!(720.0)));
+}
+
+#[test]
+fn test_block_scopes_agree_across_engines() {
+    use proto::models::LogicOperation;
+    use serde_json::json;
+
+    let set = |var: &str, value: Value| LogicOperation::Set { var: var.to_string(), value };
+    let logic = vec![
+        set("x", json!("route")),
+        set("out", json!("")),
+        LogicOperation::Loop {
+            collection: "[1, 2]".to_string(),
+            var: "x".to_string(),
+            body: vec![
+                // Each branch has a `tmp` of its own, and sees the loop's `x`
+                LogicOperation::If {
+                    condition: "{{x}} == 1".to_string(),
+                    then: vec![set("tmp", json!("a")), set("out", json!("{{out}}{{tmp}}{{x}}"))],
+                    otherwise: Some(vec![set("tmp", json!("b{{x}}")), set("out", json!("{{out}}{{tmp}}"))]),
+                },
+            ],
+        },
+        LogicOperation::Try {
+            body: vec![set("x", json!("assigned"))],
+            catch: vec![],
+            finally: None,
+        },
+        LogicOperation::Return { value: json!({"x": "{{x}}", "out": "{{out}}"}), status: None, headers: None, raw: None },
+    ];
+
+    let (vm_result, interpreter_result) = run_both_engines("test_block_scopes_agree_across_engines", &logic);
+    // The loop variable shadowed the route's `x`, which the try block then assigned
+    assert_eq!(vm_result, json!({"x": "assigned", "out": "a1b2"}));
+    assert_eq!(interpreter_result, vm_result);
+}
+
+#[test]
+fn test_parallel_max_concurrent_in_every_engine() {
+    use worpen_core::compiler::lowerer::LogicCompiler;
+    use worpen_core::vm::machine::VirtualMachine;
+    use worpen_core::services::dynamic_routes::execute_logic_extended;
+    use proto::models::{DynamicRouteExecutionContext, LogicOperation, LoopControl};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    // Four 50ms tasks, two at a time, take two rounds and keep their order
+    let task = |i: i64| vec![
+        LogicOperation::Sleep { duration_ms: 50 },
+        LogicOperation::Return { value: json!(i), status: None, headers: None, raw: None },
+    ];
+    let logic = vec![LogicOperation::Parallel { tasks: (0..4).map(task).collect(), max_concurrent: Some(2) }];
+    let expected = json!([0, 1, 2, 3]);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let tree = compiler.lower(&logic).unwrap();
+    let symbols = compiler.get_symbol_table().clone();
+
+    let started = Instant::now();
+    let mut flat_vm = VirtualMachine::new(ExecutionMemory::new(), symbols.clone());
+    assert_eq!(rt.block_on(flat_vm.execute(&program)).unwrap(), expected);
+    assert!(started.elapsed() >= Duration::from_millis(100), "VM ran {:?}", started.elapsed());
+
+    let started = Instant::now();
+    let mut tree_vm = VirtualMachine::new(ExecutionMemory::new(), symbols);
+    assert_eq!(rt.block_on(tree_vm.execute_tree(&tree)).unwrap(), expected);
+    assert!(started.elapsed() >= Duration::from_millis(100), "tree walker ran {:?}", started.elapsed());
+
+    let mut context = DynamicRouteExecutionContext {
+        route_id: "parallel".to_string(),
+        variables: HashMap::new(),
+        request_payload: None,
+        path_params: HashMap::new(),
+        query_params: HashMap::new(),
+        functions: HashMap::new(),
+        loop_control: LoopControl::default(),
+        error_context: None,
+    };
+    let started = Instant::now();
+    let interpreted = rt.block_on(execute_logic_extended(&logic, &mut context, &mut Vec::new())).unwrap();
+    assert_eq!(interpreted, expected);
+    assert!(started.elapsed() >= Duration::from_millis(100), "interpreter ran {:?}", started.elapsed());
+}
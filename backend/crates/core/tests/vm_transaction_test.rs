@@ -0,0 +1,150 @@
+use worpen_core::budget::{self, Limits};
+use worpen_core::compiler::lowerer::LogicCompiler;
+use worpen_core::vm::machine::VirtualMachine;
+use worpen_core::vm::memory::ExecutionMemory;
+use proto::models::LogicOperation;
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::time::Duration;
+
+async fn orders_db() -> SqlitePool {
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    sqlx::query("CREATE TABLE orders (id INTEGER PRIMARY KEY)").execute(&pool).await.unwrap();
+    sqlx::query("CREATE TABLE order_items (order_id INTEGER NOT NULL, sku TEXT NOT NULL)").execute(&pool).await.unwrap();
+    pool
+}
+
+fn sql(query: &str, args: Vec<Value>) -> LogicOperation {
+    LogicOperation::SqlOp { query: query.to_string(), args, params: None, datasource: None, output_var: "rows".to_string() }
+}
+
+fn insert_order(id: i64) -> LogicOperation {
+    sql("INSERT INTO orders (id) VALUES (?)", vec![json!(id)])
+}
+
+fn insert_item(order_id: i64, sku: Value) -> LogicOperation {
+    sql("INSERT INTO order_items (order_id, sku) VALUES (?, ?)", vec![json!(order_id), sku])
+}
+
+fn transaction(body: Vec<LogicOperation>) -> LogicOperation {
+    LogicOperation::Transaction { body, savepoint: false, datasource: None }
+}
+
+fn savepoint(body: Vec<LogicOperation>) -> LogicOperation {
+    LogicOperation::Transaction { body, savepoint: true, datasource: None }
+}
+
+fn throw(message: &str) -> LogicOperation {
+    LogicOperation::Throw { message: message.to_string(), code: None }
+}
+
+fn catch_all(body: Vec<LogicOperation>) -> LogicOperation {
+    LogicOperation::Try { body, catch: vec![], finally: None }
+}
+
+async fn run(pool: &SqlitePool, logic: Vec<LogicOperation>) -> Result<Value, String> {
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic)?;
+    let mut vm = VirtualMachine::with_db_pool(ExecutionMemory::new(), compiler.get_symbol_table().clone(), pool.clone());
+    vm.execute(&program).await
+}
+
+async fn rows(pool: &SqlitePool) -> (Vec<i64>, Vec<String>) {
+    let orders = sqlx::query_scalar("SELECT id FROM orders ORDER BY id").fetch_all(pool).await.unwrap();
+    let items = sqlx::query_scalar("SELECT sku FROM order_items ORDER BY rowid").fetch_all(pool).await.unwrap();
+    (orders, items)
+}
+
+#[tokio::test]
+async fn test_transaction_commits_when_its_body_completes() {
+    let pool = orders_db().await;
+    run(&pool, vec![transaction(vec![insert_order(1), insert_item(1, json!("A"))])]).await.unwrap();
+    assert_eq!(rows(&pool).await, (vec![1], vec!["A".to_string()]));
+}
+
+#[tokio::test]
+async fn test_transaction_rolls_back_on_errors() {
+    let pool = orders_db().await;
+
+    // A failed statement after a successful one leaves nothing behind
+    let err = run(&pool, vec![transaction(vec![insert_order(1), insert_item(1, json!(null))])]).await.unwrap_err();
+    assert!(err.contains("NOT NULL"), "unexpected error: {}", err);
+    assert_eq!(rows(&pool).await, (vec![], vec![]));
+
+    // So does a throw, even when a try outside the transaction catches it
+    run(&pool, vec![catch_all(vec![transaction(vec![insert_order(2), throw("out of stock")])])]).await.unwrap();
+    assert_eq!(rows(&pool).await, (vec![], vec![]));
+}
+
+#[tokio::test]
+async fn test_transaction_commits_after_a_caught_error() {
+    let pool = orders_db().await;
+    run(&pool, vec![transaction(vec![
+        insert_order(1),
+        catch_all(vec![insert_item(1, json!(null))]),
+        insert_item(1, json!("A")),
+    ])]).await.unwrap();
+    assert_eq!(rows(&pool).await, (vec![1], vec!["A".to_string()]));
+}
+
+#[tokio::test]
+async fn test_savepoints_roll_back_only_their_own_statements() {
+    let pool = orders_db().await;
+    run(&pool, vec![transaction(vec![
+        insert_order(1),
+        catch_all(vec![savepoint(vec![insert_item(1, json!("A")), throw("discount expired")])]),
+        savepoint(vec![insert_item(1, json!("B"))]),
+        // Without a savepoint the nested block is part of the outer transaction
+        catch_all(vec![transaction(vec![insert_item(1, json!("C")), throw("ignored")])]),
+    ])]).await.unwrap();
+    assert_eq!(rows(&pool).await, (vec![1], vec!["B".to_string(), "C".to_string()]));
+}
+
+#[tokio::test]
+async fn test_transaction_rolls_back_when_the_budget_runs_out() {
+    let pool = orders_db().await;
+    let logic = vec![transaction(vec![
+        insert_order(1),
+        LogicOperation::While { condition: "true".to_string(), body: vec![], max_iterations: Some(u32::MAX) },
+    ])];
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let mut vm = VirtualMachine::with_db_pool(ExecutionMemory::new(), compiler.get_symbol_table().clone(), pool.clone());
+    let limits = Limits { max_fuel: 1_000, ..Limits::global() };
+    let (result, exceeded) = budget::watch(budget::run(limits, vm.execute(&program))).await;
+    assert!(exceeded.is_some(), "unexpected error: {:?}", result);
+    assert_eq!(rows(&pool).await, (vec![], vec![]));
+
+    // An execution cut off at its deadline drops the open transaction, which rolls it back
+    let logic = vec![transaction(vec![insert_order(2), LogicOperation::Sleep { duration_ms: 10_000 }])];
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let mut vm = VirtualMachine::with_db_pool(ExecutionMemory::new(), compiler.get_symbol_table().clone(), pool.clone());
+    let limits = Limits { timeout: Duration::from_millis(50), ..Limits::global() };
+    assert!(budget::run(limits, vm.execute(&program)).await.is_err());
+    drop(vm);
+    assert_eq!(rows(&pool).await, (vec![], vec![]));
+}
+
+#[tokio::test]
+async fn test_tree_walker_runs_transactions() {
+    let pool = orders_db().await;
+    let logic = vec![
+        transaction(vec![insert_order(1), catch_all(vec![savepoint(vec![insert_item(1, json!("A")), throw("no")])])]),
+        catch_all(vec![transaction(vec![insert_order(2), throw("no")])]),
+    ];
+    let mut compiler = LogicCompiler::new();
+    let tree = compiler.lower(&logic).unwrap();
+    let mut vm = VirtualMachine::with_db_pool(ExecutionMemory::new(), compiler.get_symbol_table().clone(), pool.clone());
+    vm.execute_tree(&tree).await.unwrap();
+    assert_eq!(rows(&pool).await, (vec![1], vec![]));
+}
+
+#[tokio::test]
+async fn test_transaction_needs_a_pool() {
+    let logic = vec![transaction(vec![])];
+    let mut compiler = LogicCompiler::new();
+    let program = compiler.compile(&logic).unwrap();
+    let mut vm = VirtualMachine::new(ExecutionMemory::new(), compiler.get_symbol_table().clone());
+    assert_eq!(vm.execute(&program).await.unwrap_err(), "Database pool not available for transaction");
+}
@@ -30,7 +30,7 @@ async fn test_websocket_send_operation() {
     
     // Compile and execute
     let mut compiler = LogicCompiler::new();
-    let program = compiler.compile(&logic);
+    let program = compiler.compile(&logic).unwrap();
     let symbol_table = compiler.get_symbol_table().clone();
     
     let memory = ExecutionMemory::new();
@@ -80,7 +80,7 @@ async fn test_websocket_broadcast_operation() {
     
     // Compile and execute
     let mut compiler = LogicCompiler::new();
-    let program = compiler.compile(&logic);
+    let program = compiler.compile(&logic).unwrap();
     let symbol_table = compiler.get_symbol_table().clone();
     
     let memory = ExecutionMemory::new();
@@ -134,7 +134,7 @@ async fn test_websocket_variable_interpolation() {
     
     // Compile and execute
     let mut compiler = LogicCompiler::new();
-    let program = compiler.compile(&logic);
+    let program = compiler.compile(&logic).unwrap();
     let symbol_table = compiler.get_symbol_table().clone();
     
     let memory = ExecutionMemory::new();
@@ -189,7 +189,7 @@ async fn test_websocket_channel_broadcast() {
     
     // Compile and execute
     let mut compiler = LogicCompiler::new();
-    let program = compiler.compile(&logic);
+    let program = compiler.compile(&logic).unwrap();
     let symbol_table = compiler.get_symbol_table().clone();
     
     let memory = ExecutionMemory::new();
@@ -258,9 +258,16 @@ async fn test_websocket_route_with_hooks() {
             ],
         }),
         parameters: vec![],
+        request_schema: None,
         response_schema: None,
+        response_schema_policy: Default::default(),
         auth_required: false,
+        required_scopes: vec![],
+        required_roles: vec![],
         rate_limit: None,
+        rate_limit_key: None,
+        limits: None,
+        optimize: true,
         enabled: true,
         version: "1.0.0".to_string(),
         created_at: chrono::Utc::now().to_rfc3339(),
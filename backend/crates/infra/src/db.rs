@@ -1,4 +1,6 @@
+use proto::models::{ColumnInfo, ForeignKeyInfo, TableInfo};
 use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
 
 pub async fn initialize_db(database_url: &str) -> Result<SqlitePool, sqlx::Error> {
     let pool = SqlitePoolOptions::new()
@@ -12,3 +14,57 @@ pub async fn initialize_db(database_url: &str) -> Result<SqlitePool, sqlx::Error
 
     Ok(pool)
 }
+
+/// Read the tables of a database, skipping SQLite's own and the ones migrations keep their history in
+pub async fn introspect(pool: &SqlitePool) -> Result<Vec<TableInfo>, String> {
+    let names: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM sqlite_master WHERE type = 'table' \
+         AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' AND name NOT LIKE '\\_sqlx\\_%' ESCAPE '\\' \
+         AND name NOT LIKE '\\_worpen\\_%' ESCAPE '\\' ORDER BY name",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to list tables: {}", e))?;
+
+    let mut tables = Vec::with_capacity(names.len());
+    for name in names {
+        tables.push(introspect_table(pool, &name).await?);
+    }
+    Ok(tables)
+}
+
+/// Read one table's columns and foreign keys
+pub async fn introspect_table(pool: &SqlitePool, table: &str) -> Result<TableInfo, String> {
+    let columns: Vec<ColumnInfo> = sqlx::query("SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?) ORDER BY cid")
+        .bind(table)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to read columns of table '{}': {}", table, e))?
+        .iter()
+        .map(|row| ColumnInfo {
+            name: row.get("name"),
+            declared_type: row.get("type"),
+            not_null: row.get::<i64, _>("notnull") != 0,
+            default: row.get("dflt_value"),
+            primary_key: row.get::<i64, _>("pk") as u32,
+        })
+        .collect();
+    if columns.is_empty() {
+        return Err(format!("Table '{}' not found", table));
+    }
+
+    let foreign_keys = sqlx::query("SELECT \"from\", \"table\", \"to\" FROM pragma_foreign_key_list(?) ORDER BY id, seq")
+        .bind(table)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to read foreign keys of table '{}': {}", table, e))?
+        .iter()
+        .map(|row| ForeignKeyInfo {
+            column: row.get("from"),
+            references_table: row.get("table"),
+            references_column: row.get("to"),
+        })
+        .collect();
+
+    Ok(TableInfo { name: table.to_string(), columns, foreign_keys })
+}
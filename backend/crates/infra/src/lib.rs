@@ -3,7 +3,7 @@ pub mod db;
 pub mod repositories;
 
 pub use adapters::*;
-pub use db::initialize_db;
+pub use db::{initialize_db, introspect, introspect_table};
 
 pub fn hello() -> String {
     "Hello from infra".to_string()
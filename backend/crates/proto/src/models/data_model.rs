@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::HashMap;
+use super::routes::RouteDefinition;
+
+/// A table described declaratively. Registering it migrates the table to
+/// match and generates list/get/create/update/delete routes over it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DataModel {
+    /// Identifies the model and, unless `table` is given, names its table
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table: Option<String>,
+    /// Base path of the generated routes; `/api/<table>` when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Named database the table lives in; the default one when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datasource: Option<String>,
+    /// Columns besides the implicit `id`, `created_at` and `updated_at`
+    pub fields: Vec<ModelField>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub indexes: Vec<ModelIndex>,
+    /// Largest page the list route returns
+    #[serde(default = "default_max_page_size")]
+    pub max_page_size: u32,
+    /// Generated routes require an authenticated caller
+    #[serde(default)]
+    pub auth_required: bool,
+}
+
+fn default_max_page_size() -> u32 {
+    100
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ModelField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    /// NOT NULL, and required in create and update requests unless it has a default
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub unique: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<serde_json::Value>,
+    /// Smallest allowed value of a number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// Largest allowed value of a number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Longest allowed string, in characters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<u32>,
+    /// The only values the field may take
+    #[serde(default, rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    /// RFC 3339 timestamp, stored as text
+    Datetime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ModelIndex {
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub unique: bool,
+}
+
+/// A registered model with its migration history and the routes generated from it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RegisteredModel {
+    pub model: DataModel,
+    /// Number of the last migration applied
+    pub version: u32,
+    pub migrations: Vec<ModelMigration>,
+    /// Hash of each generated route as it was registered, by route id, to tell edited routes apart
+    #[serde(default)]
+    pub generated_routes: HashMap<String, String>,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ModelMigration {
+    pub version: u32,
+    pub statements: Vec<String>,
+    /// Drops or retypes columns, losing their data
+    pub destructive: bool,
+    pub applied_at: String,
+}
+
+/// What registering a model would change: the migration to run and the state of each route
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ModelPlan {
+    pub statements: Vec<String>,
+    pub destructive: bool,
+    pub routes: Vec<ModelRouteDiff>,
+}
+
+/// The outcome of registering a model
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ModelApplyResult {
+    pub model: RegisteredModel,
+    /// What was applied, with each route's state beforehand
+    pub plan: ModelPlan,
+    /// Edited routes that were left as they are
+    pub skipped_routes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ModelRouteDiff {
+    pub route_id: String,
+    pub status: RouteDiffStatus,
+    /// Top-level route fields that differ from the generated route
+    pub changes: Vec<String>,
+    pub generated: RouteDefinition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteDiffStatus {
+    /// Not registered yet
+    Missing,
+    /// Registered exactly as generated
+    UpToDate,
+    /// Generated from an earlier version of the model and not edited since
+    Outdated,
+    /// Edited after it was generated; only replaced when asked to
+    Edited,
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use super::routes::RouteDefinition;
+
+/// A table as read from an existing database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TableInfo {
+    pub name: String,
+    pub columns: Vec<ColumnInfo>,
+    #[serde(default)]
+    pub foreign_keys: Vec<ForeignKeyInfo>,
+}
+
+/// One row of `PRAGMA table_info`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ColumnInfo {
+    pub name: String,
+    /// Type as written in the table definition; may be empty
+    pub declared_type: String,
+    pub not_null: bool,
+    /// Default as an SQL expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// Position in the primary key, starting at 1; 0 when not part of it
+    #[serde(default)]
+    pub primary_key: u32,
+}
+
+/// One column of a foreign key, from `PRAGMA foreign_key_list`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ForeignKeyInfo {
+    pub column: String,
+    pub references_table: String,
+    /// Referenced column; the referenced table's primary key when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub references_column: Option<String>,
+}
+
+/// What introspecting a table produces: its JSON Schema and routes to register over it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TableScaffold {
+    pub table: TableInfo,
+    /// JSON Schema of a row
+    pub schema: serde_json::Value,
+    pub routes: Vec<RouteDefinition>,
+    /// Why the table has no routes, when it doesn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
@@ -6,6 +6,8 @@ pub mod logs;
 pub mod pipelines;
 pub mod terminal;
 pub mod routes;
+pub mod data_model;
+pub mod introspection;
 
 pub use agent::*;
 pub use incident::*;
@@ -15,3 +17,5 @@ pub use logs::*;
 pub use pipelines::*;
 pub use terminal::*;
 pub use routes::*;
+pub use data_model::*;
+pub use introspection::*;
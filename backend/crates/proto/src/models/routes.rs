@@ -10,6 +10,39 @@ pub enum RouteType {
     WebSocket,
 }
 
+/// Per-route execution limits; each one can only tighten the server-wide limit
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema, PartialEq, Eq)]
+pub struct ExecutionLimits {
+    /// Operations (and loop iterations) the logic may run
+    #[serde(default)]
+    pub max_fuel: Option<u64>,
+    /// Wall-clock time for the whole execution
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Approximate size of any single stored value
+    #[serde(default)]
+    pub max_value_bytes: Option<usize>,
+    /// Tasks in a single `parallel` operation
+    #[serde(default)]
+    pub max_parallel: Option<usize>,
+    /// How deeply `call_function` calls may nest
+    #[serde(default)]
+    pub max_call_depth: Option<usize>,
+}
+
+/// What happens when a route returns a value that does not match its `response_schema`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseSchemaPolicy {
+    /// Don't check the response
+    #[default]
+    Off,
+    /// Log the mismatch and flag it in a response header
+    Warn,
+    /// Replace the response with a 500 listing the mismatches
+    Enforce,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub enum HttpMethod {
     GET,
@@ -19,6 +52,19 @@ pub enum HttpMethod {
     PATCH,
 }
 
+impl HttpMethod {
+    /// Upper-case method name as it appears on the wire
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::PATCH => "PATCH",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub enum LogicOperation {
     // Data Operations
@@ -37,17 +83,43 @@ pub enum LogicOperation {
     Comment { text: String },
     
     #[serde(rename = "query_db")]
-    QueryDb { query: String, params: Vec<serde_json::Value> },
+    QueryDb {
+        query: String,
+        #[serde(default)]
+        params: SqlParams,
+        /// Named database to run on; the default one when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        datasource: Option<String>,
+    },
     
     #[serde(rename = "sql_op")]
-    SqlOp { query: String, args: Vec<serde_json::Value>, output_var: String },
+    SqlOp {
+        query: String,
+        /// Bound in order to `?` placeholders
+        #[serde(default)]
+        args: Vec<serde_json::Value>,
+        /// Bound by name to `:name` placeholders; a name missing here reads the route variable
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        params: Option<HashMap<String, serde_json::Value>>,
+        /// Named database to run on; the default one when absent
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        datasource: Option<String>,
+        output_var: String,
+    },
     
     #[serde(rename = "redis_op")]
     RedisOp { 
-        command: String,  // "GET", "SET", "DEL", "EXPIRE", "INCR", "DECR"
-        key: String,      // Target key (supports {{vars}})
-        value: Option<String>, // Value for SET (supports {{vars}})
-        ttl_seconds: Option<u64>, // For EXPIRE or SETEX
+        command: String,  // "GET", "HSET", "ZRANGE", ... or "PIPELINE"
+        /// Target key (supports {{vars}}); MGET and MSET may take all their keys from `args`
+        #[serde(default)]
+        key: String,
+        /// Sent after the key, each keeping its value's type; `value: x` is read as `args: [x]`
+        #[serde(default, alias = "value", deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+        args: Vec<serde_json::Value>,
+        ttl_seconds: Option<u64>, // For SET, EXPIRE, or to expire the key after a write
+        /// Commands a PIPELINE sends in one round trip
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        commands: Vec<RedisCommand>,
         output_var: Option<String> // Where to store result
     },
     
@@ -87,6 +159,20 @@ pub enum LogicOperation {
     
     #[serde(rename = "throw")]
     Throw { message: String, code: Option<String> },
+
+    // Transactions
+    /// Run `body` on one database connection, committed when it completes and
+    /// rolled back when an error leaves it. Nested in another transaction it
+    /// joins that one, or with `savepoint` rolls back only its own statements.
+    /// Statements on other datasources run outside of it.
+    #[serde(rename = "transaction")]
+    Transaction {
+        body: Vec<LogicOperation>,
+        #[serde(default)]
+        savepoint: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        datasource: Option<String>,
+    },
     
     // Parallel Execution
     #[serde(rename = "parallel")]
@@ -148,6 +234,83 @@ pub enum LogicOperation {
     CustomOp(HashMap<String, serde_json::Value>),
 }
 
+/// One command of a `redis_op` PIPELINE
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RedisCommand {
+    pub command: String,
+    #[serde(default)]
+    pub key: String,
+    #[serde(default, alias = "value", deserialize_with = "one_or_many", skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>,
+}
+
+/// A list, or a single value standing for a list of one
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<serde_json::Value>, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Null => Vec::new(),
+        serde_json::Value::Array(items) => items,
+        value => vec![value],
+    })
+}
+
+impl LogicOperation {
+    /// The operation's name as written in route definitions
+    pub fn kind(&self) -> &str {
+        match self {
+            LogicOperation::Return { .. } => "return",
+            LogicOperation::Comment { .. } => "comment",
+            LogicOperation::QueryDb { .. } => "query_db",
+            LogicOperation::SqlOp { .. } => "sql_op",
+            LogicOperation::RedisOp { .. } => "redis_op",
+            LogicOperation::WsOp { .. } => "ws_op",
+            LogicOperation::HttpRequest { .. } => "http_request",
+            LogicOperation::If { .. } => "if",
+            LogicOperation::Loop { .. } => "loop",
+            LogicOperation::Switch { .. } => "switch",
+            LogicOperation::While { .. } => "while",
+            LogicOperation::Break => "break",
+            LogicOperation::Continue => "continue",
+            LogicOperation::Try { .. } => "try",
+            LogicOperation::Transaction { .. } => "transaction",
+            LogicOperation::Throw { .. } => "throw",
+            LogicOperation::Parallel { .. } => "parallel",
+            LogicOperation::AwaitAll { .. } => "await_all",
+            LogicOperation::DefineFunction { .. } => "define_function",
+            LogicOperation::CallFunction { .. } => "call_function",
+            LogicOperation::Map { .. } => "map",
+            LogicOperation::Filter { .. } => "filter",
+            LogicOperation::Aggregate { .. } => "aggregate",
+            LogicOperation::Set { .. } => "set",
+            LogicOperation::Get { .. } => "get",
+            LogicOperation::StringOp { .. } => "string_op",
+            LogicOperation::MathOp { .. } => "math_op",
+            LogicOperation::DateOp { .. } => "date_op",
+            LogicOperation::JsonOp { .. } => "json_op",
+            LogicOperation::Log { .. } => "log",
+            LogicOperation::Sleep { .. } => "sleep",
+            LogicOperation::ExecuteScript { .. } => "execute_script",
+            LogicOperation::CustomOp(operation_map) => operation_map.keys().next().map_or("custom", String::as_str),
+        }
+    }
+}
+
+/// Parameters of a `query_db` statement: a list bound in order to `?`
+/// placeholders, or a map bound by name to `:name` ones
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum SqlParams {
+    Positional(Vec<serde_json::Value>),
+    Named(HashMap<String, serde_json::Value>),
+}
+
+impl Default for SqlParams {
+    fn default() -> Self {
+        Self::Positional(vec![])
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SwitchCase {
     pub value: serde_json::Value,
@@ -175,9 +338,30 @@ pub struct RouteDefinition {
     pub logic: Vec<LogicOperation>,
     pub ws_hooks: Option<WebSocketHooks>,
     pub parameters: Vec<RouteParameter>,
+    /// JSON Schema (Draft 7) the request body must satisfy
+    #[serde(default)]
+    pub request_schema: Option<serde_json::Value>,
     pub response_schema: Option<serde_json::Value>,
+    #[serde(default)]
+    pub response_schema_policy: ResponseSchemaPolicy,
     pub auth_required: bool,
+    /// Scopes the caller must all hold; implies authentication
+    #[serde(default)]
+    pub required_scopes: Vec<String>,
+    /// Roles of which the caller must hold at least one; implies authentication
+    #[serde(default)]
+    pub required_roles: Vec<String>,
+    /// Requests per minute per client
     pub rate_limit: Option<u32>,
+    /// Who `rate_limit` counts against: `ip` (default), `api_key` or a template
+    #[serde(default)]
+    pub rate_limit_key: Option<String>,
+    #[serde(default)]
+    pub limits: Option<ExecutionLimits>,
+    /// Run the optimizer over the logic before it is compiled; off keeps the
+    /// program exactly as written, e.g. to trace or debug it
+    #[serde(default = "default_optimize")]
+    pub optimize: bool,
     pub enabled: bool,
     pub version: String,
     pub created_at: String,
@@ -199,10 +383,24 @@ pub struct RegisterRouteRequest {
     pub ws_hooks: Option<WebSocketHooks>,
     #[serde(default)]
     pub parameters: Vec<RouteParameter>,
+    #[serde(default)]
+    pub request_schema: Option<serde_json::Value>,
     pub response_schema: Option<serde_json::Value>,
     #[serde(default)]
+    pub response_schema_policy: ResponseSchemaPolicy,
+    #[serde(default)]
     pub auth_required: bool,
+    #[serde(default)]
+    pub required_scopes: Vec<String>,
+    #[serde(default)]
+    pub required_roles: Vec<String>,
     pub rate_limit: Option<u32>,
+    #[serde(default)]
+    pub rate_limit_key: Option<String>,
+    #[serde(default)]
+    pub limits: Option<ExecutionLimits>,
+    #[serde(default = "default_optimize")]
+    pub optimize: bool,
     #[serde(default = "default_enabled")]
     pub enabled: bool,
     #[serde(default = "default_version")]
@@ -213,6 +411,10 @@ fn default_enabled() -> bool {
     true
 }
 
+fn default_optimize() -> bool {
+    true
+}
+
 fn default_version() -> String {
     "1.0.0".to_string()
 }
@@ -241,6 +443,114 @@ pub struct RouteTestResponse {
     pub error: Option<String>,
     pub execution_time_ms: u64,
     pub steps_executed: Vec<String>,
+    /// Ways the result breaks the route's `response_schema`, whatever its policy
+    #[serde(default)]
+    pub schema_errors: Vec<String>,
+    /// One record per executed operation
+    #[serde(default)]
+    pub trace: Vec<TraceRecord>,
+}
+
+/// What happened when one operation ran
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq)]
+pub struct TraceRecord {
+    /// Where the operation sits in the logic tree, e.g. `logic[2].then[0]`
+    pub path: String,
+    /// The operation's name, e.g. `sql_op`
+    pub op: String,
+    /// Its inputs after templates were resolved, with secrets redacted
+    pub inputs: serde_json::Value,
+    pub output: serde_json::Value,
+    /// Microseconds from the start of the execution
+    pub started_us: u64,
+    pub duration_us: u64,
+    pub error: Option<String>,
+}
+
+/// A traced live execution of a route
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RouteTrace {
+    pub route_id: String,
+    pub recorded_at: String,
+    pub duration_us: u64,
+    pub error: Option<String>,
+    pub records: Vec<TraceRecord>,
+}
+
+/// A persisted route that could not be loaded on startup. The file is left in
+/// place and tried again on the next start; registering a route with the same
+/// id takes it out of quarantine.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QuarantinedRoute {
+    /// Path of the route file
+    pub file: String,
+    /// Unknown when the file could not be parsed
+    pub route_id: Option<String>,
+    pub error: String,
+    pub quarantined_at: String,
+}
+
+/// A configured datasource and whether it answers a query
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DatasourceStatus {
+    pub name: String,
+    pub read_only: bool,
+    pub max_connections: u32,
+    /// Connections open, idle or in use
+    pub connections: u32,
+    pub idle_connections: usize,
+    pub healthy: bool,
+    /// Why the health check failed
+    pub error: Option<String>,
+}
+
+/// A paused debug session, waiting for a client on `ws_path`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DebugSessionResponse {
+    pub session_id: String,
+    pub ws_path: String,
+}
+
+/// Message a debugger client sends over the session's WebSocket
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum DebugCommand {
+    /// Run until the next breakpoint
+    Continue,
+    /// Pause before the next operation, wherever it is
+    StepInto,
+    /// Pause before the next operation at this depth or above
+    StepOver,
+    /// Pause once the block holding the current operation is done
+    StepOut,
+    /// Pause before the next operation while running
+    Pause,
+    /// Replace the breakpoints with these logic paths, e.g. `logic[2].then[0]`
+    SetBreakpoints { paths: Vec<String> },
+    /// Values of one variable, or of all of them
+    Inspect {
+        #[serde(default)]
+        var: Option<String>,
+    },
+    SetVariable { var: String, value: serde_json::Value },
+    /// Abort the execution
+    Stop,
+}
+
+/// Message the server sends to a debugger client
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DebugEvent {
+    /// Execution stopped before the operation at `path`
+    Paused { path: String, op: String, depth: usize, breakpoint: bool },
+    Breakpoints { paths: Vec<String> },
+    Variables { values: HashMap<String, serde_json::Value> },
+    Error { message: String },
+    /// The execution ended; no more events follow
+    Finished {
+        result: Option<serde_json::Value>,
+        error: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
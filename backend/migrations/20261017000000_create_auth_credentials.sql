@@ -0,0 +1,8 @@
+-- Credentials for HTTP Basic authentication on dynamic routes
+-- scopes and roles are space-separated lists
+CREATE TABLE IF NOT EXISTS auth_credentials (
+    username TEXT PRIMARY KEY NOT NULL,
+    password_hash TEXT NOT NULL,
+    scopes TEXT NOT NULL DEFAULT '',
+    roles TEXT NOT NULL DEFAULT ''
+);
@@ -5,10 +5,13 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::process;
 use colored::Colorize;
+use proto::models::TableScaffold;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use worpen_core::data_model::scaffold;
 
 #[derive(Parser)]
 #[command(name = "worpen-convert")]
-#[command(about = "Convert dynamic routes between JSON and YAML formats", long_about = None)]
+#[command(about = "Convert dynamic routes between JSON and YAML formats, and scaffold them from a database", long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
@@ -71,6 +74,29 @@ enum Commands {
         #[arg(short, long)]
         yaml: PathBuf,
     },
+    
+    /// Introspect a SQLite database and scaffold a JSON Schema and YAML routes per table
+    Introspect {
+        /// SQLite database file (opened read-only)
+        #[arg(short = 'b', long)]
+        database: PathBuf,
+        
+        /// Output directory (optional, defaults to scaffold/)
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+        
+        /// Tables to scaffold, comma-separated (optional, defaults to every table)
+        #[arg(short, long, value_delimiter = ',')]
+        tables: Vec<String>,
+        
+        /// Datasource the routes will run on
+        #[arg(long, default_value = worpen_core::datasource::DEFAULT_DATASOURCE)]
+        datasource: String,
+        
+        /// Dry run - list what would be written without writing files
+        #[arg(short, long)]
+        dry_run: bool,
+    },
 }
 
 fn main() {
@@ -89,6 +115,9 @@ fn main() {
         Commands::Diff { json, yaml } => {
             handle_diff(json, yaml);
         }
+        Commands::Introspect { database, output_dir, tables, datasource, dry_run } => {
+            handle_introspect(database, output_dir.as_ref(), tables, datasource, *dry_run);
+        }
     }
 }
 
@@ -252,7 +281,7 @@ fn find_json_files(dir: &Path, recursive: bool) -> Vec<PathBuf> {
         for entry in entries.flatten() {
             let path = entry.path();
             
-            if path.is_file() && path.extension().map_or(false, |ext| ext == "json") {
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
                 files.push(path);
             } else if recursive && path.is_dir() {
                 files.extend(find_json_files(&path, recursive));
@@ -318,7 +347,7 @@ fn handle_validate(file: &Path, verbose: bool) {
         let required_fields = vec!["path", "method", "logic", "response"];
         
         for field in &required_fields {
-            if map.contains_key(&YamlValue::String(field.to_string())) {
+            if map.contains_key(YamlValue::String(field.to_string())) {
                 if verbose {
                     println!("  {} Field '{}' present", "✓".green(), field);
                 }
@@ -330,7 +359,7 @@ fn handle_validate(file: &Path, verbose: bool) {
         }
         
         // Check path format
-        if let Some(YamlValue::String(path)) = map.get(&YamlValue::String("path".to_string())) {
+        if let Some(YamlValue::String(path)) = map.get(YamlValue::String("path".to_string())) {
             if path.starts_with('/') {
                 if verbose {
                     println!("  {} Path format valid", "✓".green());
@@ -343,8 +372,8 @@ fn handle_validate(file: &Path, verbose: bool) {
         }
         
         // Check method
-        if let Some(YamlValue::String(method)) = map.get(&YamlValue::String("method".to_string())) {
-            let valid_methods = vec!["GET", "POST", "PUT", "DELETE", "PATCH"];
+        if let Some(YamlValue::String(method)) = map.get(YamlValue::String("method".to_string())) {
+            let valid_methods = ["GET", "POST", "PUT", "DELETE", "PATCH"];
             if valid_methods.contains(&method.as_str()) {
                 if verbose {
                     println!("  {} HTTP method valid", "✓".green());
@@ -357,12 +386,12 @@ fn handle_validate(file: &Path, verbose: bool) {
         }
         
         // Check logic is array
-        if let Some(YamlValue::Sequence(_)) = map.get(&YamlValue::String("logic".to_string())) {
+        if let Some(YamlValue::Sequence(_)) = map.get(YamlValue::String("logic".to_string())) {
             if verbose {
                 println!("  {} Logic is an array", "✓".green());
             }
             checks_passed += 1;
-        } else if map.contains_key(&YamlValue::String("logic".to_string())) {
+        } else if map.contains_key(YamlValue::String("logic".to_string())) {
             println!("  {} Logic should be an array", "✗".red());
             checks_failed += 1;
         }
@@ -461,3 +490,116 @@ fn handle_diff(json_path: &Path, yaml_path: &Path) {
         println!("{}", "─".repeat(80).yellow());
     }
 }
+
+fn handle_introspect(database: &Path, output_dir: Option<&PathBuf>, tables: &[String], datasource: &str, dry_run: bool) {
+    println!("{}", "🔍 Introspecting SQLite database...".cyan().bold());
+    println!("Database: {}", database.display());
+    
+    if !database.is_file() {
+        eprintln!("{} Database file not found", "❌".red());
+        process::exit(1);
+    }
+    
+    let out_dir = output_dir.cloned().unwrap_or_else(|| PathBuf::from("scaffold"));
+    println!("Output directory: {}", out_dir.display());
+    
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("{} Failed to start runtime: {}", "❌".red(), e);
+            process::exit(1);
+        }
+    };
+    let scaffolds = match runtime.block_on(introspect_database(database, tables, datasource)) {
+        Ok(scaffolds) => scaffolds,
+        Err(e) => {
+            eprintln!("{} {}", "❌".red(), e);
+            process::exit(1);
+        }
+    };
+    
+    if scaffolds.is_empty() {
+        println!("{} No tables found", "⚠️".yellow());
+        return;
+    }
+    
+    println!("\nFound {} table(s)\n", scaffolds.len().to_string().green().bold());
+    
+    let mut written = 0;
+    let mut failed = 0;
+    
+    for scaffold in &scaffolds {
+        let columns = scaffold.table.columns.len();
+        println!("{} ({} columns, {} routes)", scaffold.table.name.cyan(), columns, scaffold.routes.len());
+        if let Some(reason) = &scaffold.skipped {
+            println!("  {} {}", "⚠️".yellow(), reason);
+        }
+        
+        let mut files = vec![(
+            out_dir.join("schemas").join(format!("{}.json", scaffold.table.name)),
+            serde_json::to_string_pretty(&scaffold.schema).map_err(|e| e.to_string()),
+        )];
+        for route in &scaffold.routes {
+            // Through a JSON value, so operations come out as plain mappings rather than YAML tags
+            let yaml = serde_json::to_value(route)
+                .map_err(|e| e.to_string())
+                .and_then(|value| serde_yaml::to_string(&value).map_err(|e| e.to_string()));
+            files.push((out_dir.join("routes").join(format!("{}.yaml", route.id)), yaml));
+        }
+        
+        for (path, content) in files {
+            let result = content.and_then(|content| {
+                if dry_run {
+                    return Ok(());
+                }
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| format!("Write error: {}", e))?;
+                }
+                fs::write(&path, content).map_err(|e| format!("Write error: {}", e))
+            });
+            match result {
+                Ok(_) => {
+                    println!("  {} {}", "✓".green(), path.display());
+                    written += 1;
+                }
+                Err(e) => {
+                    println!("  {} {} {}", "✗".red(), path.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    
+    println!("\n{}", "═".repeat(60).cyan());
+    println!("{}", "Summary:".cyan().bold());
+    println!("  Written: {}", written.to_string().green());
+    println!("  Failed:  {}", failed.to_string().red());
+    println!("{}", "═".repeat(60).cyan());
+    
+    if dry_run {
+        println!("\n{}", "💡 This was a dry run. Use without --dry-run to save files.".yellow());
+    }
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+async fn introspect_database(database: &Path, tables: &[String], datasource: &str) -> Result<Vec<TableScaffold>, String> {
+    let options = SqliteConnectOptions::new().filename(database).read_only(true);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    let tables = if tables.is_empty() {
+        infra::introspect(&pool).await?
+    } else {
+        let mut found = Vec::with_capacity(tables.len());
+        for table in tables {
+            found.push(infra::introspect_table(&pool, table).await?);
+        }
+        found
+    };
+    Ok(tables.into_iter().map(|table| scaffold::scaffold(table, datasource)).collect())
+}
@@ -457,25 +457,46 @@ export const WORPEN_ROUTE_SCHEMA: WorpenSchema = {
       "properties": {
         "redis_op": {
           "type": "object",
-          "required": ["command", "key"],
+          "required": ["command"],
           "properties": {
             "command": {
               "type": "string",
-              "enum": ["GET", "SET", "DEL", "EXPIRE", "INCR", "DECR"],
+              "enum": [
+                "GET", "SET", "DEL", "EXISTS", "EXPIRE", "TTL", "INCR", "DECR", "MGET", "MSET",
+                "HGET", "HSET", "HGETALL", "HDEL", "LPUSH", "RPUSH", "LPOP", "LRANGE",
+                "SADD", "SMEMBERS", "SISMEMBER", "ZADD", "ZRANGE", "ZREVRANGE", "PIPELINE"
+              ],
               "description": "Redis command"
             },
             "key": {
               "type": "string",
-              "description": "Redis key (supports templates)"
+              "description": "Redis key (supports templates); optional for MGET, MSET and PIPELINE"
+            },
+            "args": {
+              "type": "array",
+              "description": "Arguments after the key, each keeping its type (supports templates)"
             },
             "value": {
-              "type": "string",
-              "description": "Value for SET command"
+              "description": "Single argument, same as args: [value]"
             },
             "ttl_seconds": {
               "type": "integer",
               "minimum": 1
             },
+            "commands": {
+              "type": "array",
+              "description": "Commands a PIPELINE sends in one round trip",
+              "items": {
+                "type": "object",
+                "required": ["command"],
+                "properties": {
+                  "command": { "type": "string" },
+                  "key": { "type": "string" },
+                  "args": { "type": "array" },
+                  "ttl_seconds": { "type": "integer", "minimum": 1 }
+                }
+              }
+            },
             "output_var": {
               "type": "string"
             }